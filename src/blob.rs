@@ -0,0 +1,150 @@
+use std::io::{self, Read, Seek, SeekFrom};
+
+use crate::database::Database;
+use crate::error::{bail, format_err, Error, Result};
+use crate::page::Cell;
+use crate::record;
+
+/// A blob or text value of a table row, read a page at a time as it is
+/// asked for instead of all at once. Text is read as stored, in the
+/// encoding of the database.
+pub struct Blob<'db> {
+    database: &'db mut Database,
+    /// The part of the payload stored in the cell itself.
+    local: Vec<u8>,
+    /// The pages of the overflow chain found so far, in chain order.
+    overflow_pages: Vec<u32>,
+    /// The last overflow page read, by its position in the chain.
+    page: Option<(usize, Vec<u8>)>,
+    /// Where the value starts in the payload.
+    start: u64,
+    size: u64,
+    position: u64,
+}
+
+impl<'db> Blob<'db> {
+    /// Opens the value of `column` in the row with `rowid` of `table`.
+    pub fn open(database: &'db mut Database, table: &str, column: &str, rowid: i64) -> Result<Self> {
+        let (rootpage, index) = {
+            let table = database
+                .schema
+                .find_table(table)
+                .ok_or_else(|| Error::NoSuchTable(table.to_string()))?;
+            if table.without_rowid || table.is_virtual() {
+                bail!("Unable to open a blob of table: {}", table.name);
+            }
+            match table.find_column(column) {
+                Some((_, column)) if column.is_primary_key => bail!("Cannot open value of type integer"),
+                Some((index, _)) => (table.rootpage, index),
+                None => return Err(Error::NoSuchColumn(column.to_string())),
+            }
+        };
+
+        let page = database.find_leaf(rootpage, rowid)?;
+        let (local, overflow_page) = page
+            .cells()
+            .find_map(|cell| match cell {
+                Cell::LeafTable {
+                    rowid: cell_rowid,
+                    payload,
+                    overflow_page,
+                    ..
+                } if cell_rowid == rowid => Some((payload.to_vec(), overflow_page)),
+                _ => None,
+            })
+            .ok_or(format_err!("No row with rowid: {}", rowid))?;
+
+        let range = record::value_range(&local, index)?;
+        Ok(Self {
+            database,
+            local,
+            overflow_pages: Some(overflow_page).filter(|&page| page != 0).into_iter().collect(),
+            page: None,
+            start: range.start,
+            size: range.end - range.start,
+            position: 0,
+        })
+    }
+
+    /// Length of the value in bytes.
+    pub fn len(&self) -> u64 {
+        self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    /// Content of the overflow page at position `index` of the chain,
+    /// following the chain from the last page found to get there.
+    fn overflow_page(&mut self, index: usize) -> Result<&[u8]> {
+        while self.overflow_pages.len() <= index {
+            let Some(&last) = self.overflow_pages.last() else {
+                bail!("Overflow chain ends early");
+            };
+            let next = match &self.page {
+                Some((position, page)) if position + 1 == self.overflow_pages.len() => page[..4].to_vec(),
+                _ => self.database.read_raw_page(last)?[..4].to_vec(),
+            };
+            match u32::from_be_bytes([next[0], next[1], next[2], next[3]]) {
+                0 => bail!("Overflow chain ends early"),
+                next => self.overflow_pages.push(next),
+            }
+        }
+
+        if !matches!(&self.page, Some((position, _)) if *position == index) {
+            let page = self.database.read_raw_page(self.overflow_pages[index])?;
+            self.page = Some((index, page));
+        }
+        Ok(&self.page.as_ref().expect("page was read").1)
+    }
+
+    /// Copies as much of the value at `offset` of the payload as is on one
+    /// page into `buf`.
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let local = self.local.len() as u64;
+        if offset < local {
+            let count = buf.len().min((local - offset) as usize);
+            buf[..count].copy_from_slice(&self.local[offset as usize..offset as usize + count]);
+            return Ok(count);
+        }
+
+        // Each overflow page starts with the number of the next one.
+        let chunk = self.database.header.usable_size() as u64 - 4;
+        let (index, within) = ((offset - local) / chunk, (offset - local) % chunk);
+        let count = buf.len().min((chunk - within) as usize);
+        let page = self.overflow_page(index as usize)?;
+        let within = 4 + within as usize;
+        buf[..count].copy_from_slice(&page[within..within + count]);
+        Ok(count)
+    }
+}
+
+impl Read for Blob<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.size {
+            return Ok(0);
+        }
+        let remaining = (self.size - self.position).min(buf.len() as u64) as usize;
+        let count = self
+            .read_at(self.start + self.position, &mut buf[..remaining])
+            .map_err(io::Error::other)?;
+        self.position += count as u64;
+        Ok(count)
+    }
+}
+
+impl Seek for Blob<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.size.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        let Some(position) = position else {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Seek before the start of the blob"));
+        };
+        self.position = position;
+        Ok(position)
+    }
+}
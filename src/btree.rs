@@ -0,0 +1,900 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use crate::database::{self, Database};
+use crate::error::{bail, Error, Result};
+use crate::page::{Cell, Page, PageKind};
+use crate::varient;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Forward,
+    Backward,
+}
+
+/// A table row or index key read through a cursor, with its full payload.
+/// Index entries have a rowid of 0; the rowid is the last value of their
+/// record instead.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub rowid: i64,
+    pub payload: Vec<u8>,
+}
+
+#[derive(Debug)]
+struct Frame {
+    number: u32,
+    page: Arc<Page>,
+    step: usize,
+}
+
+impl Frame {
+    /// Interior pages alternate between descending into a child and visiting
+    /// the cell after it: child 0, cell 0, child 1, ..., right child.
+    fn step_count(&self) -> usize {
+        let cells = self.page.header.number_of_cells as usize;
+        if self.page.header.kind.is_interior() {
+            2 * cells + 1
+        } else {
+            cells
+        }
+    }
+}
+
+/// Walks the entries of a B-tree in key order, or in reverse key order.
+pub struct BTreeCursor<'db> {
+    database: &'db mut Database,
+    direction: Direction,
+    stack: Vec<Frame>,
+}
+
+impl<'db> BTreeCursor<'db> {
+    pub fn new(database: &'db mut Database, rootpage: u32, direction: Direction) -> Result<Self> {
+        let page = database.get_page(rootpage - 1)?;
+
+        Ok(Self {
+            database,
+            direction,
+            stack: vec![Frame {
+                number: rootpage,
+                page,
+                step: 0,
+            }],
+        })
+    }
+
+    pub fn database(&self) -> &Database {
+        self.database
+    }
+
+    fn advance(&mut self) -> Result<Option<Entry>> {
+        loop {
+            let Some(frame) = self.stack.last_mut() else { return Ok(None); };
+
+            let count = frame.step_count();
+            if frame.step == count {
+                self.stack.pop();
+                continue;
+            }
+            let step = match self.direction {
+                Direction::Forward => frame.step,
+                Direction::Backward => count - 1 - frame.step,
+            };
+            frame.step += 1;
+
+            let page = &frame.page;
+            let (child, cell) = if page.header.kind.is_leaf() {
+                (None, Some(step))
+            } else if step % 2 == 1 {
+                (None, Some(step / 2))
+            } else if step / 2 == page.header.number_of_cells as usize {
+                (page.header.right_child_page_number, None)
+            } else {
+                match page.cells().nth(step / 2) {
+                    Some(Cell::InteriorTable {
+                        left_child_page, ..
+                    })
+                    | Some(Cell::InteriorIndex {
+                        left_child_page, ..
+                    }) => (Some(left_child_page), None),
+                    _ => {
+                        let offset = page.cell_offset(step / 2);
+                        return Err(Error::corrupt_page(frame.number, offset, "Unsupported cell type"));
+                    }
+                }
+            };
+
+            if let Some(child) = child {
+                let page = self.database.get_page(child - 1)?;
+                if page.header.kind.is_table() != frame_is_table(&self.stack) {
+                    return Err(Error::corrupt_page(
+                        child,
+                        page.offset as usize,
+                        "Malformed B-tree: table and index pages are mixed",
+                    ));
+                }
+                self.stack.push(Frame {
+                    number: child,
+                    page,
+                    step: 0,
+                });
+                continue;
+            }
+
+            let Some(cell) = cell else { continue; };
+            let page = &self.stack.last().expect("frame exists").page;
+            match page.cells().nth(cell) {
+                // Table interior cells only hold divider keys.
+                Some(Cell::InteriorTable { .. }) => continue,
+                Some(cell @ Cell::LeafTable { rowid, .. }) => {
+                    self.database.stats.rows_scanned += 1;
+                    return Ok(Some(Entry {
+                        rowid,
+                        payload: self.database.read_payload(&cell)?,
+                    }));
+                }
+                Some(cell) => {
+                    return Ok(Some(Entry {
+                        rowid: 0,
+                        payload: self.database.read_payload(&cell)?,
+                    }));
+                }
+                None => bail!("Cell index out of range"),
+            }
+        }
+    }
+}
+
+fn frame_is_table(stack: &[Frame]) -> bool {
+    stack
+        .last()
+        .is_none_or(|frame| frame.page.header.kind.is_table())
+}
+
+impl Iterator for BTreeCursor<'_> {
+    type Item = Result<Entry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.advance().transpose()
+    }
+}
+
+/// Deletes the row with `rowid` from the table B-tree rooted at `rootpage`,
+/// returning whether it had one. A leaf left empty is freed, and so is an
+/// interior page left with only its right child, which takes its place.
+pub fn delete_row(database: &mut Database, rootpage: u32, rowid: i64) -> Result<bool> {
+    let (path, page_number) = find_leaf(database, rootpage, rowid)?;
+    let page = database.get_page(page_number - 1)?;
+    let Some(index) = page
+        .cells()
+        .position(|cell| matches!(cell, Cell::LeafTable { rowid: cell_rowid, .. } if cell_rowid == rowid))
+    else {
+        return Ok(false);
+    };
+    database.free_overflow(&page.cells().nth(index).expect("cell exists"))?;
+
+    let mut page = Arc::unwrap_or_clone(page);
+    page.remove_cell(index)?;
+    if page.cell_pointers.is_empty() && !path.is_empty() {
+        database.free_page(page_number)?;
+        remove_child(database, path)?;
+    } else {
+        database.write_page(page_number - 1, &page)?;
+    }
+    Ok(true)
+}
+
+/// Inserts a row into the table B-tree rooted at `rootpage`, which must not
+/// have one with `rowid` yet. A leaf too full for it is split.
+pub fn insert_row(database: &mut Database, rootpage: u32, rowid: i64, payload: &[u8]) -> Result<()> {
+    let (path, page_number) = find_leaf(database, rootpage, rowid)?;
+    let page = database.get_page(page_number - 1)?;
+    let mut index = page.cell_pointers.len();
+    for (i, cell) in page.cells().enumerate() {
+        let Cell::LeafTable { rowid: cell_rowid, .. } = cell else {
+            return Err(Error::corrupt_page(page_number, page.cell_offset(i), "Unsupported cell type"));
+        };
+        if cell_rowid == rowid {
+            bail!("Rowid already exists: {}", rowid);
+        }
+        if cell_rowid > rowid {
+            index = i;
+            break;
+        }
+    }
+
+    let cell = table_cell(database, rowid, payload)?;
+    insert_cell(database, path, page_number, index, cell)
+}
+
+/// Inserts `cell` as the cell at `index` of the page `page_number`, below
+/// the interior pages of `path`. A page without room for it is split in
+/// two, the cell dividing them going up into the parent, which may split in
+/// turn. A full root keeps its place: its cells move down to a new child,
+/// which splits instead, and the B-tree gets a level deeper.
+fn insert_cell(
+    database: &mut Database,
+    mut path: Vec<(u32, usize)>,
+    mut page_number: u32,
+    mut index: usize,
+    mut cell: Vec<u8>,
+) -> Result<()> {
+    loop {
+        let mut page = Arc::unwrap_or_clone(database.get_page(page_number - 1)?);
+        if page.insert_cell(index, &cell)? {
+            return database.write_page(page_number - 1, &page);
+        }
+
+        let Some((parent, parent_index)) = path.pop() else {
+            let kind = page.header.kind;
+            let child = database.allocate_page()?;
+            let mut child_page = Page::empty(kind, database.header.page_size as usize, 0, database.header.usable_size())?;
+            for i in 0..page.cell_pointers.len() {
+                if !child_page.insert_cell(i, page.cell_bytes(i))? {
+                    bail!("Cell does not fit on an empty page");
+                }
+            }
+            if let Some(right_child) = page.header.right_child_page_number {
+                child_page.set_child(page.cell_pointers.len(), right_child)?;
+            }
+            database.write_page(child - 1, &child_page)?;
+
+            let interior = match kind.is_table() {
+                true => PageKind::InteriorTable,
+                false => PageKind::InteriorIndex,
+            };
+            let mut root = Page::empty(interior, page.data.len(), page.offset, page.usable_size)?;
+            root.set_child(0, child)?;
+            database.write_page(page_number - 1, &root)?;
+            path.push((page_number, 0));
+            page_number = child;
+            continue;
+        };
+
+        let mut cells = (0..page.cell_pointers.len())
+            .map(|i| page.cell_bytes(i).to_vec())
+            .collect::<Vec<_>>();
+        cells.insert(index, cell);
+        let (left, right, divider) = split_page(database, page.header.kind, cells, page.header.right_child_page_number)?;
+        let left_page = database.allocate_page()?;
+        database.write_page(left_page - 1, &left)?;
+        database.write_page(page_number - 1, &right)?;
+
+        // The page keeps its place in the parent, after the new one.
+        page_number = parent;
+        index = parent_index;
+        cell = [left_page.to_be_bytes().as_slice(), &divider].concat();
+    }
+}
+
+/// Spreads `cells`, too many for one page of `kind`, over two: as many as
+/// fit on the first, the rest on the second, which gets `right_child`.
+/// Returns them with the divider between them, without its child pointer:
+/// the largest rowid of the first page for table leaves, otherwise the cell
+/// taken out from between the two, whose child becomes the right child of
+/// the first page.
+fn split_page(
+    database: &Database,
+    kind: PageKind,
+    mut cells: Vec<Vec<u8>>,
+    right_child: Option<u32>,
+) -> Result<(Page, Page, Vec<u8>)> {
+    let usable_size = database.header.usable_size();
+    let room = usable_size as usize - kind.header_size();
+    let size = |cells: &[Vec<u8>]| cells.iter().map(|cell| cell.len().max(4) + 2).sum::<usize>();
+
+    // Table leaves keep every cell, other pages give one up to divide them,
+    // and each page keeps at least one.
+    let taken = usize::from(kind != PageKind::LeafTable);
+    let mut split = 1;
+    while split + 1 + taken < cells.len() && size(&cells[..split + 1]) <= room {
+        split += 1;
+    }
+    let mut right_cells = cells.split_off(split);
+    if size(&cells) > room || size(&right_cells[taken..]) > room {
+        bail!("Unable to split page: its cells are too large");
+    }
+
+    let (divider, left_child) = match kind {
+        PageKind::LeafTable => {
+            let last = cells.last().expect("a page keeps a cell");
+            let (_, length) = varient::read(last);
+            let (rowid, _) = varient::read(&last[length..]);
+            (varient::write(rowid), None)
+        }
+        PageKind::LeafIndex => (right_cells.remove(0), None),
+        PageKind::InteriorTable | PageKind::InteriorIndex => {
+            let mut divider = right_cells.remove(0);
+            let child = u32::from_be_bytes(divider[..4].try_into().unwrap());
+            divider.drain(..4);
+            (divider, Some(child))
+        }
+    };
+
+    let page_size = database.header.page_size as usize;
+    let mut pages = vec![];
+    for (cells, right_child) in [(cells, left_child), (right_cells, right_child)] {
+        let mut page = Page::empty(kind, page_size, 0, usable_size)?;
+        for (index, cell) in cells.iter().enumerate() {
+            if !page.insert_cell(index, cell)? {
+                bail!("Cell does not fit on an empty page");
+            }
+        }
+        if let Some(child) = right_child {
+            page.set_child(cells.len(), child)?;
+        }
+        pages.push(page);
+    }
+    let right = pages.pop().expect("two pages");
+    let left = pages.pop().expect("two pages");
+    Ok((left, right, divider))
+}
+
+/// The largest rowid in the table B-tree rooted at `rootpage`, 0 when it is
+/// empty.
+pub fn last_rowid(database: &mut Database, rootpage: u32) -> Result<i64> {
+    let mut page_number = rootpage;
+    let mut page = database.get_page(page_number - 1)?;
+    while let Some(child) = page.header.right_child_page_number {
+        page_number = child;
+        page = database.get_page(page_number - 1)?;
+    }
+    match page.cells().last() {
+        Some(Cell::LeafTable { rowid, .. }) => Ok(rowid),
+        Some(_) => Err(Error::corrupt_page(
+            page_number,
+            page.offset as usize,
+            "Malformed table: table contains index pages",
+        )),
+        None => Ok(0),
+    }
+}
+
+/// The leaf page of the table B-tree rooted at `rootpage` where the row
+/// with `rowid` is or would go, and the interior pages descended through
+/// to get there, with the index of the child taken on each.
+fn find_leaf(database: &mut Database, rootpage: u32, rowid: i64) -> Result<(Vec<(u32, usize)>, u32)> {
+    let mut path = vec![];
+    let mut page_number = rootpage;
+    loop {
+        let page = database.get_page(page_number - 1)?;
+        match page.header.kind {
+            PageKind::InteriorTable => {
+                let index = page
+                    .cells()
+                    .position(|cell| matches!(cell, Cell::InteriorTable { key, .. } if rowid <= key as i64))
+                    .unwrap_or(page.cell_pointers.len());
+                let Some(child) = page.child(index) else {
+                    return Err(Error::corrupt_page(
+                        page_number,
+                        page.cell_offset(index),
+                        "Malformed table: interior page without right child",
+                    ));
+                };
+                path.push((page_number, index));
+                page_number = child;
+            }
+            PageKind::LeafTable => return Ok((path, page_number)),
+            PageKind::InteriorIndex | PageKind::LeafIndex => {
+                return Err(Error::corrupt_page(
+                    page_number,
+                    page.offset as usize,
+                    "Malformed table: table contains index pages",
+                ));
+            }
+        }
+    }
+}
+
+/// Takes the child at the end of `path`, which was freed, out of its parent.
+fn remove_child(database: &mut Database, mut path: Vec<(u32, usize)>) -> Result<()> {
+    let (page_number, index) = path.pop().expect("child has a parent");
+    let mut page = Arc::unwrap_or_clone(database.get_page(page_number - 1)?);
+    let count = page.cell_pointers.len();
+    if count == 0 {
+        // A root without cells whose only child was freed: the table is empty.
+        let leaf = Page::empty(PageKind::LeafTable, page.data.len(), page.offset, page.usable_size)?;
+        return database.write_page(page_number - 1, &leaf);
+    }
+    if index < count {
+        page.remove_cell(index)?;
+    } else {
+        // The child before the right child takes its place.
+        let Some(child) = page.child(count - 1) else {
+            return Err(Error::corrupt_page(
+                page_number,
+                page.cell_offset(count - 1),
+                "Malformed table: interior cell without child",
+            ));
+        };
+        page.set_child(count, child)?;
+        page.remove_cell(count - 1)?;
+    }
+    if !page.cell_pointers.is_empty() {
+        return database.write_page(page_number - 1, &page);
+    }
+
+    // Only the right child is left, which takes the place of the page.
+    let child = page.child(0).expect("interior page has a right child");
+    match path.last() {
+        Some(&(parent, index)) => {
+            let mut parent_page = Arc::unwrap_or_clone(database.get_page(parent - 1)?);
+            parent_page.set_child(index, child)?;
+            database.write_page(parent - 1, &parent_page)?;
+            database.free_page(page_number)
+        }
+        None => {
+            // The root page stays where it is, so the child is copied into
+            // it, unless it doesn't fit on the first page, which starts with
+            // the database header; SQLite then keeps a root without cells.
+            let child_page = Arc::unwrap_or_clone(database.get_page(child - 1)?);
+            let mut root = Page::empty(child_page.header.kind, page.data.len(), page.offset, page.usable_size)?;
+            for index in 0..child_page.cell_pointers.len() {
+                if !root.insert_cell(index, child_page.cell_bytes(index))? {
+                    return database.write_page(page_number - 1, &page);
+                }
+            }
+            if let Some(right_child) = child_page.header.right_child_page_number {
+                root.set_child(child_page.cell_pointers.len(), right_child)?;
+            }
+            database.write_page(page_number - 1, &root)?;
+            database.free_page(child)
+        }
+    }
+}
+
+/// The entries of an index read in key order, and where those of deleted
+/// rows are.
+#[derive(Debug, Default)]
+struct IndexScan {
+    /// Payloads of the entries kept.
+    entries: Vec<Vec<u8>>,
+    /// Every page of the B-tree, overflow pages aside.
+    pages: Vec<u32>,
+    /// The cells of the entries to remove, by page.
+    removed: Vec<(u32, Vec<usize>)>,
+    /// Whether removing them leaves the B-tree as it is otherwise: they are
+    /// all on leaves, none of which they empty.
+    in_place: bool,
+}
+
+impl IndexScan {
+    fn read(&mut self, database: &mut Database, page_number: u32, is_root: bool, rowids: &HashSet<i64>) -> Result<()> {
+        let page = database.get_page(page_number - 1)?;
+        if !page.header.kind.is_index() {
+            return Err(Error::corrupt_page(
+                page_number,
+                page.offset as usize,
+                "Malformed index: index contains table pages",
+            ));
+        }
+        self.pages.push(page_number);
+
+        let cells = page.cells().collect::<Vec<_>>();
+        let mut removed = vec![];
+        for index in 0..=cells.len() {
+            if page.header.kind.is_interior() {
+                let Some(child) = page.child(index) else {
+                    return Err(Error::corrupt_page(
+                        page_number,
+                        page.cell_offset(index),
+                        "Malformed index: interior page without right child",
+                    ));
+                };
+                self.read(database, child, false, rowids)?;
+            }
+            let Some(cell) = cells.get(index) else { break };
+            let payload = database.raw_payload(cell)?;
+            if rowids.contains(&database::index_rowid(&payload)?) {
+                removed.push(index);
+            } else {
+                self.entries.push(payload);
+            }
+        }
+
+        if !removed.is_empty() {
+            if page.header.kind.is_interior() || (removed.len() == cells.len() && !is_root) {
+                self.in_place = false;
+            }
+            self.removed.push((page_number, removed));
+        }
+        Ok(())
+    }
+}
+
+/// Removes the entries of the rows in `rowids` from the index B-tree rooted
+/// at `rootpage`, whose entries end with the rowid of their row. Entries on
+/// leaves that keep others are removed from them; otherwise the index is
+/// built again from the entries left.
+pub fn delete_index_entries(database: &mut Database, rootpage: u32, rowids: &HashSet<i64>) -> Result<()> {
+    let mut scan = IndexScan {
+        in_place: true,
+        ..Default::default()
+    };
+    scan.read(database, rootpage, true, rowids)?;
+    if scan.removed.is_empty() {
+        return Ok(());
+    }
+
+    if scan.in_place {
+        for (page_number, removed) in scan.removed {
+            let mut page = Arc::unwrap_or_clone(database.get_page(page_number - 1)?);
+            for &index in removed.iter().rev() {
+                database.free_overflow(&page.cells().nth(index).expect("cell exists"))?;
+                page.remove_cell(index)?;
+            }
+            database.write_page(page_number - 1, &page)?;
+        }
+        return Ok(());
+    }
+
+    for &page_number in &scan.pages {
+        let page = database.get_page(page_number - 1)?;
+        for cell in page.cells() {
+            database.free_overflow(&cell)?;
+        }
+        if page_number != rootpage {
+            database.free_page(page_number)?;
+        }
+    }
+    build_index(database, rootpage, &scan.entries)
+}
+
+/// Puts every page of the B-tree rooted at `rootpage` on the freelist, the
+/// root and the overflow pages of its cells included.
+pub fn free_btree(database: &mut Database, rootpage: u32) -> Result<()> {
+    let page = database.get_page(rootpage - 1)?;
+    for cell in page.cells() {
+        if let Cell::InteriorTable { left_child_page, .. } | Cell::InteriorIndex { left_child_page, .. } = cell {
+            free_btree(database, left_child_page)?;
+        }
+        database.free_overflow(&cell)?;
+    }
+    if let Some(child) = page.header.right_child_page_number {
+        free_btree(database, child)?;
+    }
+    database.free_page(rootpage)
+}
+
+/// Fills the index B-tree rooted at `rootpage`, whose other pages were
+/// freed, with `entries`, the payloads of its entries in key order. Leaves
+/// are filled up one after the other, the entry between two of them going
+/// to the level above, and so on up to the root.
+pub fn build_index(database: &mut Database, rootpage: u32, entries: &[Vec<u8>]) -> Result<()> {
+    let mut cells = entries
+        .iter()
+        .map(|payload| index_cell(database, payload))
+        .collect::<Result<Vec<_>>>()?;
+    let mut kind = PageKind::LeafIndex;
+    let mut right_child = None;
+    loop {
+        let (pages, dividers) = split_cells(database, kind, cells, right_child)?;
+        if pages.len() == 1 {
+            return database.write_page(rootpage - 1, &pages[0]);
+        }
+
+        let mut children = vec![];
+        for page in &pages {
+            let page_number = database.allocate_page()?;
+            database.write_page(page_number - 1, page)?;
+            children.push(page_number);
+        }
+        // Each divider points at the page before it.
+        cells = dividers
+            .into_iter()
+            .zip(&children)
+            .map(|(divider, child)| [child.to_be_bytes().as_slice(), &divider].concat())
+            .collect();
+        right_child = children.last().copied();
+        kind = PageKind::InteriorIndex;
+    }
+}
+
+/// Lays out `cells` in order on as few pages of `kind` as they fit on. The
+/// cell between two pages is taken out to divide them, without the child
+/// pointer of an interior cell, which becomes the right child of the page
+/// before it. The last page gets `right_child`.
+fn split_cells(
+    database: &Database,
+    kind: PageKind,
+    cells: Vec<Vec<u8>>,
+    right_child: Option<u32>,
+) -> Result<(Vec<Page>, Vec<Vec<u8>>)> {
+    let usable_size = database.header.usable_size() as usize;
+    let room = usable_size - kind.header_size();
+    let fits = |cells: &[Vec<u8>]| cells.iter().map(|cell| cell.len().max(4) + 2).sum::<usize>() <= room;
+
+    let mut groups: Vec<Vec<Vec<u8>>> = vec![vec![]];
+    let mut dividers = vec![];
+    let count = cells.len();
+    for (index, cell) in cells.into_iter().enumerate() {
+        let group = groups.last_mut().expect("there is a group");
+        group.push(cell);
+        if fits(group) {
+            continue;
+        }
+        let mut divider = group.pop().expect("cell was pushed");
+        let mut next = vec![];
+        // With no cell left for the next page, the last one of this page
+        // divides instead.
+        if index + 1 == count {
+            next.push(divider);
+            divider = group.pop().expect("a page holds several cells");
+        }
+        dividers.push(divider);
+        groups.push(next);
+    }
+
+    let mut pages = vec![];
+    let last = groups.len() - 1;
+    for (index, group) in groups.iter().enumerate() {
+        let mut page = Page::empty(kind, database.header.page_size as usize, 0, usable_size as u32)?;
+        for (index, cell) in group.iter().enumerate() {
+            if !page.insert_cell(index, cell)? {
+                bail!("Cell does not fit on an empty page");
+            }
+        }
+        if kind.is_interior() {
+            let child = match index == last {
+                true => right_child.expect("interior pages have a right child"),
+                false => u32::from_be_bytes(dividers[index][..4].try_into().unwrap()),
+            };
+            page.set_child(group.len(), child)?;
+        }
+        pages.push(page);
+    }
+    if kind.is_interior() {
+        for divider in &mut dividers {
+            divider.drain(..4);
+        }
+    }
+    Ok((pages, dividers))
+}
+
+/// A leaf table cell for the row with `rowid` and `payload`, written to
+/// overflow pages past what fits on the page.
+fn table_cell(database: &mut Database, rowid: i64, payload: &[u8]) -> Result<Vec<u8>> {
+    let usable_size = database.header.usable_size();
+    let local = PageKind::LeafTable.local_payload_size(payload.len() as u64, usable_size) as usize;
+    let mut cell = varient::write(payload.len() as i64);
+    cell.extend(varient::write(rowid));
+    cell.extend_from_slice(&payload[..local]);
+    if local < payload.len() {
+        cell.extend_from_slice(&write_overflow(database, &payload[local..])?.to_be_bytes());
+    }
+    Ok(cell)
+}
+
+/// A leaf index cell for `payload`, written to overflow pages past what
+/// fits on the page.
+fn index_cell(database: &mut Database, payload: &[u8]) -> Result<Vec<u8>> {
+    let usable_size = database.header.usable_size();
+    let local = PageKind::LeafIndex.local_payload_size(payload.len() as u64, usable_size) as usize;
+    let mut cell = varient::write(payload.len() as i64);
+    cell.extend_from_slice(&payload[..local]);
+    if local < payload.len() {
+        cell.extend_from_slice(&write_overflow(database, &payload[local..])?.to_be_bytes());
+    }
+    Ok(cell)
+}
+
+/// Writes `data` to a chain of new overflow pages, returning the first.
+pub(crate) fn write_overflow(database: &mut Database, data: &[u8]) -> Result<u32> {
+    let chunk = database.header.usable_size() as usize - 4;
+    let pages = data
+        .chunks(chunk)
+        .map(|_| database.allocate_page())
+        .collect::<Result<Vec<_>>>()?;
+    for (index, part) in data.chunks(chunk).enumerate() {
+        let mut page = vec![0; database.header.page_size as usize];
+        let next = pages.get(index + 1).copied().unwrap_or(0);
+        page[..4].copy_from_slice(&next.to_be_bytes());
+        page[4..4 + part.len()].copy_from_slice(part);
+        database.write_raw_page(pages[index], page);
+    }
+    Ok(pages[0])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::record::{ColumnValue, Record};
+    use crate::statement::Statement;
+
+    /// Written by sqlite3 with `CREATE TABLE Items (a)`, the rows `'x'`, `'y'`
+    /// and `'z'`, and `CREATE INDEX by_a ON Items (a)`, after which the
+    /// schema table was edited to name the table of the index `ITEMS`.
+    const ITEMS_DB: &[u8] = include_bytes!("../testdata/items.db");
+
+    #[test]
+    fn delete_index_entries_of_table_named_in_other_case() {
+        let path = std::env::temp_dir().join(format!("items-{}.db", std::process::id()));
+        std::fs::write(&path, ITEMS_DB).unwrap();
+        let mut database = Database::open(path.to_str().unwrap()).unwrap();
+
+        let mut statement = Statement::prepare("DELETE FROM Items WHERE a = 'y'").unwrap();
+        statement.query(&mut database).unwrap();
+        let rootpage = database.schema.find_index("by_a").unwrap().rootpage;
+        let rowids = BTreeCursor::new(&mut database, rootpage, Direction::Forward)
+            .unwrap()
+            .map(|entry| Record::read(0, &entry.unwrap().payload).values[1].as_integer().unwrap())
+            .collect::<Vec<_>>();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(rowids, [1, 3]);
+    }
+
+    /// Written by sqlite3 with 512-byte pages, `CREATE TABLE t (a)` and 150
+    /// rows of 100-byte keys, out of order and each shared by three rows:
+    /// `format!("{:03}{}", (i * 37) % 50, "k".repeat(97))` for row `i + 1`.
+    const KEYS_DB: &[u8] = include_bytes!("../testdata/keys.db");
+
+    fn key(i: i64) -> Vec<u8> {
+        format!("{:03}{}", (i * 37) % 50, "k".repeat(97)).into_bytes()
+    }
+
+    #[test]
+    fn build_index_over_interior_pages() {
+        let path = std::env::temp_dir().join(format!("keys-{}.db", std::process::id()));
+        std::fs::write(&path, KEYS_DB).unwrap();
+        let mut database = Database::open(path.to_str().unwrap()).unwrap();
+
+        Statement::prepare("CREATE INDEX by_a ON t (a)").unwrap().query(&mut database).unwrap();
+        let rootpage = database.schema.find_index("by_a").unwrap().rootpage;
+        assert_eq!(database.get_page(rootpage - 1).unwrap().header.kind, PageKind::InteriorIndex);
+        let entries = BTreeCursor::new(&mut database, rootpage, Direction::Forward)
+            .unwrap()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                match Record::read(0, &entry.payload).values[..] {
+                    [ColumnValue::Text(key), ref rowid] => (key.to_vec(), rowid.as_integer().unwrap()),
+                    ref values => panic!("unexpected index entry: {:?}", values),
+                }
+            })
+            .collect::<Vec<_>>();
+        let mut expected = (0..150).map(|i| (key(i), i + 1)).collect::<Vec<_>>();
+        expected.sort();
+        assert_eq!(entries, expected);
+
+        let mut statement = Statement::prepare("CREATE INDEX by_a_desc ON t (a DESC)").unwrap();
+        let error = statement.query(&mut database).err().unwrap();
+        assert_eq!(error.to_string(), "Unable to create an index with DESC columns: by_a_desc");
+        assert!(database.schema.find_index("by_a_desc").is_none());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /// Written by sqlite3 with 4096-byte pages and no tables.
+    const EMPTY_DB: &[u8] = include_bytes!("../testdata/empty.db");
+
+    /// A table with `count` rows of `size` bytes each, inserted out of
+    /// rowid order and not committed, and its root page.
+    fn table_with_rows(name: &str, count: i64, size: usize) -> (Database, u32) {
+        let path = std::env::temp_dir().join(format!("{}-{}.db", name, std::process::id()));
+        std::fs::write(&path, EMPTY_DB).unwrap();
+        let mut database = Database::open(path.to_str().unwrap()).unwrap();
+        Statement::prepare("CREATE TABLE t (a)").unwrap().query(&mut database).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let rootpage = database.schema.find_table("t").unwrap().rootpage;
+        for i in 0..count {
+            let rowid = (i * 7919) % count + 1;
+            insert_row(&mut database, rootpage, rowid, &payload(rowid, size)).unwrap();
+        }
+        (database, rootpage)
+    }
+
+    fn payload(rowid: i64, size: usize) -> Vec<u8> {
+        (0..size).map(|i| (rowid as usize + i) as u8).collect()
+    }
+
+    fn rowids(database: &mut Database, rootpage: u32) -> Vec<i64> {
+        BTreeCursor::new(database, rootpage, Direction::Forward)
+            .unwrap()
+            .map(|entry| entry.unwrap().rowid)
+            .collect()
+    }
+
+    /// Checks that the header, cell pointers, cells and unused bytes of a
+    /// page account for all of it without overlapping.
+    fn assert_valid_layout(page: &Page) {
+        let page = Page::parse(page.data.clone(), page.offset, page.usable_size).unwrap();
+        let offset = page.offset as usize;
+        let pointers_end = offset + page.header.kind.header_size() + 2 * page.cell_pointers.len();
+        let mut cells = (0..page.cell_pointers.len())
+            .map(|index| {
+                let start = page.cell_pointers[index] as usize + offset;
+                (start, start + page.cell_size(index))
+            })
+            .collect::<Vec<_>>();
+        cells.sort();
+        for (start, end) in &cells {
+            assert!(*start >= page.header.content_start_offset as usize && *start >= pointers_end);
+            assert!(*end <= page.usable_size as usize);
+        }
+        for pair in cells.windows(2) {
+            assert!(pair[0].1 <= pair[1].0, "cells overlap: {:?}", pair);
+        }
+        let used = cells.iter().map(|(start, end)| end - start).sum::<usize>();
+        assert_eq!(pointers_end + used + page.unused_bytes() as usize, page.usable_size as usize);
+    }
+
+    #[test]
+    fn split_leaf_and_interior_pages() {
+        let (mut database, rootpage) = table_with_rows("split", 2000, 1000);
+
+        let root = database.get_page(rootpage - 1).unwrap();
+        assert_eq!(root.header.kind, PageKind::InteriorTable);
+        let child = database.get_page(root.child(0).unwrap() - 1).unwrap();
+        assert_eq!(child.header.kind, PageKind::InteriorTable);
+
+        let mut entries = BTreeCursor::new(&mut database, rootpage, Direction::Forward).unwrap();
+        for rowid in 1..=2000 {
+            let entry = entries.next().unwrap().unwrap();
+            assert_eq!(entry, Entry { rowid, payload: payload(rowid, 1000) });
+        }
+        assert!(entries.next().is_none());
+        assert_eq!(last_rowid(&mut database, rootpage).unwrap(), 2000);
+    }
+
+    #[test]
+    fn remove_child_keeps_page_layout() {
+        let (mut database, rootpage) = table_with_rows("remove-child", 2000, 1000);
+        let root = database.get_page(rootpage - 1).unwrap();
+        let parent = root.child(0).unwrap();
+        let mut deleted = 0;
+
+        // Empty the first leaf, then the right child, of the first interior
+        // page below the root.
+        for index in [0, usize::MAX] {
+            let page = database.get_page(parent - 1).unwrap();
+            let count = page.cell_pointers.len();
+            let leaf = page.child(index.min(count)).unwrap();
+            let leaf_rowids = database
+                .get_page(leaf - 1)
+                .unwrap()
+                .cells()
+                .map(|cell| match cell {
+                    Cell::LeafTable { rowid, .. } => rowid,
+                    _ => panic!("expected a table leaf"),
+                })
+                .collect::<Vec<_>>();
+            for rowid in &leaf_rowids {
+                assert!(delete_row(&mut database, rootpage, *rowid).unwrap());
+            }
+            deleted += leaf_rowids.len();
+
+            let page = database.get_page(parent - 1).unwrap();
+            assert_eq!(page.cell_pointers.len(), count - 1);
+            assert!((0..=page.cell_pointers.len()).all(|i| page.child(i) != Some(leaf)));
+            assert_valid_layout(&page);
+
+            let remaining = rowids(&mut database, rootpage);
+            assert!(remaining.windows(2).all(|pair| pair[0] < pair[1]));
+            assert!(leaf_rowids.iter().all(|rowid| !remaining.contains(rowid)));
+            assert_eq!(remaining.len(), 2000 - deleted);
+        }
+    }
+
+    #[test]
+    fn report_index_page_in_table_as_corrupt() {
+        let path = std::env::temp_dir().join(format!("mixed-{}.db", std::process::id()));
+        let mut data = KEYS_DB.to_vec();
+        std::fs::write(&path, &data).unwrap();
+        let mut database = Database::open(path.to_str().unwrap()).unwrap();
+        let rootpage = database.schema.find_table("t").unwrap().rootpage;
+        let child = database.get_page(rootpage - 1).unwrap().child(0).unwrap();
+
+        // Turns the first child of the root into an index leaf.
+        data[(child as usize - 1) * 512] = 0x0a;
+        std::fs::write(&path, &data).unwrap();
+        let mut database = Database::open(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let error = BTreeCursor::new(&mut database, rootpage, Direction::Forward)
+            .unwrap()
+            .find_map(Result::err)
+            .unwrap();
+        assert!(
+            matches!(error, Error::CorruptPage { page, offset: 0, .. } if page == child),
+            "unexpected error: {:?}",
+            error
+        );
+    }
+}
@@ -1,240 +1,3087 @@
-use std::fs::File;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{File, OpenOptions};
 use std::io::{prelude::*, SeekFrom};
+use std::mem::{size_of, size_of_val};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::SystemTime;
 
-use anyhow::{bail, Result};
-use itertools::Itertools;
+use rayon::prelude::*;
+use serde::de::DeserializeOwned;
 
-use crate::page::{Cell, Page};
-use crate::record::{ColumnValue, Record};
-use crate::sql::{self, SelectFields};
-use crate::sqlite_schema::{Index, SchemaStore, Table};
+use crate::btree::{self, BTreeCursor, Direction};
+use crate::de;
+use crate::error::{bail, format_err, Error, Result};
+use crate::freelist::Freelist;
+use crate::expr::{Affinity, Expr, Literal};
+use crate::fts5::{Fts5Index, MatchQuery};
+use crate::journal::{self, RollbackJournal};
+use crate::lock::{FileLock, LockLevel, WalWriteLock};
+use crate::output::{Encoding, RowSink};
+use crate::page::{Cell, Page, PageKind};
+use crate::pager::PageCache;
+use crate::planner::Plan;
+use crate::ptrmap::{PointerMap, PointerMapEntry};
+use crate::record::{self, Collation, ColumnValue, Record};
+use crate::row::FromRow;
+use crate::rtree::{RTree, RTreeEntry};
+use crate::sql::{self, CountSelection, MinMax, MinMaxSelection, SelectFields};
+use crate::sqlite_schema::{Column, Index, SQLiteSchema, SQLiteSchemaRow, SchemaStore, Table};
+use crate::statement::{Row, Statement, StatementCache};
+use crate::stats::{Access, Stats};
+use crate::wal::{Checkpoint, CheckpointMode, Wal};
 
+/// Number of child pages read together when looking up rowids.
+const PREFETCH_BATCH_SIZE: usize = 16;
+/// Subtrees a parallel scan splits a table into for every thread, so that
+/// threads done early find more to do.
+const SUBTREES_PER_THREAD: usize = 4;
+/// Bytes a collected rowid counts for against the memory limit.
+const ROWID_SIZE: u64 = size_of::<i64>() as u64;
+/// The name `Database::open` takes for a new in-memory database, like
+/// SQLite.
+pub const IN_MEMORY: &str = ":memory:";
+/// Page size of new in-memory databases, SQLite's default.
+const DEFAULT_PAGE_SIZE: usize = 4096;
+
+/// Positional read that leaves the shared file cursor alone, so several
+/// threads can read from the same file.
+#[cfg(unix)]
+fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> std::io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> std::io::Result<()> {
+    use std::os::windows::fs::FileExt;
+
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset)? {
+            0 => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+            n => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Which of the databases of a connection a table is in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Schema {
+    Main,
+    Temp,
+    /// The database attached at this position.
+    Attached(usize),
+}
+
+/// Where the pages of a database are kept between transactions.
 #[derive(Debug)]
+pub enum Storage {
+    File(File),
+    /// The pages of an in-memory database, by 0-based page number, which
+    /// grows as transactions add pages.
+    Memory(Vec<Vec<u8>>),
+}
+
+impl Storage {
+    fn try_clone(&self) -> Result<Self> {
+        Ok(match self {
+            Storage::File(file) => Storage::File(file.try_clone()?),
+            Storage::Memory(pages) => Storage::Memory(pages.clone()),
+        })
+    }
+
+    /// Like `read_exact_at` on the file, which the pages of an in-memory
+    /// database stand in for.
+    fn read_exact_at(&self, mut buf: &mut [u8], mut offset: u64) -> std::io::Result<()> {
+        let pages = match self {
+            Storage::File(file) => return read_exact_at(file, buf, offset),
+            Storage::Memory(pages) => pages,
+        };
+        let page_size = pages.first().map_or(1, Vec::len) as u64;
+        while !buf.is_empty() {
+            let page = pages
+                .get((offset / page_size) as usize)
+                .ok_or(std::io::ErrorKind::UnexpectedEof)?;
+            let start = (offset % page_size) as usize;
+            let n = buf.len().min(page.len() - start);
+            buf[..n].copy_from_slice(&page[start..start + n]);
+            buf = &mut buf[n..];
+            offset += n as u64;
+        }
+        Ok(())
+    }
+
+    /// Length of the file in bytes.
+    fn len(&self) -> Result<u64> {
+        Ok(match self {
+            Storage::File(file) => file.metadata()?.len(),
+            Storage::Memory(pages) => pages.iter().map(|page| page.len() as u64).sum(),
+        })
+    }
+
+    /// When the file was last written. Nothing but the connection itself
+    /// changes an in-memory database, which is never seen to change.
+    pub fn modified(&self) -> Result<SystemTime> {
+        Ok(match self {
+            Storage::File(file) => file.metadata()?.modified()?,
+            Storage::Memory(_) => SystemTime::UNIX_EPOCH,
+        })
+    }
+}
+
+/// The first 100 bytes of the file, describing the database as a whole.
+#[derive(Debug, Clone)]
 pub struct DatabaseHeader {
-    pub page_size: u16,
+    /// In bytes, a power of two from 512 to 65536.
+    pub page_size: u32,
+    /// File format versions, 1 for rollback journal and 2 for WAL mode.
+    pub write_version: u8,
+    pub read_version: u8,
+    pub reserved_space: u8,
+    /// Incremented by every transaction that changes the file, unless it
+    /// is in WAL mode.
+    pub file_change_counter: u32,
+    pub page_count: u32,
+    /// The first trunk page of the freelist, 0 when there are no free pages.
+    pub first_freelist_trunk_page: u32,
+    pub freelist_page_count: u32,
+    /// Incremented every time the schema changes.
+    pub schema_cookie: u32,
+    pub largest_root_page: u32,
+    /// How text values are stored.
+    pub text_encoding: Encoding,
+    /// Set by `PRAGMA user_version`, for applications to use.
+    pub user_version: u32,
+    pub incremental_vacuum: bool,
+    /// Set by `PRAGMA application_id` to tell the file formats built on
+    /// SQLite apart.
+    pub application_id: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AutoVacuum {
+    None,
+    Full,
+    Incremental,
+}
+
+impl std::fmt::Display for AutoVacuum {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AutoVacuum::None => write!(f, "none"),
+            AutoVacuum::Full => write!(f, "full"),
+            AutoVacuum::Incremental => write!(f, "incremental"),
+        }
+    }
 }
 
+/// The offset SQLite takes its file locks at. No page holds data there.
+const PENDING_BYTE: u32 = 0x4000_0000;
+
 const MAGIC_HEADER: [u8; 16] = *b"SQLite format 3\0";
 impl DatabaseHeader {
     pub fn read(file: &mut File) -> Result<Self> {
         let mut header = [0; 100];
         file.read_exact(&mut header)?;
+        Self::parse(&header)
+    }
 
+    pub fn parse(header: &[u8]) -> Result<Self> {
         if &header[0..16] != MAGIC_HEADER {
-            return Err(anyhow::anyhow!("Invalid database file"));
+            return Err(format_err!("Invalid database file"));
+        }
+
+        let read_u32 = |at: usize| {
+            u32::from_be_bytes([header[at], header[at + 1], header[at + 2], header[at + 3]])
+        };
+
+        Ok(Self {
+            page_size: match u16::from_be_bytes([header[16], header[17]]) {
+                1 => 65536,
+                size => size as u32,
+            },
+            write_version: header[18],
+            read_version: header[19],
+            reserved_space: header[20],
+            file_change_counter: read_u32(24),
+            page_count: read_u32(28),
+            first_freelist_trunk_page: read_u32(32),
+            freelist_page_count: read_u32(36),
+            schema_cookie: read_u32(40),
+            largest_root_page: read_u32(52),
+            text_encoding: match read_u32(56) {
+                2 => Encoding::Utf16le,
+                3 => Encoding::Utf16be,
+                _ => Encoding::Utf8,
+            },
+            user_version: read_u32(60),
+            incremental_vacuum: read_u32(64) != 0,
+            application_id: read_u32(68),
+        })
+    }
+
+    /// Writes the fields a transaction changes into `header`, the first 100
+    /// bytes of the first page. The SQLite version that wrote the file last
+    /// stays as it was.
+    pub fn write(&self, header: &mut [u8]) {
+        let mut write_u32 = |at: usize, value: u32| header[at..at + 4].copy_from_slice(&value.to_be_bytes());
+        write_u32(24, self.file_change_counter);
+        write_u32(28, self.page_count);
+        write_u32(32, self.first_freelist_trunk_page);
+        write_u32(36, self.freelist_page_count);
+        write_u32(40, self.schema_cookie);
+        // The page count is only trusted when this matches the counter.
+        write_u32(92, self.file_change_counter);
+    }
+
+    /// Page size minus the bytes reserved at the end of every page.
+    pub fn usable_size(&self) -> u32 {
+        self.page_size - self.reserved_space as u32
+    }
+
+    /// Whether the format versions say the database is in WAL mode. See
+    /// [`crate::journal::FileState`] for what the files next to it say.
+    pub fn is_wal(&self) -> bool {
+        self.read_version == 2
+    }
+
+    /// A non-zero largest root page means the file keeps a pointer map; the
+    /// incremental-vacuum flag then tells the two auto-vacuum modes apart.
+    pub fn auto_vacuum(&self) -> AutoVacuum {
+        match (self.largest_root_page, self.incremental_vacuum) {
+            (0, _) => AutoVacuum::None,
+            (_, false) => AutoVacuum::Full,
+            (_, true) => AutoVacuum::Incremental,
+        }
+    }
+
+    pub fn pointer_map(&self) -> Option<PointerMap> {
+        match self.auto_vacuum() {
+            AutoVacuum::None => None,
+            _ => Some(PointerMap::new(self.usable_size())),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Query<'query> {
+    pub table: &'query Table,
+    pub select_fields: Vec<(usize, bool)>,
+    pub filter: Option<&'query sql::WhereClause>,
+}
+
+pub(crate) fn is_rowid_alias(name: &str) -> bool {
+    ["rowid", "oid", "_rowid_"]
+        .iter()
+        .any(|alias| alias.eq_ignore_ascii_case(name))
+}
+
+/// Whether `name` starts with `sqlite_`, which SQLite keeps for the tables
+/// and indexes it makes itself.
+fn is_reserved_name(name: &str) -> bool {
+    name.len() >= 7 && name[..7].eq_ignore_ascii_case("sqlite_")
+}
+
+/// A number unlikely to repeat, to tell the journal or WAL of one
+/// transaction from leftovers of another.
+fn nonce() -> u32 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.subsec_nanos())
+}
+
+/// Checks that a new table or index may be called `name`, returning false
+/// when there is already one of `kind` by that name to keep, as IF NOT
+/// EXISTS asks.
+fn check_new_name(schema: &SQLiteSchema, kind: &str, name: &str, if_not_exists: bool) -> Result<bool> {
+    if let Some(row) = schema.rows.iter().find(|row| row.kind != "trigger" && row.name.eq_ignore_ascii_case(name)) {
+        if if_not_exists && row.kind == kind {
+            return Ok(false);
+        }
+        bail!("Name already in use by {} {}: {}", if row.kind == "index" { "an" } else { "a" }, row.kind, name);
+    }
+    if is_reserved_name(name) {
+        bail!("Object name reserved for internal use: {}", name);
+    }
+    Ok(true)
+}
+
+/// `values`, their text read as UTF-8, as a record with the text in
+/// `encoding`.
+fn encode_record(values: &[ColumnValue], encoding: Encoding) -> Vec<u8> {
+    let texts = values
+        .iter()
+        .map(|value| match value {
+            ColumnValue::Text(text) => Some(encoding.encode(&String::from_utf8_lossy(text)).into_owned()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    let values = values
+        .iter()
+        .zip(&texts)
+        .map(|(value, text)| text.as_deref().map_or(value.clone(), ColumnValue::Text))
+        .collect::<Vec<_>>();
+    record::encode(&values)
+}
+
+/// Orders the records of index entries like SQLite: value by value, by the
+/// collation of each column, the rowid last. Text in `encoding` compares
+/// byte by byte, or as UTF-8 by the other collations.
+fn compare_index_keys(a: &Record, b: &Record, collations: &[Collation], encoding: Encoding) -> std::cmp::Ordering {
+    a.values
+        .iter()
+        .zip(&b.values)
+        .enumerate()
+        .map(|(i, (a, b))| {
+            let collation = collations.get(i).copied().unwrap_or_default();
+            match (a, b) {
+                (ColumnValue::Text(a), ColumnValue::Text(b))
+                    if collation != Collation::Binary && encoding != Encoding::Utf8 =>
+                {
+                    collation.compare(encoding.decode(a).as_bytes(), encoding.decode(b).as_bytes())
+                }
+                _ => a.compare_collated(b, collation),
+            }
+        })
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or(std::cmp::Ordering::Equal)
+}
+
+/// Checks that every column the statement selects or compares exists,
+/// before any row is read.
+fn check_columns(table: &Table, sql_statement: &SelectFields, check_where: bool) -> Result<()> {
+    let compared = sql_statement
+        .where_clause
+        .iter()
+        .filter(|_| check_where)
+        .flat_map(|where_clause| where_clause.columns());
+
+    for name in sql_statement.fields.iter().chain(compared) {
+        if table.find_column(name).is_none() && !is_rowid_alias(name) {
+            return Err(Error::NoSuchColumn(name.to_string()));
+        }
+    }
+    Ok(())
+}
+
+/// Compares a value with a literal of a WHERE clause. The literal takes the
+/// type of the value where it can, the way SQLite applies the affinity of
+/// the column: numbers compare with literals that read as numbers by value,
+/// and text compares with any literal as text, by `collation`. Otherwise
+/// numbers sort before text, and text before blobs.
+fn compare_literal(value: &ColumnValue, literal: &str, collation: Collation) -> std::cmp::Ordering {
+    let number = match literal.parse::<i64>() {
+        Ok(n) => Some(ColumnValue::I64(n)),
+        Err(_) => literal.parse::<f64>().ok().map(ColumnValue::F64),
+    };
+    match number {
+        Some(number) if value.is_number() => value.compare(&number),
+        _ => value.compare_collated(&ColumnValue::Text(literal.as_bytes()), collation),
+    }
+}
+
+/// Key of a value in the set of an `IN (SELECT ...)`. Values that compare
+/// equal have the same key: integers and reals by value, text and blobs by
+/// their bytes.
+pub(crate) fn set_key(value: &ColumnValue) -> Vec<u8> {
+    let value = match value {
+        ColumnValue::F64(n) if n.fract() == 0.0 && n.abs() < i64::MAX as f64 => ColumnValue::I64(*n as i64),
+        ColumnValue::F64(n) => ColumnValue::F64(*n),
+        value => match value.as_integer() {
+            Some(n) => ColumnValue::I64(n),
+            None => value.clone(),
+        },
+    };
+    crate::record::encode(&[value])
+}
+
+/// Whether the ordering of two values passes a comparison operator. LIKE
+/// and MATCH don't order values, and are decided by the caller.
+fn passes(operator: &sql::Operator, ordering: std::cmp::Ordering) -> bool {
+    match operator {
+        sql::Operator::Equal => ordering.is_eq(),
+        sql::Operator::NotEqual => ordering.is_ne(),
+        sql::Operator::Less => ordering.is_lt(),
+        sql::Operator::LessEqual => ordering.is_le(),
+        sql::Operator::Greater => ordering.is_gt(),
+        sql::Operator::GreaterEqual => ordering.is_ge(),
+        sql::Operator::Like | sql::Operator::Match => false,
+    }
+}
+
+/// Whether a row passes the WHERE clause, with `value` giving the value of
+/// a column by name, and `collation` how its text compares.
+pub(crate) fn evaluate<'a>(
+    condition: &'a sql::WhereClause,
+    value: &dyn Fn(&str) -> ColumnValue<'a>,
+    collation: &dyn Fn(&str) -> Collation,
+) -> bool {
+    match condition {
+        sql::WhereClause::Comparison(comparison) => {
+            let value = value(&comparison.field);
+            // Comparing NULL with anything is never true.
+            if value.is_null() {
+                return false;
+            }
+            match &comparison.operator {
+                sql::Operator::Like => sql::like(&comparison.value, &value.to_string()),
+                operator => passes(
+                    operator,
+                    compare_literal(&value, &comparison.value, collation(&comparison.field)),
+                ),
+            }
+        }
+        sql::WhereClause::Expression(comparison) => {
+            let (left, right) = (comparison.left.evaluate(value), comparison.right.evaluate(value));
+            let (left, right) = (left.get(), right.get());
+            if left.is_null() || right.is_null() {
+                return false;
+            }
+            // A column compared with anything else lends it its type, like
+            // with a constant. Blobs have no type to take. Text compares by
+            // the collation of the column on the left, or else on the right.
+            let is_blob = |value: &ColumnValue| matches!(value, ColumnValue::Blob(_));
+            let by = match (&comparison.left, &comparison.right) {
+                (Expr::Column(name), _) | (_, Expr::Column(name)) => collation(name),
+                _ => Collation::Binary,
+            };
+            let ordering = match (&comparison.left, &comparison.right) {
+                (Expr::Column(_), Expr::Column(_)) => left.compare_collated(&right, by),
+                (Expr::Column(_), _) if !is_blob(&right) => compare_literal(&left, &right.to_string(), by),
+                (_, Expr::Column(_)) if !is_blob(&left) => {
+                    compare_literal(&right, &left.to_string(), by).reverse()
+                }
+                _ => left.compare_collated(&right, by),
+            };
+            match &comparison.operator {
+                sql::Operator::Like => sql::like(&right.to_string(), &left.to_string()),
+                operator => passes(operator, ordering),
+            }
+        }
+        // A NULL in the list never matches, so only the listed values can.
+        sql::WhereClause::In(list) => {
+            let (value, collation) = (value(&list.field), collation(&list.field));
+            !value.is_null()
+                && list
+                    .values
+                    .iter()
+                    .flatten()
+                    .any(|literal| compare_literal(&value, literal, collation).is_eq())
+        }
+        sql::WhereClause::InSet(set) => {
+            let value = value(&set.field);
+            !value.is_null() && set.keys.contains(&set_key(&value))
+        }
+        // Subqueries are replaced by their values before any row is read.
+        sql::WhereClause::InSelect(_) => false,
+        sql::WhereClause::And(left, right) => {
+            evaluate(left, value, collation) && evaluate(right, value, collation)
+        }
+        sql::WhereClause::Or(left, right) => {
+            evaluate(left, value, collation) || evaluate(right, value, collation)
+        }
+    }
+}
+
+/// Payload of a cell, borrowed from the page unless part of it spilled onto
+/// overflow pages, read with `read_page`, or its text had to be converted to
+/// UTF-8 from the encoding in `header`.
+fn cell_payload<'page>(
+    cell: &Cell<'page>,
+    header: &DatabaseHeader,
+    mut read_page: impl FnMut(u32) -> Result<Vec<u8>>,
+) -> Result<Cow<'page, [u8]>> {
+    let (Cell::InteriorIndex { payload, .. }
+    | Cell::LeafIndex { payload, .. }
+    | Cell::LeafTable { payload, .. }) = cell
+    else {
+        return Ok(Cow::Borrowed(&[]));
+    };
+    let Some(mut overflow_page) = cell.overflow_page() else {
+        return Ok(record::to_utf8(Cow::Borrowed(*payload), header.text_encoding));
+    };
+
+    let size = cell.payload_size() as usize;
+    let mut data = Vec::with_capacity(size);
+    data.extend_from_slice(payload);
+
+    let usable_size = header.usable_size() as usize;
+    while data.len() < size {
+        if overflow_page == 0 {
+            bail!("Overflow chain ends early");
+        }
+        let page = read_page(overflow_page)?;
+        let chunk = (size - data.len()).min(usable_size - 4);
+        data.extend_from_slice(&page[4..4 + chunk]);
+        overflow_page = u32::from_be_bytes([page[0], page[1], page[2], page[3]]);
+    }
+
+    Ok(Cow::Owned(record::to_utf8(Cow::Owned(data), header.text_encoding).into_owned()))
+}
+
+/// The child pages of the interior table page `page_number`, in key order.
+fn child_pages(page_number: u32, page: &Page) -> Result<Vec<u32>> {
+    let mut children = page
+        .cells()
+        .enumerate()
+        .map(|(index, cell)| match cell {
+            Cell::InteriorTable { left_child_page, .. } => Ok(left_child_page),
+            _ => Err(Error::corrupt_page(page_number, page.cell_offset(index), "Unsupported cell type")),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    children.extend(page.header.right_child_page_number);
+    Ok(children)
+}
+
+/// What a thread of a parallel scan read of its subtree of a table.
+#[derive(Debug, Default)]
+struct PartialScan {
+    /// The selected values of the rows passing the WHERE clause, encoded
+    /// like records. Rows are only counted when no column is selected.
+    rows: Vec<Vec<u8>>,
+    count: u64,
+    pages_read: u64,
+    rows_scanned: u64,
+}
+
+impl PartialScan {
+    /// Reads the rows of the subtree under the page `page_number`.
+    fn read(&mut self, source: PageSource, page_number: u32, query: &Query) -> Result<()> {
+        let page = source.get_page(page_number - 1)?;
+        self.pages_read += 1;
+        match page.header.kind {
+            PageKind::InteriorTable => {
+                for child in child_pages(page_number, &page)? {
+                    self.read(source, child, query)?;
+                }
+            }
+            PageKind::LeafTable => {
+                self.rows_scanned += page.header.number_of_cells as u64;
+                for (index, cell) in page.cells().enumerate() {
+                    let Cell::LeafTable { rowid, .. } = cell else {
+                        return Err(Error::corrupt_page(page_number, page.cell_offset(index), "Unsupported cell type"));
+                    };
+                    let payload = cell_payload(&cell, source.header, |overflow_page| {
+                        self.pages_read += 1;
+                        source.read_raw_page(overflow_page)
+                    })?;
+                    let record = Record::read(rowid, &payload);
+                    if !query.matches(&record) {
+                        continue;
+                    }
+                    self.count += 1;
+                    if !query.select_fields.is_empty() {
+                        self.rows.push(record::encode(&query.values(&record)));
+                    }
+                }
+            }
+            PageKind::InteriorIndex | PageKind::LeafIndex => {
+                return Err(Error::corrupt_page(
+                    page_number,
+                    page.offset as usize,
+                    "Malformed table: table contains index pages",
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Value of the column named `name` in a row of `table`. Rowid aliases
+/// hold NULL in the record, the rowid being kept in the cell.
+pub(crate) fn column_value<'a>(table: &'a Table, record: &Record<'a>, name: &str) -> ColumnValue<'a> {
+    match table.find_column(name) {
+        Some((_, column)) if column.is_primary_key => ColumnValue::I64(record.rowid),
+        Some((pos, _)) => table.record_value(record, pos),
+        None => ColumnValue::I64(record.rowid),
+    }
+}
+
+impl<'query> Query<'query> {
+    pub fn new(table: &'query Table, sql_statement: &'query SelectFields) -> Self {
+        let select_fields = sql_statement
+            .fields
+            .iter()
+            .map(|sql_field| match table.find_column(sql_field) {
+                Some((pos, field)) => (pos, field.is_primary_key),
+                None if is_rowid_alias(sql_field) => (0, true),
+                None => panic!("Fields not found"),
+            })
+            .collect::<Vec<_>>();
+        Self {
+            table,
+            select_fields,
+            filter: sql_statement.where_clause.as_ref(),
+        }
+    }
+
+    /// The selected values of a row of the table.
+    fn values<'a>(&'a self, record: &Record<'a>) -> Vec<ColumnValue<'a>> {
+        self.select_fields
+            .iter()
+            .map(|(i, is_primary_key)| {
+                if *is_primary_key {
+                    ColumnValue::I64(record.rowid)
+                } else {
+                    self.table.record_value(record, *i)
+                }
+            })
+            .collect()
+    }
+
+    fn matches(&self, record: &Record) -> bool {
+        match self.filter {
+            Some(filter) => evaluate(
+                filter,
+                &|name| column_value(self.table, record, name),
+                &|name| self.table.collation(name),
+            ),
+            None => true,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct IndexQuery<'query> {
+    pub table: &'query Table,
+    pub select_fields: Vec<(usize, bool)>,
+    pub index: &'query Index,
+    /// The values the leading columns of the index equal.
+    pub prefix: Vec<ColumnValue<'query>>,
+    /// The smallest key looked for, and whether it is itself included.
+    pub lower: Option<(ColumnValue<'query>, bool)>,
+    /// The largest key looked for, and whether it is itself included.
+    pub upper: Option<(ColumnValue<'query>, bool)>,
+    /// The text encoding of the database, which orders the keys.
+    pub encoding: Encoding,
+}
+
+impl<'query> IndexQuery<'query> {
+    pub fn new(
+        table: &'query Table,
+        sql_statement: &'query SelectFields,
+        index: &'query Index,
+        bounds: &[&'query sql::Comparison],
+        encoding: Encoding,
+    ) -> Self {
+        let select_fields = sql_statement
+            .fields
+            .iter()
+            .map(|sql_field| match table.find_column(sql_field) {
+                Some((pos, field)) => (pos, field.is_primary_key),
+                None if is_rowid_alias(sql_field) => (0, true),
+                None => panic!("Fields not found"),
+            })
+            .collect::<Vec<_>>();
+
+        let affinity = |name: &str| {
+            table.find_column(name).map_or(Affinity::Blob, |(_, column)| {
+                Affinity::of(column.type_name.as_deref().unwrap_or(""))
+            })
+        };
+
+        // Text compares as UTF-8 everywhere else, which orders it unlike the
+        // keys of a UTF-16 database, so ranges of text are left to the WHERE
+        // clause there.
+        let ordered = |value: &ColumnValue| encoding == Encoding::Utf8 || !matches!(value, ColumnValue::Text(_));
+        let (mut prefix, mut lower, mut upper) = (vec![], None, None);
+        for comparison in bounds {
+            let value = key_value(&comparison.value, affinity(&comparison.field));
+            match comparison.operator {
+                sql::Operator::Equal => prefix.push(value),
+                _ if !ordered(&value) => {}
+                sql::Operator::Greater => lower = Some((value, false)),
+                sql::Operator::GreaterEqual => lower = Some((value, true)),
+                sql::Operator::Less => upper = Some((value, false)),
+                sql::Operator::LessEqual => upper = Some((value, true)),
+                _ => unreachable!("Not a bound"),
+            }
+        }
+        // Range comparisons never match NULL, which sorts first.
+        if upper.is_some() && lower.is_none() {
+            lower = Some((ColumnValue::Null, false));
+        }
+        Self {
+            table,
+            select_fields,
+            index,
+            prefix,
+            lower,
+            upper,
+            encoding,
+        }
+    }
+
+    /// Where the key of an index entry is against the keys looked for: Less
+    /// below them, Equal among them, Greater above them. Only the key prefix
+    /// is decoded.
+    fn compare_key(&self, payload: &[u8]) -> std::cmp::Ordering {
+        use std::cmp::Ordering;
+
+        let key = Record::read_prefix(0, payload, self.prefix.len() + 1);
+        for (value, expected) in key.values.iter().zip(&self.prefix) {
+            match self.compare(value, expected) {
+                Ordering::Equal => {}
+                ordering => return ordering,
+            }
+        }
+        let Some(key) = key.values.get(self.prefix.len()) else {
+            return Ordering::Equal;
+        };
+        if let Some((lower, inclusive)) = &self.lower {
+            match self.compare(key, lower) {
+                Ordering::Less => return Ordering::Less,
+                Ordering::Equal if !inclusive => return Ordering::Less,
+                _ => {}
+            }
+        }
+        if let Some((upper, inclusive)) = &self.upper {
+            match self.compare(key, upper) {
+                Ordering::Greater => return Ordering::Greater,
+                Ordering::Equal if !inclusive => return Ordering::Greater,
+                _ => {}
+            }
+        }
+        Ordering::Equal
+    }
+
+    /// Compares in the order of the keys, which is that of the UTF-16 bytes
+    /// of text in a UTF-16 database rather than of the UTF-8 it is read as.
+    fn compare(&self, key: &ColumnValue, value: &ColumnValue) -> std::cmp::Ordering {
+        match (key, value) {
+            (ColumnValue::Text(key), ColumnValue::Text(value)) if self.encoding != Encoding::Utf8 => {
+                let (key, value) = (String::from_utf8_lossy(key), String::from_utf8_lossy(value));
+                self.encoding.encode(&key).cmp(&self.encoding.encode(&value))
+            }
+            _ => key.compare(value),
+        }
+    }
+}
+
+/// A literal as a key of an index on a column of `affinity`. Numbers stay
+/// numbers unless the column holds text, like SQLite converts them, so that
+/// keys compare in the order of the index.
+fn key_value(literal: &str, affinity: Affinity) -> ColumnValue<'_> {
+    if affinity != Affinity::Text {
+        if let Ok(n) = literal.parse::<i64>() {
+            return ColumnValue::I64(n);
+        }
+        if let Ok(n) = literal.parse::<f64>() {
+            return ColumnValue::F64(n);
+        }
+    }
+    ColumnValue::Text(literal.as_bytes())
+}
+
+/// The rowid an index entry points at, the last value of its record.
+pub(crate) fn index_rowid(payload: &[u8]) -> Result<i64> {
+    let record = Record::read(0, payload);
+    let id = record.values.last().expect("index must have id value");
+    if !id.is_number() {
+        bail!("Id was not a number");
+    }
+    Ok(id.clone().into())
+}
+
+/// Counts the rows passed to it instead of writing them.
+#[derive(Debug, Default)]
+pub(crate) struct RowCounter(pub(crate) u64);
+
+impl RowSink for RowCounter {
+    fn write_row(&mut self, _values: &[ColumnValue]) -> Result<()> {
+        self.0 += 1;
+        Ok(())
+    }
+}
+
+/// How far the current statement has got: the pages read so far against
+/// the number of pages in the database. Pages read more than once are
+/// counted every time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    pub pages_read: u64,
+    pub page_count: u32,
+}
+
+struct ProgressHandler {
+    callback: Box<dyn FnMut(Progress)>,
+    page_count: u32,
+}
+
+impl std::fmt::Debug for ProgressHandler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProgressHandler")
+            .field("page_count", &self.page_count)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Stops the statement a database is running from another thread, like a
+/// Ctrl-C handler. The statement fails with `Error::Interrupted` the next
+/// time it reads a page.
+#[derive(Debug, Clone, Default)]
+pub struct InterruptHandle(Arc<AtomicBool>);
+
+impl InterruptHandle {
+    pub fn interrupt(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Forgets an interrupt that came in while no statement was running.
+    pub fn clear(&self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+
+    fn take(&self) -> bool {
+        self.0.swap(false, Ordering::Relaxed)
+    }
+
+    /// Whether an interrupt came in, leaving it for `take` to see.
+    fn is_set(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// What a pinned view of the database was taken from: the database file
+/// as it was, and the WAL up to the last commit then.
+#[derive(Debug, Clone, Copy)]
+struct Pin {
+    modified: SystemTime,
+    /// Bumped on every commit to the database file in rollback journal
+    /// mode, which a touch of the file alone doesn't change.
+    change_counter: u32,
+}
+
+/// A read transaction from [`Database::begin_read`], through which the
+/// database is queried like it was when the transaction started. Dropping
+/// it ends the transaction, and the next query sees the latest commit.
+pub struct ReadTransaction<'db> {
+    database: &'db mut Database,
+    /// Snapshots stay pinned once their transactions end.
+    unpin: bool,
+}
+
+impl std::ops::Deref for ReadTransaction<'_> {
+    type Target = Database;
+
+    fn deref(&self) -> &Database {
+        self.database
+    }
+}
+
+impl std::ops::DerefMut for ReadTransaction<'_> {
+    fn deref_mut(&mut self) -> &mut Database {
+        self.database
+    }
+}
+
+impl Drop for ReadTransaction<'_> {
+    fn drop(&mut self) {
+        if self.unpin {
+            self.database.pin = None;
+        }
+    }
+}
+
+/// An explicit transaction from [`Database::transaction`], through which
+/// statements are run on the database. Their changes are written together
+/// by `commit`; dropping the transaction without committing rolls them
+/// back.
+pub struct Transaction<'db> {
+    database: &'db mut Database,
+    done: bool,
+}
+
+impl Transaction<'_> {
+    /// Writes the changes of the transaction to the database.
+    pub fn commit(mut self) -> Result<()> {
+        self.done = true;
+        self.database.commit_transaction()
+    }
+
+    /// Forgets the changes of the transaction.
+    pub fn rollback(mut self) -> Result<()> {
+        self.done = true;
+        self.database.rollback_transaction()
+    }
+}
+
+impl std::ops::Deref for Transaction<'_> {
+    type Target = Database;
+
+    fn deref(&self) -> &Database {
+        self.database
+    }
+}
+
+impl std::ops::DerefMut for Transaction<'_> {
+    fn deref_mut(&mut self) -> &mut Database {
+        self.database
+    }
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        if !self.done && self.database.in_transaction {
+            // Nothing was written yet, so there is nothing left behind if
+            // rereading the schema fails.
+            let _ = self.database.rollback_transaction();
+        }
+    }
+}
+
+/// The files the pages of a database are read from, which threads scanning
+/// parts of a table at the same time share. Reads through it bypass the
+/// page cache and the counters of the statement.
+#[derive(Debug, Clone, Copy)]
+pub struct PageSource<'db> {
+    header: &'db DatabaseHeader,
+    storage: &'db Storage,
+    wal: Option<&'db Wal>,
+    journal: Option<&'db RollbackJournal>,
+    dirty: &'db BTreeMap<u32, Vec<u8>>,
+    #[cfg(feature = "mmap")]
+    mmap: Option<&'db [u8]>,
+    interrupt: &'db InterruptHandle,
+}
+
+impl<'db> PageSource<'db> {
+    /// Reads the raw bytes of the page with the given 1-based page number.
+    pub fn read_raw_page(&self, page_number: u32) -> Result<Vec<u8>> {
+        if page_number == 0 {
+            bail!("Invalid page number: 0");
+        }
+        if self.interrupt.is_set() {
+            return Err(Error::Interrupted);
+        }
+        if let Some(data) = self.dirty.get(&page_number) {
+            return Ok(data.clone());
+        }
+        if let Some(data) = self.journal.and_then(|journal| journal.page(page_number)) {
+            return Ok(data.to_vec());
+        }
+        if let Some(data) = self.mapped_page(page_number) {
+            return Ok(data.to_vec());
+        }
+
+        let mut data = vec![0; self.header.page_size as usize];
+        self.read_stored_page(page_number, &mut data).map_err(|e| match e.kind() {
+            std::io::ErrorKind::UnexpectedEof => {
+                Error::corrupt_page(page_number, 0, "Page is past the end of the file")
+            }
+            _ => Error::Io(e),
+        })?;
+        Ok(data)
+    }
+
+    /// The page with the given 0-based number, like `Database::get_page`.
+    pub fn get_page(&self, number: u32) -> Result<Page> {
+        self.parse_page(number, self.read_raw_page(number + 1)?)
+    }
+
+    /// Reads the current version of a page from where it is stored: the
+    /// WAL if a committed transaction wrote it, the database file
+    /// otherwise.
+    fn read_stored_page(&self, page_number: u32, data: &mut [u8]) -> std::io::Result<()> {
+        if let Some(wal) = self.wal {
+            if let Some(frame) = wal.find_frame(page_number) {
+                return read_exact_at(&wal.file, data, wal.frame_offset(frame));
+            }
+        }
+        let offset = (page_number - 1) as u64 * self.header.page_size as u64;
+        self.storage.read_exact_at(data, offset)
+    }
+
+    /// The bytes of a page in the mapping of the file, unless the file isn't
+    /// mapped, the page is in the WAL or it is past the end of the mapping.
+    #[cfg(feature = "mmap")]
+    fn mapped_page(&self, page_number: u32) -> Option<&'db [u8]> {
+        let mmap = self.mmap?;
+        if self.wal.and_then(|wal| wal.find_frame(page_number)).is_some() {
+            return None;
+        }
+        let page_size = self.header.page_size as usize;
+        let start = (page_number - 1) as usize * page_size;
+        mmap.get(start..start + page_size)
+    }
+
+    #[cfg(not(feature = "mmap"))]
+    fn mapped_page(&self, _page_number: u32) -> Option<&'db [u8]> {
+        None
+    }
+
+    fn parse_page(&self, number: u32, mut data: Vec<u8>) -> Result<Page> {
+        // Only a corrupt B-tree points at a pointer-map page, whose entries
+        // would otherwise be taken for a page header.
+        let pointer_map = self.header.pointer_map();
+        if pointer_map.is_some_and(|map| map.is_ptrmap_page(number + 1)) {
+            return Err(Error::corrupt_page(number + 1, 0, "Pointer-map page used as a B-tree page"));
+        }
+
+        // The first page starts with the database header.
+        let offset = if number == 0 {
+            data.drain(..100);
+            100
+        } else {
+            0
+        };
+
+        Page::parse(data, offset, self.header.usable_size())
+            .map_err(|e| Error::corrupt_page(number + 1, offset as usize, e.to_string()))
+    }
+}
+
+#[derive(Debug)]
+pub struct Database {
+    pub path: String,
+    pub header: DatabaseHeader,
+    pub storage: Storage,
+    /// Write-ahead log, when the database is in WAL mode and has one.
+    pub wal: Option<Wal>,
+    /// The pages to roll back from a hot journal, when a writer was
+    /// interrupted in the middle of a transaction.
+    journal: Option<Arc<RollbackJournal>>,
+    /// Modification time of the journal when it was last read, if any.
+    journal_modified: Option<SystemTime>,
+    /// The view of the database reads are held to, by `snapshot` or a read
+    /// transaction, which stop following new commits.
+    pin: Option<Pin>,
+    /// Pages the statement being run changed, by 1-based page number, until
+    /// `commit` writes them to the file.
+    dirty: BTreeMap<u32, Vec<u8>>,
+    /// Whether an explicit transaction is open, whose statements leave
+    /// their pages in `dirty` until it commits.
+    in_transaction: bool,
+    /// `dirty` and the header as they were before the statement being run
+    /// in an explicit transaction, which a failure goes back to.
+    savepoint: Option<(BTreeMap<u32, Vec<u8>>, DatabaseHeader)>,
+    /// The locks SQLite's writers take on the database file, held from the
+    /// start of a write until it commits or rolls back. The handle is
+    /// opened by the first write.
+    file_lock: Option<FileLock>,
+    /// The lock of the WAL writer in the -shm file, held like `file_lock`.
+    wal_lock: Option<WalWriteLock>,
+    /// The temp database, in memory, holding the tables of CREATE TEMP
+    /// TABLE and their indexes. It is made by the first of them.
+    temp: Option<Box<Database>>,
+    /// Databases attached by ATTACH, by their schema names, in the order
+    /// they were attached.
+    attached: Vec<(String, Box<Database>)>,
+    /// Modification time of the database file when it was last read from,
+    /// which a writer that doesn't go through the WAL changes.
+    modified: SystemTime,
+    pub schema: SchemaStore,
+    pub stats: Stats,
+    progress: Option<ProgressHandler>,
+    interrupt: InterruptHandle,
+    /// Most bytes a statement may hold in intermediate results, like the
+    /// rowids collected from an index before the table is read.
+    memory_limit: Option<u64>,
+    /// Pages decoded by earlier reads, kept across statements.
+    cache: PageCache,
+    /// Statements of `prepare_cached`, kept across statements.
+    statements: StatementCache,
+    /// The database file mapped into memory, when pages are read from it
+    /// instead of with a read per page.
+    #[cfg(feature = "mmap")]
+    mmap: Option<memmap2::Mmap>,
+    /// Threads a full table scan is spread across.
+    threads: usize,
+}
+
+impl Database {
+    /// Opens the database file at `path`, or a new in-memory database for
+    /// `IN_MEMORY`.
+    pub fn open(path: &str) -> Result<Self> {
+        if path == IN_MEMORY {
+            return Self::open_in_memory();
+        }
+        let mut file = File::open(path)?;
+        let header = DatabaseHeader::read(&mut file)?;
+        let wal = Wal::open(path)?;
+        Self::with_storage(path, header, Storage::File(file), wal)
+    }
+
+    /// Opens a new, empty database that lives only in memory, its pages
+    /// kept in a Vec instead of a file. It goes away when dropped, unless
+    /// `serialize` saved it.
+    pub fn open_in_memory() -> Result<Self> {
+        let mut first = vec![0; DEFAULT_PAGE_SIZE];
+        first[..16].copy_from_slice(&MAGIC_HEADER);
+        first[16..18].copy_from_slice(&(DEFAULT_PAGE_SIZE as u16).to_be_bytes());
+        // Rollback journal mode.
+        first[18] = 1;
+        first[19] = 1;
+        // The payload fractions, which the file format fixes.
+        first[21..24].copy_from_slice(&[64, 32, 32]);
+        // Schema format 4, with UTF-8 text.
+        first[44..48].copy_from_slice(&4u32.to_be_bytes());
+        first[56..60].copy_from_slice(&1u32.to_be_bytes());
+        let mut header = DatabaseHeader::parse(&first)?;
+        header.page_count = 1;
+        header.write(&mut first[..100]);
+        // The sqlite_schema table, still empty.
+        let root = Page::empty(PageKind::LeafTable, DEFAULT_PAGE_SIZE - 100, 100, header.usable_size())?;
+        first[100..].copy_from_slice(&root.data);
+
+        Self::with_storage(IN_MEMORY, header, Storage::Memory(vec![first]), None)
+    }
+
+    fn with_storage(path: &str, header: DatabaseHeader, storage: Storage, wal: Option<Wal>) -> Result<Self> {
+        let modified = storage.modified()?;
+        let mut database = Self {
+            path: path.to_string(),
+            header,
+            storage,
+            wal,
+            journal: None,
+            journal_modified: None,
+            pin: None,
+            dirty: BTreeMap::new(),
+            in_transaction: false,
+            savepoint: None,
+            file_lock: None,
+            wal_lock: None,
+            temp: None,
+            attached: vec![],
+            modified,
+            schema: SchemaStore::default(),
+            stats: Stats::default(),
+            progress: None,
+            interrupt: InterruptHandle::default(),
+            memory_limit: None,
+            cache: PageCache::default(),
+            statements: StatementCache::default(),
+            #[cfg(feature = "mmap")]
+            mmap: None,
+            threads: 1,
+        };
+        database.reload()?;
+
+        Ok(database)
+    }
+
+    /// Prepares the statement `sql` to run on this database. The tables it
+    /// reads must be in the schema, and the result columns are named, `*`
+    /// standing for the columns of the table.
+    pub fn prepare(&self, sql: &str) -> Result<Statement> {
+        let mut statement = Statement::prepare(sql)?;
+        statement.expand(self)?;
+        Ok(statement)
+    }
+
+    /// Like `prepare`, but the statement is parsed and its result columns
+    /// named once, then copied from a cache of the statements prepared
+    /// last, until the schema changes.
+    pub fn prepare_cached(&mut self, sql: &str) -> Result<Statement> {
+        let schema = self.schema_cookies();
+        if let Some(statement) = self.statements.get(sql, &schema) {
+            return Ok(statement);
+        }
+        let statement = self.prepare(sql)?;
+        self.statements.insert(&statement);
+        Ok(statement)
+    }
+
+    /// The schema cookies of this database, the temp database and the
+    /// attached ones, which statements read tables from.
+    fn schema_cookies(&self) -> Vec<u32> {
+        let attached = self.attached.iter().map(|(_, database)| database.as_ref());
+        let databases = std::iter::once(self).chain(self.temp.as_deref()).chain(attached);
+        databases.map(|database| database.header.schema_cookie).collect()
+    }
+
+    /// Runs the statement `sql` to the end, returning its rows.
+    pub fn query(&mut self, sql: &str) -> Result<Vec<Row>> {
+        let mut statement = self.prepare(sql)?;
+        let rows = statement.query(self)?.collect();
+        rows
+    }
+
+    /// Runs the statement `sql` to the end, reading its rows into `T`, like
+    /// [`de::from_row`]: a struct takes the columns by name.
+    pub fn query_as<T: DeserializeOwned>(&mut self, sql: &str) -> Result<Vec<T>> {
+        let mut statement = self.prepare(sql)?;
+        let columns = statement.columns();
+        let rows = statement.query(self)?;
+        rows.map(|row| de::from_row(&columns, &row?)).collect()
+    }
+
+    /// Runs the statement `sql` to the end, reading each row as `T`, like a
+    /// tuple of the column values.
+    pub fn query_rows<T: FromRow>(&mut self, sql: &str) -> Result<Vec<T>> {
+        self.query(sql)?.iter().map(T::from_row).collect()
+    }
+
+    /// The tables, indexes, views and triggers of the database.
+    pub fn schema(&self) -> &SchemaStore {
+        &self.schema
+    }
+
+    /// Rereads the header and schema from the current first page. A
+    /// committed transaction may have changed both, in which case the latest
+    /// copy of the page is in the WAL, and an unfinished one too, in which
+    /// case the copy to roll back to is in the journal.
+    fn reload(&mut self) -> Result<()> {
+        self.read_journal()?;
+        let in_wal = self.wal.as_ref().and_then(|wal| wal.find_frame(1)).is_some();
+        let in_journal = self.journal.as_ref().and_then(|journal| journal.page(1)).is_some();
+        if in_wal || in_journal {
+            self.header = DatabaseHeader::parse(&self.read_raw_page(1)?)?;
+        }
+
+        self.schema = SchemaStore::read(self)?;
+        Ok(())
+    }
+
+    /// Reads the journal again if it changed since it was last read, so
+    /// reads roll back a transaction a writer left unfinished.
+    fn read_journal(&mut self) -> Result<()> {
+        let modified = self.journal_modified_time();
+        if modified == self.journal_modified {
+            return Ok(());
+        }
+        self.journal_modified = modified;
+        self.journal = None;
+
+        let Some(journal) = RollbackJournal::open(&self.path)? else {
+            return Ok(());
+        };
+        if journal.page_size != self.header.page_size {
+            bail!(
+                "Unable to roll back hot journal: its page size {} differs from the database's {}",
+                journal.page_size,
+                self.header.page_size
+            );
+        }
+        self.journal = Some(Arc::new(journal));
+        Ok(())
+    }
+
+    fn journal_modified_time(&self) -> Option<SystemTime> {
+        if self.is_in_memory() {
+            return None;
+        }
+        std::fs::metadata(format!("{}-journal", self.path))
+            .and_then(|meta| meta.modified())
+            .ok()
+    }
+
+    /// The hot journal reads roll back, if there is one.
+    pub fn rollback_journal(&self) -> Option<&RollbackJournal> {
+        self.journal.as_deref()
+    }
+
+    /// Catches up with transactions other connections committed since the
+    /// last statement, to the WAL or to the file itself. Snapshots stay
+    /// where they were taken, and so does an explicit transaction.
+    pub fn refresh(&mut self) -> Result<()> {
+        if self.in_transaction {
+            return Ok(());
+        }
+        if let Some(pin) = self.pin {
+            if self.storage.modified()? == pin.modified {
+                return Ok(());
+            }
+            if self.header.is_wal() {
+                bail!("Snapshot is no longer available: the database was checkpointed");
+            }
+            if self.read_change_counter()? != pin.change_counter {
+                bail!("Snapshot is no longer available: the database was changed");
+            }
+            return Ok(());
+        }
+
+        let in_memory = self.is_in_memory();
+        let mut changed = match &mut self.wal {
+            Some(wal) => wal.refresh()?,
+            None if in_memory => false,
+            None => {
+                self.wal = Wal::open(&self.path)?;
+                self.wal.is_some()
+            }
+        };
+        let modified = self.storage.modified()?;
+        if modified != self.modified {
+            self.modified = modified;
+            changed = true;
+        }
+        if self.journal_modified_time() != self.journal_modified {
+            changed = true;
+        }
+        if changed {
+            self.cache.clear();
+            #[cfg(feature = "mmap")]
+            if self.mmap.is_some() {
+                self.set_mmap(true)?;
+            }
+            // Without the WAL, the header on disk is current again.
+            self.header = self.stored_header()?;
+            self.reload()?;
+        }
+        if self.progress.is_some() {
+            let page_count = self.page_count()?;
+            if let Some(progress) = &mut self.progress {
+                progress.page_count = page_count;
+            }
+        }
+        Ok(())
+    }
+
+    /// Calls `callback` after every page read, e.g. to show how far a full
+    /// scan has got. The page counter restarts with `stats`.
+    pub fn set_progress(&mut self, callback: impl FnMut(Progress) + 'static) -> Result<()> {
+        self.progress = Some(ProgressHandler {
+            callback: Box::new(callback),
+            page_count: self.page_count()?,
+        });
+        Ok(())
+    }
+
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt.clone()
+    }
+
+    /// Limits the memory a statement may use for intermediate results.
+    /// Statements going over it fail with `Error::MemoryLimit`. The usage
+    /// restarts with `stats`.
+    pub fn set_memory_limit(&mut self, limit: Option<u64>) {
+        self.memory_limit = limit;
+        for database in self.others_mut() {
+            database.memory_limit = limit;
+        }
+    }
+
+    pub fn memory_limit(&self) -> Option<u64> {
+        self.memory_limit
+    }
+
+    /// Reads the pages of the database file through a memory mapping of it
+    /// rather than a read each, which makes scanning large databases that
+    /// nothing writes to faster. Pages committed to the WAL are still read.
+    ///
+    /// Another process shrinking the file while it is mapped makes reading
+    /// the pages gone past its end crash this one.
+    #[cfg(feature = "mmap")]
+    pub fn set_mmap(&mut self, enabled: bool) -> Result<()> {
+        self.mmap = None;
+        if enabled {
+            // SAFETY: the mapping is only read, and remapped whenever the
+            // file changes. Writers changing pages in place are no different
+            // from them changing the file between two reads.
+            let Storage::File(file) = &self.storage else {
+                bail!("Unable to map an in-memory database");
+            };
+            self.mmap = Some(unsafe { memmap2::Mmap::map(file)? });
+        }
+        Ok(())
+    }
+
+    #[cfg(not(feature = "mmap"))]
+    pub fn set_mmap(&mut self, enabled: bool) -> Result<()> {
+        if enabled {
+            bail!("Memory-mapped reads need the mmap feature");
+        }
+        Ok(())
+    }
+
+    /// Spreads full table scans across `threads` threads, each reading a
+    /// part of the table, for queries filtering or counting the rows of
+    /// large tables. 1, the default, scans on the calling thread.
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = threads.max(1);
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Keeps up to `statements` statements for `prepare_cached`, 0 for
+    /// none.
+    pub fn set_statement_cache_size(&mut self, statements: usize) {
+        self.statements.set_capacity(statements);
+    }
+
+    pub fn statement_cache(&self) -> &StatementCache {
+        &self.statements
+    }
+
+    /// Keeps up to `pages` decoded pages between reads, 0 for none.
+    pub fn set_cache_size(&mut self, pages: usize) {
+        self.cache.set_capacity(pages);
+    }
+
+    /// The page cache, with its hits and misses since the database was
+    /// opened.
+    pub fn cache(&self) -> &PageCache {
+        &self.cache
+    }
+
+    /// Accounts for `bytes` more of intermediate results, failing once the
+    /// statement goes over the memory limit.
+    pub(crate) fn reserve_memory(&mut self, bytes: u64) -> Result<()> {
+        self.stats.memory_used += bytes;
+        match self.memory_limit {
+            Some(limit) if self.stats.memory_used > limit => {
+                Err(Error::MemoryLimit { limit })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn check_interrupt(&self) -> Result<()> {
+        if self.interrupt.take() {
+            return Err(Error::Interrupted);
+        }
+        Ok(())
+    }
+
+    fn report_progress(&mut self) {
+        if let Some(progress) = &mut self.progress {
+            (progress.callback)(Progress {
+                pages_read: self.stats.pages_read,
+                page_count: progress.page_count,
+            });
+        }
+    }
+
+    /// Returns a handle pinned to the transactions committed to the WAL so
+    /// far, which keeps seeing this point-in-time view while other
+    /// connections go on writing.
+    ///
+    /// SQLite's own readers stop checkpoints from copying frames past their
+    /// view into the database file by holding locks in the -shm file, which
+    /// this reader doesn't take. Queries through the snapshot fail instead
+    /// once a checkpoint has written to the database file.
+    pub fn snapshot(&self) -> Result<Database> {
+        let pin = match self.pin {
+            Some(pin) => pin,
+            None => self.current_pin()?,
+        };
+        Ok(Self {
+            path: self.path.clone(),
+            header: self.header.clone(),
+            storage: self.storage.try_clone()?,
+            wal: self.wal.as_ref().map(Wal::try_clone).transpose()?,
+            journal: self.journal.clone(),
+            journal_modified: self.journal_modified,
+            pin: Some(pin),
+            dirty: BTreeMap::new(),
+            in_transaction: false,
+            savepoint: None,
+            file_lock: None,
+            wal_lock: None,
+            temp: None,
+            attached: vec![],
+            modified: self.modified,
+            schema: self.schema.clone(),
+            stats: Stats::default(),
+            progress: None,
+            interrupt: InterruptHandle::default(),
+            memory_limit: self.memory_limit,
+            cache: PageCache::new(self.cache.capacity()),
+            statements: StatementCache::new(self.statements.capacity()),
+            #[cfg(feature = "mmap")]
+            mmap: None,
+            threads: self.threads,
+        })
+    }
+
+    /// Starts a read transaction: the queries run through it all see the
+    /// database as it is now, even if other connections commit in between.
+    /// Commits to the WAL are left for after the transaction; a commit to
+    /// the database file itself, or a checkpoint, makes the queries fail
+    /// instead, as this reader takes no locks to hold writers back.
+    pub fn begin_read(&mut self) -> Result<ReadTransaction<'_>> {
+        self.refresh()?;
+        let unpin = self.pin.is_none();
+        if unpin {
+            self.pin = Some(self.current_pin()?);
+        }
+        Ok(ReadTransaction { database: self, unpin })
+    }
+
+    fn current_pin(&self) -> Result<Pin> {
+        let mut counter = [0; 4];
+        self.storage.read_exact_at(&mut counter, 24)?;
+        Ok(Pin {
+            modified: self.storage.modified()?,
+            change_counter: u32::from_be_bytes(counter),
+        })
+    }
+
+    /// Copies the pages committed to the WAL back into the database file,
+    /// like `PRAGMA wal_checkpoint`. TRUNCATE then empties the WAL as well.
+    ///
+    /// No locks are taken, so this is only safe while no other connection
+    /// has the database open, e.g. to tidy up the WAL left by a writer that
+    /// exited without checkpointing.
+    pub fn checkpoint(&mut self, mode: CheckpointMode) -> Result<Checkpoint> {
+        if !self.header.is_wal() {
+            bail!("Database is not in WAL mode");
+        }
+        if self.pin.is_some() {
+            bail!("Unable to checkpoint through a snapshot");
+        }
+        self.refresh()?;
+        let Some(wal) = &self.wal else {
+            return Ok(Checkpoint {
+                log_frames: 0,
+                checkpointed_frames: 0,
+            });
+        };
+
+        let page_size = self.header.page_size as u64;
+        let mut file = OpenOptions::new().write(true).open(&self.path)?;
+        let mut data = vec![0; page_size as usize];
+        for (page_number, frame) in wal.pages() {
+            // Pages past the end were dropped by a later commit that shrank
+            // the database.
+            if page_number > wal.database_size {
+                continue;
+            }
+            read_exact_at(&wal.file, &mut data, wal.frame_offset(frame))?;
+            file.seek(SeekFrom::Start((page_number - 1) as u64 * page_size))?;
+            file.write_all(&data)?;
+        }
+        file.set_len(wal.database_size as u64 * page_size)?;
+        file.sync_all()?;
+
+        let checkpoint = Checkpoint {
+            log_frames: wal.max_frame,
+            checkpointed_frames: wal.max_frame,
+        };
+        if mode == CheckpointMode::Truncate {
+            wal.truncate()?;
+            Wal::invalidate_index(&self.path)?;
+            self.refresh()?;
+        }
+        Ok(checkpoint)
+    }
+
+    /// Starts an explicit transaction, like BEGIN: the statements run until
+    /// `commit_transaction` keep their changes in memory, to be written
+    /// together, or forgotten by `rollback_transaction`.
+    pub fn begin_transaction(&mut self) -> Result<()> {
+        if self.in_transaction {
+            bail!("Cannot start a transaction within a transaction");
+        }
+        self.check_writable()?;
+        let begun = self.others_mut().try_for_each(|database| database.begin_transaction());
+        if let Err(error) = begun {
+            for database in self.others_mut().filter(|database| database.in_transaction) {
+                database.rollback_transaction()?;
+            }
+            self.unlock_file()?;
+            return Err(error);
+        }
+        self.in_transaction = true;
+        Ok(())
+    }
+
+    /// Writes the changes of the explicit transaction, like COMMIT. If that
+    /// fails, they are forgotten, those to temp tables too. The attached
+    /// databases commit after this one, each on its own: unlike SQLite,
+    /// there is no super-journal to make the transaction atomic across
+    /// files.
+    pub fn commit_transaction(&mut self) -> Result<()> {
+        if !self.in_transaction {
+            bail!("Cannot commit: no transaction is active");
+        }
+        self.in_transaction = false;
+        self.savepoint = None;
+        let committed = self.commit();
+        for database in self.others_mut() {
+            match committed {
+                Ok(()) => database.commit_transaction()?,
+                Err(_) => database.rollback_transaction()?,
+            }
+        }
+        committed
+    }
+
+    /// Forgets the changes of the explicit transaction, like ROLLBACK.
+    pub fn rollback_transaction(&mut self) -> Result<()> {
+        if !self.in_transaction {
+            bail!("Cannot roll back: no transaction is active");
+        }
+        self.in_transaction = false;
+        self.savepoint = None;
+        for database in self.others_mut() {
+            database.rollback_transaction()?;
+        }
+        self.rollback()
+    }
+
+    /// Starts an explicit transaction, which rolls back when dropped unless
+    /// it was committed. See [`Database::begin_transaction`].
+    pub fn transaction(&mut self) -> Result<Transaction<'_>> {
+        self.begin_transaction()?;
+        Ok(Transaction {
+            database: self,
+            done: false,
+        })
+    }
+
+    /// Whether an explicit transaction is open.
+    pub fn in_transaction(&self) -> bool {
+        self.in_transaction
+    }
+
+    /// The temp database, once a temp table was made.
+    pub fn temp(&self) -> Option<&Database> {
+        self.temp.as_deref()
+    }
+
+    /// The temp database, made in memory if there is none yet. It stops
+    /// with the same interrupt handle and memory limit as this one, and
+    /// joins the explicit transaction if there is one.
+    pub(crate) fn temp_mut(&mut self) -> Result<&mut Database> {
+        if self.temp.is_none() {
+            let mut temp = Database::open_in_memory()?;
+            temp.interrupt = self.interrupt.clone();
+            temp.memory_limit = self.memory_limit;
+            if self.in_transaction {
+                temp.begin_transaction()?;
+            }
+            self.temp = Some(Box::new(temp));
+        }
+        Ok(self.temp.as_deref_mut().expect("temp database was just made"))
+    }
+
+    /// Attaches the database at `path` under the schema name `name`, like
+    /// `ATTACH 'path' AS name`. Its tables go by `name.table`, or by their
+    /// names alone when neither the temp database nor this one has them.
+    pub fn attach(&mut self, path: &str, name: &str) -> Result<()> {
+        if self.in_transaction {
+            bail!("Cannot attach a database within a transaction");
+        }
+        if ["main", "temp"].iter().any(|schema| schema.eq_ignore_ascii_case(name)) || self.find_attached(name).is_some() {
+            bail!("Database already in use: {}", name);
+        }
+        let mut database = Database::open(path)?;
+        database.interrupt = self.interrupt.clone();
+        database.memory_limit = self.memory_limit;
+        self.attached.push((name.to_string(), Box::new(database)));
+        self.statements.clear();
+        Ok(())
+    }
+
+    /// Closes the database attached as `name`, like DETACH.
+    pub fn detach(&mut self, name: &str) -> Result<()> {
+        if self.in_transaction {
+            bail!("Cannot detach a database within a transaction");
+        }
+        let Some(i) = self.find_attached(name) else {
+            bail!("No such database: {}", name);
+        };
+        self.attached.remove(i);
+        self.statements.clear();
+        Ok(())
+    }
+
+    /// The attached databases and their schema names, in the order they
+    /// were attached.
+    pub fn attached(&self) -> impl Iterator<Item = (&str, &Database)> {
+        self.attached.iter().map(|(name, database)| (name.as_str(), database.as_ref()))
+    }
+
+    fn find_attached(&self, name: &str) -> Option<usize> {
+        self.attached.iter().position(|(attached, _)| attached.eq_ignore_ascii_case(name))
+    }
+
+    /// The temp database and the attached ones, which statements and
+    /// transactions of this connection go on to.
+    fn others_mut(&mut self) -> impl Iterator<Item = &mut Database> {
+        let attached = self.attached.iter_mut().map(|(_, database)| database.as_mut());
+        self.temp.as_deref_mut().into_iter().chain(attached)
+    }
+
+    /// Whether `table` is a table of the temp database, which hides one by
+    /// the same name in this database, like in SQLite.
+    fn is_temp_table(&self, table: &str) -> bool {
+        self.temp.as_ref().is_some_and(|temp| temp.schema.find_table(table).is_some())
+    }
+
+    /// Which database `table` is in, and its name there. A name like
+    /// `aux.t` is looked for in the database of that schema name, `main`
+    /// being this one. Other names are looked for in the temp database,
+    /// this one, then the attached ones, like in SQLite; a table found in
+    /// none is left to this one to report missing.
+    fn locate<'a>(&self, table: &'a str) -> (Schema, &'a str) {
+        if let Some((schema, name)) = table.split_once('.') {
+            if schema.eq_ignore_ascii_case("main") {
+                return (Schema::Main, name);
+            }
+            if schema.eq_ignore_ascii_case("temp") && self.temp.is_some() {
+                return (Schema::Temp, name);
+            }
+            if let Some(i) = self.find_attached(schema) {
+                return (Schema::Attached(i), name);
+            }
+        }
+        let has = |database: &Database| {
+            database.schema.find_table(table).is_some() || database.schema.find_view(table).is_some()
+        };
+        if self.is_temp_table(table) {
+            (Schema::Temp, table)
+        } else if has(self) {
+            (Schema::Main, table)
+        } else if let Some(i) = self.attached.iter().position(|(_, database)| has(database)) {
+            (Schema::Attached(i), table)
+        } else {
+            (Schema::Main, table)
+        }
+    }
+
+    fn database_mut(&mut self, schema: Schema) -> &mut Database {
+        match schema {
+            Schema::Main => self,
+            Schema::Temp => self.temp.as_deref_mut().expect("table found in the temp database"),
+            Schema::Attached(i) => &mut self.attached[i].1,
+        }
+    }
+
+    /// The database a statement naming `table` works on, and the name of
+    /// the table there, without its schema name.
+    pub(crate) fn resolve<'t>(&self, table: &'t str) -> (&Database, &'t str) {
+        match self.locate(table) {
+            (Schema::Main, name) => (self, name),
+            (Schema::Temp, name) => (self.temp.as_deref().expect("table found in the temp database"), name),
+            (Schema::Attached(i), name) => (&self.attached[i].1, name),
+        }
+    }
+
+    /// Like `resolve`, for reading the rows of `table`.
+    pub(crate) fn resolve_mut<'t>(&mut self, table: &'t str) -> (&mut Database, &'t str) {
+        let (schema, name) = self.locate(table);
+        (self.database_mut(schema), name)
+    }
+
+    /// Runs `f` on the database `table` is in and the name of the table
+    /// there, like `resolve`. The counters of the statement, and the memory
+    /// it used, go on from this database's.
+    pub(crate) fn with_database_of<T>(
+        &mut self,
+        table: &str,
+        f: impl FnOnce(&mut Database, &str) -> Result<T>,
+    ) -> Result<T> {
+        let (schema, name) = self.locate(table);
+        if schema == Schema::Main {
+            return f(self, name);
+        }
+        let stats = std::mem::take(&mut self.stats);
+        let database = self.database_mut(schema);
+        database.stats = stats;
+        let result = f(database, name);
+        let stats = std::mem::take(&mut database.stats);
+        self.stats = stats;
+        result
+    }
+
+    /// Prepares for a statement that changes the database. In an explicit
+    /// transaction, it remembers the changes so far, for the statement to
+    /// go back to if it fails.
+    pub(crate) fn begin_write(&mut self) -> Result<()> {
+        if self.in_transaction {
+            self.savepoint = Some((self.dirty.clone(), self.header.clone()));
+            return Ok(());
+        }
+        self.check_writable()
+    }
+
+    /// Checks that the database may be changed, and locks it for writing
+    /// like SQLite does. Only databases without auto-vacuum are written,
+    /// and only while no other connection is.
+    fn check_writable(&mut self) -> Result<()> {
+        if self.pin.is_some() {
+            bail!("Unable to write through a snapshot or read transaction");
+        }
+        let locked = self.lock_for_writing();
+        if locked.is_err() {
+            self.unlock_file()?;
+        }
+        locked
+    }
+
+    /// Takes a shared lock on the file, under which the database is read
+    /// again, then the lock of its single writer: a reserved lock on the
+    /// file, or in WAL mode the WAL writer lock.
+    fn lock_for_writing(&mut self) -> Result<()> {
+        // No other connection sees an in-memory database.
+        if self.is_in_memory() {
+            return Ok(());
+        }
+        self.lock_file(LockLevel::Shared)?;
+        self.refresh()?;
+        if self.journal.is_some() {
+            bail!("Unable to write while a hot journal is left to roll back");
+        }
+        if self.header.auto_vacuum() != AutoVacuum::None {
+            bail!("Unable to write to an auto-vacuum database");
+        }
+        if self.header.is_wal() {
+            self.wal_lock = WalWriteLock::acquire(&self.path)?;
+            return Ok(());
+        }
+        self.lock_file(LockLevel::Reserved)
+    }
+
+    fn lock_file(&mut self, level: LockLevel) -> Result<()> {
+        if self.file_lock.is_none() {
+            self.file_lock = Some(FileLock::open(&self.path)?);
+        }
+        self.file_lock.as_mut().expect("lock was just opened").lock(level)
+    }
+
+    /// Releases the locks taken for writing.
+    fn unlock_file(&mut self) -> Result<()> {
+        self.wal_lock = None;
+        match &mut self.file_lock {
+            Some(lock) => lock.unlock(),
+            None => Ok(()),
+        }
+    }
+
+    /// Replaces the page with the given 0-based number, like in `get_page`,
+    /// until the statement commits or rolls back.
+    pub(crate) fn write_page(&mut self, number: u32, page: &Page) -> Result<()> {
+        let mut data = match number {
+            0 => self.read_raw_page(1)?[..100].to_vec(),
+            _ => Vec::with_capacity(self.header.page_size as usize),
+        };
+        data.extend_from_slice(&page.data);
+        self.write_raw_page(number + 1, data);
+        Ok(())
+    }
+
+    /// Replaces the raw bytes of the page with the given 1-based number.
+    pub(crate) fn write_raw_page(&mut self, page_number: u32, data: Vec<u8>) {
+        self.cache.remove(page_number);
+        self.dirty.insert(page_number, data);
+    }
+
+    /// A new, zeroed page, by its 1-based number: one taken off the
+    /// freelist, or else one at the end of the file. Like SQLite, the last
+    /// leaf of the first trunk page goes first, then the trunk itself, its
+    /// next trunk becoming the first.
+    pub(crate) fn allocate_page(&mut self) -> Result<u32> {
+        let trunk = self.header.first_freelist_trunk_page;
+        if trunk != 0 {
+            let mut data = self.read_raw_page(trunk)?;
+            let leaf_count = u32::from_be_bytes([data[4], data[5], data[6], data[7]]) as usize;
+            let page_number = match leaf_count {
+                0 => {
+                    self.header.first_freelist_trunk_page = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+                    trunk
+                }
+                _ => {
+                    let at = 8 + 4 * (leaf_count - 1);
+                    let leaf = u32::from_be_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]]);
+                    if leaf < 2 || leaf > self.page_count()? {
+                        return Err(Error::corrupt_page(
+                            trunk,
+                            at,
+                            format!("Freelist leaf page {} is out of range", leaf),
+                        ));
+                    }
+                    data[4..8].copy_from_slice(&(leaf_count as u32 - 1).to_be_bytes());
+                    self.write_raw_page(trunk, data);
+                    leaf
+                }
+            };
+            self.header.freelist_page_count = self.header.freelist_page_count.saturating_sub(1);
+            self.write_raw_page(page_number, vec![0; self.header.page_size as usize]);
+            return Ok(page_number);
+        }
+
+        let mut page_number = self.page_count()? + 1;
+        // The page with the byte SQLite locks the file at is never used.
+        if page_number == PENDING_BYTE / self.header.page_size + 1 {
+            page_number += 1;
+        }
+        self.write_raw_page(page_number, vec![0; self.header.page_size as usize]);
+        Ok(page_number)
+    }
+
+    /// Puts a page no B-tree uses any more on the freelist: as a leaf of the
+    /// first trunk page while it has room, like SQLite, or as the new first
+    /// trunk otherwise.
+    pub(crate) fn free_page(&mut self, page_number: u32) -> Result<()> {
+        let trunk = self.header.first_freelist_trunk_page;
+        self.header.freelist_page_count += 1;
+        if trunk != 0 {
+            let mut data = self.read_raw_page(trunk)?;
+            let leaf_count = u32::from_be_bytes([data[4], data[5], data[6], data[7]]) as usize;
+            if leaf_count < self.header.usable_size() as usize / 4 - 8 {
+                let at = 8 + 4 * leaf_count;
+                data[at..at + 4].copy_from_slice(&page_number.to_be_bytes());
+                data[4..8].copy_from_slice(&(leaf_count as u32 + 1).to_be_bytes());
+                self.write_raw_page(trunk, data);
+                // Free leaves keep whatever they held.
+                self.cache.remove(page_number);
+                return Ok(());
+            }
+        }
+
+        let mut data = vec![0; self.header.page_size as usize];
+        data[..4].copy_from_slice(&trunk.to_be_bytes());
+        self.write_raw_page(page_number, data);
+        self.header.first_freelist_trunk_page = page_number;
+        Ok(())
+    }
+
+    /// Frees the overflow pages of a cell being removed.
+    pub(crate) fn free_overflow(&mut self, cell: &Cell) -> Result<()> {
+        let Some(mut page_number) = cell.overflow_page() else {
+            return Ok(());
+        };
+        let local = match cell {
+            Cell::InteriorIndex { payload, .. }
+            | Cell::LeafIndex { payload, .. }
+            | Cell::LeafTable { payload, .. } => payload.len(),
+            Cell::InteriorTable { .. } => 0,
+        };
+        let chunk = self.header.usable_size() as usize - 4;
+        let mut remaining = cell.payload_size() as usize - local;
+        while remaining > 0 {
+            if page_number == 0 {
+                bail!("Overflow chain ends early");
+            }
+            let data = self.read_raw_page(page_number)?;
+            self.free_page(page_number)?;
+            remaining -= remaining.min(chunk);
+            page_number = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+        }
+        Ok(())
+    }
+
+    /// Writes the pages the statement changed to the file, along with the
+    /// header, and rereads the schema. In an explicit transaction, they are
+    /// kept for the transaction to write, and only the schema is reread.
+    ///
+    /// Like SQLite in DELETE journal mode, what the pages held before goes
+    /// to a rollback journal first, which is deleted once the file is
+    /// synced. A crash part way leaves a hot journal, which readers roll
+    /// back. Failing otherwise, e.g. on the lock, forgets the changes.
+    pub(crate) fn commit(&mut self) -> Result<()> {
+        if self.in_transaction {
+            self.savepoint = None;
+            self.schema = SchemaStore::read(self)?;
+            return Ok(());
+        }
+        if let Err(error) = self.write_changes() {
+            self.rollback()?;
+            return Err(error);
+        }
+        self.unlock_file()
+    }
+
+    fn write_changes(&mut self) -> Result<()> {
+        if self.dirty.is_empty() {
+            return Ok(());
+        }
+        self.header.file_change_counter = self.header.file_change_counter.wrapping_add(1);
+        self.header.page_count = self.page_count()?;
+        let mut first = self.read_raw_page(1)?;
+        self.header.write(&mut first[..100]);
+        self.dirty.insert(1, first);
+
+        if self.is_in_memory() {
+            self.write_memory();
+        } else if self.header.is_wal() {
+            self.write_wal()?;
+        } else {
+            self.write_file()?;
+        }
+
+        self.dirty.clear();
+        self.cache.clear();
+        self.modified = self.storage.modified()?;
+        #[cfg(feature = "mmap")]
+        if self.mmap.is_some() {
+            self.set_mmap(true)?;
+        }
+        self.reload()
+    }
+
+    /// Writes the changed pages to the database file in place, journaled.
+    /// This takes an exclusive lock, which no other connection may hold
+    /// any lock alongside.
+    fn write_file(&mut self) -> Result<()> {
+        self.lock_file(LockLevel::Exclusive)?;
+        let database_size = self.stored_page_count()?;
+        let unchanged = BTreeMap::new();
+        let stored = PageSource {
+            dirty: &unchanged,
+            ..self.page_source()
+        };
+        let originals = self
+            .dirty
+            .keys()
+            .filter(|&&page_number| page_number <= database_size)
+            .map(|&page_number| Ok((page_number, stored.read_raw_page(page_number)?)))
+            .collect::<Result<Vec<_>>>()?;
+        journal::write(&self.path, self.header.page_size, database_size, &originals, nonce())?;
+
+        let page_size = self.header.page_size as u64;
+        let mut file = OpenOptions::new().write(true).open(&self.path)?;
+        for (&page_number, data) in &self.dirty {
+            file.seek(SeekFrom::Start((page_number - 1) as u64 * page_size))?;
+            file.write_all(data)?;
+        }
+        file.sync_all()?;
+        journal::delete(&self.path)
+    }
+
+    /// Replaces the pages of an in-memory database with the changed ones,
+    /// adding those past the end.
+    fn write_memory(&mut self) {
+        let Storage::Memory(pages) = &mut self.storage else {
+            return;
+        };
+        for (page_number, data) in std::mem::take(&mut self.dirty) {
+            let index = page_number as usize - 1;
+            if index >= pages.len() {
+                pages.resize(index + 1, vec![0; self.header.page_size as usize]);
+            }
+            pages[index] = data;
+        }
+    }
+
+    /// Appends the changed pages to the WAL as a transaction, starting the
+    /// WAL if there is none yet. The database file is left as it is until a
+    /// checkpoint.
+    fn write_wal(&mut self) -> Result<()> {
+        let database_size = self.page_count()?;
+        if self.wal.is_none() {
+            self.wal = Some(Wal::create(&self.path, self.header.page_size, (1, nonce()))?);
+        }
+        let wal = self.wal.as_mut().expect("WAL was just started");
+        wal.append(&self.dirty, database_size)?;
+        Wal::invalidate_index(&self.path)
+    }
+
+    /// Forgets the pages the statement changed, after it failed part way.
+    /// In an explicit transaction, those of the statements before stay.
+    pub(crate) fn rollback(&mut self) -> Result<()> {
+        if let Some((dirty, header)) = self.savepoint.take() {
+            self.dirty = dirty;
+            self.header = header;
+            self.cache.clear();
+            self.schema = SchemaStore::read(self)?;
+            return Ok(());
+        }
+        self.dirty.clear();
+        self.cache.clear();
+        self.unlock_file()?;
+        self.header = self.stored_header()?;
+        self.reload()
+    }
+
+    /// Number of pages in the file, measured from its length, or as of the
+    /// last transaction committed to the WAL. Pages added by the statement
+    /// being run count too.
+    pub fn page_count(&self) -> Result<u32> {
+        let added = self.dirty.last_key_value().map_or(0, |(&page_number, _)| page_number);
+        Ok(self.stored_page_count()?.max(added))
+    }
+
+    fn stored_page_count(&self) -> Result<u32> {
+        if let Some(wal) = self.wal.as_ref().filter(|wal| wal.max_frame > 0) {
+            return Ok(wal.database_size);
+        }
+        if let Some(journal) = &self.journal {
+            return Ok(journal.database_size);
+        }
+        let len = self.storage.len()?;
+        Ok((len / self.header.page_size as u64) as u32)
+    }
+
+    /// The header as it was last committed to the database file.
+    fn stored_header(&self) -> Result<DatabaseHeader> {
+        let mut header = [0; 100];
+        self.storage.read_exact_at(&mut header, 0)?;
+        DatabaseHeader::parse(&header)
+    }
+
+    pub fn is_in_memory(&self) -> bool {
+        matches!(self.storage, Storage::Memory(_))
+    }
+
+    /// The bytes of a database file holding what was committed to this
+    /// database so far, e.g. to save an in-memory database to disk.
+    pub fn serialize(&self) -> Result<Vec<u8>> {
+        let unchanged = BTreeMap::new();
+        let committed = PageSource {
+            dirty: &unchanged,
+            ..self.page_source()
+        };
+        let mut data = Vec::new();
+        for page_number in 1..=self.stored_page_count()? {
+            data.extend(committed.read_raw_page(page_number)?);
+        }
+        Ok(data)
+    }
+
+    /// The pointer-map entry of a page: its kind and the page pointing at
+    /// it. `None` unless the database is in auto-vacuum mode.
+    pub fn pointer_map_entry(&mut self, page_number: u32) -> Result<Option<PointerMapEntry>> {
+        match self.header.pointer_map() {
+            Some(pointer_map) => Ok(Some(pointer_map.read(self, page_number)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// The pages on the freelist, by trunk page.
+    pub fn freelist_pages(&mut self) -> Result<Freelist> {
+        Freelist::read(self)
+    }
+
+    /// Reads the file change counter as currently stored on disk, which other
+    /// processes bump on every write transaction.
+    pub fn read_change_counter(&mut self) -> Result<u32> {
+        let mut counter = [0; 4];
+        self.storage.read_exact_at(&mut counter, 24)?;
+        Ok(u32::from_be_bytes(counter))
+    }
+
+    /// Reads the raw bytes of the page with the given 1-based page number.
+    pub fn read_raw_page(&mut self, page_number: u32) -> Result<Vec<u8>> {
+        if page_number == 0 {
+            bail!("Invalid page number: 0");
+        }
+        self.check_interrupt()?;
+
+        self.stats.pages_read += 1;
+        self.stats.bytes_allocated += self.header.page_size as u64;
+
+        let data = self.page_source().read_raw_page(page_number)?;
+        self.report_progress();
+        Ok(data)
+    }
+
+    /// Where pages are read from, for reading them from several threads.
+    pub fn page_source(&self) -> PageSource<'_> {
+        PageSource {
+            header: &self.header,
+            storage: &self.storage,
+            wal: self.wal.as_ref(),
+            journal: self.journal.as_deref(),
+            dirty: &self.dirty,
+            #[cfg(feature = "mmap")]
+            mmap: self.mmap.as_deref(),
+            interrupt: &self.interrupt,
+        }
+    }
+
+    /// The page with the given 0-based number, from the page cache unless
+    /// it hasn't been read yet.
+    pub fn get_page(&mut self, number: u32) -> Result<Arc<Page>> {
+        if let Some(page) = self.cached_page(number) {
+            return Ok(page);
+        }
+        let data = self.read_raw_page(number + 1)?;
+        let page = Arc::new(self.page_source().parse_page(number, data)?);
+        self.cache.insert(number + 1, page.clone());
+        Ok(page)
+    }
+
+    fn cached_page(&mut self, number: u32) -> Option<Arc<Page>> {
+        let page = self.cache.get(number + 1);
+        match page {
+            Some(_) => self.stats.cache_hits += 1,
+            None => self.stats.cache_misses += 1,
+        }
+        page
+    }
+
+    /// Reads several pages concurrently on the rayon thread pool, so the
+    /// random reads of index-then-lookup queries overlap. Page numbers are
+    /// 0-based like in `get_page`, and pages already in the cache aren't read
+    /// again.
+    pub fn prefetch_pages(&mut self, numbers: &[u32]) -> Result<Vec<Arc<Page>>> {
+        // There are no reads to overlap in a mapped file.
+        #[cfg(feature = "mmap")]
+        if self.mmap.is_some() {
+            return numbers.iter().map(|&number| self.get_page(number)).collect();
+        }
+        // Pages rolled back or changed are in memory rather than in the file.
+        if self.journal.is_some() || !self.dirty.is_empty() || self.is_in_memory() {
+            return numbers.iter().map(|&number| self.get_page(number)).collect();
+        }
+
+        let cached = numbers
+            .iter()
+            .map(|&number| self.cached_page(number))
+            .collect::<Vec<_>>();
+        let numbers = numbers
+            .iter()
+            .zip(&cached)
+            .filter(|(_, page)| page.is_none())
+            .map(|(&number, _)| number)
+            .collect::<Vec<_>>();
+        self.check_interrupt()?;
+
+        let page_size = self.header.page_size as usize;
+        let source = self.page_source();
+        let pages = numbers
+            .par_iter()
+            .map(|number| {
+                let mut data = vec![0; page_size];
+                source.read_stored_page(number + 1, &mut data)?;
+                Ok(data)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        self.stats.pages_read += numbers.len() as u64;
+        self.stats.bytes_allocated += (numbers.len() * page_size) as u64;
+        self.report_progress();
+
+        let mut read = pages.into_iter().zip(numbers);
+        cached
+            .into_iter()
+            .map(|page| match page {
+                Some(page) => Ok(page),
+                None => {
+                    let (data, number) = read.next().expect("a page read for every one not cached");
+                    let page = Arc::new(self.page_source().parse_page(number, data)?);
+                    self.cache.insert(number + 1, page.clone());
+                    Ok(page)
+                }
+            })
+            .collect()
+    }
+
+    pub fn select_fields(
+        &mut self,
+        sql_statement: &sql::SelectFields,
+        out: &mut impl RowSink,
+    ) -> Result<()> {
+        let schema_definition = self
+            .schema
+            .find_table(&sql_statement.table)
+            .ok_or_else(|| Error::NoSuchTable(sql_statement.table.to_string()))?
+            .clone();
+
+        if schema_definition.is_module("fts5") {
+            return self.select_fts5(&schema_definition, sql_statement, out);
+        }
+        if schema_definition.is_module("rtree") || schema_definition.is_module("rtree_i32") {
+            return self.select_rtree(&schema_definition, sql_statement, out);
+        }
+        if let Some(filter) = &sql_statement.where_clause {
+            if filter.comparisons().iter().any(|c| c.operator == sql::Operator::Match) {
+                bail!("Unable to use MATCH on table: {}", schema_definition.name);
+            }
+        }
+        check_columns(&schema_definition, sql_statement, true)?;
+
+        let plan = Plan::new(&schema_definition, &sql_statement.where_clause);
+        self.stats.access = Some(plan.access());
+        let query = Query::new(&schema_definition, sql_statement);
+        let page = self.get_page(schema_definition.rootpage - 1)?;
+        match plan {
+            Plan::FullScan if self.threads > 1 && page.header.kind == PageKind::InteriorTable => {
+                self.scan_in_parallel(&page, &query, out)
+            }
+            Plan::FullScan => self.read_table(&page, &query, out),
+            Plan::IndexScan { index, bounds } => {
+                let encoding = self.header.text_encoding;
+                let index_query = IndexQuery::new(&schema_definition, sql_statement, index, &bounds, encoding);
+                let index_page = self.get_page(index.rootpage - 1)?;
+
+                let mut results = Vec::with_capacity(self.header.page_size as usize);
+                self.read_index(index.rootpage, &index_page, &index_query, &mut results)?;
+                results.sort_unstable();
+                self.read_ids_from_table(&page, &query, &results, out)
+            }
+            Plan::RowidSeek(rowid) => self.read_ids_from_table(&page, &query, &[rowid], out),
+        }
+    }
+
+    /// Evaluates `COUNT(*)`. Without a WHERE clause the cells of the table
+    /// B-tree are counted, without reading any record; otherwise the rows
+    /// are filtered like a SELECT, through an index when one applies.
+    pub fn select_count(&mut self, sql_statement: &CountSelection) -> Result<u64> {
+        let table = self
+            .schema
+            .find_table(&sql_statement.table)
+            .ok_or_else(|| Error::NoSuchTable(sql_statement.table.to_string()))?
+            .clone();
+
+        if sql_statement.where_clause.is_none() && !table.is_virtual() {
+            self.stats.access = Some(Access::TableScan);
+            let page = self.get_page(table.rootpage - 1)?;
+            return self.count_entries(&page);
+        }
+
+        let select = SelectFields {
+            fields: vec![],
+            table: sql_statement.table.clone(),
+            where_clause: sql_statement.where_clause.clone(),
+            order_by: vec![],
+            limit: None,
+            offset: 0,
+        };
+        let mut counter = RowCounter::default();
+        self.select_fields(&select, &mut counter)?;
+        Ok(counter.0)
+    }
+
+    /// Entries in the B-tree under `page`. Interior index cells hold keys
+    /// too, unlike interior table cells.
+    fn count_entries(&mut self, page: &Page) -> Result<u64> {
+        let cells = page.header.number_of_cells as u64;
+        let mut count = match page.header.kind {
+            PageKind::LeafTable | PageKind::LeafIndex => return Ok(cells),
+            PageKind::InteriorIndex => cells,
+            PageKind::InteriorTable => 0,
+        };
+
+        let mut children = page
+            .cells()
+            .map(|cell| match cell {
+                Cell::InteriorTable { left_child_page, .. }
+                | Cell::InteriorIndex { left_child_page, .. } => Ok(left_child_page),
+                _ => bail!("Unsupported cell type"),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        children.extend(page.header.right_child_page_number);
+
+        for child in children {
+            let page = self.get_page(child - 1)?;
+            count += self.count_entries(&page)?;
+        }
+        Ok(count)
+    }
+
+    /// Evaluates `MIN(col)` or `MAX(col)`. Rowid columns and indexed columns
+    /// are answered from the matching end of their B-tree, walking backwards
+    /// for MAX; any other column needs a full scan.
+    pub fn select_min_max(
+        &mut self,
+        sql_statement: &MinMaxSelection,
+        out: &mut impl RowSink,
+    ) -> Result<()> {
+        let table = self
+            .schema
+            .find_table(&sql_statement.table)
+            .ok_or_else(|| Error::NoSuchTable(sql_statement.table.to_string()))?
+            .clone();
+        if table.is_virtual() {
+            bail!("Unable to use MIN/MAX on virtual table: {}", table.name);
+        }
+
+        let (pos, is_primary_key) = match table.find_column(&sql_statement.field) {
+            Some((pos, column)) => (pos, column.is_primary_key),
+            None if is_rowid_alias(&sql_statement.field) => (0, true),
+            None => return Err(Error::NoSuchColumn(sql_statement.field.to_string())),
+        };
+        let direction = match sql_statement.function {
+            MinMax::Min => Direction::Forward,
+            MinMax::Max => Direction::Backward,
+        };
+
+        if is_primary_key && !table.without_rowid {
+            self.stats.access = Some(Access::Rowid);
+            let mut cursor = BTreeCursor::new(self, table.rootpage, direction)?;
+            match cursor.next().transpose()? {
+                Some(entry) => out.write_row(&[ColumnValue::I64(entry.rowid)])?,
+                None => out.write_row(&[ColumnValue::Null])?,
+            }
+            return Ok(());
+        }
+
+        // An index only has the values in order when it compares them like
+        // the column does.
+        let collation = table.collation(&sql_statement.field);
+        let index = table.indexes.iter().find(|index| {
+            index.columns.first() == Some(&sql_statement.field) && index.collations.first() == Some(&collation)
+        });
+        if let Some(index) = index {
+            self.stats.access = Some(Access::Index(index.name.clone()));
+            // A DESC index has the largest values first.
+            let direction = match (index.descending.first(), direction) {
+                (Some(true), Direction::Forward) => Direction::Backward,
+                (Some(true), Direction::Backward) => Direction::Forward,
+                _ => direction,
+            };
+            // NULLs sort lowest in an index, so MIN skips over them.
+            for entry in BTreeCursor::new(self, index.rootpage, direction)? {
+                let entry = entry?;
+                let record = Record::read(0, &entry.payload);
+                if !record.values[0].is_null() {
+                    out.write_row(&record.values[..1])?;
+                    return Ok(());
+                }
+            }
+            out.write_row(&[ColumnValue::Null])?;
+            return Ok(());
+        }
+
+        self.stats.access = Some(Access::TableScan);
+        let mut best: Option<Vec<u8>> = None;
+        for entry in BTreeCursor::new(self, table.rootpage, Direction::Forward)? {
+            let entry = entry?;
+            let record = Record::read(entry.rowid, &entry.payload);
+            let value = record.values.get(pos).unwrap_or(&ColumnValue::Null);
+            if value.is_null() {
+                continue;
+            }
+
+            let is_better = match &best {
+                None => true,
+                Some(best) => {
+                    let best = Record::read(0, best);
+                    let ordering = value.compare_collated(&best.values[pos], collation);
+                    match sql_statement.function {
+                        MinMax::Min => ordering.is_lt(),
+                        MinMax::Max => ordering.is_gt(),
+                    }
+                }
+            };
+            if is_better {
+                best = Some(entry.payload);
+            }
+        }
+
+        match best {
+            Some(payload) => out.write_row(&Record::read(0, &payload).values[pos..=pos])?,
+            None => out.write_row(&[ColumnValue::Null])?,
+        }
+        Ok(())
+    }
+
+    fn select_fts5(
+        &mut self,
+        table: &Table,
+        sql_statement: &sql::SelectFields,
+        out: &mut impl RowSink,
+    ) -> Result<()> {
+        let arguments = &table.module.as_ref().expect("fts5 table has a module").arguments;
+
+        // Rows live in `%_content` as (id, c0, c1, ...), in an external
+        // content table, or nowhere at all for contentless tables.
+        let content_table = match crate::fts5::option(arguments, "content").as_deref() {
+            Some("") => None,
+            Some(name) => Some(
+                self.schema
+                    .find_table(name)
+                    .ok_or_else(|| Error::NoSuchTable(name.to_string()))?
+                    .clone(),
+            ),
+            None => {
+                let name = format!("{}_content", table.name);
+                let shadow = self
+                    .schema
+                    .tables
+                    .get(&name)
+                    .ok_or(format_err!("Missing fts5 shadow table: {}", name))?;
+
+                let mut columns = vec![Column {
+                    is_primary_key: true,
+                    ..Column::new("rowid".to_string())
+                }];
+                columns.extend(table.columns.iter().cloned());
+                Some(Table {
+                    columns,
+                    ..shadow.clone()
+                })
+            }
+        };
+
+        let filter = sql_statement.where_clause.as_ref();
+        let is_match = |c: &&sql::Comparison| c.operator == sql::Operator::Match;
+        let single_match = filter.and_then(|filter| filter.as_comparison()).filter(is_match);
+        if single_match.is_none() && filter.is_some_and(|f| f.comparisons().iter().any(is_match)) {
+            bail!("Unable to use MATCH inside AND or OR on table: {}", table.name);
+        }
+
+        let matching = match single_match {
+            Some(filter) => {
+                let index = Fts5Index::read(self, table)?;
+                let column = table.find_column(&filter.field).map(|(pos, _)| pos);
+                let query = MatchQuery::parse(&filter.value)?;
+                self.stats.access = Some(Access::Index(table.name.clone()));
+                let rowids = index.search(self, &query, column)?;
+                self.reserve_memory(rowids.len() as u64 * ROWID_SIZE)?;
+                Some(rowids)
+            }
+            None => None,
+        };
+
+        let Some(content_table) = content_table else {
+            let Some(rowids) = matching else {
+                bail!("Contentless fts5 table can only be queried with MATCH");
+            };
+            for rowid in rowids {
+                if out.is_done() {
+                    break;
+                }
+                out.write_row(&[ColumnValue::I64(rowid)])?;
+            }
+            return Ok(());
+        };
+
+        check_columns(&content_table, sql_statement, matching.is_none())?;
+        let query = Query::new(&content_table, sql_statement);
+        let page = self.get_page(content_table.rootpage - 1)?;
+        match matching {
+            Some(rowids) => {
+                let query = Query {
+                    filter: None,
+                    ..query
+                };
+                self.read_ids_from_table(&page, &query, &rowids, out)
+            }
+            None => {
+                self.stats.access = Some(Access::TableScan);
+                self.read_table(&page, &query, out)
+            }
+        }
+    }
+
+    fn select_rtree(
+        &mut self,
+        table: &Table,
+        sql_statement: &sql::SelectFields,
+        out: &mut impl RowSink,
+    ) -> Result<()> {
+        self.stats.access = Some(Access::TableScan);
+        let rtree = RTree::read(self, table)?;
+        check_columns(table, sql_statement, true)?;
+        let query = Query::new(table, sql_statement);
+        let position = |name: &str| table.find_column(name).map_or(0, |(pos, _)| pos);
+
+        // Auxiliary columns are stored in `%_rowid` after (rowid, nodeno).
+        let rowid_table = format!("{}_rowid", table.name);
+        let needs_auxiliary = query
+            .select_fields
+            .iter()
+            .map(|(i, _)| *i)
+            .chain(
+                query
+                    .filter
+                    .iter()
+                    .flat_map(|filter| filter.columns())
+                    .map(|column| position(column)),
+            )
+            .any(|i| i >= rtree.columns.len());
+        let rowid_rootpage = match self.schema.tables.get(&rowid_table) {
+            Some(rowid_table) if needs_auxiliary => Some(rowid_table.rootpage),
+            _ => None,
+        };
+
+        let entries = rtree.entries(self)?;
+        self.reserve_memory(
+            entries
+                .iter()
+                .map(|entry| (size_of::<RTreeEntry>() + size_of_val(&entry.bounds[..])) as u64)
+                .sum(),
+        )?;
+        for entry in entries {
+            if out.is_done() {
+                break;
+            }
+            let auxiliary = match rowid_rootpage {
+                Some(rootpage) => self.find_row_payload(rootpage, entry.id)?,
+                None => None,
+            };
+            let auxiliary = auxiliary.as_ref().map(|payload| Record::read(entry.id, payload));
+
+            let mut values = entry.values(rtree.is_integer);
+            if let Some(auxiliary) = auxiliary {
+                values.extend(auxiliary.values.into_iter().skip(2));
+            }
+            let value = |i: usize| values.get(i).cloned().unwrap_or(ColumnValue::Null);
+
+            self.stats.rows_scanned += 1;
+            if let Some(filter) = query.filter {
+                if !evaluate(filter, &|name| value(position(name)), &|name| table.collation(name)) {
+                    continue;
+                }
+            }
+
+            let row = query
+                .select_fields
+                .iter()
+                .map(|(i, _)| value(*i))
+                .collect::<Vec<_>>();
+            out.write_row(&row)?;
         }
 
-        Ok(Self {
-            page_size: u16::from_be_bytes([header[16], header[17]]),
-        })
+        Ok(())
     }
-}
 
-#[derive(Debug)]
-pub struct Query<'query> {
-    pub table: &'query Table,
-    pub select_fields: Vec<(usize, bool)>,
-    pub filter: Option<&'query sql::WhereClause>,
-}
+    /// Full payload of a cell, following its overflow chain if it has one.
+    /// Text in it is UTF-8 whatever the encoding of the database.
+    pub fn read_payload(&mut self, cell: &Cell) -> Result<Vec<u8>> {
+        Ok(self.cell_payload(cell)?.into_owned())
+    }
 
-impl<'query> Query<'query> {
-    pub fn new(table: &'query Table, sql_statement: &'query SelectFields) -> Self {
-        let select_fields = sql_statement
-            .fields
-            .iter()
-            .map(|sql_field| table.find_column(sql_field).expect("Fields not found"))
-            .map(|(pos, field)| (pos, field.is_primary_key))
-            .collect::<Vec<_>>();
-        Self {
-            table,
-            select_fields,
-            filter: sql_statement.where_clause.as_ref(),
-        }
+    /// Full payload of a cell as stored, its text in the encoding of the
+    /// database.
+    pub(crate) fn raw_payload(&mut self, cell: &Cell) -> Result<Vec<u8>> {
+        let header = DatabaseHeader {
+            text_encoding: Encoding::Utf8,
+            ..self.header.clone()
+        };
+        Ok(cell_payload(cell, &header, |page_number| self.read_raw_page(page_number))?.into_owned())
     }
-}
 
-#[derive(Debug)]
-pub struct IndexQuery<'query> {
-    pub table: &'query Table,
-    pub select_fields: Vec<(usize, bool)>,
-    pub filter: &'query sql::WhereClause,
-    pub index: &'query Index,
-    pub index_field: usize,
-}
+    /// Payload of a cell, borrowed from the page unless part of it spilled
+    /// onto overflow pages or its text had to be converted to UTF-8.
+    fn cell_payload<'page>(&mut self, cell: &Cell<'page>) -> Result<Cow<'page, [u8]>> {
+        let header = self.header.clone();
+        cell_payload(cell, &header, |page_number| self.read_raw_page(page_number))
+    }
 
-impl<'query> IndexQuery<'query> {
-    pub fn new(
-        table: &'query Table,
-        sql_statement: &'query SelectFields,
-        index: &'query Index,
-    ) -> Self {
-        let select_fields = sql_statement
-            .fields
+    /// The largest rowid handed out so far to the AUTOINCREMENT table
+    /// `table_name`, as kept in `sqlite_sequence`. None when the table has
+    /// no such entry, as before its first row.
+    pub fn sequence(&mut self, table_name: &str) -> Result<Option<i64>> {
+        let Some(rootpage) = self
+            .schema
+            .tables
+            .get("sqlite_sequence")
+            .map(|table| table.rootpage)
+        else {
+            return Ok(None);
+        };
+
+        for entry in BTreeCursor::new(self, rootpage, Direction::Forward)? {
+            let entry = entry?;
+            let record = Record::read(entry.rowid, &entry.payload);
+            if let [ColumnValue::Text(name), sequence, ..] = record.values.as_slice() {
+                if *name == table_name.as_bytes() {
+                    return Ok(sequence.as_integer());
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Deletes the rows with `rowids` from `table_name`, and their entries
+    /// from its indexes, returning how many of them it had. The deletions
+    /// stay pending until `commit`.
+    pub(crate) fn delete_rows(&mut self, table_name: &str, rowids: &[i64]) -> Result<u64> {
+        let Some(table) = self.schema.find_table(table_name) else {
+            return Err(Error::NoSuchTable(table_name.to_string()));
+        };
+        if table.without_rowid || table.is_virtual() {
+            bail!("Unable to delete from table: {}", table.name);
+        }
+        if self
+            .schema
+            .triggers()
             .iter()
-            .map(|sql_field| table.find_column(sql_field).expect("Fields not found"))
-            .map(|(pos, field)| (pos, field.is_primary_key))
+            .any(|trigger| trigger.table.eq_ignore_ascii_case(&table.name) && trigger.event == sql::TriggerEvent::Delete)
+        {
+            bail!("Unable to delete from a table with DELETE triggers: {}", table.name);
+        }
+        let rootpage = table.rootpage;
+        let table_name = table.name.clone();
+        // Indexes made for constraints are only in the schema table, which
+        // may spell the name of the table differently.
+        let indexes = SQLiteSchema::read(self)?
+            .rows
+            .into_iter()
+            .filter(|row| row.kind == "index" && row.tbl_name.eq_ignore_ascii_case(&table_name))
+            .map(|row| row.rootpage)
             .collect::<Vec<_>>();
 
-        let index_field = index
-            .find_column(&sql_statement.where_clause.as_ref().unwrap().field)
-            .unwrap()
-            .0;
-        Self {
-            table,
-            select_fields,
-            filter: &sql_statement.where_clause.as_ref().unwrap(),
-            index,
-            index_field,
+        let mut deleted = HashSet::new();
+        for &rowid in rowids {
+            if btree::delete_row(self, rootpage, rowid)? {
+                deleted.insert(rowid);
+            }
+        }
+        if !deleted.is_empty() {
+            for rootpage in indexes {
+                btree::delete_index_entries(self, rootpage, &deleted)?;
+            }
         }
+        Ok(deleted.len() as u64)
     }
-}
-
-#[derive(Debug)]
-pub struct Database {
-    pub header: DatabaseHeader,
-    pub file: File,
-    pub schema: SchemaStore,
-}
 
-impl Database {
-    pub fn open(path: &str) -> Result<Self> {
-        let mut file = File::open(path)?;
-        let header = DatabaseHeader::read(&mut file)?;
+    /// Creates the table `create` defines with the statement `sql`: a root
+    /// page for it and each of its automatic indexes, and their rows in the
+    /// schema table. The changes stay pending until `commit`.
+    pub(crate) fn create_table(&mut self, create: &sql::CreateTableStatement, sql: &str) -> Result<()> {
+        let name = &create.table;
+        let schema = SQLiteSchema::read(self)?;
+        if !check_new_name(&schema, "table", name, create.if_not_exists)? {
+            return Ok(());
+        }
 
-        let page = Page::read_with_offset(&mut file, header.page_size - 100, 100)?;
-        let schema = SchemaStore::read(page)?;
+        let kind = match create.without_rowid {
+            true => PageKind::LeafIndex,
+            false => PageKind::LeafTable,
+        };
+        let rootpage = self.create_btree(kind)?;
+        self.insert_schema_row("table", name, name, rootpage, Some(&sql::table_definition(sql)))?;
+        for (number, columns) in create.autoindexes.iter().enumerate() {
+            if create.without_rowid && *columns == create.primary_key {
+                continue;
+            }
+            let rootpage = self.create_btree(PageKind::LeafIndex)?;
+            let index = format!("sqlite_autoindex_{}_{}", name, number + 1);
+            self.insert_schema_row("index", &index, name, rootpage, None)?;
+        }
+        let autoincrement = create.fields.iter().any(|field| field.autoincrement);
+        if autoincrement && !schema.rows.iter().any(|row| row.name == "sqlite_sequence") {
+            let rootpage = self.create_btree(PageKind::LeafTable)?;
+            let sql = "CREATE TABLE sqlite_sequence(name,seq)";
+            self.insert_schema_row("table", "sqlite_sequence", "sqlite_sequence", rootpage, Some(sql))?;
+        }
 
-        Ok(Self {
-            header,
-            file,
-            schema,
-        })
+        self.header.schema_cookie = self.header.schema_cookie.wrapping_add(1);
+        Ok(())
     }
 
-    pub fn get_page(&mut self, number: u32) -> Result<Page> {
-        self.file.seek(SeekFrom::Start(
-            number as u64 * self.header.page_size as u64,
-        ))?;
+    /// Creates the index `create` defines with the statement `sql`: a root
+    /// page, filled with an entry for every row of its table in key order,
+    /// and its row in the schema table. The changes stay pending until
+    /// `commit`.
+    pub(crate) fn create_index(&mut self, create: &sql::CreateIndexStatement, sql: &str) -> Result<()> {
+        let name = &create.name;
+        if !check_new_name(&SQLiteSchema::read(self)?, "index", name, create.if_not_exists)? {
+            return Ok(());
+        }
+        let Some(table) = self.schema.find_table(&create.table).cloned() else {
+            return Err(Error::NoSuchTable(create.table.to_string()));
+        };
+        if table.without_rowid || table.is_virtual() {
+            bail!("Unable to index table: {}", table.name);
+        }
+        // Queries read every index in ascending order.
+        if create.descending.contains(&true) {
+            bail!("Unable to create an index with DESC columns: {}", name);
+        }
+        let mut collations = vec![];
+        for (field, collation) in create.fields.iter().zip(&create.collations) {
+            if table.find_column(field).is_none() {
+                return Err(Error::NoSuchColumn(field.to_string()));
+            }
+            collations.push(collation.unwrap_or(table.collation(field)));
+        }
+
+        let encoding = self.header.text_encoding;
+        let mut entries = vec![];
+        for entry in BTreeCursor::new(self, table.rootpage, Direction::Forward)? {
+            let entry = entry?;
+            let record = Record::read(entry.rowid, &entry.payload);
+            let mut values = create
+                .fields
+                .iter()
+                .map(|field| column_value(&table, &record, field))
+                .collect::<Vec<_>>();
+            values.push(ColumnValue::I64(entry.rowid));
+            entries.push(encode_record(&values, encoding));
+        }
+        let keys = entries.iter().map(|entry| Record::read(0, entry)).collect::<Vec<_>>();
+        let mut order = (0..entries.len()).collect::<Vec<_>>();
+        order.sort_by(|&a, &b| compare_index_keys(&keys[a], &keys[b], &collations, encoding));
+        let entries = order.into_iter().map(|i| entries[i].clone()).collect::<Vec<_>>();
 
-        Page::read(&mut self.file, self.header.page_size)
+        let rootpage = self.allocate_page()?;
+        btree::build_index(self, rootpage, &entries)?;
+        self.insert_schema_row("index", name, &table.name, rootpage, Some(&sql::index_definition(sql)))?;
+        self.header.schema_cookie = self.header.schema_cookie.wrapping_add(1);
+        Ok(())
     }
 
-    pub fn select_fields(
-        &mut self,
-        sql_statement: &sql::SelectFields,
-        out: &mut impl std::io::Write,
-    ) -> Result<()> {
-        let schema_definition = self
-            .schema
-            .find_table(&sql_statement.table)
-            .ok_or(anyhow::anyhow!("Table not found: {}", &sql_statement.table))?
-            .clone();
+    /// Drops the table `drop` names: frees the pages of its B-tree and of
+    /// its indexes, and deletes their rows from the schema table along with
+    /// those of its triggers, and its row in sqlite_sequence. The changes
+    /// stay pending until `commit`.
+    pub(crate) fn drop_table(&mut self, drop: &sql::DropTableStatement) -> Result<()> {
+        let schema = SQLiteSchema::read(self)?;
+        let Some(table) = schema
+            .rows
+            .iter()
+            .find(|row| row.kind == "table" && row.name.eq_ignore_ascii_case(&drop.table))
+        else {
+            if drop.if_exists {
+                return Ok(());
+            }
+            return Err(Error::NoSuchTable(drop.table.to_string()));
+        };
+        // Statistics from ANALYZE are the only internal tables users drop.
+        if is_reserved_name(&table.name) && !table.name[7..].to_ascii_lowercase().starts_with("stat") {
+            bail!("Table may not be dropped: {}", table.name);
+        }
+        if table.rootpage == 0 {
+            bail!("Unable to drop virtual table: {}", table.name);
+        }
 
-        if let Some(index) = schema_definition.find_applicable_index(&sql_statement.where_clause) {
-            let query = IndexQuery::new(&schema_definition, sql_statement, index);
-            let page = self.get_page(index.rootpage - 1)?;
+        for row in schema.rows.iter().filter(|row| row.tbl_name.eq_ignore_ascii_case(&table.name)) {
+            if row.rootpage != 0 {
+                btree::free_btree(self, row.rootpage)?;
+            }
+            btree::delete_row(self, 1, row.rowid)?;
+        }
+        if let Some(sequence) = schema.rows.iter().find(|row| row.name == "sqlite_sequence") {
+            let mut rowids = vec![];
+            for entry in BTreeCursor::new(self, sequence.rootpage, Direction::Forward)? {
+                let entry = entry?;
+                let record = Record::read(entry.rowid, &entry.payload);
+                if let Some(ColumnValue::Text(name)) = record.values.first() {
+                    if *name == table.name.as_bytes() {
+                        rowids.push(entry.rowid);
+                    }
+                }
+            }
+            for rowid in rowids {
+                btree::delete_row(self, sequence.rootpage, rowid)?;
+            }
+        }
 
-            let mut results = Vec::with_capacity(self.header.page_size as usize);
-            self.read_index(&page, &query, &mut results)?;
-            results.sort_unstable();
+        self.header.schema_cookie = self.header.schema_cookie.wrapping_add(1);
+        Ok(())
+    }
 
-            let query = Query::new(&schema_definition, sql_statement);
-            let page = self.get_page(schema_definition.rootpage - 1)?;
-            self.read_ids_from_table(&page, &query, &results, out)?;
+    /// Adds the column `alter` defines to the end of its table. Only the
+    /// definition in the schema table changes: rows already stored end
+    /// before the column, and read as its DEFAULT. The changes stay pending
+    /// until `commit`.
+    pub(crate) fn alter_table(&mut self, alter: &sql::AlterTableStatement) -> Result<()> {
+        let schema = SQLiteSchema::read(self)?;
+        let Some(row) = schema
+            .rows
+            .iter()
+            .find(|row| row.kind == "table" && row.name.eq_ignore_ascii_case(&alter.table))
+        else {
+            return Err(Error::NoSuchTable(alter.table.to_string()));
+        };
+        if is_reserved_name(&row.name) {
+            bail!("Table may not be altered: {}", row.name);
+        }
+        let Some(table) = self.schema.tables.get(&row.name) else {
+            return Err(Error::NoSuchTable(alter.table.to_string()));
+        };
+        if table.is_virtual() {
+            bail!("Unable to alter virtual table: {}", table.name);
+        }
 
-            return Ok(());
+        let column = &alter.column;
+        if table.columns.iter().any(|other| other.name.eq_ignore_ascii_case(&column.name)) {
+            bail!("Duplicate column name: {}", column.name);
+        }
+        if column.in_primary_key {
+            bail!("Cannot add a PRIMARY KEY column");
+        }
+        if column.unique {
+            bail!("Cannot add a UNIQUE column");
+        }
+        if column.generated.as_ref().is_some_and(|generated| generated.stored) {
+            bail!("Cannot add a STORED column");
+        }
+        // Rows already stored read the default, so it can't depend on them
+        // or on when they are read.
+        let default = column.default_value.as_ref();
+        if default.is_some_and(|default| !default.columns().is_empty()) {
+            bail!("Cannot add a column with non-constant default");
+        }
+        let null_default = default.is_none_or(|default| matches!(default, Expr::Literal(Literal::Null)));
+        if !column.nullable && null_default && column.generated.is_none() {
+            bail!("Cannot add a NOT NULL column with default value NULL");
         }
 
-        let query = Query::new(&schema_definition, sql_statement);
-        let page = self.get_page(schema_definition.rootpage - 1)?;
-        self.read_table(&page, &query, out)
+        let row = SQLiteSchemaRow {
+            sql: sql::add_column_definition(&row.sql, &alter.definition),
+            ..row.clone()
+        };
+        btree::delete_row(self, 1, row.rowid)?;
+        self.write_schema_row(&row)?;
+        self.header.schema_cookie = self.header.schema_cookie.wrapping_add(1);
+        Ok(())
+    }
+
+    /// A new, empty B-tree of `kind`, by its root page.
+    fn create_btree(&mut self, kind: PageKind) -> Result<u32> {
+        let page_number = self.allocate_page()?;
+        let page_size = self.header.page_size as usize;
+        let page = Page::empty(kind, page_size, 0, self.header.usable_size())?;
+        self.write_page(page_number - 1, &page)?;
+        Ok(page_number)
+    }
+
+    /// Adds a row to the schema table after the last one.
+    fn insert_schema_row(&mut self, kind: &str, name: &str, tbl_name: &str, rootpage: u32, sql: Option<&str>) -> Result<()> {
+        let row = SQLiteSchemaRow {
+            rowid: btree::last_rowid(self, 1)? + 1,
+            kind: kind.to_string(),
+            name: name.to_string(),
+            tbl_name: tbl_name.to_string(),
+            rootpage,
+            sql: sql.unwrap_or_default().to_string(),
+        };
+        self.write_schema_row(&row)
+    }
+
+    /// Inserts `row` into the schema table, its text in the encoding of the
+    /// database. An empty `sql` is stored as NULL, like for automatic
+    /// indexes.
+    fn write_schema_row(&mut self, row: &SQLiteSchemaRow) -> Result<()> {
+        let encoding = self.header.text_encoding;
+        let [kind, name, tbl_name, sql] =
+            [&row.kind, &row.name, &row.tbl_name, &row.sql].map(|text| encoding.encode(text).into_owned());
+        let payload = record::encode(&[
+            ColumnValue::Text(&kind),
+            ColumnValue::Text(&name),
+            ColumnValue::Text(&tbl_name),
+            ColumnValue::I64(row.rootpage as i64),
+            match sql.is_empty() {
+                true => ColumnValue::Null,
+                false => ColumnValue::Text(&sql),
+            },
+        ]);
+        btree::insert_row(self, 1, row.rowid, &payload)
+    }
+
+    /// Payload of the row with `rowid` in the table B-tree rooted at `rootpage`.
+    pub fn find_row_payload(&mut self, rootpage: u32, rowid: i64) -> Result<Option<Vec<u8>>> {
+        let page = self.find_leaf(rootpage, rowid)?;
+        let cell = page.cells().find(|cell| {
+            matches!(cell, Cell::LeafTable { rowid: cell_rowid, .. } if *cell_rowid == rowid)
+        });
+        cell.map(|cell| self.read_payload(&cell)).transpose()
+    }
+
+    /// The leaf page of the table B-tree rooted at `rootpage` where the row
+    /// with `rowid` is, if the table has it.
+    pub(crate) fn find_leaf(&mut self, rootpage: u32, rowid: i64) -> Result<Arc<Page>> {
+        let mut page_number = rootpage;
+        let mut page = self.get_page(page_number - 1)?;
+        loop {
+            match page.header.kind {
+                PageKind::InteriorTable => {
+                    let child = page
+                        .cells()
+                        .find_map(|cell| match cell {
+                            Cell::InteriorTable {
+                                left_child_page,
+                                key,
+                            } if rowid <= key as i64 => Some(left_child_page),
+                            _ => None,
+                        })
+                        .or(page.header.right_child_page_number);
+
+                    let Some(child) = child else {
+                        return Err(Error::corrupt_page(
+                            page_number,
+                            page.cell_offset(page.cell_pointers.len()),
+                            "Malformed table: interior page without right child",
+                        ));
+                    };
+                    page_number = child;
+                    page = self.get_page(page_number - 1)?;
+                }
+                PageKind::LeafTable => return Ok(page),
+                PageKind::InteriorIndex | PageKind::LeafIndex => {
+                    return Err(Error::corrupt_page(
+                        page_number,
+                        page.offset as usize,
+                        "Malformed table: table contains index pages",
+                    ));
+                }
+            }
+        }
     }
 
     fn read_index(
         &mut self,
+        page_number: u32,
         page: &Page,
         query: &IndexQuery,
         results: &mut Vec<i64>,
     ) -> Result<()> {
         match page.header.kind {
             crate::page::PageKind::InteriorIndex => {
-                self.read_interior_index(&page, &query, results)
+                self.read_interior_index(page_number, &page, &query, results)
             }
-            crate::page::PageKind::LeafIndex => self.read_leaf_index(&page, &query, results),
+            crate::page::PageKind::LeafIndex => self.read_leaf_index(page_number, &page, &query, results),
             crate::page::PageKind::InteriorTable | crate::page::PageKind::LeafTable => {
-                bail!("Malformed index: index contains table pages")
+                Err(Error::corrupt_page(
+                    page_number,
+                    page.offset as usize,
+                    "Malformed index: index contains table pages",
+                ))
             }
         }
     }
 
     fn read_interior_index(
         &mut self,
+        page_number: u32,
         page: &Page,
         query: &IndexQuery,
         results: &mut Vec<i64>,
     ) -> Result<()> {
-        for cell in page.cells() {
-            let Cell::InteriorIndex { left_child_page, payload, .. } = cell else {
-                bail!("Unsupported cell type");
-            };
-            let record = Record::read(0, payload);
-
-            let ColumnValue::Text(value) = record.values[query.index_field]  else {
-                let page = self.get_page(left_child_page - 1)?;
-                self.read_index(&page, query, results)?;
-                continue;
-            };
+        let cells = page
+            .cells()
+            .enumerate()
+            .map(|(index, cell)| match cell {
+                Cell::InteriorIndex {
+                    left_child_page, ..
+                } => Ok((left_child_page, self.cell_payload(&cell)?)),
+                _ => Err(Error::corrupt_page(page_number, page.cell_offset(index), "Unsupported cell type")),
+            })
+            .collect::<Result<Vec<_>>>()?;
 
-            if query.filter.value.as_bytes() == value {
-                let id = record.values.last().expect("index must have id value");
-                if id.is_number() {
-                    let id: i64 = id.clone().into();
-                    results.push(id);
-                } else {
-                    return Err(anyhow::anyhow!("Id was not a number"));
-                }
-            }
+        // Cells are sorted by key, so binary search for the first one that is
+        // not below the value looked for.
+        let start = cells.partition_point(|(_, payload)| query.compare_key(payload).is_lt());
 
+        // Matching keys may sit in the left child of every cell up to and
+        // including the first one above the value.
+        for (left_child_page, payload) in cells[start..].iter() {
             let page = self.get_page(left_child_page - 1)?;
-            self.read_index(&page, query, results)?;
+            self.read_index(*left_child_page, &page, query, results)?;
+
+            if query.compare_key(payload).is_gt() {
+                return Ok(());
+            }
+            self.reserve_memory(ROWID_SIZE)?;
+            results.push(index_rowid(payload)?);
         }
 
         if let Some(number) = page.header.right_child_page_number {
             let page = self.get_page(number - 1)?;
-            self.read_index(&page, query, results)?;
+            self.read_index(number, &page, query, results)?;
         }
         Ok(())
     }
 
     fn read_leaf_index(
         &mut self,
+        page_number: u32,
         page: &Page,
         query: &IndexQuery,
         results: &mut Vec<i64>,
     ) -> Result<()> {
-        let ids = page
+        let payloads = page
             .cells()
-            .map(|cell| match cell {
-                Cell::LeafIndex { payload, .. } => Ok(Record::read(0, payload)),
-                _ => bail!("Unsupported cell type"),
-            })
-            .filter(|record| {
-                let Ok(record) = record else { return true; };
-                format!("{}", record.values[query.index_field]) == query.filter.value
+            .enumerate()
+            .map(|(index, cell)| match cell {
+                Cell::LeafIndex { .. } => self.cell_payload(&cell),
+                _ => Err(Error::corrupt_page(page_number, page.cell_offset(index), "Unsupported cell type")),
             })
-            .map_ok(|record| {
-                let id = record.values.last().expect("index must have id value");
-                if id.is_number() {
-                    let id: i64 = id.clone().into();
-                    Ok(id)
-                } else {
-                    Err(anyhow::anyhow!("Id was not a number"))
-                }
-            });
-
-        for id in ids {
-            let id = id??;
-            results.push(id);
+            .collect::<Result<Vec<_>>>()?;
+        // The matching keys are next to each other, after the ones below.
+        let start = payloads.partition_point(|payload| query.compare_key(payload).is_lt());
+        let matching = payloads[start..]
+            .iter()
+            .take_while(|payload| query.compare_key(payload).is_eq());
+        for payload in matching {
+            self.reserve_memory(ROWID_SIZE)?;
+            results.push(index_rowid(payload)?);
         }
 
         Ok(())
@@ -245,7 +3092,7 @@ impl Database {
         page: &Page,
         query: &Query,
         ids: &[i64],
-        out: &mut impl std::io::Write,
+        out: &mut impl RowSink,
     ) -> Result<()> {
         match page.header.kind {
             crate::page::PageKind::InteriorTable => {
@@ -264,77 +3111,157 @@ impl Database {
         page: &Page,
         query: &Query,
         ids: &[i64],
-        out: &mut impl std::io::Write,
+        out: &mut impl RowSink,
     ) -> Result<()> {
         let mut ids = ids;
+        let mut children = vec![];
         for cell in page.cells() {
             let Cell::InteriorTable { left_child_page, key } = cell else {
                 bail!("Unsupported cell type");
             };
 
-            let split_at = ids.split_at(ids.partition_point(|id| *id < key as i64));
-            let left_ids = split_at.0; 
+            let split_at = ids.split_at(ids.partition_point(|id| *id <= key as i64));
+            let left_ids = split_at.0;
             ids = split_at.1;
 
             if !left_ids.is_empty() {
-                let page = self.get_page(left_child_page - 1)?;
-                self.read_ids_from_table(&page, query, left_ids, out)?;
+                children.push((left_child_page, left_ids));
             }
         }
 
-        if ids.len() == 0 {
-            return Ok(());
+        if !ids.is_empty() {
+            if let Some(number) = page.header.right_child_page_number {
+                children.push((number, ids));
+            }
         }
 
-        if let Some(number) = page.header.right_child_page_number {
-            let page = self.get_page(number - 1)?;
-            self.read_ids_from_table(&page, query, ids, out)?;
+        // Every child holding a wanted rowid is known up front, so read them
+        // together before decoding any of them.
+        for batch in children.chunks(PREFETCH_BATCH_SIZE) {
+            if out.is_done() {
+                break;
+            }
+            let numbers = batch.iter().map(|(child, _)| child - 1).collect::<Vec<_>>();
+            let pages = self.prefetch_pages(&numbers)?;
+            for (page, (_, ids)) in pages.iter().zip(batch) {
+                self.read_ids_from_table(page, query, ids, out)?;
+            }
         }
         Ok(())
     }
 
     fn read_ids_from_leaf_table(
-        &self,
+        &mut self,
         page: &Page,
         query: &Query,
         ids: &[i64],
-        out: &mut impl std::io::Write,
+        out: &mut impl RowSink,
     ) -> Result<()> {
-        let records = page
-            .cells()
-            .map(|cell| match cell {
-                Cell::LeafTable { payload, rowid, .. } => Ok(Record::read(rowid, payload)),
-                _ => bail!("Unsupported cell type"),
-            })
-            .filter(|record| {
-                let Ok(record) = record else { return true; };
-                ids.binary_search(&record.rowid).is_ok()
-            })
-            .collect::<Result<Vec<Record>>>()?;
+        self.stats.rows_scanned += page.header.number_of_cells as u64;
+        let mut payloads = vec![];
+        for cell in page.cells() {
+            let Cell::LeafTable { rowid, .. } = cell else {
+                bail!("Unsupported cell type");
+            };
+            if ids.binary_search(&rowid).is_ok() {
+                payloads.push((rowid, self.cell_payload(&cell)?));
+            }
+        }
+        let records = payloads
+            .iter()
+            .map(|(rowid, payload)| Record::read(*rowid, payload))
+            // The index narrows down the rows, the rest of the clause decides.
+            .filter(|record| query.matches(record))
+            .collect::<Vec<_>>();
 
         for record in records {
-            let values = query
-                .select_fields
-                .iter()
-                .map(|(i, is_primary_key)| {
-                    if *is_primary_key {
-                        format!("{}", record.rowid)
-                    } else {
-                        format!("{}", record.values[*i])
+            if out.is_done() {
+                break;
+            }
+            out.write_row(&query.values(&record))?;
+        }
+        Ok(())
+    }
+
+    /// Reads a table like `read_table`, with the subtrees under `root` read
+    /// side by side on a pool of threads, as many at a time as there are
+    /// threads. Their rows are passed on in order once they are all read.
+    fn scan_in_parallel(&mut self, root: &Page, query: &Query, out: &mut impl RowSink) -> Result<()> {
+        let subtrees = self.subtrees(query.table.rootpage, root, self.threads * SUBTREES_PER_THREAD)?;
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(self.threads)
+            .build()
+            .map_err(|error| Error::Other(error.to_string()))?;
+        for batch in subtrees.chunks(self.threads) {
+            if out.is_done() {
+                break;
+            }
+            let source = self.page_source();
+            let scans = pool.install(|| {
+                batch
+                    .par_iter()
+                    .map(|&page_number| {
+                        let mut scan = PartialScan::default();
+                        scan.read(source, page_number, query)?;
+                        Ok(scan)
+                    })
+                    .collect::<Vec<Result<PartialScan>>>()
+            });
+            self.check_interrupt()?;
+
+            for scan in scans {
+                let scan = scan?;
+                self.stats.pages_read += scan.pages_read;
+                self.stats.bytes_allocated += scan.pages_read * self.header.page_size as u64;
+                self.stats.rows_scanned += scan.rows_scanned;
+                if query.select_fields.is_empty() {
+                    for _ in 0..scan.count {
+                        if out.is_done() {
+                            break;
+                        }
+                        out.write_row(&[])?;
                     }
-                })
-                .collect::<Vec<_>>()
-                .join("|");
-            write!(out, "{}\n", values)?;
+                    continue;
+                }
+                for row in &scan.rows {
+                    if out.is_done() {
+                        break;
+                    }
+                    out.write_row(&Record::read(0, row).values)?;
+                }
+            }
+            self.report_progress();
         }
         Ok(())
     }
 
+    /// Pages under the interior page `root` whose subtrees split the table
+    /// into at least `count` parts, or into its leaves when there are fewer.
+    fn subtrees(&mut self, rootpage: u32, root: &Page, count: usize) -> Result<Vec<u32>> {
+        let mut subtrees = child_pages(rootpage, root)?;
+        while subtrees.len() < count {
+            let pages = subtrees
+                .iter()
+                .map(|page_number| self.get_page(page_number - 1))
+                .collect::<Result<Vec<_>>>()?;
+            if pages.iter().any(|page| page.header.kind != PageKind::InteriorTable) {
+                break;
+            }
+            subtrees = pages
+                .iter()
+                .zip(&subtrees)
+                .map(|(page, &page_number)| child_pages(page_number, page))
+                .collect::<Result<Vec<_>>>()?
+                .concat();
+        }
+        Ok(subtrees)
+    }
+
     fn read_table(
         &mut self,
         page: &Page,
         query: &Query,
-        out: &mut impl std::io::Write,
+        out: &mut impl RowSink,
     ) -> Result<()> {
         match page.header.kind {
             crate::page::PageKind::InteriorTable => self.read_interior_table(&page, &query, out),
@@ -349,17 +3276,23 @@ impl Database {
         &mut self,
         page: &Page,
         query: &Query,
-        out: &mut impl std::io::Write,
+        out: &mut impl RowSink,
     ) -> Result<()> {
         for cell in page.cells() {
             let Cell::InteriorTable { left_child_page, .. } = cell else {
                 bail!("Unsupported cell type");
             };
 
+            if out.is_done() {
+                return Ok(());
+            }
             let page = self.get_page(left_child_page - 1)?;
             self.read_table(&page, query, out)?;
         }
 
+        if out.is_done() {
+            return Ok(());
+        }
         if let Some(number) = page.header.right_child_page_number {
             let page = self.get_page(number - 1)?;
             self.read_table(&page, query, out)?;
@@ -368,47 +3301,286 @@ impl Database {
     }
 
     fn read_leaf_table(
-        &self,
+        &mut self,
         page: &Page,
         query: &Query,
-        out: &mut impl std::io::Write,
+        out: &mut impl RowSink,
     ) -> Result<()> {
-        let records = page
+        self.stats.rows_scanned += page.header.number_of_cells as u64;
+        let payloads = page
             .cells()
             .map(|cell| match cell {
-                Cell::LeafTable { payload, rowid, .. } => Ok(Record::read(rowid, payload)),
+                Cell::LeafTable { rowid, .. } => Ok((rowid, self.cell_payload(&cell)?)),
                 _ => bail!("Unsupported cell type"),
             })
-            .filter(|record| {
-                let Ok(record) = record else { return true; };
-                let Some(ref filter) = query.filter else {
-                                return true;
-                            };
-
-                let (pos, _field) = query
-                    .table
-                    .find_column(&filter.field)
-                    .expect("Field not found");
-
-                format!("{}", record.values[pos]) == filter.value
-            })
-            .collect::<Result<Vec<Record>>>()?;
+            .collect::<Result<Vec<_>>>()?;
+        let records = payloads
+            .iter()
+            .map(|(rowid, payload)| Record::read(*rowid, payload))
+            .filter(|record| query.matches(record))
+            .collect::<Vec<_>>();
 
         for record in records {
-            let values = query
-                .select_fields
-                .iter()
-                .map(|(i, is_primary_key)| {
-                    if *is_primary_key {
-                        format!("{}", record.rowid)
-                    } else {
-                        format!("{}", record.values[*i])
-                    }
-                })
-                .collect::<Vec<_>>()
-                .join("|");
-            write!(out, "{}\n", values)?;
+            if out.is_done() {
+                break;
+            }
+            out.write_row(&query.values(&record))?;
         }
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::record::OwnedValue;
+
+    #[test]
+    fn parse_header() {
+        let mut data = [0; 100];
+        data[..16].copy_from_slice(&MAGIC_HEADER);
+        data[16..18].copy_from_slice(&1u16.to_be_bytes());
+        data[18] = 2;
+        data[19] = 2;
+        data[40..44].copy_from_slice(&7u32.to_be_bytes());
+        data[56..60].copy_from_slice(&2u32.to_be_bytes());
+        data[60..64].copy_from_slice(&42u32.to_be_bytes());
+        data[68..72].copy_from_slice(&0x0f055112u32.to_be_bytes());
+
+        let header = DatabaseHeader::parse(&data).unwrap();
+        assert_eq!(header.page_size, 65536);
+        assert!(header.is_wal());
+        assert_eq!(header.schema_cookie, 7);
+        assert_eq!(header.text_encoding, Encoding::Utf16le);
+        assert_eq!(header.user_version, 42);
+        assert_eq!(header.application_id, 0x0f055112);
+
+        data[0] = b'X';
+        assert!(DatabaseHeader::parse(&data).is_err());
+    }
+
+    #[test]
+    fn split_interior_page_into_children() {
+        let mut data = vec![0; 512];
+        data[0] = 0x05;
+        data[3..5].copy_from_slice(&2u16.to_be_bytes());
+        data[5..7].copy_from_slice(&490u16.to_be_bytes());
+        data[8..12].copy_from_slice(&9u32.to_be_bytes());
+        data[12..14].copy_from_slice(&500u16.to_be_bytes());
+        data[14..16].copy_from_slice(&490u16.to_be_bytes());
+        data[500..505].copy_from_slice(&[0, 0, 0, 4, 10]);
+        data[490..495].copy_from_slice(&[0, 0, 0, 7, 20]);
+
+        let page = Page::parse(data, 0, 512).unwrap();
+        assert_eq!(child_pages(2, &page).unwrap(), vec![4, 7, 9]);
+    }
+
+    /// Copied by sqlite3 while open after `CREATE TABLE notes (body TEXT)`,
+    /// `INSERT INTO notes VALUES ('checkpointed')`, a checkpoint, and
+    /// `INSERT INTO notes VALUES ('only in the wal')`.
+    const WAL_DB: &[u8] = include_bytes!("../testdata/wal.db");
+    const WAL_DB_WAL: &[u8] = include_bytes!("../testdata/wal.db-wal");
+
+    #[test]
+    fn read_pages_committed_to_the_wal() {
+        let path = std::env::temp_dir().join(format!("wal-{}.db", std::process::id()));
+        let path = path.to_str().unwrap();
+        let wal_path = format!("{}-wal", path);
+        std::fs::write(path, WAL_DB).unwrap();
+        std::fs::write(&wal_path, WAL_DB_WAL).unwrap();
+        let mut database = Database::open(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_file(&wal_path).unwrap();
+
+        let rootpage = database.schema.find_table("notes").unwrap().rootpage;
+        assert!(database.wal.as_ref().unwrap().find_frame(rootpage).is_some());
+        let mut statement = crate::statement::Statement::prepare("SELECT body FROM notes").unwrap();
+        let bodies = statement
+            .query(&mut database)
+            .unwrap()
+            .map(|row| row.unwrap().into_values())
+            .collect::<Vec<_>>();
+        let text = |body: &str| vec![OwnedValue::Text(body.to_string())];
+        assert_eq!(bodies, [text("checkpointed"), text("only in the wal")]);
+    }
+
+    #[test]
+    fn snapshot_misses_later_commits() {
+        let path = std::env::temp_dir().join(format!("wal-snapshot-{}.db", std::process::id()));
+        let path = path.to_str().unwrap();
+        let wal_path = format!("{}-wal", path);
+        std::fs::write(path, WAL_DB).unwrap();
+        std::fs::write(&wal_path, WAL_DB_WAL).unwrap();
+        let mut database = Database::open(path).unwrap();
+        let mut snapshot = database.snapshot().unwrap();
+        let mut other = Database::open(path).unwrap();
+
+        // Another connection catches up with the commit, the snapshot doesn't.
+        database.query("CREATE TABLE later (a)").unwrap();
+        other.refresh().unwrap();
+        assert!(other.query("SELECT count(*) FROM later").is_ok());
+        snapshot.refresh().unwrap();
+        let error = snapshot.query("SELECT count(*) FROM later").unwrap_err();
+        assert!(matches!(error, Error::NoSuchTable(_)), "{:?}", error);
+        assert_eq!(snapshot.query("SELECT body FROM notes").unwrap().len(), 2);
+        // Nor does it read the schema page the commit wrote to the WAL.
+        assert_eq!(other.get_page(0).unwrap().header.number_of_cells, 2);
+        assert_eq!(snapshot.get_page(0).unwrap().header.number_of_cells, 1);
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_file(&wal_path).unwrap();
+    }
+
+    #[test]
+    fn refuse_writes_through_a_snapshot() {
+        let path = std::env::temp_dir().join(format!("wal-read-only-{}.db", std::process::id()));
+        let path = path.to_str().unwrap();
+        let wal_path = format!("{}-wal", path);
+        std::fs::write(path, WAL_DB).unwrap();
+        std::fs::write(&wal_path, WAL_DB_WAL).unwrap();
+        let mut database = Database::open(path).unwrap();
+
+        let mut snapshot = database.snapshot().unwrap();
+        let error = snapshot.query("CREATE TABLE t (a)").unwrap_err();
+        assert_eq!(error.to_string(), "Unable to write through a snapshot or read transaction");
+        {
+            let mut read = database.begin_read().unwrap();
+            let error = read.query("CREATE TABLE t (a)").unwrap_err();
+            assert_eq!(error.to_string(), "Unable to write through a snapshot or read transaction");
+        }
+
+        // Neither wrote anything, and the database itself still writes.
+        assert!(snapshot.schema.find_table("t").is_none());
+        assert!(database.schema.find_table("t").is_none());
+        database.query("CREATE TABLE t (a)").unwrap();
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_file(&wal_path).unwrap();
+    }
+
+    /// Written by sqlite3 with `CREATE TABLE Items (a)`, the rows `'x'`, `'y'`
+    /// and `'z'`, and `CREATE INDEX by_a ON Items (a)`.
+    const ITEMS_DB: &[u8] = include_bytes!("../testdata/items.db");
+
+    #[test]
+    fn add_column_to_stored_rows() {
+        let path = std::env::temp_dir().join(format!("add-column-{}.db", std::process::id()));
+        std::fs::write(&path, ITEMS_DB).unwrap();
+        let mut database = Database::open(path.to_str().unwrap()).unwrap();
+        let mut run = |sql: &str| -> Result<Vec<Vec<OwnedValue>>> {
+            let mut statement = crate::statement::Statement::prepare(sql)?;
+            let rows = statement.query(&mut database)?;
+            rows.map(|row| Ok(row?.into_values())).collect()
+        };
+
+        run("ALTER TABLE Items ADD COLUMN b INTEGER NOT NULL DEFAULT 7").unwrap();
+        run("ALTER TABLE Items ADD COLUMN c TEXT").unwrap();
+        let row = |a: &str| vec![OwnedValue::Text(a.to_string()), OwnedValue::Integer(7), OwnedValue::Null];
+        assert_eq!(run("SELECT a, b, c FROM Items").unwrap(), [row("x"), row("y"), row("z")]);
+        assert_eq!(run("SELECT a FROM Items WHERE b = 7").unwrap().len(), 3);
+
+        let error = run("ALTER TABLE Items ADD COLUMN d NOT NULL").unwrap_err();
+        assert_eq!(error.to_string(), "Cannot add a NOT NULL column with default value NULL");
+        let error = run("ALTER TABLE Items ADD COLUMN d TEXT NOT NULL DEFAULT NULL").unwrap_err();
+        assert_eq!(error.to_string(), "Cannot add a NOT NULL column with default value NULL");
+        assert!(database.schema.find_table("Items").unwrap().find_column("d").is_none());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /// Written by sqlite3 with 512-byte pages: `CREATE TABLE t (id INTEGER
+    /// PRIMARY KEY, body TEXT)` with bodies of 100, 1050, 1051, 1052 and 3000
+    /// letters. The record of the 1051-letter body ends exactly at the end of
+    /// its second overflow page.
+    const OVERFLOW_DB: &[u8] = include_bytes!("../testdata/overflow.db");
+
+    #[test]
+    fn reassemble_payloads_from_overflow_chains() {
+        let path = std::env::temp_dir().join(format!("overflow-{}.db", std::process::id()));
+        std::fs::write(&path, OVERFLOW_DB).unwrap();
+        let mut database = Database::open(path.to_str().unwrap()).unwrap();
+
+        let body = |id: i64, length: usize| {
+            (0..length)
+                .map(|i| (b'a' + ((i as i64 * 7 + id) % 26) as u8) as char)
+                .collect::<String>()
+        };
+        let rows = database.query_rows::<(i64, String)>("SELECT id, body FROM t").unwrap();
+        let lengths = [100, 1050, 1051, 1052, 3000];
+        assert_eq!(rows.len(), lengths.len());
+        for ((id, text), length) in rows.into_iter().zip(lengths) {
+            assert_eq!(text, body(id, length), "row {}", id);
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /// Written by sqlite3 with 1024-byte pages: `CREATE TABLE keep (a)` with
+    /// 30 rows of 300-byte text, then `CREATE TABLE t (a)` with 60 more.
+    const DROP_DB: &[u8] = include_bytes!("../testdata/drop.db");
+
+    #[test]
+    fn reuse_freed_pages_before_growing_the_file() {
+        let path = std::env::temp_dir().join(format!("drop-{}.db", std::process::id()));
+        std::fs::write(&path, DROP_DB).unwrap();
+        let mut database = Database::open(path.to_str().unwrap()).unwrap();
+        let run = |database: &mut Database, sql: &str| -> Result<()> {
+            let mut statement = crate::statement::Statement::prepare(sql)?;
+            let rows = statement.query(database)?.collect::<Result<Vec<_>>>();
+            rows.map(|_| ())
+        };
+
+        let page_count = DROP_DB.len() as u32 / 1024;
+        run(&mut database, "DROP TABLE t").unwrap();
+        let freed = database.freelist_pages().unwrap().pages().collect::<HashSet<_>>();
+        assert_eq!(freed.len() as u32, database.header.freelist_page_count);
+        assert!(freed.len() > 20);
+        assert!(freed.iter().all(|&page| page > 2 && page <= page_count));
+        assert_eq!(database.page_count().unwrap(), page_count);
+
+        run(&mut database, "CREATE INDEX by_a ON keep (a)").unwrap();
+        let rootpage = database.schema.find_index("by_a").unwrap().rootpage;
+        assert!(freed.contains(&rootpage));
+        assert_eq!(database.header.freelist_page_count, 0);
+        assert!(database.freelist_pages().unwrap().pages().next().is_none());
+        assert!(database.page_count().unwrap() > page_count);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn prefetch_the_pages_get_page_reads() {
+        let path = std::env::temp_dir().join(format!("prefetch-{}.db", std::process::id()));
+        std::fs::write(&path, DROP_DB).unwrap();
+        let mut database = Database::open(path.to_str().unwrap()).unwrap();
+
+        // Page 1 is in the cache after opening, the rest are read.
+        let numbers = (0..DROP_DB.len() as u32 / 1024).rev().collect::<Vec<_>>();
+        let pages = database.prefetch_pages(&numbers).unwrap();
+        assert_eq!(pages.len(), numbers.len());
+        assert_eq!(database.stats.cache_hits, 1);
+        let mut fresh = Database::open(path.to_str().unwrap()).unwrap();
+        for (page, &number) in pages.iter().zip(&numbers) {
+            assert_eq!(page.data, fresh.get_page(number).unwrap().data, "page {}", number + 1);
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn write_in_memory_database() {
+        let mut database = Database::open(IN_MEMORY).unwrap();
+        assert!(database.is_in_memory());
+        for sql in ["CREATE TABLE t(a, b)", "CREATE INDEX i ON t(b)"] {
+            crate::statement::Statement::prepare(sql)
+                .unwrap()
+                .query(&mut database)
+                .unwrap();
+        }
+        assert!(database.schema.find_table("t").is_some());
+        assert!(database.schema.find_index("i").is_some());
+
+        let path = std::env::temp_dir().join(format!("memory-{}.db", std::process::id()));
+        let path = path.to_str().unwrap();
+        std::fs::write(path, database.serialize().unwrap()).unwrap();
+        let saved = Database::open(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(saved.header.page_count, 3);
+        assert_eq!(saved.header.schema_cookie, 2);
+        assert!(saved.schema.find_index("i").is_some());
+    }
+}
@@ -0,0 +1,292 @@
+use serde::de::{self, DeserializeOwned, IntoDeserializer, Visitor};
+use serde::forward_to_deserialize_any;
+
+use crate::error::Error;
+use crate::record::OwnedValue;
+use crate::statement::Row;
+
+impl de::Error for Error {
+    fn custom<T: std::fmt::Display>(message: T) -> Self {
+        Error::Deserialize(message.to_string())
+    }
+}
+
+/// Reads a result row into `T`: a struct or map takes the columns by
+/// name, a tuple or sequence takes them in order, and a single value takes
+/// the only column.
+pub fn from_row<T: DeserializeOwned>(columns: &[String], row: &Row) -> Result<T, Error> {
+    T::deserialize(RowDeserializer {
+        columns,
+        values: row.values(),
+    })
+}
+
+/// A result row, whose columns are named `columns`.
+struct RowDeserializer<'a> {
+    columns: &'a [String],
+    values: &'a [OwnedValue],
+}
+
+impl<'a> RowDeserializer<'a> {
+    /// The only column of the row, for a value to be read from.
+    fn single(&self) -> Result<ValueDeserializer<'a>, Error> {
+        match (self.columns, self.values) {
+            ([column], [value]) => Ok(ValueDeserializer { column, value }),
+            _ => Err(Error::Deserialize(format!(
+                "Expected 1 column for a single value, got {}",
+                self.values.len()
+            ))),
+        }
+    }
+}
+
+macro_rules! deserialize_single {
+    ($($method:ident)*) => {
+        $(fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+            self.single()?.$method(visitor)
+        })*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for RowDeserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_map(Columns { row: self, next: 0 })
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_seq(Columns { row: self, next: 0 })
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.single()?.deserialize_enum(name, variants, visitor)
+    }
+
+    deserialize_single! {
+        deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64
+        deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64 deserialize_f32
+        deserialize_f64 deserialize_char deserialize_str deserialize_string deserialize_bytes
+        deserialize_byte_buf deserialize_option deserialize_unit deserialize_identifier
+    }
+
+    forward_to_deserialize_any! {
+        i128 u128 unit_struct ignored_any
+    }
+}
+
+/// The columns of a row, by name for a map or in order for a sequence.
+struct Columns<'a> {
+    row: RowDeserializer<'a>,
+    next: usize,
+}
+
+impl<'de> de::MapAccess<'de> for Columns<'de> {
+    type Error = Error;
+
+    fn next_key_seed<K: de::DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, Error> {
+        match self.row.columns.get(self.next) {
+            Some(column) => seed.deserialize(column.as_str().into_deserializer()).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: de::DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        let column = &self.row.columns[self.next];
+        let value = &self.row.values[self.next];
+        self.next += 1;
+        seed.deserialize(ValueDeserializer { column, value })
+    }
+}
+
+impl<'de> de::SeqAccess<'de> for Columns<'de> {
+    type Error = Error;
+
+    fn next_element_seed<T: de::DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>, Error> {
+        let (Some(column), Some(value)) = (self.row.columns.get(self.next), self.row.values.get(self.next)) else {
+            return Ok(None);
+        };
+        self.next += 1;
+        seed.deserialize(ValueDeserializer { column, value }).map(Some)
+    }
+}
+
+/// The value of the column `column`, which errors name.
+struct ValueDeserializer<'a> {
+    column: &'a str,
+    value: &'a OwnedValue,
+}
+
+impl ValueDeserializer<'_> {
+    /// Puts the name of the column in front of the message of `error`.
+    fn in_column(&self, error: Error) -> Error {
+        match error {
+            Error::Deserialize(message) => Error::Deserialize(format!("Column {}: {}", self.column, message)),
+            error => error,
+        }
+    }
+}
+
+impl<'de> de::Deserializer<'de> for ValueDeserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let result = match self.value {
+            OwnedValue::Null => visitor.visit_unit(),
+            OwnedValue::Integer(n) => visitor.visit_i64(*n),
+            OwnedValue::Real(n) => visitor.visit_f64(*n),
+            OwnedValue::Text(text) => visitor.visit_borrowed_str(text),
+            OwnedValue::Blob(blob) => visitor.visit_borrowed_bytes(blob),
+        };
+        result.map_err(|error| self.in_column(error))
+    }
+
+    /// SQLite keeps booleans as the integers 0 and 1.
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let result = match self.value {
+            OwnedValue::Integer(n @ (0 | 1)) => visitor.visit_bool(*n == 1),
+            _ => return self.deserialize_any(visitor),
+        };
+        result.map_err(|error| self.in_column(error))
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.value {
+            OwnedValue::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    /// Enums with unit variants are read from text naming the variant.
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        let result = match self.value {
+            OwnedValue::Text(text) => visitor.visit_enum(text.as_str().into_deserializer()),
+            _ => return self.deserialize_any(visitor),
+        };
+        result.map_err(|error| self.in_column(error))
+    }
+
+    forward_to_deserialize_any! {
+        i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+        unit unit_struct seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::Database;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    enum Color {
+        Red,
+        Green,
+    }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Product {
+        name: String,
+        price: f64,
+        color: Option<Color>,
+        stocked: bool,
+    }
+
+    fn row(values: Vec<OwnedValue>) -> Row {
+        Row::from(values)
+    }
+
+    #[test]
+    fn read_rows_into_structs_and_tuples() {
+        let columns = ["stocked", "name", "price", "color"].map(String::from);
+        let values = vec![
+            OwnedValue::Integer(1),
+            OwnedValue::Text("apple".to_string()),
+            OwnedValue::Integer(2),
+            OwnedValue::Text("Red".to_string()),
+        ];
+        let product: Product = from_row(&columns, &row(values.clone())).unwrap();
+        assert_eq!(
+            product,
+            Product {
+                name: "apple".to_string(),
+                price: 2.0,
+                color: Some(Color::Red),
+                stocked: true,
+            }
+        );
+        let tuple: (bool, String, i64, Option<String>) = from_row(&columns, &row(values)).unwrap();
+        assert_eq!(tuple, (true, "apple".to_string(), 2, Some("Red".to_string())));
+
+        let count: u32 = from_row(&["count(*)".to_string()], &row(vec![OwnedValue::Integer(3)])).unwrap();
+        assert_eq!(count, 3);
+
+        let values = vec![
+            OwnedValue::Integer(0),
+            OwnedValue::Text("pear".to_string()),
+            OwnedValue::Text("cheap".to_string()),
+            OwnedValue::Null,
+        ];
+        let error = from_row::<Product>(&columns, &row(values)).unwrap_err();
+        assert_eq!(error.to_string(), "Column price: invalid type: string \"cheap\", expected f64");
+        let error = from_row::<u8>(&["n".to_string()], &row(vec![OwnedValue::Integer(300)])).unwrap_err();
+        assert_eq!(error.to_string(), "Column n: invalid value: integer `300`, expected u8");
+        let values = vec![OwnedValue::Integer(1), OwnedValue::Text("fig".to_string())];
+        let error = from_row::<Product>(&columns[..2], &row(values)).unwrap_err();
+        assert_eq!(error.to_string(), "missing field `price`");
+    }
+
+    #[test]
+    fn query_rows_as_structs() {
+        let mut database = Database::open_in_memory().unwrap();
+        database.query("CREATE TABLE products (name, price, color, stocked)").unwrap();
+        let products: Vec<Product> = database.query_as("SELECT * FROM products").unwrap();
+        assert!(products.is_empty());
+        let counts: Vec<i64> = database.query_as("SELECT count(*) FROM products").unwrap();
+        assert_eq!(counts, [0]);
+    }
+}
@@ -0,0 +1,319 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+use crate::btree::{BTreeCursor, Direction, Entry};
+use crate::database::Database;
+use crate::error::{bail, Error, Result};
+use crate::record::Record;
+use crate::sql::{self, quote_identifier};
+use crate::sqlite_schema::SQLiteSchema;
+
+/// How the rows of a table are stored, enough to compare them and write
+/// statements for them.
+#[derive(Debug, Clone, PartialEq)]
+struct TableLayout {
+    name: String,
+    /// Column names in record order.
+    columns: Vec<String>,
+    /// Position of the INTEGER PRIMARY KEY, stored as NULL in place of the
+    /// rowid.
+    rowid_alias: Option<usize>,
+    /// Number of leading record values forming the key of a WITHOUT ROWID
+    /// table; 0 for tables keyed by rowid.
+    key_len: usize,
+}
+
+impl TableLayout {
+    fn new(statement: &sql::CreateTableStatement) -> Self {
+        Self {
+            name: statement.table.clone(),
+            columns: statement.record_columns(),
+            rowid_alias: statement
+                .fields
+                .iter()
+                .position(|field| field.is_primary_key)
+                .filter(|_| !statement.without_rowid),
+            key_len: if statement.without_rowid {
+                statement.primary_key.len().max(1)
+            } else {
+                0
+            },
+        }
+    }
+
+    fn compare_keys(&self, a: &Entry, b: &Entry) -> Ordering {
+        if self.key_len == 0 {
+            return a.rowid.cmp(&b.rowid);
+        }
+
+        let a = Record::read_prefix(0, &a.payload, self.key_len);
+        let b = Record::read_prefix(0, &b.payload, self.key_len);
+        a.values
+            .iter()
+            .zip(b.values.iter())
+            .map(|(a, b)| a.compare(b))
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or(Ordering::Equal)
+    }
+
+    /// Values of every column as SQL literals.
+    fn values(&self, entry: &Entry) -> Vec<String> {
+        let mut values = Record::read(entry.rowid, &entry.payload)
+            .values
+            .iter()
+            .map(|value| value.sql_literal())
+            .collect::<Vec<_>>();
+        // Rows written before an ALTER TABLE ADD COLUMN are shorter.
+        values.resize(self.columns.len().max(values.len()), "NULL".to_string());
+
+        if let Some(i) = self.rowid_alias {
+            values[i] = entry.rowid.to_string();
+        }
+        values
+    }
+
+    fn where_clause(&self, entry: &Entry, values: &[String]) -> String {
+        if self.key_len > 0 {
+            return self
+                .columns
+                .iter()
+                .zip(values)
+                .take(self.key_len)
+                .map(|(column, value)| format!("{}={}", quote_identifier(column), value))
+                .collect::<Vec<_>>()
+                .join(" AND ");
+        }
+
+        match self.rowid_alias {
+            Some(i) => format!("{}={}", quote_identifier(&self.columns[i]), entry.rowid),
+            None => format!("rowid={}", entry.rowid),
+        }
+    }
+
+    fn write_insert(&self, out: &mut impl std::io::Write, entry: &Entry) -> Result<()> {
+        let mut values = self.values(entry);
+        let mut names = self
+            .columns
+            .iter()
+            .map(|column| quote_identifier(column))
+            .collect::<Vec<_>>();
+        names.truncate(values.len());
+
+        if self.key_len == 0 && self.rowid_alias.is_none() {
+            names.insert(0, "rowid".to_string());
+            values.insert(0, entry.rowid.to_string());
+        }
+
+        writeln!(
+            out,
+            "INSERT INTO {}({}) VALUES({});",
+            quote_identifier(&self.name),
+            names.join(","),
+            values.join(",")
+        )?;
+        Ok(())
+    }
+
+    fn write_delete(&self, out: &mut impl std::io::Write, entry: &Entry) -> Result<()> {
+        let values = self.values(entry);
+        writeln!(
+            out,
+            "DELETE FROM {} WHERE {};",
+            quote_identifier(&self.name),
+            self.where_clause(entry, &values)
+        )?;
+        Ok(())
+    }
+
+    fn write_update(
+        &self,
+        out: &mut impl std::io::Write,
+        from: &Entry,
+        to: &Entry,
+    ) -> Result<()> {
+        let old = self.values(from);
+        let new = self.values(to);
+        let changes = self
+            .columns
+            .iter()
+            .zip(old.iter().zip(new.iter()))
+            .filter(|(_, (old, new))| old != new)
+            .map(|(column, (_, new))| format!("{}={}", quote_identifier(column), new))
+            .collect::<Vec<_>>();
+        if changes.is_empty() {
+            return Ok(());
+        }
+
+        writeln!(
+            out,
+            "UPDATE {} SET {} WHERE {};",
+            quote_identifier(&self.name),
+            changes.join(", "),
+            self.where_clause(from, &old)
+        )?;
+        Ok(())
+    }
+}
+
+/// Ordinary tables of a database with the statement that created them.
+/// Virtual tables and their shadow tables are left out.
+pub(crate) fn read_tables(database: &mut Database) -> Result<BTreeMap<String, (String, u32)>> {
+    let schema = SQLiteSchema::read(database)?;
+    let virtual_tables = database
+        .schema
+        .tables
+        .values()
+        .filter(|table| table.is_virtual())
+        .map(|table| format!("{}_", table.name))
+        .collect::<Vec<_>>();
+
+    Ok(schema
+        .rows
+        .into_iter()
+        .filter(|row| row.kind == "table" && row.rootpage != 0)
+        .filter(|row| !row.name.starts_with("sqlite_"))
+        .filter(|row| !virtual_tables.iter().any(|prefix| row.name.starts_with(prefix)))
+        .map(|row| (row.name, (row.sql, row.rootpage)))
+        .collect())
+}
+
+/// Writes the statements that turn the data of `from` into that of `to`,
+/// like sqldiff. Rows are matched by rowid, or by primary key in WITHOUT
+/// ROWID tables; tables whose definition changed are recreated.
+pub fn diff(
+    from: &mut Database,
+    to: &mut Database,
+    table: Option<&str>,
+    out: &mut impl std::io::Write,
+) -> Result<()> {
+    let from_tables = read_tables(from)?;
+    let to_tables = read_tables(to)?;
+
+    let mut names = from_tables.keys().chain(to_tables.keys()).collect::<Vec<_>>();
+    names.sort();
+    names.dedup();
+    if let Some(table) = table {
+        names.retain(|name| name.as_str() == table);
+        if names.is_empty() {
+            return Err(Error::NoSuchTable(table.to_string()));
+        }
+    }
+
+    for name in names {
+        match (from_tables.get(name), to_tables.get(name)) {
+            (Some(_), None) => writeln!(out, "DROP TABLE {};", quote_identifier(name))?,
+            (None, Some((sql, rootpage))) => {
+                writeln!(out, "{};", sql)?;
+                copy_table(to, sql, *rootpage, out)?;
+            }
+            (Some((from_sql, _)), Some((to_sql, to_rootpage))) if from_sql != to_sql => {
+                writeln!(out, "DROP TABLE {};", quote_identifier(name))?;
+                writeln!(out, "{};", to_sql)?;
+                copy_table(to, to_sql, *to_rootpage, out)?;
+            }
+            (Some((sql, from_rootpage)), Some((_, to_rootpage))) => {
+                let layout = layout(sql)?;
+                diff_table(&layout, from, *from_rootpage, to, *to_rootpage, out)?;
+            }
+            (None, None) => unreachable!(),
+        }
+    }
+
+    Ok(())
+}
+
+fn layout(sql: &str) -> Result<TableLayout> {
+    match sql::parse_create(sql.as_bytes()) {
+        Ok((_, sql::SQLCommand::CreateTable(statement))) => Ok(TableLayout::new(&statement)),
+        _ => bail!("Failed to parse table definition: {}", sql),
+    }
+}
+
+pub(crate) fn copy_table(
+    database: &mut Database,
+    sql: &str,
+    rootpage: u32,
+    out: &mut impl std::io::Write,
+) -> Result<()> {
+    let layout = layout(sql)?;
+    for entry in BTreeCursor::new(database, rootpage, Direction::Forward)? {
+        layout.write_insert(out, &entry?)?;
+    }
+    Ok(())
+}
+
+/// Walks both tables in key order side by side.
+fn diff_table(
+    layout: &TableLayout,
+    from: &mut Database,
+    from_rootpage: u32,
+    to: &mut Database,
+    to_rootpage: u32,
+    out: &mut impl std::io::Write,
+) -> Result<()> {
+    let mut from = BTreeCursor::new(from, from_rootpage, Direction::Forward)?;
+    let mut to = BTreeCursor::new(to, to_rootpage, Direction::Forward)?;
+    let mut old = from.next().transpose()?;
+    let mut new = to.next().transpose()?;
+
+    loop {
+        let ordering = match (&old, &new) {
+            (None, None) => return Ok(()),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (Some(old), Some(new)) => layout.compare_keys(old, new),
+        };
+
+        match ordering {
+            Ordering::Less => {
+                layout.write_delete(out, old.as_ref().unwrap())?;
+                old = from.next().transpose()?;
+            }
+            Ordering::Greater => {
+                layout.write_insert(out, new.as_ref().unwrap())?;
+                new = to.next().transpose()?;
+            }
+            Ordering::Equal => {
+                layout.write_update(out, old.as_ref().unwrap(), new.as_ref().unwrap())?;
+                old = from.next().transpose()?;
+                new = to.next().transpose()?;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn statements_for_changed_rows() {
+        let layout = TableLayout {
+            name: "t".to_string(),
+            columns: vec!["id".to_string(), "a".to_string(), "b".to_string()],
+            rowid_alias: Some(0),
+            key_len: 0,
+        };
+        // Records of (NULL, 42, 'x') and (NULL, 42, 'y').
+        let from = Entry {
+            rowid: 7,
+            payload: vec![4, 0, 1, 15, 42, b'x'],
+        };
+        let to = Entry {
+            rowid: 7,
+            payload: vec![4, 0, 1, 15, 42, b'y'],
+        };
+
+        let mut out = vec![];
+        layout.write_update(&mut out, &from, &to).unwrap();
+        layout.write_delete(&mut out, &from).unwrap();
+        layout.write_insert(&mut out, &to).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "UPDATE \"t\" SET \"b\"='y' WHERE \"id\"=7;\n\
+             DELETE FROM \"t\" WHERE \"id\"=7;\n\
+             INSERT INTO \"t\"(\"id\",\"a\",\"b\") VALUES(7,42,'y');\n"
+        );
+    }
+}
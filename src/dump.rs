@@ -0,0 +1,43 @@
+use crate::database::Database;
+use crate::diff::{copy_table, read_tables};
+use crate::error::{Error, Result};
+use crate::sqlite_schema::SQLiteSchema;
+
+/// Writes the schema and rows of `database` as SQL statements, like the
+/// sqlite3 `.dump` command; `table` limits it to one table and its indexes,
+/// views and triggers. Virtual tables are created empty.
+pub fn dump(
+    database: &mut Database,
+    table: Option<&str>,
+    out: &mut impl std::io::Write,
+) -> Result<()> {
+    let tables = read_tables(database)?;
+    if let Some(table) = table {
+        if !tables.contains_key(table) {
+            return Err(Error::NoSuchTable(table.to_string()));
+        }
+    }
+    let included = |name: &str| table.is_none_or(|table| table == name);
+
+    writeln!(out, "BEGIN TRANSACTION;")?;
+    let schema = SQLiteSchema::read(database)?;
+    for row in schema.rows.iter().filter(|row| included(&row.name)) {
+        if let Some((sql, rootpage)) = tables.get(&row.name) {
+            writeln!(out, "{};", sql)?;
+            copy_table(database, sql, *rootpage, out)?;
+        }
+    }
+
+    // Indexes are created after the rows so they are built only once.
+    for row in schema.rows {
+        let is_virtual_table = row.kind == "table" && row.rootpage == 0;
+        if (row.kind != "table" || is_virtual_table)
+            && !row.sql.is_empty()
+            && included(&row.tbl_name)
+        {
+            writeln!(out, "{};", row.sql)?;
+        }
+    }
+    writeln!(out, "COMMIT;")?;
+    Ok(())
+}
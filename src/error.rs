@@ -0,0 +1,150 @@
+use crate::output::json_string;
+
+/// The result of the fallible functions of the crate.
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The ways the functions of the crate fail, for callers to match on, e.g.
+/// to point at the problem in an editor. Failures without a variant of
+/// their own are `Other`, described by their message.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// A statement that doesn't parse; `offset` is the byte where parsing
+    /// got stuck.
+    #[error("{message}")]
+    Parse { message: String, offset: usize },
+    /// A page whose contents don't make sense; `offset` is the byte of the
+    /// page where the bad data is.
+    #[error("Database is corrupt: page {page}, offset {offset}: {message}")]
+    CorruptPage { page: u32, offset: usize, message: String },
+    /// A table or view missing from the schema.
+    #[error("Table not found: {0}")]
+    NoSuchTable(String),
+    /// A column missing from its table.
+    #[error("Column not found: {0}")]
+    NoSuchColumn(String),
+    /// A value of another storage class than the one asked for.
+    #[error("Invalid column type: {found}, expected {expected}")]
+    TypeMismatch { expected: &'static str, found: &'static str },
+    /// The statement was stopped through its interrupt handle.
+    #[error("Interrupted")]
+    Interrupted,
+    /// Intermediate results of the statement needed more than the memory
+    /// limit allows.
+    #[error("Memory limit exceeded: the statement needs more than {limit} bytes")]
+    MemoryLimit { limit: u64 },
+    /// Another connection holds a lock on the database in the way of the
+    /// statement.
+    #[error("Database is locked")]
+    Busy,
+    /// A result row that doesn't fit the type it was read into.
+    #[error("{0}")]
+    Deserialize(String),
+    /// Reading or writing a file failed.
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("{0}")]
+    Other(String),
+}
+
+/// Returns early with an `Error::Other` of the formatted message.
+macro_rules! bail {
+    ($($arg:tt)*) => {
+        return Err($crate::error::Error::Other(format!($($arg)*)))
+    };
+}
+
+/// An `Error::Other` of the formatted message.
+macro_rules! format_err {
+    ($($arg:tt)*) => {
+        $crate::error::Error::Other(format!($($arg)*))
+    };
+}
+
+pub(crate) use {bail, format_err};
+
+impl Error {
+    /// An `Error::CorruptPage` for the byte at `offset` of page `page`.
+    pub(crate) fn corrupt_page(page: u32, offset: usize, message: impl Into<String>) -> Self {
+        Self::CorruptPage {
+            page,
+            offset,
+            message: message.into(),
+        }
+    }
+}
+
+/// Describes an error as a JSON object with its kind, message, and the SQL
+/// offset or page number when they are known. The kind is that of the
+/// first error of the crate among `error` and its sources.
+pub fn error_json(error: &(dyn std::error::Error + 'static)) -> String {
+    let mut sources = std::iter::successors(Some(error), |error| error.source());
+    let (kind, offset, page) = match sources.find_map(|error| error.downcast_ref::<Error>()) {
+        Some(Error::Parse { offset, .. }) => ("parse", Some(*offset), None),
+        Some(Error::CorruptPage { page, .. }) => ("corrupt", None, Some(*page)),
+        Some(Error::NoSuchTable(_)) => ("no_such_table", None, None),
+        Some(Error::NoSuchColumn(_)) => ("no_such_column", None, None),
+        Some(Error::TypeMismatch { .. }) => ("type_mismatch", None, None),
+        Some(Error::Interrupted) => ("interrupted", None, None),
+        Some(Error::MemoryLimit { .. }) => ("memory", None, None),
+        Some(Error::Busy) => ("busy", None, None),
+        Some(Error::Deserialize(_)) => ("deserialize", None, None),
+        Some(Error::Io(_)) => ("io", None, None),
+        Some(Error::Other(_)) => ("error", None, None),
+        None if error.is::<std::io::Error>() => ("io", None, None),
+        None => ("error", None, None),
+    };
+    let or_null = |value: Option<String>| value.unwrap_or_else(|| "null".to_string());
+
+    format!(
+        "{{\"kind\":\"{}\",\"message\":{},\"offset\":{},\"page\":{}}}",
+        kind,
+        json_string(&error.to_string()),
+        or_null(offset.map(|offset| offset.to_string())),
+        or_null(page.map(|page| page.to_string()))
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describe_errors_as_json() {
+        let parse = Error::Parse {
+            message: "near \"form\": syntax error".to_string(),
+            offset: 9,
+        };
+        assert_eq!(
+            error_json(&parse),
+            r#"{"kind":"parse","message":"near \"form\": syntax error","offset":9,"page":null}"#
+        );
+
+        let corrupt = Error::CorruptPage {
+            page: 3,
+            offset: 0,
+            message: "Invalid page kind: 7".to_string(),
+        };
+        assert_eq!(
+            error_json(&corrupt),
+            r#"{"kind":"corrupt","message":"Database is corrupt: page 3, offset 0: Invalid page kind: 7","offset":null,"page":3}"#
+        );
+
+        let memory = Error::MemoryLimit { limit: 1024 };
+        assert_eq!(
+            error_json(&memory),
+            r#"{"kind":"memory","message":"Memory limit exceeded: the statement needs more than 1024 bytes","offset":null,"page":null}"#
+        );
+
+        let missing = Error::NoSuchTable("t".to_string());
+        assert_eq!(
+            error_json(&missing),
+            r#"{"kind":"no_such_table","message":"Table not found: t","offset":null,"page":null}"#
+        );
+
+        let other = Error::Other("Unsupported command: VACUUM".to_string());
+        assert_eq!(
+            error_json(&other),
+            r#"{"kind":"error","message":"Unsupported command: VACUUM","offset":null,"page":null}"#
+        );
+    }
+}
@@ -0,0 +1,406 @@
+use crate::error::{bail, Result};
+use crate::functions;
+use crate::record::ColumnValue;
+
+/// A constant written in a statement.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    /// `X'hex'`.
+    Blob(Vec<u8>),
+}
+
+impl std::fmt::Display for Literal {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Literal::Null => write!(f, "NULL"),
+            Literal::Integer(n) => write!(f, "{}", n),
+            Literal::Real(n) => write!(f, "{}", n),
+            Literal::Text(text) => write!(f, "{}", text),
+            Literal::Blob(bytes) => write!(f, "{}", ColumnValue::Blob(bytes).sql_literal()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOperator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+}
+
+/// The type a value is converted to, named after SQLite's column
+/// affinities.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Affinity {
+    Integer,
+    Real,
+    Numeric,
+    Text,
+    Blob,
+}
+
+impl Affinity {
+    /// The affinity of a type name, by SQLite's rules: the first of INT,
+    /// CHAR, CLOB or TEXT, BLOB, and REAL, FLOA or DOUB the name contains
+    /// decides, and anything else is NUMERIC.
+    pub fn of(type_name: &str) -> Self {
+        let type_name = type_name.to_ascii_uppercase();
+        let contains = |words: &[&str]| words.iter().any(|word| type_name.contains(word));
+        if contains(&["INT"]) {
+            Affinity::Integer
+        } else if contains(&["CHAR", "CLOB", "TEXT"]) {
+            Affinity::Text
+        } else if contains(&["BLOB"]) || type_name.trim().is_empty() {
+            Affinity::Blob
+        } else if contains(&["REAL", "FLOA", "DOUB"]) {
+            Affinity::Real
+        } else {
+            Affinity::Numeric
+        }
+    }
+}
+
+/// An expression of a SELECT list or a WHERE clause.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    /// A column of the row, or a result column like `count(*)` of a
+    /// grouped query.
+    Column(String),
+    Literal(Literal),
+    Negate(Box<Expr>),
+    Binary(BinaryOperator, Box<Expr>, Box<Expr>),
+    /// A call of a scalar function, by name.
+    Function(String, Vec<Expr>),
+    Cast(Box<Expr>, Affinity),
+    /// `?N`, the Nth value bound to the statement, counting from 1. A bare
+    /// `?` is 0 until the statement numbers it.
+    Parameter(usize),
+}
+
+/// The value of an expression: a value of the row, or one the expression
+/// made up.
+#[derive(Debug, Clone)]
+pub enum Value<'a> {
+    Read(ColumnValue<'a>),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+impl Value<'_> {
+    pub fn get(&self) -> ColumnValue<'_> {
+        match self {
+            Value::Read(value) => value.clone(),
+            Value::Text(text) => ColumnValue::Text(text.as_bytes()),
+            Value::Blob(bytes) => ColumnValue::Blob(bytes),
+        }
+    }
+}
+
+impl<'a> From<ColumnValue<'a>> for Value<'a> {
+    fn from(value: ColumnValue<'a>) -> Self {
+        Value::Read(value)
+    }
+}
+
+impl Expr {
+    /// Every column the expression reads, left to right.
+    pub fn columns(&self) -> Vec<&String> {
+        match self {
+            Expr::Column(name) => vec![name],
+            Expr::Literal(_) | Expr::Parameter(_) => vec![],
+            Expr::Negate(operand) | Expr::Cast(operand, _) => operand.columns(),
+            Expr::Binary(_, left, right) => {
+                let mut columns = left.columns();
+                columns.extend(right.columns());
+                columns
+            }
+            Expr::Function(_, arguments) => arguments.iter().flat_map(Expr::columns).collect(),
+        }
+    }
+
+    /// Checks that every function called exists and gets as many
+    /// arguments as it takes.
+    pub fn check(&self) -> Result<()> {
+        match self {
+            Expr::Column(_) | Expr::Literal(_) | Expr::Parameter(_) => Ok(()),
+            Expr::Negate(operand) | Expr::Cast(operand, _) => operand.check(),
+            Expr::Binary(_, left, right) => {
+                left.check()?;
+                right.check()
+            }
+            Expr::Function(name, arguments) => {
+                let Some(function) = functions::find(name) else {
+                    bail!("No such function: {}", name);
+                };
+                if !function.arguments.contains(&arguments.len()) {
+                    bail!("Wrong number of arguments to function {}()", name);
+                }
+                arguments.iter().try_for_each(Expr::check)
+            }
+        }
+    }
+
+    /// Calls `f` on every part of the expression, operands left to right
+    /// and before the expression itself.
+    pub fn visit_mut(&mut self, f: &mut dyn FnMut(&mut Expr)) {
+        match self {
+            Expr::Column(_) | Expr::Literal(_) | Expr::Parameter(_) => {}
+            Expr::Negate(operand) | Expr::Cast(operand, _) => operand.visit_mut(f),
+            Expr::Binary(_, left, right) => {
+                left.visit_mut(f);
+                right.visit_mut(f);
+            }
+            Expr::Function(_, arguments) => {
+                arguments.iter_mut().for_each(|argument| argument.visit_mut(f))
+            }
+        }
+        f(self);
+    }
+
+    /// The value of the expression, with `value` giving the value of a
+    /// column by name. Arithmetic follows SQLite: NULL makes NULL, integers
+    /// stay integers unless they overflow, and dividing by zero is NULL.
+    pub fn evaluate<'a>(&'a self, value: &dyn Fn(&str) -> ColumnValue<'a>) -> Value<'a> {
+        let value = match self {
+            Expr::Column(name) => value(name),
+            // Parameters are replaced by their values before a statement
+            // runs, so one left is unbound.
+            Expr::Literal(Literal::Null) | Expr::Parameter(_) => ColumnValue::Null,
+            Expr::Literal(Literal::Integer(n)) => ColumnValue::I64(*n),
+            Expr::Literal(Literal::Real(n)) => ColumnValue::F64(*n),
+            Expr::Literal(Literal::Text(text)) => ColumnValue::Text(text.as_bytes()),
+            Expr::Literal(Literal::Blob(bytes)) => ColumnValue::Blob(bytes),
+            Expr::Negate(operand) => match numeric(&operand.evaluate(value).get()) {
+                Some(ColumnValue::I64(n)) => match n.checked_neg() {
+                    Some(n) => ColumnValue::I64(n),
+                    None => ColumnValue::F64(-(n as f64)),
+                },
+                Some(ColumnValue::F64(n)) => ColumnValue::F64(-n),
+                _ => ColumnValue::Null,
+            },
+            Expr::Binary(operator, left, right) => {
+                let (left, right) = (left.evaluate(value), right.evaluate(value));
+                match (numeric(&left.get()), numeric(&right.get())) {
+                    (Some(left), Some(right)) => arithmetic(*operator, left, right),
+                    _ => ColumnValue::Null,
+                }
+            }
+            Expr::Function(name, arguments) => {
+                let Some(function) = functions::find(name) else {
+                    return Value::Read(ColumnValue::Null);
+                };
+                let arguments = arguments
+                    .iter()
+                    .map(|argument| argument.evaluate(value))
+                    .collect::<Vec<_>>();
+                let arguments = arguments.iter().map(Value::get).collect::<Vec<_>>();
+                return (function.call)(&arguments);
+            }
+            Expr::Cast(operand, affinity) => return cast(operand.evaluate(value), *affinity),
+        };
+        Value::Read(value)
+    }
+}
+
+/// The value as an integer or a real for arithmetic, or `None` for NULL.
+/// Text and blobs are read as far as they look like a number, and are 0
+/// when they don't start with one.
+pub(crate) fn numeric(value: &ColumnValue) -> Option<ColumnValue<'static>> {
+    match value {
+        ColumnValue::Null => None,
+        ColumnValue::F64(n) => Some(ColumnValue::F64(*n)),
+        ColumnValue::Text(bytes) | ColumnValue::Blob(bytes) => {
+            let text = String::from_utf8_lossy(bytes);
+            let text = text.trim();
+            let number = (1..=text.len())
+                .rev()
+                .filter(|&end| text.is_char_boundary(end))
+                .map(|end| &text[..end])
+                .filter(|prefix| prefix.chars().all(|c| "0123456789+-.eE".contains(c)))
+                .find(|prefix| prefix.parse::<f64>().is_ok());
+            Some(match number {
+                Some(number) => match number.parse::<i64>() {
+                    Ok(n) => ColumnValue::I64(n),
+                    Err(_) => ColumnValue::F64(number.parse().unwrap()),
+                },
+                None => ColumnValue::I64(0),
+            })
+        }
+        value => value.as_integer().map(ColumnValue::I64),
+    }
+}
+
+/// The value written as text, the way SQLite converts numbers: reals keep
+/// their decimal point.
+pub(crate) fn as_text(value: &ColumnValue) -> String {
+    match value {
+        ColumnValue::Text(bytes) | ColumnValue::Blob(bytes) => {
+            String::from_utf8_lossy(bytes).into_owned()
+        }
+        ColumnValue::F64(n) if n.is_finite() => format!("{:?}", n),
+        value => value.to_string(),
+    }
+}
+
+/// Converts a value the way `CAST` does. NULL stays NULL.
+fn cast(value: Value, affinity: Affinity) -> Value {
+    let read = value.get();
+    let converted = match (affinity, &read) {
+        (_, ColumnValue::Null) => ColumnValue::Null,
+        (Affinity::Integer, ColumnValue::Text(bytes) | ColumnValue::Blob(bytes)) => {
+            ColumnValue::I64(integer_prefix(bytes))
+        }
+        (Affinity::Integer, ColumnValue::F64(n)) => ColumnValue::I64(*n as i64),
+        (Affinity::Integer | Affinity::Numeric, n) if n.is_number() && !matches!(n, ColumnValue::F64(_)) => {
+            ColumnValue::I64(n.as_integer().unwrap())
+        }
+        (Affinity::Real, value) => ColumnValue::F64(numeric(value).unwrap().as_number()),
+        (Affinity::Numeric, ColumnValue::F64(n)) => ColumnValue::F64(*n),
+        (Affinity::Numeric, value) => match numeric(value).unwrap() {
+            ColumnValue::F64(n) if n.fract() == 0.0 && n.abs() < i64::MAX as f64 => {
+                ColumnValue::I64(n as i64)
+            }
+            n => n,
+        },
+        (Affinity::Text, ColumnValue::Text(_)) | (Affinity::Blob, ColumnValue::Blob(_)) => {
+            return value
+        }
+        (Affinity::Text, value) => return Value::Text(as_text(value)),
+        (Affinity::Blob, value) => return Value::Blob(as_text(value).into_bytes()),
+        (Affinity::Integer, _) => unreachable!("numbers are integers or reals"),
+    };
+    Value::Read(converted)
+}
+
+/// The integer text starts with, after any spaces and a sign, or 0. Too
+/// large integers are clamped.
+fn integer_prefix(bytes: &[u8]) -> i64 {
+    let text = String::from_utf8_lossy(bytes);
+    let text = text.trim_start();
+    let (negative, digits) = match text.as_bytes().first() {
+        Some(b'-') => (true, &text[1..]),
+        Some(b'+') => (false, &text[1..]),
+        _ => (false, text),
+    };
+    digits
+        .bytes()
+        .take_while(u8::is_ascii_digit)
+        .fold(0i64, |n, digit| {
+            let digit = (digit - b'0') as i64;
+            let n = n.saturating_mul(10);
+            if negative {
+                n.saturating_sub(digit)
+            } else {
+                n.saturating_add(digit)
+            }
+        })
+}
+
+fn arithmetic(
+    operator: BinaryOperator,
+    left: ColumnValue<'static>,
+    right: ColumnValue<'static>,
+) -> ColumnValue<'static> {
+    if let (Some(a), Some(b)) = (left.as_integer(), right.as_integer()) {
+        let result = match operator {
+            BinaryOperator::Add => a.checked_add(b),
+            BinaryOperator::Subtract => a.checked_sub(b),
+            BinaryOperator::Multiply => a.checked_mul(b),
+            _ if b == 0 => return ColumnValue::Null,
+            BinaryOperator::Divide => a.checked_div(b),
+            BinaryOperator::Remainder => Some(a.wrapping_rem(b)),
+        };
+        if let Some(n) = result {
+            return ColumnValue::I64(n);
+        }
+    }
+
+    let (a, b) = (left.as_number(), right.as_number());
+    let result = match operator {
+        BinaryOperator::Add => a + b,
+        BinaryOperator::Subtract => a - b,
+        BinaryOperator::Multiply => a * b,
+        _ if b == 0.0 => return ColumnValue::Null,
+        BinaryOperator::Divide => a / b,
+        // Like SQLite, the remainder of reals is that of their integer parts.
+        BinaryOperator::Remainder => match (a as i64).checked_rem(b as i64) {
+            Some(n) => n as f64,
+            None => return ColumnValue::Null,
+        },
+    };
+    if result.is_nan() {
+        ColumnValue::Null
+    } else {
+        ColumnValue::F64(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn evaluate(expr: &Expr) -> String {
+        let price = |name: &str| match name {
+            "price" => ColumnValue::I8(4),
+            "label" => ColumnValue::Text(b"3abc"),
+            _ => ColumnValue::Null,
+        };
+        expr.evaluate(&price).get().to_string()
+    }
+
+    fn binary(operator: BinaryOperator, left: Expr, right: Expr) -> Expr {
+        Expr::Binary(operator, Box::new(left), Box::new(right))
+    }
+
+    #[test]
+    fn cast_values() {
+        let cast = |value: ColumnValue<'static>, type_name: &str| {
+            let expr = Expr::Cast(Box::new(Expr::Column("value".to_string())), Affinity::of(type_name));
+            expr.evaluate(&|_| value.clone()).get().sql_literal()
+        };
+        assert_eq!(cast(ColumnValue::Text(b" -12.9abc"), "INTEGER"), "-12");
+        assert_eq!(cast(ColumnValue::Text(b"99999999999999999999"), "int"), "9223372036854775807");
+        assert_eq!(cast(ColumnValue::F64(-3.9), "BIGINT"), "-3");
+        assert_eq!(cast(ColumnValue::Text(b"1.5x"), "REAL"), "1.5");
+        assert_eq!(cast(ColumnValue::Text(b"x"), "double"), "0.0");
+        assert_eq!(cast(ColumnValue::Text(b"1e2"), "NUMERIC"), "100");
+        assert_eq!(cast(ColumnValue::F64(3.0), "decimal(10,2)"), "3.0");
+        assert_eq!(cast(ColumnValue::F64(1.0), "TEXT"), "'1.0'");
+        assert_eq!(cast(ColumnValue::I8(12), "varchar(10)"), "'12'");
+        assert_eq!(cast(ColumnValue::Text(b"ab"), "BLOB"), "X'6162'");
+        assert_eq!(cast(ColumnValue::Blob(b"AB"), "text"), "'AB'");
+        assert_eq!(cast(ColumnValue::Null, "TEXT"), "NULL");
+    }
+
+    #[test]
+    fn evaluate_arithmetic() {
+        let column = |name: &str| Expr::Column(name.to_string());
+        let integer = |n| Expr::Literal(Literal::Integer(n));
+        let real = |n| Expr::Literal(Literal::Real(n));
+
+        assert_eq!(evaluate(&binary(BinaryOperator::Multiply, column("price"), real(1.5))), "6");
+        assert_eq!(evaluate(&binary(BinaryOperator::Divide, integer(7), integer(2))), "3");
+        assert_eq!(evaluate(&binary(BinaryOperator::Divide, real(7.0), integer(2))), "3.5");
+        assert_eq!(evaluate(&binary(BinaryOperator::Divide, column("price"), integer(0))), "NULL");
+        assert_eq!(evaluate(&binary(BinaryOperator::Remainder, integer(-5), integer(3))), "-2");
+        assert_eq!(evaluate(&binary(BinaryOperator::Remainder, real(5.5), integer(2))), "1");
+        assert_eq!(evaluate(&binary(BinaryOperator::Add, column("label"), integer(1))), "4");
+        assert_eq!(evaluate(&binary(BinaryOperator::Add, column("size"), integer(1))), "NULL");
+        assert_eq!(
+            evaluate(&binary(BinaryOperator::Add, integer(i64::MAX), integer(1))),
+            "9223372036854776000"
+        );
+        assert_eq!(evaluate(&Expr::Negate(Box::new(column("price")))), "-4");
+        assert_eq!(
+            binary(BinaryOperator::Add, column("price"), Expr::Negate(Box::new(column("size"))))
+                .columns(),
+            vec!["price", "size"]
+        );
+    }
+}
@@ -0,0 +1,145 @@
+use std::collections::HashSet;
+
+use crate::database::Database;
+use crate::error::{Error, Result};
+
+/// A freelist trunk page: the next trunk page, 0 for the last one, and the
+/// free leaf pages it lists.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrunkPage {
+    pub page_number: u32,
+    pub next: u32,
+    pub leaves: Vec<u32>,
+}
+
+impl TrunkPage {
+    /// Parses the trunk page `page_number`. Leaf numbers past the end of a
+    /// file with `page_count` pages, or more than fit, mean corruption.
+    pub fn parse(page_number: u32, data: &[u8], page_count: u32) -> Result<Self> {
+        let read_u32 = |at: usize| u32::from_be_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]]);
+        let corrupt = |offset: usize, message: String| Error::corrupt_page(page_number, offset, message);
+
+        let leaf_count = read_u32(4) as usize;
+        if leaf_count > data.len() / 4 - 2 {
+            return Err(corrupt(4, format!("Freelist trunk lists {} leaf pages", leaf_count)));
+        }
+        let leaves = (0..leaf_count).map(|i| read_u32(8 + 4 * i)).collect::<Vec<_>>();
+        if let Some(i) = leaves.iter().position(|&leaf| leaf < 2 || leaf > page_count) {
+            return Err(corrupt(8 + 4 * i, format!("Freelist leaf page {} is out of range", leaves[i])));
+        }
+
+        Ok(Self {
+            page_number,
+            next: read_u32(0),
+            leaves,
+        })
+    }
+}
+
+/// The pages no table or index uses, kept for reuse in a chain of trunk
+/// pages starting from the database header.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Freelist {
+    pub trunks: Vec<TrunkPage>,
+}
+
+impl Freelist {
+    pub fn read(database: &mut Database) -> Result<Self> {
+        let page_count = database.page_count()?;
+        let mut trunks: Vec<TrunkPage> = vec![];
+        let mut visited = HashSet::new();
+        let mut page_number = database.header.first_freelist_trunk_page;
+        while page_number != 0 {
+            // The pointer to the trunk: in the database header, or at the
+            // start of the trunk before it.
+            let (previous, offset) = trunks.last().map_or((1, 32), |trunk| (trunk.page_number, 0));
+            if page_number > page_count || !visited.insert(page_number) {
+                return Err(Error::corrupt_page(
+                    previous,
+                    offset,
+                    format!("Freelist trunk page {} is out of range or repeated", page_number),
+                ));
+            }
+
+            let data = database.read_raw_page(page_number)?;
+            let trunk = TrunkPage::parse(page_number, &data, page_count)?;
+            page_number = trunk.next;
+            trunks.push(trunk);
+        }
+        Ok(Self { trunks })
+    }
+
+    /// Free pages, trunks included, which the header should count as well.
+    pub fn page_count(&self) -> u32 {
+        self.trunks
+            .iter()
+            .map(|trunk| 1 + trunk.leaves.len() as u32)
+            .sum()
+    }
+
+    /// Every free page, each trunk followed by its leaves.
+    pub fn pages(&self) -> impl Iterator<Item = u32> + '_ {
+        self.trunks
+            .iter()
+            .flat_map(|trunk| std::iter::once(trunk.page_number).chain(trunk.leaves.iter().copied()))
+    }
+
+    /// Takes a page off the list the way SQLite does when it needs one: the
+    /// last leaf of the first trunk, or the trunk itself once it has none.
+    pub fn allocate(&mut self) -> Option<u32> {
+        let first = self.trunks.first_mut()?;
+        match first.leaves.pop() {
+            Some(leaf) => Some(leaf),
+            None => Some(self.trunks.remove(0).page_number),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trunk(next: u32, leaves: &[u32]) -> Vec<u8> {
+        let mut data = vec![0; 512];
+        data[..4].copy_from_slice(&next.to_be_bytes());
+        data[4..8].copy_from_slice(&(leaves.len() as u32).to_be_bytes());
+        for (i, leaf) in leaves.iter().enumerate() {
+            data[8 + 4 * i..12 + 4 * i].copy_from_slice(&leaf.to_be_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn parse_trunk_pages() {
+        let parsed = TrunkPage::parse(3, &trunk(9, &[4, 7]), 10).unwrap();
+        assert_eq!(
+            parsed,
+            TrunkPage {
+                page_number: 3,
+                next: 9,
+                leaves: vec![4, 7],
+            }
+        );
+
+        assert!(TrunkPage::parse(3, &trunk(0, &[11]), 10).is_err());
+        let mut data = trunk(0, &[]);
+        data[4..8].copy_from_slice(&200u32.to_be_bytes());
+        assert!(TrunkPage::parse(3, &data, 10).is_err());
+    }
+
+    #[test]
+    fn allocate_from_the_first_trunk() {
+        let mut freelist = Freelist {
+            trunks: vec![
+                TrunkPage::parse(3, &trunk(9, &[4, 7]), 10).unwrap(),
+                TrunkPage::parse(9, &trunk(0, &[]), 10).unwrap(),
+            ],
+        };
+        assert_eq!(freelist.page_count(), 4);
+        assert_eq!(freelist.pages().collect::<Vec<_>>(), vec![3, 4, 7, 9]);
+
+        let allocated = std::iter::from_fn(|| freelist.allocate()).collect::<Vec<_>>();
+        assert_eq!(allocated, vec![7, 4, 3, 9]);
+        assert_eq!(freelist.page_count(), 0);
+    }
+}
@@ -0,0 +1,534 @@
+use std::collections::BTreeSet;
+
+use crate::database::Database;
+use crate::error::{bail, format_err, Result};
+use crate::record::{ColumnValue, Record};
+use crate::sqlite_schema::Table;
+use crate::varient;
+
+/// Rowid of the record describing the segments of the index in `%_data`.
+const STRUCTURE_ROWID: i64 = 10;
+const STRUCTURE_V2: [u8; 4] = [0xff, 0x00, 0x00, 0x01];
+/// Terms of the main index are prefixed with '0'; prefix indexes use '1'..
+const MAIN_INDEX_PREFIX: u8 = b'0';
+
+/// Column names declared in `USING fts5(...)`, skipping `key=value` options.
+pub fn column_names(arguments: &[String]) -> Vec<String> {
+    arguments
+        .iter()
+        .filter(|argument| !argument.contains('='))
+        .filter_map(|argument| argument.split_whitespace().next())
+        .map(unquote)
+        .collect()
+}
+
+/// Value of a `key=value` option given in `USING fts5(...)`.
+pub fn option(arguments: &[String], key: &str) -> Option<String> {
+    arguments.iter().find_map(|argument| {
+        let (name, value) = argument.split_once('=')?;
+        name.trim()
+            .eq_ignore_ascii_case(key)
+            .then(|| unquote(value.trim()))
+    })
+}
+
+fn unquote(value: &str) -> String {
+    let quoted = value.len() >= 2
+        && matches!(
+            (value.as_bytes()[0], value.as_bytes()[value.len() - 1]),
+            (b'\'', b'\'') | (b'"', b'"') | (b'`', b'`') | (b'[', b']')
+        );
+    if quoted {
+        value[1..value.len() - 1].to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    pub id: i64,
+    pub first_page: i64,
+    pub last_page: i64,
+}
+
+/// The structure record: segments grouped by level, newest level first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Structure {
+    pub levels: Vec<Vec<Segment>>,
+}
+
+impl Structure {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < 4 {
+            bail!("Malformed fts5 structure record");
+        }
+
+        // Skip the configuration cookie.
+        let mut cursor = 4;
+        let is_v2 = data[cursor..].starts_with(&STRUCTURE_V2);
+        if is_v2 {
+            cursor += STRUCTURE_V2.len();
+        }
+
+        let mut next = || {
+            let (value, offset) = varient::read(&data[cursor.min(data.len())..]);
+            cursor += offset;
+            value
+        };
+
+        let level_count = next();
+        let _segment_count = next();
+        let _write_counter = next();
+
+        let mut levels = Vec::with_capacity(level_count as usize);
+        for _ in 0..level_count {
+            let _merge = next();
+            let segment_count = next();
+
+            let mut segments = Vec::with_capacity(segment_count as usize);
+            for _ in 0..segment_count {
+                let id = next();
+                let first_page = next();
+                let last_page = next();
+                if is_v2 {
+                    // Origin range, tombstone pages, tombstone and entry counts.
+                    for _ in 0..5 {
+                        next();
+                    }
+                }
+                segments.push(Segment {
+                    id,
+                    first_page,
+                    last_page,
+                });
+            }
+            levels.push(segments);
+        }
+
+        Ok(Self { levels })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Term {
+    Exact(Vec<u8>),
+    Prefix(Vec<u8>),
+}
+
+impl Term {
+    fn new(token: &str) -> Self {
+        let mut key = vec![MAIN_INDEX_PREFIX];
+        match token.strip_suffix('*') {
+            Some(prefix) => {
+                key.extend(prefix.to_lowercase().bytes());
+                Term::Prefix(key)
+            }
+            None => {
+                key.extend(token.to_lowercase().bytes());
+                Term::Exact(key)
+            }
+        }
+    }
+
+    fn matches(&self, term: &[u8]) -> bool {
+        match self {
+            Term::Exact(key) => term == key.as_slice(),
+            Term::Prefix(key) => term.starts_with(key),
+        }
+    }
+
+    /// Whether no term sorting after `term` can match.
+    fn is_past(&self, term: &[u8]) -> bool {
+        match self {
+            Term::Exact(key) => term > key.as_slice(),
+            Term::Prefix(key) => term > key.as_slice() && !term.starts_with(key),
+        }
+    }
+}
+
+/// A `MATCH` query: documents matching every term of any one group.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchQuery {
+    pub groups: Vec<Vec<Term>>,
+}
+
+impl MatchQuery {
+    /// Parses the subset of the fts5 query syntax made of bare terms, `term*`
+    /// prefixes, AND, implicit or written, and `OR`. The rest of the syntax,
+    /// like phrases, NOT, NEAR and column filters, is refused rather than
+    /// read as something else.
+    pub fn parse(query: &str) -> Result<Self> {
+        let mut groups = vec![vec![]];
+        for token in query.split_whitespace() {
+            match token {
+                "OR" => {
+                    groups.push(vec![]);
+                    continue;
+                }
+                "AND" => continue,
+                "NOT" | "NEAR" => bail!("Unsupported MATCH operator: {}", token),
+                _ => {}
+            }
+
+            let word = token.strip_suffix('*').unwrap_or(token);
+            if word.is_empty() || !word.chars().all(char::is_alphanumeric) {
+                bail!("Unsupported MATCH syntax: {}", token);
+            }
+            groups.last_mut().unwrap().push(Term::new(token));
+        }
+
+        if groups.iter().any(|group| group.is_empty()) {
+            bail!("Malformed MATCH expression: {}", query);
+        }
+
+        Ok(Self { groups })
+    }
+}
+
+/// The full-text index of an fts5 table, stored in its `%_data` shadow table.
+#[derive(Debug)]
+pub struct Fts5Index {
+    pub name: String,
+    pub columns: Vec<String>,
+    pub data_rootpage: u32,
+    pub structure: Structure,
+}
+
+impl Fts5Index {
+    pub fn read(database: &mut Database, table: &Table) -> Result<Self> {
+        if !table.is_module("fts5") {
+            bail!("Not an fts5 table: {}", table.name);
+        }
+
+        let data_table = format!("{}_data", table.name);
+        let data_rootpage = database
+            .schema
+            .tables
+            .get(&data_table)
+            .ok_or(format_err!("Missing fts5 shadow table: {}", data_table))?
+            .rootpage;
+
+        let mut index = Self {
+            name: table.name.clone(),
+            columns: table.columns.iter().map(|column| column.name.clone()).collect(),
+            data_rootpage,
+            structure: Structure { levels: vec![] },
+        };
+        let structure = index
+            .read_block(database, STRUCTURE_ROWID)?
+            .ok_or(format_err!("Missing fts5 structure record"))?;
+        index.structure = Structure::parse(&structure)?;
+
+        Ok(index)
+    }
+
+    fn read_block(&self, database: &mut Database, id: i64) -> Result<Option<Vec<u8>>> {
+        let Some(payload) = database.find_row_payload(self.data_rootpage, id)? else {
+            return Ok(None);
+        };
+
+        match Record::read(id, &payload).values.get(1) {
+            Some(ColumnValue::Blob(block)) => Ok(Some(block.to_vec())),
+            _ => bail!("Malformed fts5 data record: {}", id),
+        }
+    }
+
+    /// Rowids of the documents matching `query`, in ascending order. With a
+    /// `column`, only occurrences in that column count.
+    pub fn search(
+        &self,
+        database: &mut Database,
+        query: &MatchQuery,
+        column: Option<usize>,
+    ) -> Result<Vec<i64>> {
+        let mut rowids = BTreeSet::new();
+        for group in query.groups.iter() {
+            let mut matching: Option<BTreeSet<i64>> = None;
+            for term in group.iter() {
+                let found = self.lookup(database, term, column)?;
+                matching = Some(match matching {
+                    Some(matching) => matching.intersection(&found).copied().collect(),
+                    None => found,
+                });
+            }
+            rowids.extend(matching.unwrap_or_default());
+        }
+
+        Ok(rowids.into_iter().collect())
+    }
+
+    pub fn lookup(
+        &self,
+        database: &mut Database,
+        term: &Term,
+        column: Option<usize>,
+    ) -> Result<BTreeSet<i64>> {
+        let mut rowids = BTreeSet::new();
+
+        // Older segments live on higher levels; apply them first so that
+        // delete markers in newer segments win.
+        for level in self.structure.levels.iter().rev() {
+            for segment in level.iter() {
+                for (rowid, deleted) in self.read_segment(database, segment, term, column)? {
+                    if deleted {
+                        rowids.remove(&rowid);
+                    } else {
+                        rowids.insert(rowid);
+                    }
+                }
+            }
+        }
+
+        Ok(rowids)
+    }
+
+    fn read_segment(
+        &self,
+        database: &mut Database,
+        segment: &Segment,
+        term: &Term,
+        column: Option<usize>,
+    ) -> Result<Vec<(i64, bool)>> {
+        let mut entries = vec![];
+        let mut in_doclist = false;
+        let mut pending: Option<PendingEntry> = None;
+        let mut previous_term: Vec<u8> = vec![];
+
+        for page_number in segment.first_page..=segment.last_page {
+            let id = (segment.id << 37) + page_number;
+            let Some(page) = self.read_block(database, id)? else {
+                bail!("Missing fts5 leaf page: {}", id);
+            };
+            if page.len() < 4 {
+                bail!("Malformed fts5 leaf page: {}", id);
+            }
+
+            let rowid_offset = u16::from_be_bytes([page[0], page[1]]) as usize;
+            let leaf_end = (u16::from_be_bytes([page[2], page[3]]) as usize).min(page.len());
+
+            let mut term_offsets = vec![];
+            let mut cursor = leaf_end;
+            let mut offset = 0;
+            while cursor < page.len() {
+                let (delta, read) = varient::read(&page[cursor..]);
+                cursor += read;
+                offset += delta as usize;
+                term_offsets.push(offset.min(leaf_end));
+            }
+            let first_term = term_offsets.first().copied().unwrap_or(leaf_end);
+
+            // A doclist started on an earlier page continues on this one, first
+            // with the rest of a position list cut off at the end of that page.
+            if in_doclist {
+                if let Some(mut entry) = pending.take() {
+                    let end = match rowid_offset {
+                        0 => first_term,
+                        offset => offset.min(first_term),
+                    };
+                    entry.append(&page[4.min(end)..end], column, &mut entries);
+                    pending = Some(entry).filter(|entry| entry.remaining > 0);
+                }
+                if rowid_offset != 0 && rowid_offset < first_term {
+                    pending = read_doclist(&page[rowid_offset..first_term], column, &mut entries);
+                }
+            }
+            if term_offsets.is_empty() {
+                continue;
+            }
+            in_doclist = false;
+            pending = None;
+
+            for (i, offset) in term_offsets.iter().enumerate() {
+                let mut cursor = *offset;
+                let mut current_term = vec![];
+                if i > 0 {
+                    let (prefix, read) = varient::read(&page[cursor..]);
+                    cursor += read;
+                    current_term.extend(&previous_term[..(prefix as usize).min(previous_term.len())]);
+                }
+                let (suffix, read) = varient::read(&page[cursor..]);
+                cursor += read;
+                let suffix_end = (cursor + suffix as usize).min(leaf_end);
+                current_term.extend(&page[cursor..suffix_end]);
+
+                let end = term_offsets.get(i + 1).copied().unwrap_or(leaf_end);
+                if term.matches(&current_term) {
+                    pending =
+                        read_doclist(&page[suffix_end..end.max(suffix_end)], column, &mut entries);
+                    in_doclist = end == leaf_end;
+                } else if term.is_past(&current_term) {
+                    return Ok(entries);
+                }
+                previous_term = current_term;
+            }
+        }
+
+        Ok(entries)
+    }
+}
+
+/// A doclist entry whose position list continues on the next leaf page.
+#[derive(Debug)]
+struct PendingEntry {
+    rowid: i64,
+    deleted: bool,
+    poslist: Vec<u8>,
+    remaining: usize,
+}
+
+impl PendingEntry {
+    fn append(&mut self, data: &[u8], column: Option<usize>, entries: &mut Vec<(i64, bool)>) {
+        let data = &data[..self.remaining.min(data.len())];
+        self.poslist.extend(data);
+        self.remaining -= data.len();
+
+        if self.remaining == 0 {
+            let in_column = column.is_none_or(|column| poslist_has_column(&self.poslist, column));
+            if self.deleted || in_column {
+                entries.push((self.rowid, self.deleted));
+            }
+        }
+    }
+}
+
+/// Collects `(rowid, is_delete_marker)` pairs from a doclist fragment that
+/// starts with an absolute rowid. Returns the last entry if its position list
+/// is cut off and still needed to check `column`.
+fn read_doclist(
+    data: &[u8],
+    column: Option<usize>,
+    entries: &mut Vec<(i64, bool)>,
+) -> Option<PendingEntry> {
+    let mut cursor = 0;
+    let mut rowid = 0;
+    let mut first = true;
+
+    while cursor < data.len() {
+        let (value, read) = varient::read(&data[cursor..]);
+        cursor += read;
+        rowid = if first { value } else { rowid + value };
+        first = false;
+        if cursor >= data.len() {
+            break;
+        }
+
+        let (header, read) = varient::read(&data[cursor..]);
+        cursor += read;
+        let size = (header >> 1) as usize;
+        let deleted = header & 1 == 1;
+        let poslist = &data[cursor..(cursor + size).min(data.len())];
+        cursor += size;
+
+        if poslist.len() < size && column.is_some() && !deleted {
+            return Some(PendingEntry {
+                rowid,
+                deleted,
+                poslist: poslist.to_vec(),
+                remaining: size - poslist.len(),
+            });
+        }
+
+        let in_column = column.is_none_or(|column| poslist_has_column(poslist, column));
+        if deleted || in_column {
+            entries.push((rowid, deleted));
+        }
+    }
+
+    None
+}
+
+/// Position lists start in column 0; `0x01 <column>` switches columns.
+fn poslist_has_column(poslist: &[u8], column: usize) -> bool {
+    let mut cursor = 0;
+    let mut current = 0;
+    while cursor < poslist.len() {
+        let (value, read) = varient::read(&poslist[cursor..]);
+        cursor += read;
+        if value == 1 {
+            let (next, read) = varient::read(&poslist[cursor.min(poslist.len())..]);
+            cursor += read;
+            current = next as usize;
+        } else if current == column {
+            return true;
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_structure_record() {
+        let data = [
+            0x00, 0x00, 0x00, 0x00, 0x01, 0x02, 0x22, 0x00, 0x02, 0x01, 0x01, 0x21, 0x02, 0x01,
+            0x01,
+        ];
+
+        assert_eq!(
+            Structure::parse(&data).unwrap(),
+            Structure {
+                levels: vec![vec![
+                    Segment {
+                        id: 1,
+                        first_page: 1,
+                        last_page: 33
+                    },
+                    Segment {
+                        id: 2,
+                        first_page: 1,
+                        last_page: 1
+                    }
+                ]]
+            }
+        );
+    }
+
+    #[test]
+    fn parse_match_query() {
+        let query = MatchQuery::parse("Apple ban* OR cherry").unwrap();
+
+        assert_eq!(
+            query.groups,
+            vec![
+                vec![
+                    Term::Exact(b"0apple".to_vec()),
+                    Term::Prefix(b"0ban".to_vec())
+                ],
+                vec![Term::Exact(b"0cherry".to_vec())]
+            ]
+        );
+        assert_eq!(MatchQuery::parse("apple AND ban*").unwrap(), MatchQuery::parse("apple ban*").unwrap());
+
+        for query in [
+            "\"apple pie\"",
+            "apple NOT pie",
+            "NEAR(apple pie)",
+            "apple NEAR pie",
+            "title:apple",
+            "(apple OR pie)",
+            "+apple",
+            "^apple",
+            "ap*ple",
+            "*",
+            "apple-pie",
+        ] {
+            assert!(MatchQuery::parse(query).is_err(), "{} was accepted", query);
+        }
+        assert!(MatchQuery::parse("apple OR").is_err());
+    }
+
+    #[test]
+    fn column_names_skip_options() {
+        let arguments = vec![
+            "title".to_string(),
+            "body UNINDEXED".to_string(),
+            "tokenize = 'porter'".to_string(),
+        ];
+
+        assert_eq!(column_names(&arguments), vec!["title", "body"]);
+        assert_eq!(option(&arguments, "tokenize"), Some("porter".to_string()));
+    }
+}
@@ -0,0 +1,175 @@
+use std::ops::RangeInclusive;
+
+use crate::expr::{as_text as text, numeric, Value};
+use crate::record::ColumnValue;
+
+/// A scalar function: its name, how many arguments it takes, and how it
+/// computes its value from them.
+pub struct Function {
+    pub name: &'static str,
+    pub arguments: RangeInclusive<usize>,
+    pub call: fn(&[ColumnValue]) -> Value<'static>,
+}
+
+/// Every scalar function expressions can call.
+pub const FUNCTIONS: &[Function] = &[
+    Function {
+        name: "length",
+        arguments: 1..=1,
+        call: length,
+    },
+    Function {
+        name: "lower",
+        arguments: 1..=1,
+        call: lower,
+    },
+    Function {
+        name: "substr",
+        arguments: 2..=3,
+        call: substr,
+    },
+    Function {
+        name: "substring",
+        arguments: 2..=3,
+        call: substr,
+    },
+    Function {
+        name: "upper",
+        arguments: 1..=1,
+        call: upper,
+    },
+];
+
+/// The function named `name`, in any case.
+pub fn find(name: &str) -> Option<&'static Function> {
+    FUNCTIONS
+        .iter()
+        .find(|function| function.name.eq_ignore_ascii_case(name))
+}
+
+const NULL: Value = Value::Read(ColumnValue::Null);
+
+/// The value as an integer, for arguments that count.
+fn integer(value: &ColumnValue) -> Option<i64> {
+    match numeric(value)? {
+        ColumnValue::F64(n) => Some(n as i64),
+        n => n.as_integer(),
+    }
+}
+
+/// Characters of text up to the first NUL, bytes of a blob, and characters
+/// of numbers written as text.
+fn length(arguments: &[ColumnValue]) -> Value<'static> {
+    let length = match &arguments[0] {
+        ColumnValue::Null => return NULL,
+        ColumnValue::Blob(bytes) => bytes.len(),
+        value => text(value).chars().take_while(|&c| c != '\0').count(),
+    };
+    Value::Read(ColumnValue::I64(length as i64))
+}
+
+/// Only ASCII letters change case, as in SQLite.
+fn lower(arguments: &[ColumnValue]) -> Value<'static> {
+    match &arguments[0] {
+        ColumnValue::Null => NULL,
+        value => Value::Text(text(value).to_ascii_lowercase()),
+    }
+}
+
+fn upper(arguments: &[ColumnValue]) -> Value<'static> {
+    match &arguments[0] {
+        ColumnValue::Null => NULL,
+        value => Value::Text(text(value).to_ascii_uppercase()),
+    }
+}
+
+/// `substr(X, Y, Z)`: `Z` characters of `X` from the `Y`th, counting from
+/// 1, or from the end when `Y` is negative. A negative `Z` takes the
+/// characters before instead. Blobs are cut in bytes.
+fn substr(arguments: &[ColumnValue]) -> Value<'static> {
+    let Some(start) = integer(&arguments[1]) else {
+        return NULL;
+    };
+    let count = match arguments.get(2) {
+        Some(count) => match integer(count) {
+            Some(count) => Some(count),
+            None => return NULL,
+        },
+        None => None,
+    };
+
+    let range = |length: usize| {
+        let length = length as i64;
+        let mut start = start;
+        let mut taken = count.map_or(i64::MAX, i64::saturating_abs);
+        if start < 0 {
+            start += length;
+            if start < 0 {
+                taken = (taken + start).max(0);
+                start = 0;
+            }
+        } else if start > 0 {
+            start -= 1;
+        } else if taken > 0 {
+            // SQLite counts the position before the first character as 0.
+            taken -= 1;
+        }
+        if count.is_some_and(|count| count < 0) {
+            start -= taken;
+            if start < 0 {
+                taken += start;
+                start = 0;
+            }
+        }
+        let start = start.min(length);
+        (start as usize, start.saturating_add(taken).min(length) as usize)
+    };
+
+    match &arguments[0] {
+        ColumnValue::Null => NULL,
+        ColumnValue::Blob(bytes) => {
+            let (start, end) = range(bytes.len());
+            Value::Blob(bytes[start..end].to_vec())
+        }
+        value => {
+            let chars = text(value).chars().collect::<Vec<_>>();
+            let (start, end) = range(chars.len());
+            Value::Text(chars[start..end].iter().collect())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(name: &str, arguments: &[ColumnValue]) -> String {
+        (find(name).unwrap().call)(arguments).get().to_string()
+    }
+
+    #[test]
+    fn string_functions() {
+        let text = [ColumnValue::Text("Épée".as_bytes())];
+        assert_eq!(call("LENGTH", &text), "4");
+        assert_eq!(call("length", &[ColumnValue::F64(1.5)]), "3");
+        assert_eq!(call("length", &[ColumnValue::Null]), "NULL");
+        assert_eq!(call("upper", &text), "ÉPéE");
+        assert_eq!(call("lower", &text), "Épée");
+
+        let substr = |start: i64, count: Option<i64>| {
+            let mut arguments = vec![ColumnValue::Text(b"abcdef"), ColumnValue::I64(start)];
+            arguments.extend(count.map(ColumnValue::I64));
+            call("substr", &arguments)
+        };
+        assert_eq!(substr(2, Some(3)), "bcd");
+        assert_eq!(substr(2, None), "bcdef");
+        assert_eq!(substr(0, Some(2)), "a");
+        assert_eq!(substr(-2, None), "ef");
+        assert_eq!(substr(-8, Some(4)), "ab");
+        assert_eq!(substr(4, Some(-2)), "bc");
+        assert_eq!(substr(2, Some(-3)), "a");
+        assert_eq!(substr(9, Some(2)), "");
+        assert_eq!(call("substr", &[text[0].clone(), ColumnValue::I64(2), ColumnValue::I64(2)]), "pé");
+        assert!(find("nope").is_none());
+    }
+}
@@ -0,0 +1,330 @@
+use std::collections::HashMap;
+
+use crate::error::{bail, Error, Result};
+use crate::output::RowSink;
+use crate::record::{self, Collation, ColumnValue, Record};
+use crate::sql::AggregateFunction;
+
+/// Running state of one aggregate over the rows of a group.
+#[derive(Debug, Clone)]
+enum Accumulator {
+    Count(u64),
+    /// Integers are added exactly until a real shows up.
+    Sum {
+        integer: i64,
+        real: f64,
+        is_real: bool,
+        seen: bool,
+    },
+    Avg {
+        total: f64,
+        count: u64,
+    },
+    /// Always a real, and 0.0 rather than NULL without values.
+    Total(f64),
+    /// The best value so far, encoded as a record, and how text compares.
+    Min(Option<Vec<u8>>, Collation),
+    Max(Option<Vec<u8>>, Collation),
+}
+
+impl Accumulator {
+    fn new(function: AggregateFunction, collation: Collation) -> Self {
+        match function {
+            AggregateFunction::Count => Accumulator::Count(0),
+            AggregateFunction::Sum => Accumulator::Sum {
+                integer: 0,
+                real: 0.0,
+                is_real: false,
+                seen: false,
+            },
+            AggregateFunction::Avg => Accumulator::Avg { total: 0.0, count: 0 },
+            AggregateFunction::Total => Accumulator::Total(0.0),
+            AggregateFunction::Min => Accumulator::Min(None, collation),
+            AggregateFunction::Max => Accumulator::Max(None, collation),
+        }
+    }
+
+    /// Adds a row. `value` is `None` for `COUNT(*)`, which counts rows
+    /// rather than values. Every other aggregate skips NULLs.
+    fn add(&mut self, value: Option<&ColumnValue>) -> Result<()> {
+        if value.is_some_and(|value| value.is_null()) {
+            return Ok(());
+        }
+        let is_min = matches!(self, Accumulator::Min(..));
+
+        match self {
+            Accumulator::Count(count) => *count += 1,
+            Accumulator::Sum {
+                integer,
+                real,
+                is_real,
+                seen,
+            } => {
+                let value = value.expect("SUM has an argument");
+                *seen = true;
+                match value.as_integer() {
+                    Some(n) if !*is_real => {
+                        *integer = match integer.checked_add(n) {
+                            Some(sum) => sum,
+                            None => bail!("integer overflow"),
+                        };
+                    }
+                    _ => {
+                        if !*is_real {
+                            *is_real = true;
+                            *real = *integer as f64;
+                        }
+                        *real += value.as_number();
+                    }
+                }
+            }
+            Accumulator::Avg { total, count } => {
+                *total += value.expect("AVG has an argument").as_number();
+                *count += 1;
+            }
+            Accumulator::Total(total) => *total += value.expect("TOTAL has an argument").as_number(),
+            Accumulator::Min(best, collation) | Accumulator::Max(best, collation) => {
+                let value = value.expect("MIN and MAX have an argument");
+                let is_better = match best {
+                    None => true,
+                    Some(best) => {
+                        let ordering = value.compare_collated(&Record::read(0, best).values[0], *collation);
+                        if is_min {
+                            ordering.is_lt()
+                        } else {
+                            ordering.is_gt()
+                        }
+                    }
+                };
+                if is_better {
+                    *best = Some(record::encode(std::slice::from_ref(value)));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Bytes the accumulator holds besides itself.
+    fn size(&self) -> u64 {
+        match self {
+            Accumulator::Min(Some(best), _) | Accumulator::Max(Some(best), _) => best.len() as u64,
+            _ => 0,
+        }
+    }
+
+    fn value(&self) -> ColumnValue<'_> {
+        match self {
+            Accumulator::Count(count) => ColumnValue::I64(*count as i64),
+            Accumulator::Sum { seen: false, .. } => ColumnValue::Null,
+            Accumulator::Sum {
+                real, is_real: true, ..
+            } => ColumnValue::F64(*real),
+            Accumulator::Sum { integer, .. } => ColumnValue::I64(*integer),
+            Accumulator::Avg { count: 0, .. } => ColumnValue::Null,
+            Accumulator::Avg { total, count } => ColumnValue::F64(total / *count as f64),
+            Accumulator::Total(total) => ColumnValue::F64(*total),
+            Accumulator::Min(best, _) | Accumulator::Max(best, _) => match best {
+                Some(best) => Record::read(0, best).values[0].clone(),
+                None => ColumnValue::Null,
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Group {
+    /// The GROUP BY values, encoded as a record.
+    key: Vec<u8>,
+    /// The last row of the group, for columns that aren't aggregated.
+    row: Vec<u8>,
+    accumulators: Vec<Accumulator>,
+}
+
+/// Collects rows into groups with equal GROUP BY values and computes the
+/// aggregates of every group. Groups are kept in memory, so the memory
+/// limit applies to them.
+#[derive(Debug)]
+pub struct Grouper {
+    /// Positions of the GROUP BY values in the rows.
+    keys: Vec<usize>,
+    /// Aggregates to compute, with the position of their argument and the
+    /// collation MIN and MAX compare text by.
+    aggregates: Vec<(AggregateFunction, Option<usize>, Collation)>,
+    /// Values in every row.
+    width: usize,
+    memory_limit: Option<u64>,
+    memory_used: u64,
+    groups: Vec<Group>,
+    positions: HashMap<Vec<u8>, usize>,
+}
+
+impl Grouper {
+    pub fn new(
+        keys: Vec<usize>,
+        aggregates: Vec<(AggregateFunction, Option<usize>, Collation)>,
+        width: usize,
+        memory_limit: Option<u64>,
+    ) -> Self {
+        Self {
+            keys,
+            aggregates,
+            width,
+            memory_limit,
+            memory_used: 0,
+            groups: vec![],
+            positions: HashMap::new(),
+        }
+    }
+
+    pub fn memory_used(&self) -> u64 {
+        self.memory_used
+    }
+
+    fn reserve(&mut self, bytes: u64) -> Result<()> {
+        self.memory_used += bytes;
+        match self.memory_limit {
+            Some(limit) if self.memory_used > limit => Err(Error::MemoryLimit { limit }),
+            _ => Ok(()),
+        }
+    }
+
+    /// One row per group, ordered by the GROUP BY values: the values of the
+    /// last row of the group followed by the aggregates, encoded as a
+    /// record. Without GROUP BY there is always exactly one group, even
+    /// when there were no rows.
+    pub fn finish(mut self) -> Vec<Vec<u8>> {
+        if self.keys.is_empty() && self.groups.is_empty() {
+            self.groups.push(Group {
+                key: record::encode(&[]),
+                row: record::encode(&vec![ColumnValue::Null; self.width]),
+                accumulators: self
+                    .aggregates
+                    .iter()
+                    .map(|&(function, _, collation)| Accumulator::new(function, collation))
+                    .collect(),
+            });
+        }
+
+        let mut groups = self.groups;
+        groups.sort_by(|a, b| {
+            let (a, b) = (Record::read(0, &a.key), Record::read(0, &b.key));
+            a.values
+                .iter()
+                .zip(b.values.iter())
+                .map(|(a, b)| a.compare(b))
+                .find(|ordering| ordering.is_ne())
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        groups
+            .into_iter()
+            .map(|group| {
+                let row = Record::read(0, &group.row);
+                let mut values = row.values.clone();
+                values.extend(group.accumulators.iter().map(|accumulator| accumulator.value()));
+                record::encode(&values)
+            })
+            .collect()
+    }
+}
+
+impl RowSink for Grouper {
+    fn write_row(&mut self, values: &[ColumnValue]) -> Result<()> {
+        let key = self
+            .keys
+            .iter()
+            .map(|&i| values.get(i).cloned().unwrap_or(ColumnValue::Null))
+            .collect::<Vec<_>>();
+        let key = record::encode(&key);
+
+        let position = match self.positions.get(&key) {
+            Some(&position) => position,
+            None => {
+                let accumulators = self
+                    .aggregates
+                    .iter()
+                    .map(|&(function, _, collation)| Accumulator::new(function, collation))
+                    .collect::<Vec<_>>();
+                self.reserve(
+                    2 * key.len() as u64
+                        + (std::mem::size_of::<Group>()
+                            + accumulators.len() * std::mem::size_of::<Accumulator>())
+                            as u64,
+                )?;
+
+                self.positions.insert(key.clone(), self.groups.len());
+                self.groups.push(Group {
+                    key,
+                    row: vec![],
+                    accumulators,
+                });
+                self.groups.len() - 1
+            }
+        };
+
+        let row = record::encode(values);
+        let mut grown = row.len() as i64 - self.groups[position].row.len() as i64;
+        let group = &mut self.groups[position];
+        group.row = row;
+        for (accumulator, (_, argument, _)) in group.accumulators.iter_mut().zip(self.aggregates.iter())
+        {
+            let size = accumulator.size();
+            accumulator.add(argument.map(|i| values.get(i).unwrap_or(&ColumnValue::Null)))?;
+            grown += accumulator.size() as i64 - size as i64;
+        }
+
+        if grown > 0 {
+            self.reserve(grown as u64)?;
+        } else {
+            self.memory_used -= grown.unsigned_abs();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn group(keys: Vec<usize>, rows: &[Vec<ColumnValue>]) -> Vec<String> {
+        let aggregates = vec![
+            (AggregateFunction::Count, None, Collation::Binary),
+            (AggregateFunction::Sum, Some(1), Collation::Binary),
+            (AggregateFunction::Avg, Some(1), Collation::Binary),
+            (AggregateFunction::Min, Some(1), Collation::NoCase),
+            (AggregateFunction::Max, Some(1), Collation::NoCase),
+            (AggregateFunction::Total, Some(1), Collation::Binary),
+        ];
+        let mut grouper = Grouper::new(keys, aggregates, 2, None);
+        for row in rows {
+            grouper.write_row(row).unwrap();
+        }
+
+        grouper
+            .finish()
+            .iter()
+            .map(|row| {
+                let values = Record::read(0, row).values;
+                values.iter().map(|value| value.to_string()).collect::<Vec<_>>().join("|")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn aggregate_groups() {
+        let rows = vec![
+            vec![ColumnValue::Text(b"red"), ColumnValue::I8(3)],
+            vec![ColumnValue::Text(b"green"), ColumnValue::I8(1)],
+            vec![ColumnValue::Text(b"red"), ColumnValue::F64(1.5)],
+            vec![ColumnValue::Text(b"red"), ColumnValue::Null],
+        ];
+
+        assert_eq!(
+            group(vec![0], &rows),
+            vec!["green|1|1|1|1|1|1|1", "red|NULL|3|4.5|2.25|1.5|3|4.5"]
+        );
+        assert_eq!(group(vec![], &rows), vec!["red|NULL|4|5.5|1.8333333333333333|1|3|5.5"]);
+        assert_eq!(group(vec![], &[]), vec!["NULL|NULL|0|NULL|NULL|NULL|NULL|0"]);
+        assert!(group(vec![0], &[]).is_empty());
+    }
+}
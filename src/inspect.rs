@@ -0,0 +1,480 @@
+use std::fmt::Write;
+
+use crate::database::Database;
+use crate::error::{bail, format_err, Result};
+use crate::page::{Cell, Page, PageKind};
+use crate::ptrmap::PointerMapEntry;
+use crate::record::Record;
+use crate::varient;
+
+/// Annotated dump of a single page, used by the `.page N` command.
+#[derive(Debug)]
+pub struct PageDump {
+    pub page_number: u32,
+    pub data: Vec<u8>,
+    pub page: Option<Page>,
+    /// Entries of a pointer-map page.
+    pub ptrmap_entries: Option<Vec<PointerMapEntry>>,
+}
+
+impl PageDump {
+    pub fn read(database: &mut Database, page_number: u32) -> Result<Self> {
+        let page_count = database.page_count()?;
+        if page_number == 0 || page_number > page_count {
+            bail!("page {} out of range (1..={})", page_number, page_count);
+        }
+        let data = database.read_raw_page(page_number)?;
+
+        if let Some(pointer_map) = database.header.pointer_map() {
+            if pointer_map.is_ptrmap_page(page_number) {
+                let entries = pointer_map.parse(page_number, &data, page_count)?;
+                return Ok(Self {
+                    page_number,
+                    data,
+                    page: None,
+                    ptrmap_entries: Some(entries),
+                });
+            }
+        }
+
+        let offset = if page_number == 1 { 100 } else { 0 };
+        let page = Page::parse(
+            data[offset..].to_vec(),
+            offset as u16,
+            database.header.usable_size(),
+        )
+        .ok();
+
+        Ok(Self {
+            page_number,
+            data,
+            page,
+            ptrmap_entries: None,
+        })
+    }
+
+    fn write_header(&self, out: &mut String, page: &Page) -> std::fmt::Result {
+        let base = page.offset as usize;
+        let bytes = |from: usize, len: usize| hex(&self.data[base + from..base + from + len]);
+        let kind = match page.header.kind {
+            PageKind::InteriorIndex => "index interior (0x02)",
+            PageKind::InteriorTable => "table interior (0x05)",
+            PageKind::LeafIndex => "index leaf (0x0a)",
+            PageKind::LeafTable => "table leaf (0x0d)",
+        };
+
+        writeln!(out, "Header:")?;
+        if base != 0 {
+            writeln!(out, "  0000  {:<12} database file header (100 bytes)", "..")?;
+        }
+        writeln!(out, "  {:04x}  {:<12} page type: {}", base, bytes(0, 1), kind)?;
+        writeln!(
+            out,
+            "  {:04x}  {:<12} first freeblock: {}",
+            base + 1,
+            bytes(1, 2),
+            page.header.first_freeblock_start
+        )?;
+        writeln!(
+            out,
+            "  {:04x}  {:<12} number of cells: {}",
+            base + 3,
+            bytes(3, 2),
+            page.header.number_of_cells
+        )?;
+        writeln!(
+            out,
+            "  {:04x}  {:<12} cell content start: {}",
+            base + 5,
+            bytes(5, 2),
+            page.header.content_start_offset
+        )?;
+        writeln!(
+            out,
+            "  {:04x}  {:<12} fragmented free bytes: {}",
+            base + 7,
+            bytes(7, 1),
+            page.header.fragment_free_bytes
+        )?;
+        if let Some(right_child_page) = page.header.right_child_page_number {
+            writeln!(
+                out,
+                "  {:04x}  {:<12} right-most child page: {}",
+                base + 8,
+                bytes(8, 4),
+                right_child_page
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn write_cell_pointers(&self, out: &mut String, page: &Page) -> std::fmt::Result {
+        let start = page.offset as usize + page.header.kind.header_size();
+
+        writeln!(out, "Cell pointers:")?;
+        for (i, pointer) in page.cell_pointers.iter().enumerate() {
+            let at = start + 2 * i;
+            writeln!(
+                out,
+                "  {:04x}  {:<12} cell {} at {}",
+                at,
+                hex(&self.data[at..at + 2]),
+                i,
+                *pointer as usize + page.offset as usize
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn write_cells(&self, out: &mut String, page: &Page) -> std::fmt::Result {
+        writeln!(out, "Cells:")?;
+        for (i, (cell, pointer)) in page.cells().zip(page.cell_pointers.iter()).enumerate() {
+            let at = *pointer as usize + page.offset as usize;
+            write!(out, "  cell {} at {:04x}: ", i, at)?;
+
+            let (rowid, payload) = match cell {
+                Cell::InteriorTable {
+                    left_child_page,
+                    key,
+                } => {
+                    writeln!(out, "left child page {}, key {}", left_child_page, key)?;
+                    continue;
+                }
+                Cell::InteriorIndex {
+                    left_child_page,
+                    size,
+                    payload,
+                    ..
+                } => {
+                    writeln!(out, "left child page {}, payload {} bytes", left_child_page, size)?;
+                    (0, payload)
+                }
+                Cell::LeafIndex { size, payload, .. } => {
+                    writeln!(out, "payload {} bytes", size)?;
+                    (0, payload)
+                }
+                Cell::LeafTable {
+                    size,
+                    rowid,
+                    payload,
+                    ..
+                } => {
+                    writeln!(out, "rowid {}, payload {} bytes", rowid, size)?;
+                    (rowid, payload)
+                }
+            };
+
+            let (header_size, mut cursor) = varient::read(payload);
+            let mut serial_types = vec![];
+            while cursor < (header_size as usize).min(payload.len()) {
+                let (serial_type, offset) = varient::read(&payload[cursor..]);
+                serial_types.push(serial_type.to_string());
+                cursor += offset;
+            }
+            writeln!(
+                out,
+                "    record header: {} bytes, serial types [{}]",
+                header_size,
+                serial_types.join(", ")
+            )?;
+
+            let values = Record::read(rowid, payload)
+                .values
+                .iter()
+                .map(|value| value.to_string())
+                .collect::<Vec<_>>()
+                .join("|");
+            writeln!(out, "    values: {}", values)?;
+
+            if let Some(overflow_page) = cell.overflow_page() {
+                writeln!(out, "    first overflow page: {}", overflow_page)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_hex_dump(&self, out: &mut String) -> std::fmt::Result {
+        writeln!(out, "Hex dump:")?;
+
+        let mut previous: Option<&[u8]> = None;
+        let mut skipping = false;
+        for (i, line) in self.data.chunks(16).enumerate() {
+            if previous == Some(line) {
+                if !skipping {
+                    writeln!(out, "  *")?;
+                    skipping = true;
+                }
+                continue;
+            }
+            skipping = false;
+            previous = Some(line);
+
+            let ascii = line
+                .iter()
+                .map(|b| if b.is_ascii_graphic() || *b == b' ' { *b as char } else { '.' })
+                .collect::<String>();
+            writeln!(
+                out,
+                "  {:04x}  {:<23}  {:<23}  |{}|",
+                i * 16,
+                hex(&line[..8.min(line.len())]),
+                hex(&line[8.min(line.len())..]),
+                ascii
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl std::fmt::Display for PageDump {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut out = String::new();
+        writeln!(out, "Page {} ({} bytes)", self.page_number, self.data.len())?;
+
+        match &self.page {
+            Some(page) => {
+                self.write_header(&mut out, page)?;
+                self.write_cell_pointers(&mut out, page)?;
+                self.write_cells(&mut out, page)?;
+                writeln!(out, "Unused bytes: {}", page.unused_bytes())?;
+            }
+            None => match &self.ptrmap_entries {
+                Some(entries) => {
+                    writeln!(out, "Pointer-map entries:")?;
+                    for entry in entries.iter() {
+                        writeln!(
+                            out,
+                            "  page {}: {}, parent {}",
+                            entry.page_number, entry.kind, entry.parent
+                        )?;
+                    }
+                }
+                None => writeln!(out, "Not a B-tree page (overflow or freelist page)")?,
+            },
+        }
+
+        self.write_hex_dump(&mut out)?;
+        write!(f, "{}", out)
+    }
+}
+
+#[derive(Debug)]
+pub struct BTreeNode {
+    pub page_number: u32,
+    pub kind: PageKind,
+    pub cell_count: u16,
+    pub first_key: Option<String>,
+    pub last_key: Option<String>,
+    /// Child page numbers with the key range they cover.
+    pub children: Vec<(u32, String)>,
+}
+
+/// Shape of a table or index B-tree, rendered as a Graphviz DOT graph.
+#[derive(Debug)]
+pub struct BTreeGraph {
+    pub name: String,
+    pub nodes: Vec<BTreeNode>,
+}
+
+impl BTreeGraph {
+    pub fn read(database: &mut Database, name: &str) -> Result<Self> {
+        let rootpage = if name == "sqlite_schema" || name == "sqlite_master" {
+            1
+        } else if let Some(table) = database.schema.tables.get(name) {
+            table.rootpage
+        } else {
+            database
+                .schema
+                .find_index(name)
+                .ok_or(format_err!("No table or index named: {}", name))?
+                .rootpage
+        };
+
+        let mut nodes = vec![];
+        let mut stack = vec![rootpage];
+        while let Some(page_number) = stack.pop() {
+            let page = database.get_page(page_number - 1)?;
+
+            let mut keys = vec![];
+            let mut children = vec![];
+            let mut lower: Option<String> = None;
+            for cell in page.cells() {
+                let key = match cell {
+                    Cell::InteriorTable { key, .. } => key.to_string(),
+                    Cell::LeafTable { rowid, .. } => rowid.to_string(),
+                    Cell::InteriorIndex { payload, .. } | Cell::LeafIndex { payload, .. } => {
+                        index_key(&Record::read(0, payload))
+                    }
+                };
+
+                if let Cell::InteriorTable {
+                    left_child_page, ..
+                }
+                | Cell::InteriorIndex {
+                    left_child_page, ..
+                } = cell
+                {
+                    let range = match &lower {
+                        Some(lower) => format!("({}, {}]", lower, key),
+                        None => format!("<= {}", key),
+                    };
+                    children.push((left_child_page, range));
+                    lower = Some(key.clone());
+                }
+                keys.push(key);
+            }
+
+            if let Some(right_child_page) = page.header.right_child_page_number {
+                let range = match &lower {
+                    Some(lower) => format!("> {}", lower),
+                    None => "all".to_string(),
+                };
+                children.push((right_child_page, range));
+            }
+
+            stack.extend(children.iter().rev().map(|(child, _)| *child));
+            nodes.push(BTreeNode {
+                page_number,
+                kind: page.header.kind,
+                cell_count: page.header.number_of_cells,
+                first_key: keys.first().cloned(),
+                last_key: keys.last().cloned(),
+                children,
+            });
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            nodes,
+        })
+    }
+}
+
+/// Index keys without the trailing rowid.
+fn index_key(record: &Record) -> String {
+    let key_len = record.values.len().saturating_sub(1).max(1);
+    record
+        .values
+        .iter()
+        .take(key_len)
+        .map(|value| value.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn escape_dot(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+impl std::fmt::Display for BTreeGraph {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "digraph \"{}\" {{", escape_dot(&self.name))?;
+        writeln!(f, "  node [shape=box, fontname=\"monospace\"];")?;
+
+        for node in self.nodes.iter() {
+            let (kind, keys) = match node.kind {
+                PageKind::InteriorTable => ("table interior", "keys"),
+                PageKind::LeafTable => ("table leaf", "rowids"),
+                PageKind::InteriorIndex => ("index interior", "keys"),
+                PageKind::LeafIndex => ("index leaf", "keys"),
+            };
+            let range = match (&node.first_key, &node.last_key) {
+                (Some(first), Some(last)) => format!("{} {} .. {}", keys, first, last),
+                _ => "empty".to_string(),
+            };
+            writeln!(
+                f,
+                "  page{} [label=\"page {}\\n{}\\n{} cells\\n{}\"];",
+                node.page_number,
+                node.page_number,
+                kind,
+                node.cell_count,
+                escape_dot(&range)
+            )?;
+        }
+
+        for node in self.nodes.iter() {
+            for (child, range) in node.children.iter() {
+                writeln!(
+                    f,
+                    "  page{} -> page{} [label=\"{}\"];",
+                    node.page_number,
+                    child,
+                    escape_dot(range)
+                )?;
+            }
+        }
+
+        write!(f, "}}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Written by sqlite3 like the sample database of the codecrafters SQLite
+    /// challenge: `apples (id integer primary key autoincrement, name text,
+    /// color text)` with 4 rows and `oranges (id integer primary key
+    /// autoincrement, name text, description text)` with 6.
+    const SAMPLE_DB: &[u8] = include_bytes!("../testdata/sample.db");
+
+    #[test]
+    fn visualize_sample_tables() {
+        let path = std::env::temp_dir().join(format!("sample-visualize-{}.db", std::process::id()));
+        std::fs::write(&path, SAMPLE_DB).unwrap();
+        let mut database = Database::open(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let graph = |database: &mut Database, name: &str| BTreeGraph::read(database, name).unwrap().to_string();
+        assert_eq!(
+            graph(&mut database, "apples"),
+            "digraph \"apples\" {\n  \
+               node [shape=box, fontname=\"monospace\"];\n  \
+               page2 [label=\"page 2\\ntable leaf\\n4 cells\\nrowids 1 .. 4\"];\n\
+             }"
+        );
+        assert_eq!(
+            graph(&mut database, "oranges"),
+            "digraph \"oranges\" {\n  \
+               node [shape=box, fontname=\"monospace\"];\n  \
+               page4 [label=\"page 4\\ntable leaf\\n6 cells\\nrowids 1 .. 6\"];\n\
+             }"
+        );
+        assert_eq!(
+            graph(&mut database, "sqlite_schema"),
+            "digraph \"sqlite_schema\" {\n  \
+               node [shape=box, fontname=\"monospace\"];\n  \
+               page1 [label=\"page 1\\ntable leaf\\n3 cells\\nrowids 1 .. 3\"];\n\
+             }"
+        );
+        assert!(BTreeGraph::read(&mut database, "pears").is_err());
+    }
+
+    #[test]
+    fn dump_only_pages_in_the_file() {
+        let path = std::env::temp_dir().join(format!("sample-page-{}.db", std::process::id()));
+        std::fs::write(&path, SAMPLE_DB).unwrap();
+        let mut database = Database::open(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let dump = PageDump::read(&mut database, 4).unwrap();
+        assert_eq!(dump.page.unwrap().header.number_of_cells, 6);
+        let error = PageDump::read(&mut database, 0).unwrap_err();
+        assert_eq!(error.to_string(), "page 0 out of range (1..=4)");
+        let error = PageDump::read(&mut database, 5).unwrap_err();
+        assert_eq!(error.to_string(), "page 5 out of range (1..=4)");
+    }
+}
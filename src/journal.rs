@@ -0,0 +1,360 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use crate::database::Database;
+use crate::error::{bail, Result};
+
+/// Bytes a rollback journal starts with while it holds a transaction.
+const JOURNAL_MAGIC: [u8; 8] = [0xd9, 0xd5, 0x05, 0xf9, 0x20, 0xa1, 0x63, 0xd7];
+
+/// Size of the fixed part of a journal header, which is padded to a sector.
+const HEADER_SIZE: usize = 28;
+
+/// The sector size journals are written with, SQLite's default.
+const SECTOR_SIZE: usize = 512;
+
+fn read_u32(data: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]])
+}
+
+/// The pages of a hot journal: the content they had before a writer was
+/// interrupted in the middle of changing them. Reading these instead of
+/// the pages in the database file rolls the transaction back, like SQLite
+/// does the next time it opens the database, without writing to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RollbackJournal {
+    pub page_size: u32,
+    /// Number of pages of the database before the transaction.
+    pub database_size: u32,
+    pages: HashMap<u32, Vec<u8>>,
+}
+
+impl RollbackJournal {
+    /// Reads the journal of the database at `path` if it is hot. It isn't
+    /// when there is none, when its header was cleared to commit, when the
+    /// database file is empty, or when it belongs to a transaction across
+    /// databases whose super-journal was already deleted.
+    pub fn open(path: &str) -> Result<Option<Self>> {
+        let mut journal = match File::open(format!("{}-journal", path)) {
+            Ok(journal) => journal,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        if std::fs::metadata(path)?.len() == 0 {
+            return Ok(None);
+        }
+
+        let mut magic = [0; 8];
+        if journal.read_exact(&mut magic).is_err() || magic != JOURNAL_MAGIC {
+            return Ok(None);
+        }
+        let mut data = magic.to_vec();
+        journal.read_to_end(&mut data)?;
+
+        if let Some(name) = super_journal(&data) {
+            if !Path::new(&name).exists() {
+                return Ok(None);
+            }
+        }
+        Self::parse(&data)
+    }
+
+    /// Reads the page records of a journal, segment by segment. Records
+    /// stop at the first one whose checksum is wrong, which the writer
+    /// never finished writing.
+    pub fn parse(data: &[u8]) -> Result<Option<Self>> {
+        if data.len() < HEADER_SIZE || data[..8] != JOURNAL_MAGIC {
+            return Ok(None);
+        }
+        let sector_size = read_u32(data, 20) as usize;
+        let page_size = read_u32(data, 24);
+        if !(512..=65536).contains(&page_size) || !page_size.is_power_of_two() {
+            bail!("Hot journal is corrupt: invalid page size {}", page_size);
+        }
+        if !(32..=65536).contains(&sector_size) || !sector_size.is_power_of_two() {
+            bail!("Hot journal is corrupt: invalid sector size {}", sector_size);
+        }
+
+        let mut journal = Self {
+            page_size,
+            database_size: read_u32(data, 16),
+            pages: HashMap::new(),
+        };
+        let record_size = page_size as usize + 8;
+        let mut offset = 0;
+        'segments: while offset + HEADER_SIZE <= data.len() && data[offset..offset + 8] == JOURNAL_MAGIC {
+            let nonce = read_u32(data, offset + 12);
+            let records = match read_u32(data, offset + 8) {
+                // Written without syncing: the records run to the end of
+                // the file.
+                0xffff_ffff => (data.len().saturating_sub(offset + sector_size) / record_size) as u32,
+                records => records,
+            };
+            offset += sector_size;
+
+            for _ in 0..records {
+                let Some(record) = data.get(offset..offset + record_size) else {
+                    break 'segments;
+                };
+                let page_number = read_u32(record, 0);
+                let page = &record[4..record_size - 4];
+                if page_number == 0 || checksum(page, nonce) != read_u32(record, record_size - 4) {
+                    break 'segments;
+                }
+                // Only the first copy of a page is from before the
+                // transaction.
+                journal.pages.entry(page_number).or_insert_with(|| page.to_vec());
+                offset += record_size;
+            }
+            offset = offset.next_multiple_of(sector_size);
+        }
+        Ok(Some(journal))
+    }
+
+    /// The content of the page with the given 1-based number before the
+    /// transaction, if the transaction changed it.
+    pub fn page(&self, page_number: u32) -> Option<&[u8]> {
+        self.pages.get(&page_number).map(Vec::as_slice)
+    }
+
+    /// Number of pages rolled back.
+    pub fn page_count(&self) -> usize {
+        self.pages.len()
+    }
+}
+
+/// Writes the journal of a transaction about to change the database at
+/// `path`: a header with a random `nonce` and the `database_size` in pages
+/// before it, then `pages`, the content each page had by its number. The
+/// journal is synced before returning, so the database file may then be
+/// written in place; until the journal is deleted, readers roll the
+/// changes back.
+pub fn write(path: &str, page_size: u32, database_size: u32, pages: &[(u32, Vec<u8>)], nonce: u32) -> Result<()> {
+    let mut data = JOURNAL_MAGIC.to_vec();
+    for value in [pages.len() as u32, nonce, database_size, SECTOR_SIZE as u32, page_size] {
+        data.extend(value.to_be_bytes());
+    }
+    data.resize(SECTOR_SIZE, 0);
+    for (page_number, page) in pages {
+        data.extend(page_number.to_be_bytes());
+        data.extend(page);
+        data.extend(checksum(page, nonce).to_be_bytes());
+    }
+
+    let mut journal = File::create(format!("{}-journal", path))?;
+    journal.write_all(&data)?;
+    journal.sync_all()?;
+    Ok(())
+}
+
+/// Deletes the journal of the database at `path`, which commits the
+/// transaction it was written for.
+pub fn delete(path: &str) -> Result<()> {
+    match std::fs::remove_file(format!("{}-journal", path)) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Checksum of a page record: the nonce of the journal plus every 200th
+/// byte of the page, counting down from the end.
+fn checksum(page: &[u8], nonce: u32) -> u32 {
+    (1..)
+        .map(|step| page.len() as isize - 200 * step)
+        .take_while(|&at| at > 0)
+        .fold(nonce, |sum, at| sum.wrapping_add(page[at as usize] as u32))
+}
+
+/// The name of the super-journal recorded at the end of the journal of a
+/// transaction across several databases.
+fn super_journal(data: &[u8]) -> Option<String> {
+    let end = data.len().checked_sub(16)?;
+    if data[end + 8..] != JOURNAL_MAGIC {
+        return None;
+    }
+    let length = read_u32(data, end) as usize;
+    let name = data.get(end.checked_sub(length)?..end)?;
+    let sum = name.iter().fold(0u32, |sum, &b| sum.wrapping_add(b as u32));
+    (sum == read_u32(data, end + 4) && !name.is_empty()).then(|| String::from_utf8_lossy(name).into_owned())
+}
+
+/// Journal mode as far as it can be told from the files on disk. Rollback
+/// journal modes differ only in how the journal is discarded on commit, so
+/// without a leftover journal the default DELETE mode is assumed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JournalMode {
+    Delete,
+    Truncate,
+    Persist,
+    Wal,
+}
+
+impl std::fmt::Display for JournalMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JournalMode::Delete => write!(f, "delete"),
+            JournalMode::Truncate => write!(f, "truncate"),
+            JournalMode::Persist => write!(f, "persist"),
+            JournalMode::Wal => write!(f, "wal"),
+        }
+    }
+}
+
+/// The journal, WAL and shared-memory files next to a database, and what
+/// they say about the state it is read in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileState {
+    pub journal_mode: JournalMode,
+    /// Size of the rollback journal, if there is one.
+    pub journal_size: Option<u64>,
+    /// A rollback journal holding a transaction that was never finished,
+    /// which reads roll back.
+    pub hot_journal: bool,
+    pub wal_file: bool,
+    pub shm_file: bool,
+    /// A writer appears to be in the middle of a transaction: there is a hot
+    /// journal, or frames after the last commit in the WAL.
+    pub mid_transaction: bool,
+}
+
+impl FileState {
+    pub fn read(database: &Database) -> Result<Self> {
+        let path = &database.path;
+        let journal = format!("{}-journal", path);
+        let journal_size = std::fs::metadata(&journal).ok().map(|meta| meta.len());
+
+        let journal_header = match journal_size {
+            Some(size) if size >= 8 => {
+                let mut header = [0; 8];
+                File::open(&journal)?.read_exact(&mut header)?;
+                Some(header)
+            }
+            _ => None,
+        };
+        let hot_journal = database.rollback_journal().is_some();
+
+        let journal_mode = if database.header.is_wal() {
+            JournalMode::Wal
+        } else {
+            match (journal_size, journal_header) {
+                (Some(0), _) => JournalMode::Truncate,
+                // Persisted journals are invalidated by zeroing their header.
+                (_, Some([0, 0, 0, 0, 0, 0, 0, 0])) => JournalMode::Persist,
+                _ => JournalMode::Delete,
+            }
+        };
+
+        let uncommitted_frames = database
+            .wal
+            .as_ref()
+            .is_some_and(|wal| wal.valid_frames > wal.max_frame);
+
+        Ok(Self {
+            journal_mode,
+            journal_size,
+            hot_journal,
+            wal_file: Path::new(&format!("{}-wal", path)).exists(),
+            shm_file: Path::new(&format!("{}-shm", path)).exists(),
+            mid_transaction: hot_journal || uncommitted_frames,
+        })
+    }
+}
+
+impl std::fmt::Display for FileState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let yes_no = |value: bool| if value { "yes" } else { "no" };
+
+        writeln!(f, "journal mode: {}", self.journal_mode)?;
+        match self.journal_size {
+            Some(size) => writeln!(f, "journal file: {} bytes", size)?,
+            None => writeln!(f, "journal file: none")?,
+        }
+        writeln!(f, "hot journal: {}", yes_no(self.hot_journal))?;
+        writeln!(f, "wal file: {}", yes_no(self.wal_file))?;
+        writeln!(f, "shm file: {}", yes_no(self.shm_file))?;
+        write!(f, "mid-transaction: {}", yes_no(self.mid_transaction))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(records: u32, nonce: u32, database_size: u32) -> Vec<u8> {
+        let mut header = JOURNAL_MAGIC.to_vec();
+        for value in [records, nonce, database_size, 512, 512] {
+            header.extend(value.to_be_bytes());
+        }
+        header.resize(512, 0);
+        header
+    }
+
+    fn record(page_number: u32, fill: u8, nonce: u32) -> Vec<u8> {
+        let page = vec![fill; 512];
+        let mut record = page_number.to_be_bytes().to_vec();
+        record.extend(&page);
+        record.extend(checksum(&page, nonce).to_be_bytes());
+        record
+    }
+
+    #[test]
+    fn read_pages_to_roll_back() {
+        let mut data = header(2, 7, 3);
+        data.extend(record(1, 0xaa, 7));
+        data.extend(record(3, 0xbb, 7));
+        data.resize(data.len().next_multiple_of(512), 0);
+        // A second segment with a later copy of page 3, then a torn record.
+        data.extend(header(0xffff_ffff, 9, 4));
+        data.extend(record(3, 0xcc, 9));
+        data.extend(record(2, 0xdd, 9));
+        data.extend(record(4, 0xee, 1));
+
+        let journal = RollbackJournal::parse(&data).unwrap().unwrap();
+        assert_eq!((journal.page_size, journal.database_size), (512, 3));
+        assert_eq!(journal.page_count(), 3);
+        assert_eq!(journal.page(1), Some(&[0xaa; 512][..]));
+        assert_eq!(journal.page(3), Some(&[0xbb; 512][..]));
+        assert_eq!(journal.page(2), Some(&[0xdd; 512][..]));
+        assert_eq!(journal.page(4), None);
+
+        // A cleared header means the transaction was committed.
+        data[..8].fill(0);
+        assert_eq!(RollbackJournal::parse(&data).unwrap(), None);
+
+        let mut data = header(0, 0, 1);
+        data[27] = 100;
+        assert!(RollbackJournal::parse(&data).is_err());
+    }
+
+    #[test]
+    fn read_written_journal() {
+        let path = std::env::temp_dir().join(format!("journal-{}.db", std::process::id()));
+        let path = path.to_str().unwrap();
+        write(path, 512, 3, &[(1, vec![0xaa; 512]), (3, vec![0xbb; 512])], 7).unwrap();
+        let data = std::fs::read(format!("{}-journal", path)).unwrap();
+        delete(path).unwrap();
+        delete(path).unwrap();
+
+        let journal = RollbackJournal::parse(&data).unwrap().unwrap();
+        assert_eq!((journal.page_size, journal.database_size), (512, 3));
+        assert_eq!(journal.page(1), Some(&[0xaa; 512][..]));
+        assert_eq!(journal.page(3), Some(&[0xbb; 512][..]));
+        assert_eq!(journal.page(2), None);
+    }
+
+    #[test]
+    fn read_super_journal_name() {
+        let mut data = header(0, 0, 1);
+        assert_eq!(super_journal(&data), None);
+
+        let name = b"/tmp/test.db-mj01";
+        data.extend(1u32.to_be_bytes());
+        data.extend(name);
+        data.extend((name.len() as u32).to_be_bytes());
+        data.extend(name.iter().map(|&b| b as u32).sum::<u32>().to_be_bytes());
+        data.extend(JOURNAL_MAGIC);
+        assert_eq!(super_journal(&data).as_deref(), Some("/tmp/test.db-mj01"));
+    }
+}
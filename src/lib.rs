@@ -1,6 +1,53 @@
+//! Reads and writes SQLite database files.
+//!
+//! ```
+//! use simple_sqlite::Database;
+//!
+//! let mut database = Database::open(":memory:")?;
+//! database.query("CREATE TABLE fruits (name, color)")?;
+//! let statement = database.prepare("SELECT * FROM fruits")?;
+//! assert_eq!(statement.columns(), ["name", "color"]);
+//! assert!(database.query("SELECT name FROM fruits WHERE color = 'red'")?.is_empty());
+//! assert!(database.schema().find_table("fruits").is_some());
+//! # Ok::<(), simple_sqlite::Error>(())
+//! ```
+
+pub mod blob;
+pub mod btree;
 pub mod database;
+pub mod de;
+pub mod diff;
+pub mod dump;
+pub mod error;
+pub mod expr;
+pub mod freelist;
+pub mod fts5;
+pub mod functions;
+pub mod group;
+pub mod inspect;
+pub mod journal;
+pub mod lock;
+pub mod output;
 pub mod page;
+pub mod pager;
+pub mod planner;
+pub mod pragma;
+pub mod ptrmap;
 pub mod record;
+pub mod recover;
+pub mod report;
+pub mod row;
+pub mod rtree;
+pub mod sort;
 pub mod sql;
 pub mod sqlite_schema;
+pub mod statement;
+pub mod stats;
 pub mod varient;
+pub mod wal;
+
+pub use database::Database;
+pub use error::Error;
+pub use row::{FromRow, FromValue};
+pub use sqlite_schema::SchemaStore;
+pub use statement::{ColumnOrigin, Row, Rows, Statement};
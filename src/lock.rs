@@ -0,0 +1,199 @@
+use std::fs::{File, OpenOptions};
+
+use crate::error::{Error, Result};
+
+/// The byte a connection locks while it waits for readers to finish before
+/// writing the file, which keeps new readers out meanwhile. It starts the
+/// page no data is stored on, at 1 GiB.
+const PENDING_BYTE: u64 = 0x4000_0000;
+/// The byte the single writer of the database locks.
+const RESERVED_BYTE: u64 = PENDING_BYTE + 1;
+/// The range readers take shared locks in, and a writer locks as a whole.
+const SHARED_FIRST: u64 = PENDING_BYTE + 2;
+const SHARED_SIZE: u64 = 510;
+/// The byte of the -shm file that WAL writers lock, after the 120 bytes of
+/// the wal-index header.
+const WAL_WRITE_LOCK: u64 = 120;
+
+/// How far a connection has locked the database file, as in SQLite: readers
+/// hold `Shared`, the one connection about to write `Reserved`, and it
+/// takes `Exclusive` to change the file once the readers are gone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LockLevel {
+    None,
+    Shared,
+    Reserved,
+    Exclusive,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LockKind {
+    Read,
+    Write,
+    Unlock,
+}
+
+/// Advisory locks on the bytes past `PENDING_BYTE` that SQLite locks, so
+/// that SQLite connections and this crate see each other's transactions.
+/// The locks belong to their own handle of the file, which closing other
+/// handles of it doesn't release.
+#[derive(Debug)]
+pub struct FileLock {
+    file: File,
+    level: LockLevel,
+}
+
+impl FileLock {
+    /// A handle to lock the database at `path` with, holding no locks yet.
+    pub fn open(path: &str) -> Result<Self> {
+        Ok(FileLock {
+            file: OpenOptions::new().read(true).write(true).open(path)?,
+            level: LockLevel::None,
+        })
+    }
+
+    pub fn level(&self) -> LockLevel {
+        self.level
+    }
+
+    /// Raises the lock to `level`, going through the levels in between.
+    /// Fails with `Error::Busy`, at the highest level it got to, if another
+    /// connection holds a lock in the way; there is no waiting for it.
+    pub fn lock(&mut self, level: LockLevel) -> Result<()> {
+        if level <= self.level {
+            return Ok(());
+        }
+        if self.level == LockLevel::None {
+            // Readers are kept out while a writer holds the pending byte.
+            self.set(LockKind::Read, PENDING_BYTE, 1)?;
+            let shared = self.set(LockKind::Read, SHARED_FIRST, SHARED_SIZE);
+            self.set(LockKind::Unlock, PENDING_BYTE, 1)?;
+            shared?;
+            self.level = LockLevel::Shared;
+        }
+        if level >= LockLevel::Reserved && self.level == LockLevel::Shared {
+            self.set(LockKind::Write, RESERVED_BYTE, 1)?;
+            self.level = LockLevel::Reserved;
+        }
+        if level == LockLevel::Exclusive {
+            self.set(LockKind::Write, PENDING_BYTE, 1)?;
+            if let Err(error) = self.set(LockKind::Write, SHARED_FIRST, SHARED_SIZE) {
+                // Unlike SQLite, which keeps the pending lock to wait for the
+                // readers, give up on writing for now.
+                self.set(LockKind::Unlock, PENDING_BYTE, 1)?;
+                return Err(error);
+            }
+            self.level = LockLevel::Exclusive;
+        }
+        Ok(())
+    }
+
+    /// Releases all locks.
+    pub fn unlock(&mut self) -> Result<()> {
+        if self.level != LockLevel::None {
+            self.set(LockKind::Unlock, PENDING_BYTE, SHARED_FIRST + SHARED_SIZE - PENDING_BYTE)?;
+            self.level = LockLevel::None;
+        }
+        Ok(())
+    }
+
+    fn set(&self, kind: LockKind, start: u64, len: u64) -> Result<()> {
+        set_lock(&self.file, kind, start, len)
+    }
+}
+
+/// The lock a WAL writer holds on the -shm file next to the database while
+/// it appends to the WAL, released by dropping it.
+#[derive(Debug)]
+pub struct WalWriteLock {
+    _file: File,
+}
+
+impl WalWriteLock {
+    /// Locks out other WAL writers of the database at `path`. Without a
+    /// -shm file, no SQLite connection has the WAL open to write to it, and
+    /// there is nothing to lock.
+    pub fn acquire(path: &str) -> Result<Option<Self>> {
+        let file = match OpenOptions::new().read(true).write(true).open(format!("{}-shm", path)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        set_lock(&file, LockKind::Write, WAL_WRITE_LOCK, 1)?;
+        Ok(Some(WalWriteLock { _file: file }))
+    }
+}
+
+/// Takes or releases an advisory lock on `len` bytes of the file from
+/// `start`. On Linux these are open file description locks, which other
+/// handles of the file in the same process don't release when closed, and
+/// which conflict with the POSIX locks of SQLite in other processes.
+#[cfg(unix)]
+fn set_lock(file: &File, kind: LockKind, start: u64, len: u64) -> Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    #[cfg(target_os = "linux")]
+    const SET_LOCK: libc::c_int = libc::F_OFD_SETLK;
+    #[cfg(not(target_os = "linux"))]
+    const SET_LOCK: libc::c_int = libc::F_SETLK;
+
+    // SAFETY: flock is plain old data, for which zeroes are valid, as the
+    // unused l_pid must be for open file description locks.
+    let mut lock: libc::flock = unsafe { std::mem::zeroed() };
+    lock.l_type = match kind {
+        LockKind::Read => libc::F_RDLCK as _,
+        LockKind::Write => libc::F_WRLCK as _,
+        LockKind::Unlock => libc::F_UNLCK as _,
+    };
+    lock.l_whence = libc::SEEK_SET as _;
+    lock.l_start = start as _;
+    lock.l_len = len as _;
+    // SAFETY: the descriptor stays open while `file` is borrowed, and the
+    // lock description outlives the call.
+    if unsafe { libc::fcntl(file.as_raw_fd(), SET_LOCK, &lock) } == -1 {
+        let error = std::io::Error::last_os_error();
+        return match error.raw_os_error() {
+            Some(libc::EACCES | libc::EAGAIN) => Err(Error::Busy),
+            _ => Err(error.into()),
+        };
+    }
+    Ok(())
+}
+
+/// Other platforms take no locks, so only one connection may write at a
+/// time there.
+#[cfg(not(unix))]
+fn set_lock(_file: &File, _kind: LockKind, _start: u64, _len: u64) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writers_and_readers_lock_each_other_out() {
+        let path = std::env::temp_dir().join(format!("lock-{}.db", std::process::id()));
+        let path = path.to_str().unwrap();
+        std::fs::write(path, [0; 512]).unwrap();
+        let mut first = FileLock::open(path).unwrap();
+        let mut second = FileLock::open(path).unwrap();
+        let busy = |result: Result<()>| matches!(result, Err(Error::Busy));
+
+        first.lock(LockLevel::Reserved).unwrap();
+        second.lock(LockLevel::Shared).unwrap();
+        assert!(busy(second.lock(LockLevel::Reserved)));
+        assert_eq!(second.level(), LockLevel::Shared);
+        assert!(busy(first.lock(LockLevel::Exclusive)));
+        assert_eq!(first.level(), LockLevel::Reserved);
+
+        second.unlock().unwrap();
+        first.lock(LockLevel::Exclusive).unwrap();
+        assert!(busy(second.lock(LockLevel::Shared)));
+        assert_eq!(second.level(), LockLevel::None);
+
+        first.unlock().unwrap();
+        second.lock(LockLevel::Reserved).unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -1,56 +1,610 @@
-use std::io::stdout;
+use std::io::{stdin, stdout, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
 
 use anyhow::{bail, Result};
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
 use simple_sqlite::database;
-use simple_sqlite::sql;
-use crate::{database::Database};
+use simple_sqlite::diff;
+use simple_sqlite::dump;
+use simple_sqlite::error::error_json;
+use simple_sqlite::inspect::{BTreeGraph, PageDump};
+use simple_sqlite::journal::FileState;
+use simple_sqlite::output::{Encoding, OutputFormat, OutputOptions, RowWriter};
+use simple_sqlite::pager::DEFAULT_CACHE_SIZE;
+use simple_sqlite::recover::Recovery;
+use simple_sqlite::report::SpaceReport;
+use simple_sqlite::statement::Statement;
+use simple_sqlite::wal::CheckpointMode;
+use crate::database::{Database, Progress};
+
+/// Reads SQLite database files.
+#[derive(Debug, Parser)]
+#[command(name = "simple-sqlite", version)]
+struct Cli {
+    #[command(flatten)]
+    options: Options,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Clone, Args)]
+struct Options {
+    /// Output format of query results: list (the default), csv, tabs or json
+    #[arg(long, global = true)]
+    format: Option<OutputFormat>,
+
+    /// Encoding of query results: utf8 (the default), utf16le or utf16be
+    #[arg(long, global = true)]
+    encoding: Option<Encoding>,
+
+    /// Refuses commands that write to the database, like .checkpoint and DELETE
+    #[arg(long, global = true)]
+    read_only: bool,
+
+    /// Shows on stderr how many of the pages have been read
+    #[arg(long, global = true)]
+    progress: bool,
+
+    /// Most memory a statement may use for intermediate results, in bytes
+    /// or with a K, M or G suffix
+    #[arg(long, global = true, value_parser = parse_size)]
+    memory_limit: Option<u64>,
+
+    /// Most pages kept in memory between reads, 0 to read every page from
+    /// the file
+    #[arg(long, global = true)]
+    cache_size: Option<usize>,
+
+    /// Reads pages through a memory mapping of the database file, for
+    /// databases nothing else writes to (needs the mmap feature)
+    #[arg(long, global = true)]
+    mmap: bool,
+
+    /// Threads a scan of a whole table is spread across
+    #[arg(long, global = true)]
+    threads: Option<usize>,
+
+    /// How errors are printed on stderr
+    #[arg(long, global = true, value_enum, default_value_t = ErrorFormat::Text)]
+    errors: ErrorFormat,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+enum ErrorFormat {
+    /// A line starting with "Error:"
+    #[default]
+    Text,
+    /// An object with the kind, message, SQL offset and page number
+    Json,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Runs a SQL statement or dot-command
+    Query {
+        database: String,
+        statement: String,
+        /// Runs the statement again every time the database is modified
+        #[arg(long)]
+        watch: bool,
+    },
+    /// Prints the schema and rows as SQL statements
+    Dump {
+        database: String,
+        /// Only dumps this table
+        table: Option<String>,
+    },
+    /// Prints information about the database file
+    Info { database: String },
+    /// Reports how the pages of the database are used
+    Analyze { database: String },
+    /// Starts an interactive shell
+    Repl { database: String },
+    /// Prints the statements that turn one database into another
+    Diff {
+        from: String,
+        to: String,
+        /// Only compares this table
+        table: Option<String>,
+    },
+    /// Salvages what it can from a damaged database as SQL statements
+    Recover { database: String },
+    /// Prints a script completing subcommands and flags in the shell
+    Completions { shell: Shell },
+}
+
+#[derive(Debug, Default)]
+struct Settings {
+    stats: bool,
+    timer: bool,
+    output: OutputOptions,
+    read_only: bool,
+    progress: bool,
+    errors: ErrorFormat,
+    memory_limit: Option<u64>,
+    /// `None` for the default of the page cache.
+    cache_size: Option<usize>,
+    mmap: bool,
+    threads: Option<usize>,
+}
+
+impl Settings {
+    /// Command line options override the defaults and the configuration file.
+    fn apply(&mut self, options: &Options) {
+        if let Some(format) = options.format {
+            self.output.format = format;
+        }
+        if let Some(encoding) = options.encoding {
+            self.output.encoding = encoding;
+        }
+        self.read_only |= options.read_only;
+        self.progress |= options.progress;
+        self.mmap |= options.mmap;
+        if options.threads.is_some() {
+            self.threads = options.threads;
+        }
+        self.errors = options.errors;
+        if options.memory_limit.is_some() {
+            self.memory_limit = options.memory_limit;
+        }
+        if options.cache_size.is_some() {
+            self.cache_size = options.cache_size;
+        }
+    }
+
+    /// Dot-commands restoring the current options, as kept in the
+    /// configuration file.
+    fn to_commands(&self) -> String {
+        let on_off = |value: bool| if value { "on" } else { "off" };
+        format!(
+            ".mode {}\n.headers {}\n.nullvalue {}\n.timer {}\n.stats {}\n.memory-limit {}\n.cache-size {}\n.threads {}\n",
+            self.output.format,
+            on_off(self.output.headers),
+            self.output.null_value,
+            on_off(self.timer),
+            on_off(self.stats),
+            self.memory_limit.map_or("off".to_string(), |limit| limit.to_string()),
+            self.cache_size.unwrap_or(DEFAULT_CACHE_SIZE),
+            self.threads.unwrap_or(1)
+        )
+    }
+}
+
+/// The REPL configuration file: `~/.simpledbrc`, or `simpledb/simpledbrc`
+/// in the XDG config directory. Whichever exists is used, the first one
+/// otherwise.
+fn config_path() -> Option<PathBuf> {
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+    let rc = home.as_ref().map(|home| home.join(".simpledbrc"));
+    let xdg = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| home.map(|home| home.join(".config")))
+        .map(|config| config.join("simpledb").join("simpledbrc"));
+
+    match (rc, xdg) {
+        (Some(rc), _) if rc.exists() => Some(rc),
+        (_, Some(xdg)) if xdg.exists() => Some(xdg),
+        (rc, xdg) => rc.or(xdg),
+    }
+}
+
+/// Runs the commands of the configuration file, one per line, like sqlite3
+/// does with `~/.sqliterc`. Lines starting with `--` are comments.
+fn load_settings(database: &mut Database, settings: &mut Settings) -> Result<()> {
+    let Some(path) = config_path().filter(|path| path.exists()) else {
+        return Ok(());
+    };
+
+    for line in std::fs::read_to_string(&path)?.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with("--") {
+            continue;
+        }
+        if let Err(e) = run(database, settings, line) {
+            report_error(settings, &e.context(path.display().to_string()));
+        }
+    }
+    Ok(())
+}
+
+fn save_settings(settings: &Settings) -> Result<PathBuf> {
+    let Some(path) = config_path() else {
+        bail!("Unable to find the home directory");
+    };
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&path, settings.to_commands())?;
+    Ok(path)
+}
+
+const PROGRESS_BAR_WIDTH: usize = 30;
+
+/// Draws a progress bar on stderr, redrawn whenever another percent of the
+/// pages has been read.
+fn progress_bar() -> impl FnMut(Progress) {
+    let mut drawn = None;
+    move |progress| {
+        let percent = (progress.pages_read * 100 / progress.page_count.max(1) as u64).min(100);
+        if drawn == Some(percent) {
+            return;
+        }
+        drawn = Some(percent);
+
+        let filled = percent as usize * PROGRESS_BAR_WIDTH / 100;
+        eprint!(
+            "\r[{}{}] {:>3}% {}/{} pages",
+            "#".repeat(filled),
+            " ".repeat(PROGRESS_BAR_WIDTH - filled),
+            percent,
+            progress.pages_read,
+            progress.page_count
+        );
+    }
+}
+
+fn report_error(settings: &Settings, error: &anyhow::Error) {
+    match settings.errors {
+        ErrorFormat::Text => eprintln!("Error: {:#}", error),
+        ErrorFormat::Json => eprintln!("{}", error_json(error.as_ref())),
+    }
+}
+
+fn clear_progress(settings: &Settings) {
+    if settings.progress {
+        eprint!("\r\x1b[K");
+    }
+}
+
+fn open_database(path: &str, settings: &Settings) -> Result<Database> {
+    let mut database = Database::open(path)?;
+    database.set_memory_limit(settings.memory_limit);
+    if let Some(pages) = settings.cache_size {
+        database.set_cache_size(pages);
+    }
+    database.set_mmap(settings.mmap)?;
+    if let Some(threads) = settings.threads {
+        database.set_threads(threads);
+    }
+    if settings.progress {
+        database.set_progress(progress_bar())?;
+    }
+    Ok(database)
+}
+
+fn parse_switch(value: &str) -> Result<bool> {
+    match value {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => bail!("Expected on or off: {}", value),
+    }
+}
+
+/// Parses a number of bytes, optionally followed by K, M or G.
+fn parse_size(value: &str) -> Result<u64> {
+    let (digits, unit) = match value.char_indices().last() {
+        Some((i, 'K' | 'k')) => (&value[..i], 1 << 10),
+        Some((i, 'M' | 'm')) => (&value[..i], 1 << 20),
+        Some((i, 'G' | 'g')) => (&value[..i], 1 << 30),
+        _ => (value, 1),
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(unit))
+        .ok_or_else(|| anyhow::anyhow!("Expected a size like 512K, 64M or 1G: {}", value))
+}
 
 fn main() -> Result<()> {
-    let args = std::env::args().collect::<Vec<_>>();
-    match args.len() {
-        0 | 1 => bail!("Missing <database path> and <command>"),
-        2 => bail!("Missing <command>"),
-        _ => {}
+    let cli = Cli::parse();
+    let mut settings = Settings::default();
+    settings.apply(&cli.options);
+
+    let result = match cli.command {
+        Command::Query {
+            database,
+            statement,
+            watch: true,
+        } => watch_command(&database, &mut settings, &statement),
+        Command::Query {
+            database,
+            statement,
+            watch: false,
+        } => {
+            let mut database = open_database(&database, &settings)?;
+            run(&mut database, &mut settings, &statement)
+        }
+        Command::Dump { database, table } => {
+            let mut database = open_database(&database, &settings)?;
+            Ok(dump::dump(&mut database, table.as_deref(), &mut stdout())?)
+        }
+        Command::Info { database } => {
+            let mut database = open_database(&database, &settings)?;
+            run(&mut database, &mut settings, ".dbinfo")
+        }
+        Command::Analyze { database } => {
+            let mut database = open_database(&database, &settings)?;
+            run(&mut database, &mut settings, ".report")
+        }
+        Command::Repl { database } => {
+            let mut database = open_database(&database, &settings)?;
+            let mut settings = Settings::default();
+            load_settings(&mut database, &mut settings)?;
+            settings.apply(&cli.options);
+            repl(&mut database, &mut settings)
+        }
+        Command::Diff { from, to, table } => {
+            let mut from = open_database(&from, &settings)?;
+            let mut to = open_database(&to, &settings)?;
+            Ok(diff::diff(&mut from, &mut to, table.as_deref(), &mut stdout())?)
+        }
+        // Recovery reads the file itself, since opening a damaged database fails.
+        Command::Recover { database } => {
+            println!("{}", Recovery::read(&database)?);
+            Ok(())
+        }
+        Command::Completions { shell } => {
+            clap_complete::generate(shell, &mut Cli::command(), "simple-sqlite", &mut stdout());
+            Ok(())
+        }
+    };
+    clear_progress(&settings);
+
+    match result {
+        Err(e) if settings.errors == ErrorFormat::Json => {
+            report_error(&settings, &e);
+            std::process::exit(1);
+        }
+        result => result,
     }
+}
+
+/// Re-runs `command` every time the database file is modified.
+fn watch_command(path: &str, settings: &mut Settings, command: &str) -> Result<()> {
+    let version = |database: &mut Database| -> Result<(u32, SystemTime)> {
+        let modified = database.storage.modified()?;
+        Ok((database.read_change_counter()?, modified))
+    };
 
-    let mut database = Database::open(&args[2])?;
+    loop {
+        // Reopening drops all state read from the previous version of the file.
+        let mut database = open_database(path, settings)?;
+        let seen = version(&mut database)?;
 
-    let command = &args[2];
-    match command.as_str() {
+        if let Err(e) = run(&mut database, settings, command) {
+            report_error(settings, &e);
+        }
+        stdout().flush()?;
+
+        while version(&mut database)? == seen {
+            std::thread::sleep(Duration::from_millis(500));
+        }
+        println!();
+    }
+}
+
+fn repl(database: &mut Database, settings: &mut Settings) -> Result<()> {
+    // Ctrl-C stops the running statement instead of the shell.
+    let interrupt = database.interrupt_handle();
+    ctrlc::set_handler(move || interrupt.interrupt())?;
+
+    let mut line = String::new();
+    loop {
+        print!("simple-sqlite> ");
+        stdout().flush()?;
+
+        line.clear();
+        if stdin().read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+
+        match line.trim() {
+            "" => continue,
+            ".exit" | ".quit" => return Ok(()),
+            command => {
+                if let Err(e) = run(database, settings, command) {
+                    report_error(settings, &e);
+                }
+            }
+        }
+    }
+}
+
+fn run(database: &mut Database, settings: &mut Settings, command: &str) -> Result<()> {
+    database.interrupt_handle().clear();
+    let result = execute(database, settings, command);
+    clear_progress(settings);
+    result
+}
+
+fn execute(database: &mut Database, settings: &mut Settings, command: &str) -> Result<()> {
+    database.refresh()?;
+    database.stats.reset();
+
+    match command {
         ".dbinfo" => {
-            println!("database page size: {}", database.header.page_size);
+            let header = &database.header;
+            println!("database page size: {}", header.page_size);
+            println!("file change counter: {}", header.file_change_counter);
+            println!("database page count: {}", header.page_count);
+            println!("freelist page count: {}", header.freelist_page_count);
+            println!("schema cookie: {}", header.schema_cookie);
+            println!("text encoding: {}", header.text_encoding);
+            println!("user version: {}", header.user_version);
+            println!("application id: {}", header.application_id);
             println!(
                 "number of tables: {}",
                 database.schema.user_tables().count()
             );
+            println!("auto-vacuum: {}", database.header.auto_vacuum());
+            println!("{}", FileState::read(database)?);
+            if let Some(wal) = &database.wal {
+                println!(
+                    "wal frames: {} committed, {} valid",
+                    wal.max_frame, wal.valid_frames
+                );
+            }
         }
 
-        ".tables" => database
-            .schema
-            .table_names
-            .iter()
-            .for_each(|name| println!("{}", name)),
-
-        query_string => {
-            let (_, query) = sql::parse(query_string.as_bytes())
-                .map_err(|_e| anyhow::anyhow!("Failed to parse query"))?;
-
-            match query {
-                sql::SQLCommand::Select(sql::SelectStatement::Count(table)) => {
-                    let row = database
-                        .schema
-                        .find_table(&table)
-                        .ok_or(anyhow::anyhow!("Table not found: {}", table))?;
-                    let page = database.get_page(row.rootpage - 1)?;
-                    println!("{}", page.header.number_of_cells);
-                }
-                sql::SQLCommand::Select(sql::SelectStatement::Fields(command)) => {
-                    database.select_fields(&command, &mut stdout())?;
-                }
-                _ => bail!("Unsupported command: {}", query_string),
+        command if command == ".checkpoint" || command.starts_with(".checkpoint ") => {
+            if settings.read_only {
+                bail!("Unable to checkpoint: the database was opened read-only");
+            }
+            let mode = match command[".checkpoint".len()..].trim() {
+                "" => CheckpointMode::Passive,
+                mode => mode.parse()?,
             };
+            println!("{}", database.checkpoint(mode)?);
+        }
+
+        ".tables" => {
+            database.schema.table_names.iter().for_each(|name| println!("{}", name));
+            // Like the sqlite3 shell, temp and attached tables go by their
+            // schema name.
+            let temp = database.temp().map(|temp| ("temp", temp));
+            for (schema, database) in temp.into_iter().chain(database.attached()) {
+                database.schema.table_names.iter().for_each(|name| println!("{}.{}", schema, name));
+            }
+        }
+
+        ".report" => print!("{}", SpaceReport::read(database)?),
+
+        command if command.starts_with(".sequence ") => {
+            let name = command[".sequence ".len()..].trim();
+            let table = database
+                .schema
+                .find_table(name)
+                .ok_or(anyhow::anyhow!("Table not found: {}", name))?;
+            if !table.columns.iter().any(|column| column.autoincrement) {
+                bail!("Table without AUTOINCREMENT: {}", name);
+            }
+            match database.sequence(name)? {
+                Some(sequence) => println!("{}", sequence),
+                None => println!("no rows inserted yet"),
+            }
+        }
+
+        command if command == ".dump" || command.starts_with(".dump ") => {
+            let table = Some(command[".dump".len()..].trim()).filter(|table| !table.is_empty());
+            dump::dump(database, table, &mut stdout())?;
+        }
+
+        command if command.starts_with(".page ") => {
+            let page_number = command[".page ".len()..]
+                .trim()
+                .parse::<u32>()
+                .map_err(|_e| anyhow::anyhow!("Usage: .page N"))?;
+            print!("{}", PageDump::read(database, page_number)?);
+        }
+
+        command if command.starts_with(".visualize ") => {
+            let name = command[".visualize ".len()..].trim();
+            println!("{}", BTreeGraph::read(database, name)?);
+        }
+
+        ".stats on" => settings.stats = true,
+        ".stats off" => settings.stats = false,
+        ".stats" => println!("stats: {}", if settings.stats { "on" } else { "off" }),
+
+        ".mode" => println!("mode: {}", settings.output.format),
+        command if command.starts_with(".mode ") => {
+            settings.output.format = command[".mode ".len()..].trim().parse()?;
         }
+
+        command if command.starts_with(".headers ") => {
+            settings.output.headers = parse_switch(command[".headers ".len()..].trim())?;
+        }
+
+        command if command == ".nullvalue" || command.starts_with(".nullvalue ") => {
+            settings.output.null_value = command[".nullvalue".len()..].trim().to_string();
+        }
+
+        command if command.starts_with(".timer ") => {
+            settings.timer = parse_switch(command[".timer ".len()..].trim())?;
+        }
+
+        ".memory-limit" => match database.memory_limit() {
+            Some(limit) => println!("memory limit: {} bytes", limit),
+            None => println!("memory limit: off"),
+        },
+        command if command.starts_with(".memory-limit ") => {
+            settings.memory_limit = match command[".memory-limit ".len()..].trim() {
+                "off" => None,
+                size => Some(parse_size(size)?),
+            };
+            database.set_memory_limit(settings.memory_limit);
+        }
+
+        ".cache-size" => {
+            let cache = database.cache();
+            println!(
+                "cache size: {} pages, {} hits, {} misses",
+                cache.capacity(),
+                cache.hits,
+                cache.misses
+            );
+        }
+        command if command.starts_with(".cache-size ") => {
+            let pages = command[".cache-size ".len()..].trim();
+            let pages = pages
+                .parse()
+                .map_err(|_| anyhow::anyhow!("Expected a number of pages: {}", pages))?;
+            settings.cache_size = Some(pages);
+            database.set_cache_size(pages);
+        }
+
+        ".threads" => println!("threads: {}", database.threads()),
+        command if command.starts_with(".threads ") => {
+            let threads = command[".threads ".len()..].trim();
+            let threads = threads
+                .parse()
+                .map_err(|_| anyhow::anyhow!("Expected a number of threads: {}", threads))?;
+            settings.threads = Some(threads);
+            database.set_threads(threads);
+        }
+
+        ".save-settings" => {
+            println!("Settings saved to {}", save_settings(settings)?.display());
+        }
+
+        command if command.starts_with(".read ") => {
+            let path = command[".read ".len()..].trim();
+            let script = std::fs::read_to_string(path)
+                .map_err(|e| anyhow::anyhow!("Cannot open \"{}\": {}", path, e))?;
+            run_statements(database, settings, &script)?;
+        }
+
+        query_string => run_statements(database, settings, query_string)?,
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Runs the statements of a script in turn, stopping at the first error.
+fn run_statements(database: &mut Database, settings: &Settings, script: &str) -> Result<()> {
+    for mut statement in Statement::prepare_all(script)? {
+        if settings.read_only && !statement.is_read_only() {
+            bail!("Unable to run {}: the database was opened read-only", statement.sql());
+        }
+        statement.expand(database)?;
+        if statement.is_explain() {
+            print!("{}", statement.query_plan(database)?);
+            continue;
+        }
+        let started = Instant::now();
+        let mut out = RowWriter::new(stdout(), &settings.output, statement.columns());
+        statement.execute(database, &mut out)?;
+        out.finish()?;
+
+        if settings.timer {
+            println!("Run Time: real {:.3}", started.elapsed().as_secs_f64());
+        }
+
+        if settings.stats {
+            println!("{}", statement.stats());
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,380 @@
+use std::borrow::Cow;
+use std::io::Write;
+
+use itertools::Itertools;
+
+use crate::error::{bail, Error, Result};
+use crate::record::ColumnValue;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Values separated by `|`, one row per line.
+    #[default]
+    List,
+    Csv,
+    /// Values separated by tabs.
+    Tabs,
+    /// An array with an object per row.
+    Json,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "list" => Ok(Self::List),
+            "csv" => Ok(Self::Csv),
+            "tabs" => Ok(Self::Tabs),
+            "json" => Ok(Self::Json),
+            _ => bail!("Unknown output format: {} (expected list, csv, tabs or json)", s),
+        }
+    }
+}
+
+impl std::fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OutputFormat::List => write!(f, "list"),
+            OutputFormat::Csv => write!(f, "csv"),
+            OutputFormat::Tabs => write!(f, "tabs"),
+            OutputFormat::Json => write!(f, "json"),
+        }
+    }
+}
+
+/// Text encoding of the output, or of the text in a database.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    Utf16le,
+    Utf16be,
+}
+
+impl std::str::FromStr for Encoding {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().replace('-', "").as_str() {
+            "utf8" => Ok(Self::Utf8),
+            "utf16le" => Ok(Self::Utf16le),
+            "utf16be" => Ok(Self::Utf16be),
+            _ => bail!("Unknown encoding: {} (expected utf8, utf16le or utf16be)", s),
+        }
+    }
+}
+
+impl Encoding {
+    /// The bytes of `text` in this encoding, without a byte order mark.
+    pub fn encode<'a>(&self, text: &'a str) -> Cow<'a, [u8]> {
+        match self {
+            Encoding::Utf8 => Cow::Borrowed(text.as_bytes()),
+            Encoding::Utf16le => Cow::Owned(text.encode_utf16().flat_map(u16::to_le_bytes).collect()),
+            Encoding::Utf16be => Cow::Owned(text.encode_utf16().flat_map(u16::to_be_bytes).collect()),
+        }
+    }
+
+    /// Text from bytes in this encoding. Invalid sequences become U+FFFD,
+    /// and so does an odd byte left at the end of UTF-16.
+    pub fn decode<'a>(&self, bytes: &'a [u8]) -> Cow<'a, str> {
+        let units = |unit: fn([u8; 2]) -> u16| {
+            let chunks = bytes.chunks_exact(2);
+            let odd = !chunks.remainder().is_empty();
+            let units = chunks.map(|pair| unit([pair[0], pair[1]]));
+            let mut text = char::decode_utf16(units)
+                .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                .collect::<String>();
+            if odd {
+                text.push(char::REPLACEMENT_CHARACTER);
+            }
+            Cow::Owned(text)
+        };
+        match self {
+            Encoding::Utf8 => String::from_utf8_lossy(bytes),
+            Encoding::Utf16le => units(u16::from_le_bytes),
+            Encoding::Utf16be => units(u16::from_be_bytes),
+        }
+    }
+}
+
+impl std::fmt::Display for Encoding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Encoding::Utf8 => write!(f, "utf8"),
+            Encoding::Utf16le => write!(f, "utf16le"),
+            Encoding::Utf16be => write!(f, "utf16be"),
+        }
+    }
+}
+
+fn csv_quote(text: &str) -> String {
+    if text.contains(['"', ',', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+pub(crate) fn json_string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn json_value(value: &ColumnValue) -> String {
+    match value {
+        ColumnValue::Null => "null".to_string(),
+        ColumnValue::F64(n) if !n.is_finite() => "null".to_string(),
+        ColumnValue::Text(text) => json_string(&String::from_utf8_lossy(text)),
+        ColumnValue::Blob(content) => json_string(
+            &content
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect::<String>(),
+        ),
+        value => value.to_string(),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputOptions {
+    pub format: OutputFormat,
+    pub encoding: Encoding,
+    /// Whether a line with the column names comes before the rows.
+    pub headers: bool,
+    /// Text shown for NULL values, except in JSON.
+    pub null_value: String,
+}
+
+impl Default for OutputOptions {
+    fn default() -> Self {
+        Self {
+            format: OutputFormat::default(),
+            encoding: Encoding::default(),
+            headers: false,
+            null_value: "NULL".to_string(),
+        }
+    }
+}
+
+/// Where the rows of a query result go: written out, or collected first,
+/// e.g. to be sorted.
+pub trait RowSink {
+    fn write_row(&mut self, values: &[ColumnValue]) -> Result<()>;
+
+    /// Whether further rows would be thrown away, so scans can stop early.
+    fn is_done(&self) -> bool {
+        false
+    }
+}
+
+/// Skips the first `offset` rows and passes on at most `limit` of the rest,
+/// for LIMIT and OFFSET.
+#[derive(Debug)]
+pub struct Limit<'sink, S: RowSink> {
+    out: &'sink mut S,
+    offset: u64,
+    limit: Option<u64>,
+    seen: u64,
+}
+
+impl<'sink, S: RowSink> Limit<'sink, S> {
+    pub fn new(out: &'sink mut S, offset: u64, limit: Option<u64>) -> Self {
+        Self {
+            out,
+            offset,
+            limit,
+            seen: 0,
+        }
+    }
+}
+
+impl<S: RowSink> RowSink for Limit<'_, S> {
+    fn write_row(&mut self, values: &[ColumnValue]) -> Result<()> {
+        if self.is_done() {
+            return Ok(());
+        }
+        self.seen += 1;
+        if self.seen <= self.offset {
+            return Ok(());
+        }
+        self.out.write_row(values)
+    }
+
+    fn is_done(&self) -> bool {
+        self.limit.is_some_and(|limit| self.seen >= self.offset + limit) || self.out.is_done()
+    }
+}
+
+/// Writes the rows of a query result in the chosen format and encoding.
+#[derive(Debug)]
+pub struct RowWriter<'options, W: Write> {
+    out: W,
+    options: &'options OutputOptions,
+    columns: Vec<String>,
+    rows: u64,
+}
+
+impl<'options, W: Write> RowWriter<'options, W> {
+    /// `columns` names the values of every row, for formats that show them.
+    pub fn new(out: W, options: &'options OutputOptions, columns: Vec<String>) -> Self {
+        Self {
+            out,
+            options,
+            columns,
+            rows: 0,
+        }
+    }
+
+    /// Rows written so far.
+    pub fn rows(&self) -> u64 {
+        self.rows
+    }
+
+    fn text(&self, value: &ColumnValue) -> String {
+        match value {
+            ColumnValue::Null => self.options.null_value.clone(),
+            value => value.to_string(),
+        }
+    }
+
+    /// Joins the values of a row, or the column names, for the line-based
+    /// formats.
+    fn join(&self, mut values: impl Iterator<Item = String>) -> String {
+        match self.options.format {
+            OutputFormat::Tabs => values.join("\t"),
+            OutputFormat::Csv => values.map(|value| csv_quote(&value)).join(","),
+            _ => values.join("|"),
+        }
+    }
+
+    /// Closes the output once every row has been written.
+    pub fn finish(&mut self) -> Result<()> {
+        if self.options.format == OutputFormat::Json && self.rows > 0 {
+            self.emit("]\n")?;
+        }
+        self.out.flush()?;
+        Ok(())
+    }
+
+    fn emit(&mut self, text: &str) -> Result<()> {
+        self.out.write_all(&self.options.encoding.encode(text))?;
+        Ok(())
+    }
+}
+
+impl<W: Write> RowSink for RowWriter<'_, W> {
+    fn write_row(&mut self, values: &[ColumnValue]) -> Result<()> {
+        if self.rows == 0 && self.options.headers && self.options.format != OutputFormat::Json {
+            let header = self.join(self.columns.iter().cloned());
+            self.emit(&format!("{}\n", header))?;
+        }
+
+        let line = match self.options.format {
+            OutputFormat::List | OutputFormat::Tabs | OutputFormat::Csv => {
+                self.join(values.iter().map(|value| self.text(value)))
+            }
+            OutputFormat::Json => {
+                let fields = values
+                    .iter()
+                    .enumerate()
+                    .map(|(i, value)| {
+                        let name = self.columns.get(i).cloned().unwrap_or_default();
+                        format!("{}:{}", json_string(&name), json_value(value))
+                    })
+                    .join(",");
+                let separator = if self.rows == 0 { "[" } else { ",\n" };
+                format!("{}{{{}}}", separator, fields)
+            }
+        };
+
+        if self.options.format == OutputFormat::Json {
+            self.emit(&line)?;
+        } else {
+            self.emit(&format!("{}\n", line))?;
+        }
+        self.rows += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(options: &OutputOptions, rows: &[Vec<ColumnValue>]) -> String {
+        let columns = vec!["id".to_string(), "name".to_string()];
+        let mut out = vec![];
+        let mut writer = RowWriter::new(&mut out, options, columns);
+        for row in rows {
+            writer.write_row(row).unwrap();
+        }
+        writer.finish().unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn write_rows_in_each_format() {
+        let rows = vec![
+            vec![ColumnValue::I8(1), ColumnValue::Text(b"a, \"b\"")],
+            vec![ColumnValue::I8(2), ColumnValue::Null],
+        ];
+
+        let format = |format| OutputOptions {
+            format,
+            ..OutputOptions::default()
+        };
+
+        assert_eq!(render(&format(OutputFormat::List), &rows), "1|a, \"b\"\n2|NULL\n");
+        assert_eq!(
+            render(&format(OutputFormat::Csv), &rows),
+            "1,\"a, \"\"b\"\"\"\n2,NULL\n"
+        );
+        assert_eq!(
+            render(&format(OutputFormat::Json), &rows),
+            "[{\"id\":1,\"name\":\"a, \\\"b\\\"\"},\n{\"id\":2,\"name\":null}]\n"
+        );
+        assert_eq!(render(&format(OutputFormat::Json), &[]), "");
+    }
+
+    #[test]
+    fn limit_and_offset() {
+        let rows = (1..=5).map(|n| vec![ColumnValue::I8(n), ColumnValue::Null]).collect::<Vec<_>>();
+        let options = OutputOptions::default();
+        let mut out = vec![];
+        let mut writer = RowWriter::new(&mut out, &options, vec![]);
+        let mut limit = Limit::new(&mut writer, 1, Some(2));
+        for row in rows.iter() {
+            limit.write_row(row).unwrap();
+        }
+
+        assert!(limit.is_done());
+        assert_eq!(String::from_utf8(out).unwrap(), "2|NULL\n3|NULL\n");
+    }
+
+    #[test]
+    fn write_headers_and_null_value() {
+        let options = OutputOptions {
+            format: OutputFormat::Csv,
+            headers: true,
+            null_value: String::new(),
+            ..OutputOptions::default()
+        };
+        let rows = vec![vec![ColumnValue::I8(2), ColumnValue::Null]];
+
+        assert_eq!(render(&options, &rows), "id,name\n2,\n");
+    }
+}
@@ -1,9 +1,9 @@
-use anyhow::Result;
 use std::{fs::File, io::prelude::*};
 
+use crate::error::{format_err, Error, Result};
 use crate::varient;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PageKind {
     InteriorIndex,
     LeafIndex,
@@ -20,18 +20,67 @@ impl<'page> PageKind {
         matches!(self, Self::LeafIndex | Self::LeafTable)
     }
 
-    pub fn read_cell(&self, data: &'page [u8]) -> Cell<'page> {
+    pub fn is_table(&self) -> bool {
+        matches!(self, Self::InteriorTable | Self::LeafTable)
+    }
+
+    pub fn is_index(&self) -> bool {
+        matches!(self, Self::InteriorIndex | Self::LeafIndex)
+    }
+
+    pub fn header_size(&self) -> usize {
+        if self.is_interior() {
+            12
+        } else {
+            8
+        }
+    }
+
+    /// The flag byte that starts the header of a page of this kind.
+    pub fn flag(&self) -> u8 {
         match self {
-            PageKind::InteriorIndex => Cell::read_interior_index(data),
-            PageKind::LeafIndex => Cell::read_leaf_index(data),
+            PageKind::InteriorIndex => 0x02,
+            PageKind::InteriorTable => 0x05,
+            PageKind::LeafIndex => 0x0a,
+            PageKind::LeafTable => 0x0d,
+        }
+    }
+
+    pub fn read_cell(&self, data: &'page [u8], usable_size: u32) -> Cell<'page> {
+        match self {
+            PageKind::InteriorIndex => Cell::read_interior_index(data, usable_size),
+            PageKind::LeafIndex => Cell::read_leaf_index(data, usable_size),
             PageKind::InteriorTable => Cell::read_interior_table(data),
-            PageKind::LeafTable => Cell::read_leaf_table(data),
+            PageKind::LeafTable => Cell::read_leaf_table(data, usable_size),
+        }
+    }
+
+    /// Number of payload bytes stored in the cell itself; the rest spills to
+    /// overflow pages. See "B-tree Cell Format" in the SQLite file format docs.
+    pub fn local_payload_size(&self, payload_size: u64, usable_size: u32) -> u64 {
+        let usable_size = usable_size as u64;
+        let max_local = if *self == PageKind::LeafTable {
+            usable_size - 35
+        } else {
+            ((usable_size - 12) * 64 / 255) - 23
+        };
+        let min_local = ((usable_size - 12) * 32 / 255) - 23;
+
+        if payload_size <= max_local {
+            return payload_size;
+        }
+
+        let local = min_local + ((payload_size - min_local) % (usable_size - 4));
+        if local <= max_local {
+            local
+        } else {
+            min_local
         }
     }
 }
 
 impl TryFrom<u8> for PageKind {
-    type Error = anyhow::Error;
+    type Error = Error;
 
     fn try_from(value: u8) -> std::result::Result<Self, Self::Error> {
         match value {
@@ -39,7 +88,7 @@ impl TryFrom<u8> for PageKind {
             0x05 => Ok(Self::InteriorTable),
             0x0a => Ok(Self::LeafIndex),
             0x0d => Ok(Self::LeafTable),
-            _ => Err(anyhow::anyhow!("Invalid page kind")),
+            _ => Err(format_err!("Invalid page kind: {}", value)),
         }
     }
 }
@@ -70,7 +119,23 @@ pub enum Cell<'page> {
 }
 
 impl<'page> Cell<'page> {
-    fn read_interior_index(data: &'page [u8]) -> Cell {
+    fn split_payload(
+        kind: PageKind,
+        data: &'page [u8],
+        size: u64,
+        usable_size: u32,
+    ) -> (&'page [u8], u32) {
+        let local = kind.local_payload_size(size, usable_size) as usize;
+        let overflow_page = if (local as u64) < size {
+            u32::from_be_bytes([data[local], data[local + 1], data[local + 2], data[local + 3]])
+        } else {
+            0
+        };
+
+        (&data[..local], overflow_page)
+    }
+
+    fn read_interior_index(data: &'page [u8], usable_size: u32) -> Cell {
         let left_child_page = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
 
         let mut cursor = 4;
@@ -78,43 +143,29 @@ impl<'page> Cell<'page> {
         let size = size as u64;
         cursor += offset;
 
-        let (overflow_page, end) = if size > data[cursor..].len() as u64 {
-            let end = data.len() - 4;
-            (
-                u32::from_be_bytes([data[end], data[end + 1], data[end + 2], data[end + 3]]),
-                end,
-            )
-        } else {
-            (0, cursor + size as usize)
-        };
+        let (payload, overflow_page) =
+            Self::split_payload(PageKind::InteriorIndex, &data[cursor..], size, usable_size);
 
         Cell::InteriorIndex {
             left_child_page: left_child_page as u32,
             size,
-            payload: &data[cursor..end],
+            payload,
             overflow_page,
         }
     }
 
-    fn read_leaf_index(data: &'page [u8]) -> Cell {
+    fn read_leaf_index(data: &'page [u8], usable_size: u32) -> Cell {
         let mut cursor = 0;
         let (size, offset) = varient::read(&data[..]);
         let size = size as u64;
         cursor += offset;
 
-        let (overflow_page, end) = if size > data[cursor..].len() as u64 {
-            let end = data.len() - 4;
-            (
-                u32::from_be_bytes([data[end], data[end + 1], data[end + 2], data[end + 3]]),
-                end,
-            )
-        } else {
-            (0, cursor + size as usize)
-        };
+        let (payload, overflow_page) =
+            Self::split_payload(PageKind::LeafIndex, &data[cursor..], size, usable_size);
 
         Cell::LeafIndex {
             size,
-            payload: &data[cursor..end],
+            payload,
             overflow_page,
         }
     }
@@ -129,7 +180,7 @@ impl<'page> Cell<'page> {
         }
     }
 
-    fn read_leaf_table(data: &'page [u8]) -> Cell {
+    fn read_leaf_table(data: &'page [u8], usable_size: u32) -> Cell {
         let mut cursor = 0;
         let (payload_size, offset) = varient::read(data);
         let payload_size = payload_size as u64;
@@ -138,26 +189,39 @@ impl<'page> Cell<'page> {
         let (rowid, offset) = varient::read(&data[cursor..]);
         cursor += offset;
 
-        let (overflow_page, end) = if payload_size > data[cursor..].len() as u64 {
-            let end = data.len() - 4;
-            (
-                u32::from_be_bytes([data[end], data[end + 1], data[end + 2], data[end + 3]]),
-                end,
-            )
-        } else {
-            (0, cursor + payload_size as usize)
-        };
+        let (payload, overflow_page) =
+            Self::split_payload(PageKind::LeafTable, &data[cursor..], payload_size, usable_size);
 
         Cell::LeafTable {
             size: payload_size,
             rowid: rowid as i64,
-            payload: &data[cursor..end],
+            payload,
             overflow_page,
         }
     }
+
+    pub fn payload_size(&self) -> u64 {
+        match self {
+            Cell::InteriorIndex { size, .. }
+            | Cell::LeafIndex { size, .. }
+            | Cell::LeafTable { size, .. } => *size,
+            Cell::InteriorTable { .. } => 0,
+        }
+    }
+
+    pub fn overflow_page(&self) -> Option<u32> {
+        match self {
+            Cell::InteriorIndex { overflow_page, .. }
+            | Cell::LeafIndex { overflow_page, .. }
+            | Cell::LeafTable { overflow_page, .. } => {
+                Some(*overflow_page).filter(|page| *page != 0)
+            }
+            Cell::InteriorTable { .. } => None,
+        }
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct PageHeader {
     pub kind: PageKind,
     pub first_freeblock_start: u16,
@@ -167,11 +231,13 @@ pub struct PageHeader {
     pub right_child_page_number: Option<u32>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Page {
     pub header: PageHeader,
     pub cell_pointers: Vec<u16>,
     pub data: Vec<u8>,
+    pub offset: u16,
+    pub usable_size: u32,
 }
 
 impl Page {
@@ -183,19 +249,35 @@ impl Page {
         let mut page = vec![0; page_size as usize];
         file.read_exact(&mut page)?;
 
+        Self::parse(page, offset, page_size as u32 + offset as u32)
+    }
+
+    /// Parses a B-tree page. `offset` is the number of bytes of the page that
+    /// precede `page` (100 for the first page, which starts with the file header).
+    pub fn parse(page: Vec<u8>, offset: u16, usable_size: u32) -> Result<Self> {
+        let page = Self::parse_layout(page, offset, usable_size)?;
+        let overruns = |&&pointer: &&u16| {
+            cell_end(page.header.kind, &page.data, pointer as usize, usable_size).is_none()
+        };
+        if let Some(&pointer) = page.cell_pointers.iter().find(overruns) {
+            return Err(format_err!("Cell at {} runs past the end of the page", pointer + offset));
+        }
+        Ok(page)
+    }
+
+    /// Parses the header and cell pointers of a page, but not the cells.
+    fn parse_layout(page: Vec<u8>, offset: u16, usable_size: u32) -> Result<Self> {
         let kind = PageKind::try_from(u8::from_be_bytes([page[0]]))?;
         let first_freeblock_start = u16::from_be_bytes([page[1], page[2]]);
         let number_of_cells = u16::from_be_bytes([page[3], page[4]]);
         let content_start_offset = u16::from_be_bytes([page[5], page[6]]);
         let fragment_free_bytes = page[7];
-        let (header_size, right_child_page_number) = if kind.is_interior() {
-            (
-                12,
-                Some(u32::from_be_bytes([page[8], page[9], page[10], page[11]])),
-            )
+        let right_child_page_number = if kind.is_interior() {
+            Some(u32::from_be_bytes([page[8], page[9], page[10], page[11]]))
         } else {
-            (8, None)
+            None
         };
+        let header_size = kind.header_size();
 
         let header = PageHeader {
             kind,
@@ -206,22 +288,476 @@ impl Page {
             right_child_page_number,
         };
 
-        let cell_pointers: Vec<u16> = page[header_size..]
-            .chunks_exact(2)
-            .take(header.number_of_cells.into())
-            .map(|chunk| u16::from_be_bytes([chunk[0], chunk[1]]) - offset as u16)
-            .collect();
+        // Cells start after the cell pointers, within the page.
+        let pointers_end = header_size + 2 * number_of_cells as usize;
+        if pointers_end > page.len() {
+            return Err(format_err!("Too many cells for the page: {}", number_of_cells));
+        }
+        let mut cell_pointers = Vec::with_capacity(number_of_cells as usize);
+        for chunk in page[header_size..pointers_end].chunks_exact(2) {
+            let pointer = u16::from_be_bytes([chunk[0], chunk[1]]);
+            match (pointer as usize).checked_sub(offset as usize) {
+                Some(at) if at >= pointers_end && at < page.len() => cell_pointers.push(at as u16),
+                _ => return Err(format_err!("Cell pointer out of range: {}", pointer)),
+            }
+        }
 
         Ok(Self {
             header,
             cell_pointers,
             data: page,
+            offset,
+            usable_size,
         })
     }
 
+    /// A page of `kind` without cells, `size` bytes long once the `offset`
+    /// bytes before it are taken off.
+    pub fn empty(kind: PageKind, size: usize, offset: u16, usable_size: u32) -> Result<Self> {
+        let mut data = vec![0; size];
+        data[0] = kind.flag();
+        data[5..7].copy_from_slice(&(usable_size as u16).to_be_bytes());
+        Self::parse(data, offset, usable_size)
+    }
+
+    /// The cells in pointer order. `parse` has checked that they fit on the
+    /// page, so reading them can't go past its end.
     pub fn cells(&self) -> impl Iterator<Item = Cell> {
-        self.cell_pointers
-            .iter()
-            .map(move |pointer| self.header.kind.read_cell(&self.data[*pointer as usize..]))
+        self.cell_pointers.iter().map(move |pointer| {
+            self.header
+                .kind
+                .read_cell(&self.data[*pointer as usize..], self.usable_size)
+        })
+    }
+
+    /// Bytes of the page not used by the header, cell pointers or cells.
+    pub fn unused_bytes(&self) -> u32 {
+        let offset = self.offset as usize;
+        let content_start = match self.header.content_start_offset {
+            0 => 65536,
+            n => n as usize,
+        };
+        let pointers_end =
+            offset + self.header.kind.header_size() + 2 * self.header.number_of_cells as usize;
+
+        let mut unused = content_start.saturating_sub(pointers_end) as u32
+            + self.header.fragment_free_bytes as u32;
+
+        let mut freeblock = self.header.first_freeblock_start as usize;
+        while freeblock != 0 && freeblock + 4 - offset <= self.data.len() {
+            let at = freeblock - offset;
+            let next = u16::from_be_bytes([self.data[at], self.data[at + 1]]) as usize;
+            unused += u16::from_be_bytes([self.data[at + 2], self.data[at + 3]]) as u32;
+            if next <= freeblock {
+                break;
+            }
+            freeblock = next;
+        }
+
+        unused
+    }
+
+    fn read_u16(&self, at: usize) -> usize {
+        let at = at - self.offset as usize;
+        u16::from_be_bytes([self.data[at], self.data[at + 1]]) as usize
+    }
+
+    /// Writes a two-byte field at `at`, an offset from the start of the page
+    /// like those stored in the page.
+    fn write_u16(&mut self, at: usize, value: usize) {
+        let at = at - self.offset as usize;
+        self.data[at..at + 2].copy_from_slice(&(value as u16).to_be_bytes());
     }
-}
\ No newline at end of file
+
+    /// Bytes the cell at `index` takes up on the page, at least 4 like in
+    /// SQLite.
+    pub fn cell_size(&self, index: usize) -> usize {
+        let data = &self.data[self.cell_pointers[index] as usize..];
+        let kind = self.header.kind;
+        let size = match kind {
+            PageKind::InteriorTable => 4 + varient::read(&data[4..]).1,
+            _ => {
+                let start = if kind.is_interior() { 4 } else { 0 };
+                let (payload_size, mut size) = varient::read(&data[start..]);
+                size += start;
+                if kind == PageKind::LeafTable {
+                    size += varient::read(&data[size..]).1;
+                }
+                let local = kind.local_payload_size(payload_size as u64, self.usable_size);
+                size + local as usize + if local < payload_size as u64 { 4 } else { 0 }
+            }
+        };
+        size.max(4)
+    }
+
+    /// The bytes of the cell at `index`.
+    pub fn cell_bytes(&self, index: usize) -> &[u8] {
+        let start = self.cell_pointers[index] as usize;
+        &self.data[start..start + self.cell_size(index)]
+    }
+
+    /// Where the cell at `index` starts in the page, or the right child in
+    /// the page header for the index one past the last cell, for pointing
+    /// at them in errors.
+    pub fn cell_offset(&self, index: usize) -> usize {
+        let at = match self.cell_pointers.get(index) {
+            Some(&pointer) => pointer as usize,
+            None => 8,
+        };
+        self.offset as usize + at
+    }
+
+    /// Points the cell at `index` of an interior page, or its right child
+    /// for the index one past the last cell, at `child`.
+    pub fn set_child(&mut self, index: usize, child: u32) -> Result<()> {
+        let at = if index == self.cell_pointers.len() {
+            self.header.right_child_page_number = Some(child);
+            8
+        } else {
+            self.cell_pointers[index] as usize
+        };
+        self.data[at..at + 4].copy_from_slice(&child.to_be_bytes());
+        Ok(())
+    }
+
+    /// The child of an interior page at `index`, the right child for the
+    /// index one past the last cell.
+    pub fn child(&self, index: usize) -> Option<u32> {
+        if index == self.cell_pointers.len() {
+            return self.header.right_child_page_number;
+        }
+        match self.cells().nth(index)? {
+            Cell::InteriorTable { left_child_page, .. } | Cell::InteriorIndex { left_child_page, .. } => {
+                Some(left_child_page)
+            }
+            _ => None,
+        }
+    }
+
+    /// Removes the cell at `index`, giving its space back to the page the
+    /// way SQLite does: as a freeblock merged with the ones next to it, or
+    /// to the unallocated space if it is at the start of the cell content.
+    pub fn remove_cell(&mut self, index: usize) -> Result<()> {
+        let size = self.cell_size(index);
+        let start = self.cell_pointers[index] as usize + self.offset as usize;
+
+        let pointers = self.header.kind.header_size();
+        let count = self.cell_pointers.len();
+        self.data
+            .copy_within(pointers + 2 * (index + 1)..pointers + 2 * count, pointers + 2 * index);
+        let header = self.offset as usize;
+        self.write_u16(header + 3, count - 1);
+
+        if count == 1 {
+            // An empty page has all of its space unallocated.
+            self.write_u16(header + 1, 0);
+            self.write_u16(header + 5, self.usable_size as usize);
+            self.data[7] = 0;
+        } else {
+            self.free_space(start, size)?;
+        }
+        self.reparse()
+    }
+
+    /// Adds `start..start + size` to the freeblocks, kept in order of their
+    /// offsets. Gaps of up to 3 bytes to the blocks around it, which would
+    /// otherwise be fragments, are merged in too.
+    fn free_space(&mut self, mut start: usize, mut size: usize) -> Result<()> {
+        let header = self.offset as usize;
+        let corrupt = || format_err!("Malformed page: freeblocks out of order");
+
+        // The location of the offset of the next freeblock: the page header,
+        // or the freeblock before the one freed.
+        let mut pointer = header + 1;
+        let mut next = self.read_u16(pointer);
+        while next != 0 && next < start {
+            if next <= pointer {
+                return Err(corrupt());
+            }
+            pointer = next;
+            next = self.read_u16(pointer);
+        }
+
+        let mut end = start + size;
+        let mut fragments = 0;
+        if next != 0 && end + 3 >= next {
+            fragments += next - end;
+            end = next + self.read_u16(next + 2);
+            next = self.read_u16(next);
+        }
+        if pointer > header + 1 {
+            let previous_end = pointer + self.read_u16(pointer + 2);
+            if previous_end + 3 >= start {
+                fragments += start - previous_end;
+                start = pointer;
+            }
+        }
+        size = end - start;
+        let fragment_bytes = &mut self.data[7];
+        *fragment_bytes = fragment_bytes.checked_sub(fragments as u8).ok_or_else(corrupt)?;
+
+        let content_start = self.read_u16(header + 5);
+        if start <= content_start {
+            if start < content_start || pointer != header + 1 {
+                return Err(corrupt());
+            }
+            self.write_u16(header + 1, next);
+            self.write_u16(header + 5, end);
+        } else {
+            self.write_u16(pointer, start);
+            self.write_u16(start, next);
+            self.write_u16(start + 2, size);
+        }
+        Ok(())
+    }
+
+    /// Inserts `cell` so that it becomes the cell at `index`. Returns false,
+    /// leaving the page as it was, when there isn't room for it.
+    pub fn insert_cell(&mut self, index: usize, cell: &[u8]) -> Result<bool> {
+        let size = cell.len().max(4);
+        if (self.unused_bytes() as usize) < size + 2 {
+            return Ok(false);
+        }
+
+        let start = match self.allocate_freeblock(size) {
+            Some(start) => start,
+            None => {
+                if self.gap() < size + 2 {
+                    self.defragment()?;
+                }
+                let start = self.content_start() - size;
+                self.write_u16(self.offset as usize + 5, start);
+                start
+            }
+        };
+        let at = start - self.offset as usize;
+        self.data[at..at + cell.len()].copy_from_slice(cell);
+
+        let pointers = self.header.kind.header_size();
+        let count = self.cell_pointers.len();
+        self.data
+            .copy_within(pointers + 2 * index..pointers + 2 * count, pointers + 2 * (index + 1));
+        self.data[pointers + 2 * index..pointers + 2 * index + 2].copy_from_slice(&(start as u16).to_be_bytes());
+        self.write_u16(self.offset as usize + 3, count + 1);
+        self.reparse()?;
+        Ok(true)
+    }
+
+    /// Start of the cell content area.
+    fn content_start(&self) -> usize {
+        match self.read_u16(self.offset as usize + 5) {
+            0 => 65536,
+            start => start,
+        }
+    }
+
+    /// Unallocated bytes between the cell pointers and the cell content.
+    fn gap(&self) -> usize {
+        let pointers_end =
+            self.offset as usize + self.header.kind.header_size() + 2 * self.cell_pointers.len();
+        self.content_start().saturating_sub(pointers_end)
+    }
+
+    /// Takes `size` bytes from the end of the first freeblock big enough,
+    /// as long as the cell pointer still fits in the gap. A remainder too
+    /// small to be a freeblock becomes fragments.
+    fn allocate_freeblock(&mut self, size: usize) -> Option<usize> {
+        if self.gap() < 2 {
+            return None;
+        }
+        let mut pointer = self.offset as usize + 1;
+        let mut block = self.read_u16(pointer);
+        while block != 0 {
+            let block_size = self.read_u16(block + 2);
+            if block_size >= size {
+                let remainder = block_size - size;
+                if remainder < 4 {
+                    // SQLite defragments rather than go past 60 fragment bytes.
+                    if self.data[7] as usize + remainder > 60 {
+                        return None;
+                    }
+                    let next = self.read_u16(block);
+                    self.write_u16(pointer, next);
+                    self.data[7] += remainder as u8;
+                } else {
+                    self.write_u16(block + 2, remainder);
+                }
+                return Some(block + remainder);
+            }
+            pointer = block;
+            block = self.read_u16(block);
+        }
+        None
+    }
+
+    /// Moves the cells to the end of the page, leaving all of the free
+    /// space unallocated between them and the cell pointers.
+    pub fn defragment(&mut self) -> Result<()> {
+        let cells = (0..self.cell_pointers.len())
+            .map(|index| self.cell_bytes(index).to_vec())
+            .collect::<Vec<_>>();
+        let pointers = self.header.kind.header_size();
+        let mut start = self.usable_size as usize;
+        for (index, cell) in cells.iter().enumerate() {
+            start -= cell.len();
+            let at = start - self.offset as usize;
+            self.data[at..at + cell.len()].copy_from_slice(cell);
+            self.data[pointers + 2 * index..pointers + 2 * index + 2].copy_from_slice(&(start as u16).to_be_bytes());
+        }
+        let header = self.offset as usize;
+        self.write_u16(header + 1, 0);
+        self.write_u16(header + 5, start);
+        self.data[7] = 0;
+        self.reparse()
+    }
+
+    /// Parses the page again after a change. Its cells were checked when it
+    /// was read, and the ones put in since are well-formed.
+    fn reparse(&mut self) -> Result<()> {
+        *self = Page::parse_layout(std::mem::take(&mut self.data), self.offset, self.usable_size)?;
+        Ok(())
+    }
+}
+
+/// Where the cell at `start` of `data`, a page of `kind`, ends, or `None`
+/// when it doesn't fit, like in a corrupt page. Overflow cells end with the
+/// number of their first overflow page.
+fn cell_end(kind: PageKind, data: &[u8], start: usize, usable_size: u32) -> Option<usize> {
+    let varint_end = |at: usize| {
+        let (_, size) = varient::read(data.get(at..)?);
+        Some(at + size).filter(|&end| end <= data.len() && size > 0)
+    };
+    let at = if kind.is_interior() { start + 4 } else { start };
+    if kind == PageKind::InteriorTable {
+        return varint_end(at);
+    }
+    let (payload_size, _) = varient::read(data.get(at..)?);
+    let mut end = varint_end(at)?;
+    if kind == PageKind::LeafTable {
+        end = varint_end(end)?;
+    }
+    let payload_size = u64::try_from(payload_size).ok()?;
+    let local = kind.local_payload_size(payload_size, usable_size) as usize;
+    let end = end + local + if (local as u64) < payload_size { 4 } else { 0 };
+    (end <= data.len()).then_some(end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn small_payloads_are_stored_locally() {
+        assert_eq!(PageKind::LeafTable.local_payload_size(100, 4096), 100);
+        assert_eq!(PageKind::LeafTable.local_payload_size(4061, 4096), 4061);
+        assert_eq!(PageKind::LeafIndex.local_payload_size(1002, 4096), 1002);
+    }
+
+    #[test]
+    fn large_payloads_spill_to_overflow_pages() {
+        assert_eq!(PageKind::LeafTable.local_payload_size(4062, 4096), 489);
+        assert_eq!(PageKind::LeafTable.local_payload_size(10000, 4096), 1816);
+        assert_eq!(PageKind::LeafIndex.local_payload_size(1003, 4096), 489);
+        assert_eq!(PageKind::InteriorIndex.local_payload_size(5000, 4096), 908);
+    }
+
+    fn leaf_cell(rowid: u8, payload: &[u8]) -> Vec<u8> {
+        let mut cell = vec![payload.len() as u8, rowid];
+        cell.extend_from_slice(payload);
+        cell
+    }
+
+    fn rowids(page: &Page) -> Vec<i64> {
+        page.cells()
+            .map(|cell| match cell {
+                Cell::LeafTable { rowid, .. } => rowid,
+                _ => unreachable!(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn insert_and_remove_cells() {
+        let mut page = Page::empty(PageKind::LeafTable, 512, 0, 512).unwrap();
+        for rowid in [1, 3, 2] {
+            let index = rowids(&page).iter().filter(|&&r| r < rowid as i64).count();
+            assert!(page.insert_cell(index, &leaf_cell(rowid, &[rowid; 20])).unwrap());
+        }
+        assert_eq!(rowids(&page), vec![1, 2, 3]);
+        assert_eq!(page.unused_bytes(), 512 - 8 - 3 * 24);
+
+        // A cell in the middle of the content becomes a freeblock, and the
+        // space of the one at its start goes back to the gap.
+        page.remove_cell(1).unwrap();
+        assert_eq!(page.header.first_freeblock_start, 0);
+        page.remove_cell(0).unwrap();
+        assert_eq!(page.header.first_freeblock_start, 512 - 22);
+        assert_eq!(rowids(&page), vec![3]);
+        assert_eq!(page.unused_bytes(), 512 - 8 - 24);
+
+        // The freeblock is reused for a cell that fits in it.
+        assert!(page.insert_cell(1, &leaf_cell(4, &[4; 20])).unwrap());
+        assert_eq!(page.header.first_freeblock_start, 0);
+        assert_eq!(page.cell_bytes(1), leaf_cell(4, &[4; 20]).as_slice());
+
+        page.remove_cell(0).unwrap();
+        page.remove_cell(0).unwrap();
+        assert_eq!(page.header.content_start_offset, 512);
+        assert_eq!(page.unused_bytes(), 512 - 8);
+    }
+
+    #[test]
+    fn full_pages_take_no_more_cells() {
+        let mut page = Page::empty(PageKind::LeafTable, 412, 100, 512).unwrap();
+        let mut count = 0;
+        while page.insert_cell(count, &leaf_cell(count as u8, &[0; 60])).unwrap() {
+            count += 1;
+        }
+        assert_eq!(count, 6);
+
+        // Freeing every other cell leaves room only after defragmenting.
+        for index in [4, 2, 0] {
+            page.remove_cell(index).unwrap();
+        }
+        assert!(page.insert_cell(3, &leaf_cell(9, &[9; 120])).unwrap());
+        assert_eq!(page.header.first_freeblock_start, 0);
+        assert_eq!(rowids(&page), vec![1, 3, 5, 9]);
+        assert_eq!(page.cell_bytes(3), leaf_cell(9, &[9; 120]).as_slice());
+        assert!(!page.insert_cell(4, &leaf_cell(10, &[0; 120])).unwrap());
+    }
+
+    #[test]
+    fn point_interior_cells_at_children() {
+        let mut page = Page::empty(PageKind::InteriorTable, 512, 0, 512).unwrap();
+        page.insert_cell(0, &[0, 0, 0, 2, 10]).unwrap();
+        page.set_child(1, 3).unwrap();
+        page.set_child(0, 4).unwrap();
+        assert_eq!(page.child(0), Some(4));
+        assert_eq!(page.child(1), Some(3));
+        assert_eq!(page.cell_size(0), 5);
+    }
+
+    #[test]
+    fn reject_cells_outside_the_page() {
+        let mut page = Page::empty(PageKind::LeafTable, 412, 100, 512).unwrap();
+        page.insert_cell(0, &leaf_cell(1, &[1; 20])).unwrap();
+        assert!(Page::parse(page.data.clone(), 100, 512).is_ok());
+
+        // A pointer into the file header, past the page and into the pointers.
+        for pointer in [50u16, 512, 108] {
+            let mut data = page.data.clone();
+            data[8..10].copy_from_slice(&pointer.to_be_bytes());
+            assert!(Page::parse(data, 100, 512).is_err(), "pointer {}", pointer);
+        }
+
+        // A payload size larger than what is left of the page.
+        let mut data = page.data.clone();
+        data[page.cell_pointers[0] as usize] = 30;
+        assert!(Page::parse(data, 100, 512).is_err());
+
+        // More cell pointers than fit in the page.
+        let mut data = page.data.clone();
+        data[3..5].copy_from_slice(&300u16.to_be_bytes());
+        assert!(Page::parse(data, 100, 512).is_err());
+    }
+}
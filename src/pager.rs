@@ -0,0 +1,144 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use crate::page::Page;
+
+/// Pages kept by default, like SQLite's default cache of 2000 KiB holds
+/// 2000 pages of 1 KiB.
+pub const DEFAULT_CACHE_SIZE: usize = 2000;
+
+/// Decoded pages kept between reads, by page number, dropping the least
+/// recently used page once `capacity` pages are held. A capacity of 0
+/// keeps nothing.
+#[derive(Debug)]
+pub struct PageCache {
+    capacity: usize,
+    pages: HashMap<u32, (Arc<Page>, u64)>,
+    /// Page numbers by the tick of their last use, oldest first.
+    recency: BTreeMap<u64, u32>,
+    tick: u64,
+    /// Lookups that found their page, since the cache was created.
+    pub hits: u64,
+    /// Lookups that had to read their page.
+    pub misses: u64,
+}
+
+impl PageCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            pages: HashMap::new(),
+            recency: BTreeMap::new(),
+            tick: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Changes how many pages are kept, dropping the least recently used
+    /// ones above the new capacity.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.evict();
+    }
+
+    pub fn len(&self) -> usize {
+        self.pages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pages.is_empty()
+    }
+
+    /// The page with this 1-based number, now the most recently used.
+    pub fn get(&mut self, page_number: u32) -> Option<Arc<Page>> {
+        let Some((page, used)) = self.pages.get_mut(&page_number) else {
+            self.misses += 1;
+            return None;
+        };
+        self.hits += 1;
+        self.tick += 1;
+        self.recency.remove(used);
+        self.recency.insert(self.tick, page_number);
+        *used = self.tick;
+        Some(page.clone())
+    }
+
+    pub fn insert(&mut self, page_number: u32, page: Arc<Page>) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.tick += 1;
+        if let Some((_, used)) = self.pages.insert(page_number, (page, self.tick)) {
+            self.recency.remove(&used);
+        }
+        self.recency.insert(self.tick, page_number);
+        self.evict();
+    }
+
+    /// Forgets the page with this 1-based number, e.g. once it is written.
+    pub fn remove(&mut self, page_number: u32) {
+        if let Some((_, used)) = self.pages.remove(&page_number) {
+            self.recency.remove(&used);
+        }
+    }
+
+    /// Forgets every page, e.g. once the file has changed under them. The
+    /// counters carry on.
+    pub fn clear(&mut self) {
+        self.pages.clear();
+        self.recency.clear();
+    }
+
+    fn evict(&mut self) {
+        while self.pages.len() > self.capacity {
+            let Some((_, page_number)) = self.recency.pop_first() else {
+                break;
+            };
+            self.pages.remove(&page_number);
+        }
+    }
+}
+
+impl Default for PageCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_CACHE_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page() -> Arc<Page> {
+        let mut data = vec![0; 512];
+        data[0] = 0x0d;
+        Arc::new(Page::parse(data, 0, 512).unwrap())
+    }
+
+    #[test]
+    fn evict_least_recently_used() {
+        let mut cache = PageCache::new(2);
+        cache.insert(1, page());
+        cache.insert(2, page());
+        assert!(cache.get(1).is_some());
+        cache.insert(3, page());
+
+        assert!(cache.get(2).is_none());
+        assert!(cache.get(1).is_some());
+        assert!(cache.get(3).is_some());
+        assert_eq!((cache.hits, cache.misses), (3, 1));
+
+        cache.set_capacity(1);
+        assert_eq!(cache.len(), 1);
+        assert!(cache.get(3).is_some());
+
+        cache.set_capacity(0);
+        cache.insert(4, page());
+        assert!(cache.is_empty());
+    }
+}
@@ -0,0 +1,275 @@
+use crate::database::is_rowid_alias;
+use crate::sql::{Comparison, Operator, WhereClause};
+use crate::sqlite_schema::{Index, Table};
+use crate::stats::Access;
+
+/// How the rows of a table that may pass a WHERE clause are read.
+#[derive(Debug, Clone)]
+pub enum Plan<'a> {
+    /// Every row of the table.
+    FullScan,
+    /// The rows whose keys in `index` the `bounds` narrow down. See
+    /// [`Table::index_bounds`].
+    IndexScan {
+        index: &'a Index,
+        bounds: Vec<&'a Comparison>,
+    },
+    /// The one row with this rowid.
+    RowidSeek(i64),
+}
+
+impl<'a> Plan<'a> {
+    /// The way that reads the fewest rows: the row an equality on the rowid
+    /// names, then the rows of the index bounding the most columns, then
+    /// every row. The rows read still go through the whole clause.
+    pub fn new(table: &'a Table, filter: &'a Option<WhereClause>) -> Self {
+        let Some(clause) = filter else {
+            return Plan::FullScan;
+        };
+        if let Some(rowid) = rowid_seek(table, clause) {
+            return Plan::RowidSeek(rowid);
+        }
+        match table.find_applicable_index(filter) {
+            Some(index) => Plan::IndexScan {
+                index,
+                bounds: table.index_bounds(index, clause),
+            },
+            None => Plan::FullScan,
+        }
+    }
+
+    pub fn access(&self) -> Access {
+        match self {
+            Plan::FullScan => Access::TableScan,
+            Plan::IndexScan { index, .. } => Access::Index(index.name.clone()),
+            Plan::RowidSeek(_) => Access::Rowid,
+        }
+    }
+
+    /// The line EXPLAIN QUERY PLAN shows for reading `table` this way,
+    /// worded like SQLite's.
+    pub fn detail(&self, table: &str) -> String {
+        match self {
+            Plan::FullScan => format!("SCAN {}", table),
+            Plan::IndexScan { index, bounds } => {
+                let terms = bounds
+                    .iter()
+                    .map(|bound| {
+                        let operator = match bound.operator {
+                            Operator::Equal => "=",
+                            Operator::Greater => ">",
+                            Operator::GreaterEqual => ">=",
+                            Operator::Less => "<",
+                            _ => "<=",
+                        };
+                        format!("{}{}?", bound.field, operator)
+                    })
+                    .collect::<Vec<_>>();
+                format!("SEARCH {} USING INDEX {} ({})", table, index.name, terms.join(" AND "))
+            }
+            Plan::RowidSeek(_) => format!("SEARCH {} USING INTEGER PRIMARY KEY (rowid=?)", table),
+        }
+    }
+}
+
+/// The rowid an equality joined to the rest of the clause by AND asks for,
+/// on the rowid or the INTEGER PRIMARY KEY column.
+fn rowid_seek(table: &Table, clause: &WhereClause) -> Option<i64> {
+    if table.without_rowid || table.is_virtual() {
+        return None;
+    }
+    let is_rowid = |name: &str| match table.find_column(name) {
+        Some((_, column)) => column.is_primary_key,
+        None => is_rowid_alias(name),
+    };
+    clause
+        .conjuncts()
+        .into_iter()
+        .filter(|comparison| comparison.operator == Operator::Equal && is_rowid(&comparison.field))
+        .find_map(|comparison| comparison.value.parse().ok())
+}
+
+/// A step of a query plan, made of the steps under it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Step {
+    pub detail: String,
+    pub children: Vec<Step>,
+}
+
+impl Step {
+    pub fn new(detail: impl Into<String>) -> Self {
+        Self {
+            detail: detail.into(),
+            children: vec![],
+        }
+    }
+
+    pub fn with_children(detail: impl Into<String>, children: Vec<Step>) -> Self {
+        Self {
+            detail: detail.into(),
+            children,
+        }
+    }
+}
+
+/// What EXPLAIN QUERY PLAN shows of a statement.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QueryPlan {
+    pub steps: Vec<Step>,
+}
+
+impl QueryPlan {
+    /// The steps as SQLite returns them: an id, the id of the step above,
+    /// 0 at the top, and the detail. Ids count from 1 in the order shown.
+    pub fn rows(&self) -> Vec<(i64, i64, String)> {
+        fn add(steps: &[Step], parent: i64, rows: &mut Vec<(i64, i64, String)>) {
+            for step in steps {
+                let id = rows.len() as i64 + 1;
+                rows.push((id, parent, step.detail.clone()));
+                add(&step.children, id, rows);
+            }
+        }
+
+        let mut rows = vec![];
+        add(&self.steps, 0, &mut rows);
+        rows
+    }
+}
+
+/// Draws the steps as a tree under a `QUERY PLAN` line, like sqlite3.
+impl std::fmt::Display for QueryPlan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn draw(f: &mut std::fmt::Formatter<'_>, steps: &[Step], indent: &str) -> std::fmt::Result {
+            for (i, step) in steps.iter().enumerate() {
+                let last = i + 1 == steps.len();
+                writeln!(f, "{}{}{}", indent, if last { "`--" } else { "|--" }, step.detail)?;
+                draw(f, &step.children, &format!("{}{}", indent, if last { "   " } else { "|  " }))?;
+            }
+            Ok(())
+        }
+
+        writeln!(f, "QUERY PLAN")?;
+        draw(f, &self.steps, "")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::record::Collation;
+    use crate::sql::parse_statement;
+    use crate::sql::{SQLCommand, SelectStatement};
+    use crate::sqlite_schema::Column;
+
+    fn table() -> Table {
+        let index = Index {
+            name: "by_country".to_string(),
+            columns: vec!["country".to_string(), "city".to_string()],
+            collations: vec![Collation::Binary; 2],
+            descending: vec![false; 2],
+            table_name: "places".to_string(),
+            rootpage: 3,
+            stat: vec![],
+        };
+        Table {
+            columns: vec![
+                Column {
+                    is_primary_key: true,
+                    ..Column::new("id".to_string())
+                },
+                Column::new("country".to_string()),
+                Column::new("city".to_string()),
+            ],
+            rootpage: 2,
+            ..Table::from(index)
+        }
+    }
+
+    fn filter(sql: &str) -> Option<WhereClause> {
+        match parse_statement(sql).unwrap() {
+            SQLCommand::Select(SelectStatement::Fields(select)) => select.where_clause,
+            command => panic!("Not a SELECT: {:?}", command),
+        }
+    }
+
+    #[test]
+    fn choose_access_path() {
+        let table = table();
+        let detail = |sql: &str| Plan::new(&table, &filter(sql)).detail("places");
+
+        assert_eq!(detail("SELECT id FROM places"), "SCAN places");
+        assert_eq!(detail("SELECT id FROM places WHERE city = 'Seoul'"), "SCAN places");
+        assert_eq!(
+            detail("SELECT id FROM places WHERE country = 'KR' AND city >= 'B'"),
+            "SEARCH places USING INDEX by_country (country=? AND city>=?)"
+        );
+        assert_eq!(
+            detail("SELECT id FROM places WHERE country = 'KR' AND id = 7"),
+            "SEARCH places USING INTEGER PRIMARY KEY (rowid=?)"
+        );
+        assert!(matches!(
+            Plan::new(&table, &filter("SELECT id FROM places WHERE rowid = 7")),
+            Plan::RowidSeek(7)
+        ));
+        assert!(matches!(
+            Plan::new(&table, &filter("SELECT id FROM places WHERE id = 7 OR id = 8")),
+            Plan::FullScan
+        ));
+    }
+
+    #[test]
+    fn choose_index_by_statistics() {
+        let mut table = table();
+        table.indexes.push(Index {
+            name: "by_city".to_string(),
+            columns: vec!["city".to_string()],
+            collations: vec![Collation::Binary],
+            descending: vec![false],
+            table_name: "places".to_string(),
+            rootpage: 4,
+            stat: vec![],
+        });
+        let filter = filter("SELECT id FROM places WHERE country = 'KR' AND city = 'Seoul'");
+
+        // Without statistics the index bounding more columns is taken.
+        let index = |table: &Table| table.find_applicable_index(&filter).unwrap().name.clone();
+        assert_eq!(index(&table), "by_country");
+
+        // Many places are in a country, but few have a city's name.
+        table.indexes[0].stat = vec![1000, 500, 400];
+        table.indexes[1].stat = vec![1000, 2];
+        assert_eq!(index(&table), "by_city");
+
+        let bounds = table.index_bounds(&table.indexes[0], filter.as_ref().unwrap());
+        assert_eq!(table.indexes[0].estimated_rows(&bounds), 400);
+    }
+
+    #[test]
+    fn draw_query_plan_tree() {
+        let plan = QueryPlan {
+            steps: vec![
+                Step::with_children(
+                    "COMPOUND QUERY",
+                    vec![
+                        Step::with_children("LEFT-MOST SUBQUERY", vec![Step::new("SCAN a")]),
+                        Step::with_children("UNION ALL", vec![Step::new("SCAN b")]),
+                    ],
+                ),
+                Step::new("USE TEMP B-TREE FOR ORDER BY"),
+            ],
+        };
+
+        assert_eq!(
+            plan.to_string(),
+            "QUERY PLAN\n\
+             |--COMPOUND QUERY\n\
+             |  |--LEFT-MOST SUBQUERY\n\
+             |  |  `--SCAN a\n\
+             |  `--UNION ALL\n\
+             |     `--SCAN b\n\
+             `--USE TEMP B-TREE FOR ORDER BY\n"
+        );
+        assert_eq!(plan.rows()[2], (3, 2, "SCAN a".to_string()));
+        assert_eq!(plan.rows()[5], (6, 0, "USE TEMP B-TREE FOR ORDER BY".to_string()));
+    }
+}
@@ -0,0 +1,260 @@
+use crate::database::Database;
+use crate::error::Result;
+use crate::output::RowSink;
+use crate::record::ColumnValue;
+use crate::sql::PragmaStatement;
+use crate::sqlite_schema::{Index, Table};
+use crate::statement::describe_view;
+
+/// Names of the result columns of the PRAGMA `name`, as sqlite3 gives
+/// them, or `None` for one that isn't supported.
+pub fn columns(name: &str) -> Option<&'static [&'static str]> {
+    match name.to_ascii_lowercase().as_str() {
+        "table_info" => Some(&["cid", "name", "type", "notnull", "dflt_value", "pk"]),
+        "index_list" => Some(&["seq", "name", "unique", "origin", "partial"]),
+        "index_info" => Some(&["seqno", "cid", "name"]),
+        _ => None,
+    }
+}
+
+/// Writes the rows of `pragma` to `out`, read from the schema. Like in
+/// SQLite, a table or index that doesn't exist has no rows.
+pub(crate) fn run(database: &Database, pragma: &PragmaStatement, out: &mut impl RowSink) -> Result<()> {
+    let Some(argument) = &pragma.argument else {
+        return Ok(());
+    };
+    let name = match &pragma.schema {
+        Some(schema) => format!("{}.{}", schema, argument),
+        None => argument.clone(),
+    };
+    match pragma.name.to_ascii_lowercase().as_str() {
+        "table_info" => table_info(database, &name, out),
+        "index_list" => {
+            let (database, name) = database.resolve(&name);
+            match database.schema.find_table(name) {
+                Some(table) => index_list(table, out),
+                None => Ok(()),
+            }
+        }
+        "index_info" => match find_index(database, pragma.schema.as_deref(), argument) {
+            Some((table, index)) => index_info(table, index, out),
+            None => Ok(()),
+        },
+        _ => Ok(()),
+    }
+}
+
+/// A row for every column of a table or view, leaving out generated ones.
+fn table_info(database: &Database, name: &str, out: &mut impl RowSink) -> Result<()> {
+    let (database, name) = database.resolve(name);
+    if let Some(view) = database.schema.find_view(name) {
+        for (cid, (name, origin)) in describe_view(database, view)?.iter().enumerate() {
+            let type_name = origin.as_ref().and_then(|origin| origin.decl_type.as_deref());
+            out.write_row(&[
+                ColumnValue::I64(cid as i64),
+                ColumnValue::Text(name.as_bytes()),
+                ColumnValue::Text(type_name.unwrap_or_default().as_bytes()),
+                ColumnValue::I64(0),
+                ColumnValue::Null,
+                ColumnValue::I64(0),
+            ])?;
+        }
+        return Ok(());
+    }
+
+    let Some(table) = database.schema.find_table(name) else {
+        return Ok(());
+    };
+    let columns = table.columns.iter().filter(|column| column.generated.is_none());
+    for (cid, column) in columns.enumerate() {
+        // The PRIMARY KEY of a WITHOUT ROWID table can't be NULL.
+        let not_null = !column.nullable || (table.without_rowid && column.in_primary_key);
+        let pk = table
+            .primary_key
+            .iter()
+            .position(|key| key.eq_ignore_ascii_case(&column.name))
+            .map_or(0, |i| i + 1);
+        // SQLite keeps a parenthesized DEFAULT without its parentheses.
+        let default = column.default_text.as_deref().map(|text| {
+            text.strip_prefix('(')
+                .and_then(|text| text.strip_suffix(')'))
+                .map_or(text, str::trim)
+        });
+        out.write_row(&[
+            ColumnValue::I64(cid as i64),
+            ColumnValue::Text(column.name.as_bytes()),
+            ColumnValue::Text(column.type_name.as_deref().unwrap_or_default().as_bytes()),
+            ColumnValue::I64(not_null as i64),
+            default.map_or(ColumnValue::Null, |text| ColumnValue::Text(text.as_bytes())),
+            ColumnValue::I64(pk as i64),
+        ])?;
+    }
+    Ok(())
+}
+
+/// A row for every index of `table`, newest first as in SQLite, saying
+/// whether it is unique and whether CREATE INDEX ("c"), a UNIQUE
+/// constraint ("u") or the PRIMARY KEY ("pk") made it.
+fn index_list(table: &Table, out: &mut impl RowSink) -> Result<()> {
+    let indexes = table
+        .autoindexes
+        .iter()
+        .map(|index| (index, true))
+        .chain(table.indexes.iter().map(|index| (index, false)));
+    for (seq, (index, automatic)) in indexes.rev().enumerate() {
+        let is_primary_key = index.columns.len() == table.primary_key.len()
+            && index
+                .columns
+                .iter()
+                .zip(&table.primary_key)
+                .all(|(column, key)| column.eq_ignore_ascii_case(key));
+        let origin = match (automatic, is_primary_key) {
+            (false, _) => "c",
+            (true, true) => "pk",
+            (true, false) => "u",
+        };
+        out.write_row(&[
+            ColumnValue::I64(seq as i64),
+            ColumnValue::Text(index.name.as_bytes()),
+            ColumnValue::I64(automatic as i64),
+            ColumnValue::Text(origin.as_bytes()),
+            ColumnValue::I64(0),
+        ])?;
+    }
+    Ok(())
+}
+
+/// A row for every column of `index`, in key order, with its position in
+/// `table`.
+fn index_info(table: &Table, index: &Index, out: &mut impl RowSink) -> Result<()> {
+    for (seqno, name) in index.columns.iter().enumerate() {
+        let column = table
+            .columns
+            .iter()
+            .enumerate()
+            .find(|(_, column)| column.name.eq_ignore_ascii_case(name));
+        let (cid, name) = column.map_or((-1, name), |(cid, column)| (cid as i64, &column.name));
+        out.write_row(&[
+            ColumnValue::I64(seqno as i64),
+            ColumnValue::I64(cid),
+            ColumnValue::Text(name.as_bytes()),
+        ])?;
+    }
+    Ok(())
+}
+
+/// The index named `name` and its table, in the database of `schema` if
+/// given, or else in the temp database, this one, then the attached ones.
+fn find_index<'a>(database: &'a Database, schema: Option<&str>, name: &str) -> Option<(&'a Table, &'a Index)> {
+    let find = |database: &'a Database| {
+        let index = database.schema.find_index(name)?;
+        Some((database.schema.tables.get(&index.table_name)?, index))
+    };
+    match schema {
+        Some(schema) => find(database.resolve(&format!("{}.{}", schema, name)).0),
+        None => database
+            .temp()
+            .into_iter()
+            .chain(std::iter::once(database))
+            .chain(database.attached().map(|(_, database)| database))
+            .find_map(find),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::Database;
+
+    #[test]
+    fn describe_tables_and_indexes() {
+        let mut database = Database::open_in_memory().unwrap();
+        for sql in [
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, a TEXT UNIQUE NOT NULL DEFAULT 'x', b REAL DEFAULT ( 1+2 ), c, UNIQUE (b, c))",
+            "CREATE INDEX by_c ON t (c, a)",
+            "CREATE TEMP TABLE w (x, y, PRIMARY KEY (y, x)) WITHOUT ROWID",
+        ] {
+            database.query(sql).unwrap();
+        }
+
+        let table_info = database
+            .query_rows::<(i64, String, String, bool, Option<String>, i64)>("PRAGMA table_info(t)")
+            .unwrap();
+        let column = |cid, name: &str, type_name: &str, not_null, default: Option<&str>, pk| {
+            (cid, name.to_string(), type_name.to_string(), not_null, default.map(str::to_string), pk)
+        };
+        assert_eq!(
+            table_info,
+            [
+                column(0, "id", "INTEGER", false, None, 1),
+                column(1, "a", "TEXT", true, Some("'x'"), 0),
+                column(2, "b", "REAL", false, Some("1+2"), 0),
+                column(3, "c", "", false, None, 0),
+            ]
+        );
+        let keys = database
+            .query_rows::<(i64, String, String, bool, Option<String>, i64)>("PRAGMA temp.table_info = w")
+            .unwrap();
+        let keys: Vec<_> = keys.into_iter().map(|(_, name, _, not_null, _, pk)| (name, not_null, pk)).collect();
+        assert_eq!(keys, [("x".to_string(), true, 2), ("y".to_string(), true, 1)]);
+
+        let index_list = database
+            .query_rows::<(i64, String, bool, String, bool)>("PRAGMA index_list(t)")
+            .unwrap();
+        let indexes: Vec<_> = index_list
+            .iter()
+            .map(|(seq, name, unique, origin, _)| (*seq, name.as_str(), *unique, origin.as_str()))
+            .collect();
+        assert_eq!(
+            indexes,
+            [
+                (0, "by_c", false, "c"),
+                (1, "sqlite_autoindex_t_2", true, "u"),
+                (2, "sqlite_autoindex_t_1", true, "u"),
+            ]
+        );
+        let index_info = database.query_rows::<(i64, i64, String)>("PRAGMA index_info(by_c)").unwrap();
+        assert_eq!(index_info, [(0, 3, "c".to_string()), (1, 1, "a".to_string())]);
+        let index_info = database
+            .query_rows::<(i64, i64, String)>("PRAGMA index_info('sqlite_autoindex_w_1')")
+            .unwrap();
+        assert_eq!(index_info, [(0, 1, "y".to_string()), (1, 0, "x".to_string())]);
+
+        assert!(database.query("PRAGMA table_info(missing)").unwrap().is_empty());
+        let error = database.query("PRAGMA journal_mode = WAL").unwrap_err();
+        assert_eq!(error.to_string(), "Unsupported PRAGMA: journal_mode");
+    }
+
+    #[test]
+    fn table_info_like_sqlite3() {
+        let mut database = Database::open_in_memory().unwrap();
+        database
+            .query(
+                "CREATE TABLE known (id integer primary key autoincrement, name varchar(20) NOT NULL \
+                 DEFAULT 'it''s', size DECIMAL( 10 , 5 ) DEFAULT -1, created DEFAULT CURRENT_TIMESTAMP, \
+                 note text DEFAULT NULL, flags DEFAULT 0x1F, data blob DEFAULT x'00ff', rest)",
+            )
+            .unwrap();
+
+        // The rows sqlite3 gives, except that it upper-cases the declared
+        // types INTEGER, TEXT and BLOB.
+        let table_info = database
+            .query_rows::<(i64, String, String, bool, Option<String>, i64)>("PRAGMA table_info(known)")
+            .unwrap();
+        let column = |cid, name: &str, type_name: &str, not_null, default: Option<&str>, pk| {
+            (cid, name.to_string(), type_name.to_string(), not_null, default.map(str::to_string), pk)
+        };
+        assert_eq!(
+            table_info,
+            [
+                column(0, "id", "integer", false, None, 1),
+                column(1, "name", "varchar(20)", true, Some("'it''s'"), 0),
+                column(2, "size", "DECIMAL( 10 , 5 )", false, Some("-1"), 0),
+                column(3, "created", "", false, Some("CURRENT_TIMESTAMP"), 0),
+                column(4, "note", "text", false, Some("NULL"), 0),
+                column(5, "flags", "", false, Some("0x1F"), 0),
+                column(6, "data", "blob", false, Some("x'00ff'"), 0),
+                column(7, "rest", "", false, None, 0),
+            ]
+        );
+    }
+}
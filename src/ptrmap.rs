@@ -0,0 +1,179 @@
+use crate::database::Database;
+use crate::error::{bail, Error, Result};
+
+const ENTRY_SIZE: u32 = 5;
+/// Pointer-map pages only exist from page 2 onwards.
+const FIRST_PTRMAP_PAGE: u32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PointerType {
+    /// Root page of a B-tree; has no parent.
+    RootPage,
+    /// Page on the freelist; has no parent.
+    FreePage,
+    /// First page of an overflow chain; the parent is the B-tree page.
+    Overflow1,
+    /// Later page of an overflow chain; the parent is the previous page.
+    Overflow2,
+    /// Non-root B-tree page; the parent is its parent B-tree page.
+    BTree,
+}
+
+impl TryFrom<u8> for PointerType {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            1 => Ok(Self::RootPage),
+            2 => Ok(Self::FreePage),
+            3 => Ok(Self::Overflow1),
+            4 => Ok(Self::Overflow2),
+            5 => Ok(Self::BTree),
+            n => bail!("Invalid pointer-map entry type: {}", n),
+        }
+    }
+}
+
+impl std::fmt::Display for PointerType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PointerType::RootPage => write!(f, "root page"),
+            PointerType::FreePage => write!(f, "free page"),
+            PointerType::Overflow1 => write!(f, "first overflow page"),
+            PointerType::Overflow2 => write!(f, "overflow page"),
+            PointerType::BTree => write!(f, "b-tree page"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PointerMapEntry {
+    pub page_number: u32,
+    pub kind: PointerType,
+    pub parent: u32,
+}
+
+/// Page layout of the pointer map, which auto-vacuum and incremental-vacuum
+/// databases keep so pages can be moved when the file is truncated. Every
+/// pointer-map page describes the pages that follow it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PointerMap {
+    usable_size: u32,
+}
+
+impl PointerMap {
+    pub fn new(usable_size: u32) -> Self {
+        Self { usable_size }
+    }
+
+    fn entries_per_page(&self) -> u32 {
+        self.usable_size / ENTRY_SIZE
+    }
+
+    /// The pointer-map page holding the entry of `page_number`.
+    pub fn ptrmap_page(&self, page_number: u32) -> Option<u32> {
+        if page_number < FIRST_PTRMAP_PAGE {
+            return None;
+        }
+        let group = self.entries_per_page() + 1;
+        Some((page_number - FIRST_PTRMAP_PAGE) / group * group + FIRST_PTRMAP_PAGE)
+    }
+
+    pub fn is_ptrmap_page(&self, page_number: u32) -> bool {
+        self.ptrmap_page(page_number) == Some(page_number)
+    }
+
+    /// Pointer-map pages of a file with `page_count` pages.
+    pub fn ptrmap_pages(&self, page_count: u32) -> impl Iterator<Item = u32> {
+        (FIRST_PTRMAP_PAGE..=page_count).step_by(self.entries_per_page() as usize + 1)
+    }
+
+    /// Parses the entries of a pointer-map page, stopping at the end of the
+    /// file since the last page is usually only partly used.
+    pub fn parse(
+        &self,
+        ptrmap_page: u32,
+        data: &[u8],
+        page_count: u32,
+    ) -> Result<Vec<PointerMapEntry>> {
+        let count = self
+            .entries_per_page()
+            .min(page_count.saturating_sub(ptrmap_page));
+
+        (0..count)
+            .map(|i| {
+                let at = (i * ENTRY_SIZE) as usize;
+                Ok(PointerMapEntry {
+                    page_number: ptrmap_page + 1 + i,
+                    kind: PointerType::try_from(data[at])?,
+                    parent: u32::from_be_bytes([
+                        data[at + 1],
+                        data[at + 2],
+                        data[at + 3],
+                        data[at + 4],
+                    ]),
+                })
+            })
+            .collect()
+    }
+
+    /// Looks up the entry of a single page.
+    pub fn read(&self, database: &mut Database, page_number: u32) -> Result<PointerMapEntry> {
+        let Some(ptrmap_page) = self.ptrmap_page(page_number) else {
+            bail!("Page {} has no pointer-map entry", page_number);
+        };
+        if ptrmap_page == page_number {
+            bail!("Page {} is a pointer-map page", page_number);
+        }
+
+        let data = database.read_raw_page(ptrmap_page)?;
+        let at = ((page_number - ptrmap_page - 1) * ENTRY_SIZE) as usize;
+        Ok(PointerMapEntry {
+            page_number,
+            kind: PointerType::try_from(data[at])?,
+            parent: u32::from_be_bytes([data[at + 1], data[at + 2], data[at + 3], data[at + 4]]),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pointer_map_page_math() {
+        // 4096 / 5 = 819 entries per pointer-map page.
+        let map = PointerMap::new(4096);
+
+        assert_eq!(map.ptrmap_page(1), None);
+        assert_eq!(map.ptrmap_page(3), Some(2));
+        assert_eq!(map.ptrmap_page(821), Some(2));
+        assert_eq!(map.ptrmap_page(822), Some(822));
+        assert_eq!(map.ptrmap_page(823), Some(822));
+        assert!(map.is_ptrmap_page(1642));
+        assert_eq!(map.ptrmap_pages(1700).collect::<Vec<_>>(), vec![2, 822, 1642]);
+    }
+
+    #[test]
+    fn parse_pointer_map_entries() {
+        let map = PointerMap::new(4096);
+        let mut data = vec![0; 4096];
+        data[..10].copy_from_slice(&[1, 0, 0, 0, 0, 5, 0, 0, 0, 3]);
+
+        assert_eq!(
+            map.parse(2, &data, 4).unwrap(),
+            vec![
+                PointerMapEntry {
+                    page_number: 3,
+                    kind: PointerType::RootPage,
+                    parent: 0,
+                },
+                PointerMapEntry {
+                    page_number: 4,
+                    kind: PointerType::BTree,
+                    parent: 3,
+                },
+            ]
+        );
+    }
+}
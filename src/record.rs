@@ -1,3 +1,8 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+
+use crate::error::{bail, Result};
+use crate::output::Encoding;
 use crate::varient;
 
 #[derive(Debug, Clone)]
@@ -29,13 +34,68 @@ impl From<u64> for ColumnType {
             7 => Self::F64,
             8 => Self::Zero,
             9 => Self::One,
-            n if n > 12 && n % 2 == 0 => Self::Blob((n as usize - 12) / 2),
-            n if n > 13 && n % 2 == 1 => Self::Text((n as usize - 13) / 2),
+            n if n >= 12 && n % 2 == 0 => Self::Blob((n as usize - 12) / 2),
+            n if n >= 13 && n % 2 == 1 => Self::Text((n as usize - 13) / 2),
             _ => unreachable!(),
         }
     }
 }
 
+impl ColumnType {
+    /// Number of bytes the value takes up in the body of the record.
+    fn size(&self) -> usize {
+        match self {
+            ColumnType::Null | ColumnType::Zero | ColumnType::One => 0,
+            ColumnType::I8 => 1,
+            ColumnType::I16 => 2,
+            ColumnType::I24 => 3,
+            ColumnType::I32 => 4,
+            ColumnType::I48 => 6,
+            ColumnType::I64 | ColumnType::F64 => 8,
+            ColumnType::Blob(size) | ColumnType::Text(size) => *size,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            ColumnType::Null => "null",
+            ColumnType::F64 => "real",
+            ColumnType::Blob(_) => "blob",
+            ColumnType::Text(_) => "text",
+            _ => "integer",
+        }
+    }
+}
+
+/// Where the blob or text value at `index` of a record lies in the record,
+/// from the start of the payload. Only the header of the record has to be
+/// in `payload`, so the rest may still be on overflow pages.
+pub(crate) fn value_range(payload: &[u8], index: usize) -> Result<std::ops::Range<u64>> {
+    let (header_size, mut cursor) = varient::read(payload);
+    let header_size = header_size as usize;
+    if header_size > payload.len() {
+        bail!("Record header spills onto overflow pages");
+    }
+
+    let mut start = header_size as u64;
+    let mut column = 0;
+    while cursor < header_size {
+        let (serial_type, offset) = varient::read(&payload[cursor..]);
+        cursor += offset;
+        let column_type = ColumnType::from(serial_type as u64);
+        if column == index {
+            return match column_type {
+                ColumnType::Blob(size) | ColumnType::Text(size) => Ok(start..start + size as u64),
+                _ => bail!("Cannot open value of type {}", column_type.name()),
+            };
+        }
+        start += column_type.size() as u64;
+        column += 1;
+    }
+    // Columns added after the row was written are NULL.
+    bail!("Cannot open value of type null")
+}
+
 #[derive(Debug, Clone)]
 pub enum ColumnValue<'page> {
     Null,
@@ -67,6 +127,231 @@ impl<'page> ColumnValue<'page> {
             ColumnValue::Blob(_) | ColumnValue::Text(_) => false,
         }
     }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, ColumnValue::Null)
+    }
+
+    pub(crate) fn as_integer(&self) -> Option<i64> {
+        match self {
+            ColumnValue::I8(n)
+            | ColumnValue::I16(n)
+            | ColumnValue::I24(n)
+            | ColumnValue::I32(n)
+            | ColumnValue::I48(n)
+            | ColumnValue::I64(n) => Some(*n),
+            ColumnValue::Zero => Some(0),
+            ColumnValue::One => Some(1),
+            _ => None,
+        }
+    }
+
+    fn as_real(&self) -> Option<f64> {
+        match self {
+            ColumnValue::F64(n) => Some(*n),
+            _ => self.as_integer().map(|n| n as f64),
+        }
+    }
+
+    /// The value as a number for arithmetic: text is read as far as it
+    /// looks like a number, and anything else that isn't a number is 0.
+    pub(crate) fn as_number(&self) -> f64 {
+        match self {
+            ColumnValue::Text(text) => {
+                let text = String::from_utf8_lossy(text);
+                let text = text.trim();
+                (1..=text.len())
+                    .rev()
+                    .filter(|&end| text.is_char_boundary(end))
+                    .find_map(|end| text[..end].parse::<f64>().ok())
+                    .unwrap_or(0.0)
+            }
+            value => value.as_real().unwrap_or(0.0),
+        }
+    }
+
+    /// Rank of the storage class in SQLite's sort order.
+    fn type_order(&self) -> u8 {
+        match self {
+            ColumnValue::Null => 0,
+            ColumnValue::Text(_) => 2,
+            ColumnValue::Blob(_) => 3,
+            _ => 1,
+        }
+    }
+
+    /// Orders values like SQLite does: NULLs first, then numbers by value,
+    /// then text and blobs byte by byte.
+    pub fn compare(&self, other: &ColumnValue) -> Ordering {
+        self.compare_collated(other, Collation::Binary)
+    }
+
+    /// Like [`ColumnValue::compare`], with text compared by `collation`.
+    pub fn compare_collated(&self, other: &ColumnValue, collation: Collation) -> Ordering {
+        match (self, other) {
+            (ColumnValue::Text(a), ColumnValue::Text(b)) => collation.compare(a, b),
+            (ColumnValue::Blob(a), ColumnValue::Blob(b)) => a.cmp(b),
+            _ if self.is_number() && other.is_number() => {
+                match (self.as_integer(), other.as_integer()) {
+                    (Some(a), Some(b)) => a.cmp(&b),
+                    _ => self
+                        .as_real()
+                        .unwrap()
+                        .partial_cmp(&other.as_real().unwrap())
+                        .unwrap_or(Ordering::Equal),
+                }
+            }
+            _ => self.type_order().cmp(&other.type_order()),
+        }
+    }
+}
+
+/// How text is compared, named after SQLite's built-in collating
+/// sequences.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Collation {
+    /// Byte by byte.
+    #[default]
+    Binary,
+    /// Byte by byte, with ASCII letters folded to lower case.
+    NoCase,
+    /// Byte by byte, ignoring trailing spaces.
+    RTrim,
+}
+
+impl Collation {
+    /// The collation of a COLLATE clause, whatever the case of its name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "binary" => Some(Collation::Binary),
+            "nocase" => Some(Collation::NoCase),
+            "rtrim" => Some(Collation::RTrim),
+            _ => None,
+        }
+    }
+
+    pub fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        match self {
+            Collation::Binary => a.cmp(b),
+            Collation::NoCase => a
+                .iter()
+                .map(u8::to_ascii_lowercase)
+                .cmp(b.iter().map(u8::to_ascii_lowercase)),
+            Collation::RTrim => {
+                let trim = |text: &[u8]| {
+                    let spaces = text.iter().rev().take_while(|&&c| c == b' ').count();
+                    text.len() - spaces
+                };
+                a[..trim(a)].cmp(&b[..trim(b)])
+            }
+        }
+    }
+}
+
+impl ColumnValue<'_> {
+    /// The value written as an SQL literal, for generated statements.
+    pub fn sql_literal(&self) -> String {
+        match self {
+            ColumnValue::Null => "NULL".to_string(),
+            ColumnValue::F64(n) if n.is_nan() => "NULL".to_string(),
+            ColumnValue::F64(n) if n.is_infinite() => {
+                if *n > 0.0 { "9e999" } else { "-9e999" }.to_string()
+            }
+            // Debug formatting keeps the decimal point, so the value stays real.
+            ColumnValue::F64(n) => format!("{:?}", n),
+            ColumnValue::Text(content) => {
+                format!("'{}'", String::from_utf8_lossy(content).replace('\'', "''"))
+            }
+            ColumnValue::Blob(content) => format!(
+                "X'{}'",
+                content.iter().map(|b| format!("{:02X}", b)).collect::<String>()
+            ),
+            _ => self.to_string(),
+        }
+    }
+}
+
+impl ColumnValue<'_> {
+    /// Serial type of the value in a record header, with its content.
+    fn serialize(&self) -> (u64, Vec<u8>) {
+        let integer = |n: i64| -> (u64, Vec<u8>) {
+            let bytes = n.to_be_bytes();
+            let (serial_type, size) = match n {
+                -0x80..=0x7f => (1, 1),
+                -0x8000..=0x7fff => (2, 2),
+                -0x80_0000..=0x7f_ffff => (3, 3),
+                -0x8000_0000..=0x7fff_ffff => (4, 4),
+                -0x8000_0000_0000..=0x7fff_ffff_ffff => (5, 6),
+                _ => (6, 8),
+            };
+            (serial_type, bytes[8 - size..].to_vec())
+        };
+
+        match self {
+            ColumnValue::Null => (0, vec![]),
+            ColumnValue::Zero => (8, vec![]),
+            ColumnValue::One => (9, vec![]),
+            ColumnValue::F64(n) => (7, n.to_be_bytes().to_vec()),
+            ColumnValue::Blob(content) => (content.len() as u64 * 2 + 12, content.to_vec()),
+            ColumnValue::Text(content) => (content.len() as u64 * 2 + 13, content.to_vec()),
+            value => integer(value.as_integer().expect("integer value")),
+        }
+    }
+}
+
+/// Encodes values as a record payload, which `Record::read` turns back
+/// into the same values.
+pub fn encode(values: &[ColumnValue]) -> Vec<u8> {
+    let (types, contents): (Vec<_>, Vec<_>) = values.iter().map(|value| value.serialize()).unzip();
+    let types = types
+        .into_iter()
+        .flat_map(|serial_type| varient::write(serial_type as i64))
+        .collect::<Vec<_>>();
+
+    // The header size counts its own varint, which may make it one longer.
+    let mut header_size = types.len() + 1;
+    while types.len() + varient::write(header_size as i64).len() != header_size {
+        header_size = types.len() + varient::write(header_size as i64).len();
+    }
+
+    let mut payload = varient::write(header_size as i64);
+    payload.extend(types);
+    payload.extend(contents.into_iter().flatten());
+    payload
+}
+
+/// The payload of a record from a database storing text in `encoding`,
+/// with the text in UTF-8 like everything reading records expects.
+pub fn to_utf8(payload: Cow<'_, [u8]>, encoding: Encoding) -> Cow<'_, [u8]> {
+    if encoding == Encoding::Utf8 {
+        return payload;
+    }
+
+    let transcoded = {
+        let record = Record::read(0, &payload);
+        let texts = record
+            .values
+            .iter()
+            .map(|value| match value {
+                ColumnValue::Text(text) => Some(encoding.decode(text)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let values = record
+            .values
+            .iter()
+            .zip(&texts)
+            .map(|(value, text)| match text {
+                Some(text) => ColumnValue::Text(text.as_bytes()),
+                None => value.clone(),
+            })
+            .collect::<Vec<_>>();
+        texts.iter().any(Option::is_some).then(|| encode(&values))
+    };
+    match transcoded {
+        Some(transcoded) => Cow::Owned(transcoded),
+        None => payload,
+    }
 }
 
 impl Into<i64> for ColumnValue<'_> {
@@ -106,6 +391,77 @@ impl<'page> std::fmt::Display for ColumnValue<'page> {
     }
 }
 
+/// A value that owns its content, to keep after the page it was read from
+/// is gone.
+#[derive(Debug, Clone, PartialEq)]
+pub enum OwnedValue {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+impl OwnedValue {
+    pub fn is_null(&self) -> bool {
+        matches!(self, OwnedValue::Null)
+    }
+
+    /// The storage class of the value, as `typeof()` names it.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            OwnedValue::Null => "null",
+            OwnedValue::Integer(_) => "integer",
+            OwnedValue::Real(_) => "real",
+            OwnedValue::Text(_) => "text",
+            OwnedValue::Blob(_) => "blob",
+        }
+    }
+
+    /// The value borrowed again, for comparing and writing it out.
+    pub fn as_column_value(&self) -> ColumnValue<'_> {
+        ColumnValue::from(self)
+    }
+}
+
+impl ColumnValue<'_> {
+    /// Copies the value out of its page. Text that isn't valid UTF-8 has
+    /// its bad bytes replaced.
+    pub fn to_owned(&self) -> OwnedValue {
+        match self {
+            ColumnValue::Null => OwnedValue::Null,
+            ColumnValue::F64(n) => OwnedValue::Real(*n),
+            ColumnValue::Text(content) => OwnedValue::Text(String::from_utf8_lossy(content).into_owned()),
+            ColumnValue::Blob(content) => OwnedValue::Blob(content.to_vec()),
+            value => OwnedValue::Integer(value.as_integer().expect("integer value")),
+        }
+    }
+}
+
+impl From<ColumnValue<'_>> for OwnedValue {
+    fn from(value: ColumnValue<'_>) -> Self {
+        value.to_owned()
+    }
+}
+
+impl<'a> From<&'a OwnedValue> for ColumnValue<'a> {
+    fn from(value: &'a OwnedValue) -> Self {
+        match value {
+            OwnedValue::Null => ColumnValue::Null,
+            OwnedValue::Integer(n) => ColumnValue::I64(*n),
+            OwnedValue::Real(n) => ColumnValue::F64(*n),
+            OwnedValue::Text(text) => ColumnValue::Text(text.as_bytes()),
+            OwnedValue::Blob(content) => ColumnValue::Blob(content),
+        }
+    }
+}
+
+impl std::fmt::Display for OwnedValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.as_column_value().fmt(f)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Record<'page> {
     pub rowid: i64,
@@ -114,7 +470,9 @@ pub struct Record<'page> {
 
 macro_rules! read_n_bytes {
     ($t:ident, $payload:expr, $cursor:expr, $n:expr) => {{
-        let mut bytes = [0; 8];
+        // Integers are stored in two's complement, so sign-extend them.
+        let fill = if $payload[$cursor] & 0x80 != 0 { 0xff } else { 0 };
+        let mut bytes = [fill; 8];
         bytes[(8 - $n)..].copy_from_slice(&$payload[$cursor..$cursor + $n]);
         $cursor += $n;
         $t::from_be_bytes(bytes)
@@ -123,6 +481,12 @@ macro_rules! read_n_bytes {
 
 impl<'page> Record<'page> {
     pub fn read(rowid: i64, payload: &'page [u8]) -> Self {
+        Self::read_prefix(rowid, payload, usize::MAX)
+    }
+
+    /// Reads only the first `count` values, which is all that is needed to
+    /// compare index keys.
+    pub fn read_prefix(rowid: i64, payload: &'page [u8], count: usize) -> Self {
         let mut cursor = 0;
         let (header_size, offset) = varient::read(&payload[cursor..]);
         cursor += offset;
@@ -137,8 +501,8 @@ impl<'page> Record<'page> {
             columns.push(ColumnType::from(column as u64));
         }
 
-        let mut values = Vec::with_capacity(columns.len());
-        for column in columns.iter() {
+        let mut values = Vec::with_capacity(columns.len().min(count));
+        for column in columns.iter().take(count) {
             let value = match column {
                 ColumnType::Null => ColumnValue::Null,
                 ColumnType::I8 => ColumnValue::I8(read_n_bytes!(i64, payload, cursor, 1)),
@@ -150,13 +514,17 @@ impl<'page> Record<'page> {
                 ColumnType::F64 => ColumnValue::F64(read_n_bytes!(f64, payload, cursor, 8)),
                 ColumnType::Zero => ColumnValue::Zero,
                 ColumnType::One => ColumnValue::One,
+                // Values spilling onto overflow pages are cut off at the end
+                // of the local payload.
                 ColumnType::Blob(size) => {
-                    let value = ColumnValue::Blob(&payload[cursor..(cursor + *size)]);
+                    let end = (cursor + *size).min(payload.len());
+                    let value = ColumnValue::Blob(&payload[cursor.min(end)..end]);
                     cursor += *size;
                     value
                 }
                 ColumnType::Text(size) => {
-                    let value = ColumnValue::Text(&payload[cursor..(cursor + *size)]);
+                    let end = (cursor + *size).min(payload.len());
+                    let value = ColumnValue::Text(&payload[cursor.min(end)..end]);
                     cursor += *size;
                     value
                 }
@@ -166,4 +534,120 @@ impl<'page> Record<'page> {
 
         Record { values, rowid }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_round_trips() {
+        let values = [
+            ColumnValue::Null,
+            ColumnValue::I8(-3),
+            ColumnValue::I64(1 << 40),
+            ColumnValue::F64(1.5),
+            ColumnValue::Text(b"apple"),
+            ColumnValue::Blob(&[0xde, 0xad]),
+            ColumnValue::One,
+        ];
+        let payload = encode(&values);
+        let record = Record::read(0, &payload);
+
+        assert_eq!(record.values.len(), values.len());
+        for (read, written) in record.values.iter().zip(values.iter()) {
+            assert!(read.compare(written).is_eq());
+            assert_eq!(read.to_string(), written.to_string());
+        }
+
+        let wide = vec![ColumnValue::Null; 200];
+        assert_eq!(Record::read(0, &encode(&wide)).values.len(), 200);
+    }
+
+    #[test]
+    fn convert_utf16_text_to_utf8() {
+        let encoding = Encoding::Utf16be;
+        let text = encoding.encode("naïve 😀");
+        assert_eq!(text.len(), 16);
+        assert_eq!(encoding.decode(&text), "naïve 😀");
+        assert_eq!(Encoding::Utf16le.decode(&[0x61, 0x00, 0x3d, 0xd8, 0x62]), "a\u{fffd}\u{fffd}");
+
+        let payload = encode(&[ColumnValue::I8(7), ColumnValue::Text(&text), ColumnValue::Blob(&text)]);
+        let converted = to_utf8(Cow::Borrowed(&payload), encoding);
+        let record = Record::read(0, &converted);
+        assert_eq!(record.values[0].to_string(), "7");
+        assert!(record.values[1].compare(&ColumnValue::Text("naïve 😀".as_bytes())).is_eq());
+        assert!(record.values[2].compare(&ColumnValue::Blob(&text)).is_eq());
+
+        let numbers = encode(&[ColumnValue::I8(7)]);
+        assert!(matches!(to_utf8(Cow::Borrowed(&numbers), encoding), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn compare_text_by_collation() {
+        let (upper, lower) = (ColumnValue::Text(b"Apple"), ColumnValue::Text(b"apple "));
+        assert!(upper.compare(&lower).is_lt());
+        assert!(upper.compare_collated(&lower, Collation::NoCase).is_lt());
+        assert!(lower.compare_collated(&ColumnValue::Text(b"APPLE "), Collation::NoCase).is_eq());
+        assert!(lower.compare_collated(&ColumnValue::Text(b"apple"), Collation::RTrim).is_eq());
+        assert!(upper.compare_collated(&lower, Collation::RTrim).is_lt());
+
+        // Blobs and numbers compare the same whatever the collation.
+        let blob = ColumnValue::Blob(b"A");
+        assert!(blob.compare_collated(&ColumnValue::Blob(b"a"), Collation::NoCase).is_lt());
+        assert!(ColumnValue::I8(2).compare_collated(&ColumnValue::Text(b"1"), Collation::NoCase).is_lt());
+
+        assert_eq!(Collation::from_name("NoCase"), Some(Collation::NoCase));
+        assert_eq!(Collation::from_name("unicode"), None);
+    }
+
+    #[test]
+    fn owned_values_round_trip() {
+        let values = [
+            ColumnValue::Null,
+            ColumnValue::I24(-70000),
+            ColumnValue::One,
+            ColumnValue::F64(2.5),
+            ColumnValue::Text(b"pear"),
+            ColumnValue::Blob(&[0xbe, 0xef]),
+        ];
+        let owned = values.iter().map(ColumnValue::to_owned).collect::<Vec<_>>();
+        assert_eq!(
+            owned,
+            [
+                OwnedValue::Null,
+                OwnedValue::Integer(-70000),
+                OwnedValue::Integer(1),
+                OwnedValue::Real(2.5),
+                OwnedValue::Text("pear".to_string()),
+                OwnedValue::Blob(vec![0xbe, 0xef]),
+            ]
+        );
+        for (value, owned) in values.iter().zip(&owned) {
+            assert!(owned.as_column_value().compare(value).is_eq());
+            assert_eq!(owned.to_string(), value.to_string());
+        }
+
+        assert_eq!(OwnedValue::from(ColumnValue::Text(b"\xffa")), OwnedValue::Text("\u{fffd}a".to_string()));
+    }
+
+    #[test]
+    fn find_range_of_blob_value() {
+        let payload = encode(&[
+            ColumnValue::I24(70000),
+            ColumnValue::Null,
+            ColumnValue::Text(b"pear"),
+            ColumnValue::Blob(&[1, 2, 3]),
+        ]);
+        assert_eq!(value_range(&payload, 2).unwrap(), 8..12);
+        assert_eq!(value_range(&payload, 3).unwrap(), 12..15);
+        assert_eq!(&payload[12..15], &[1, 2, 3]);
+
+        // Only the header is needed.
+        assert_eq!(value_range(&payload[..5], 3).unwrap(), 12..15);
+        let error = value_range(&payload, 0).unwrap_err();
+        assert_eq!(error.to_string(), "Cannot open value of type integer");
+        assert!(value_range(&payload, 1).is_err());
+        assert!(value_range(&payload, 4).is_err());
+        assert!(value_range(&payload[..3], 3).is_err());
+    }
+}
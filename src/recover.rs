@@ -0,0 +1,647 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::Read;
+
+use crate::error::{bail, Result};
+use crate::fts5;
+use crate::page::PageKind;
+use crate::record::{ColumnValue, Record};
+use crate::rtree::{self, ROOT_NODE};
+use crate::sql::{self, quote_identifier};
+use crate::varient;
+
+const DEFAULT_PAGE_SIZE: usize = 4096;
+const LOST_AND_FOUND: &str = "lost_and_found";
+
+/// A row carved out of a B-tree page.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecoveredRow {
+    pub page_number: u32,
+    /// None for rows of WITHOUT ROWID tables.
+    pub rowid: Option<i64>,
+    /// Values as SQL literals, in record order.
+    pub values: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct RecoveredTable {
+    pub name: String,
+    pub sql: String,
+    /// Column names in the order values are stored in a record. WITHOUT
+    /// ROWID tables store their primary key columns first.
+    pub columns: Vec<String>,
+    /// Position of the INTEGER PRIMARY KEY, stored as NULL in place of the
+    /// rowid.
+    pub rowid_alias: Option<usize>,
+    pub rows: Vec<RecoveredRow>,
+}
+
+/// Salvages rows from a possibly corrupt database file, used by the
+/// `.recover` command. The file is read directly instead of through
+/// `Database`, so it works even when the schema cannot be parsed.
+///
+/// Rows reachable from a table in the schema are recovered into that table.
+/// Every other page that still looks like a table leaf (detached subtrees,
+/// freelist pages) is carved into a `lost_and_found` table. Virtual tables
+/// are refilled through the module from their shadow tables where the data
+/// allows it: fts5 tables storing their own content and rtree tables.
+#[derive(Debug)]
+pub struct Recovery {
+    pub tables: Vec<RecoveredTable>,
+    pub lost_and_found: Vec<RecoveredRow>,
+    /// Index, view and trigger definitions, replayed after the data.
+    pub other_sql: Vec<String>,
+}
+
+struct RawFile {
+    data: Vec<u8>,
+    page_size: usize,
+    usable_size: u32,
+}
+
+impl RawFile {
+    fn page_count(&self) -> u32 {
+        (self.data.len() / self.page_size) as u32
+    }
+
+    fn page(&self, page_number: u32) -> Option<&[u8]> {
+        if page_number == 0 || page_number > self.page_count() {
+            return None;
+        }
+        let start = (page_number as usize - 1) * self.page_size;
+        Some(&self.data[start..start + self.page_size])
+    }
+
+    /// Offset of the B-tree header inside a page.
+    fn header_offset(page_number: u32) -> usize {
+        if page_number == 1 {
+            100
+        } else {
+            0
+        }
+    }
+
+    fn kind(&self, page_number: u32) -> Option<PageKind> {
+        let page = self.page(page_number)?;
+        PageKind::try_from(page[Self::header_offset(page_number)]).ok()
+    }
+
+    /// Pages of the B-tree rooted at `rootpage` that hold rows: table leaves,
+    /// and every page of an index-shaped tree. Pages already in `visited` are
+    /// skipped so a damaged tree cannot loop or claim pages of another tree.
+    fn btree_pages(&self, rootpage: u32, visited: &mut HashSet<u32>) -> Vec<u32> {
+        let mut pages = vec![];
+        let mut stack = vec![rootpage];
+
+        while let Some(page_number) = stack.pop() {
+            let (Some(page), Some(kind)) = (self.page(page_number), self.kind(page_number)) else {
+                continue;
+            };
+            if !visited.insert(page_number) {
+                continue;
+            }
+
+            if kind != PageKind::InteriorTable {
+                pages.push(page_number);
+            }
+            if kind.is_interior() {
+                let base = Self::header_offset(page_number);
+                let mut children = cell_pointers(page, base, kind.header_size())
+                    .filter_map(|pointer| page.get(pointer..pointer + 4))
+                    .map(|bytes| read_u32(bytes, 0))
+                    .collect::<Vec<_>>();
+                children.push(read_u32(page, base + 8));
+                stack.extend(children.into_iter().rev());
+            }
+        }
+
+        pages
+    }
+
+    /// Rows of a B-tree page, skipping any cell whose record does not hold
+    /// together.
+    fn carve_page(&self, page_number: u32) -> Vec<(Option<i64>, Vec<u8>)> {
+        let (Some(page), Some(kind)) = (self.page(page_number), self.kind(page_number)) else {
+            return vec![];
+        };
+        let base = Self::header_offset(page_number);
+
+        cell_pointers(page, base, kind.header_size())
+            .filter_map(|pointer| self.read_cell(page, kind, pointer))
+            .filter(|(_, payload)| is_plausible_record(payload))
+            .collect()
+    }
+
+    fn read_cell(
+        &self,
+        page: &[u8],
+        kind: PageKind,
+        pointer: usize,
+    ) -> Option<(Option<i64>, Vec<u8>)> {
+        let mut at = pointer;
+        match kind {
+            PageKind::InteriorTable => return None,
+            PageKind::InteriorIndex => at += 4,
+            PageKind::LeafIndex | PageKind::LeafTable => {}
+        }
+
+        let (payload_size, offset) = read_varint(page, at)?;
+        at += offset;
+        let rowid = if kind == PageKind::LeafTable {
+            let (rowid, offset) = read_varint(page, at)?;
+            at += offset;
+            Some(rowid)
+        } else {
+            None
+        };
+
+        let payload_size = u64::try_from(payload_size).ok()?;
+        if payload_size > self.data.len() as u64 {
+            return None;
+        }
+        let local = kind.local_payload_size(payload_size, self.usable_size) as usize;
+        let mut payload = page.get(at..at + local)?.to_vec();
+
+        if payload_size as usize > local {
+            let mut overflow_page = read_u32(page.get(at + local..at + local + 4)?, 0);
+            let mut visited = HashSet::new();
+            while payload.len() < payload_size as usize {
+                if !visited.insert(overflow_page) {
+                    return None;
+                }
+                let data = self.page(overflow_page)?;
+                let chunk =
+                    (payload_size as usize - payload.len()).min(self.usable_size as usize - 4);
+                payload.extend_from_slice(&data[4..4 + chunk]);
+                overflow_page = read_u32(data, 0);
+            }
+        }
+
+        Some((rowid, payload))
+    }
+
+    fn carve_records(&self, rootpage: u32, visited: &mut HashSet<u32>) -> Vec<(i64, Vec<u8>)> {
+        self.btree_pages(rootpage, visited)
+            .into_iter()
+            .flat_map(|page_number| self.carve_page(page_number))
+            .map(|(rowid, payload)| (rowid.unwrap_or(0), payload))
+            .collect()
+    }
+
+    fn carve_rows(&self, pages: &[u32]) -> Vec<RecoveredRow> {
+        pages
+            .iter()
+            .flat_map(|page_number| {
+                self.carve_page(*page_number)
+                    .into_iter()
+                    .map(|(rowid, payload)| RecoveredRow {
+                        page_number: *page_number,
+                        rowid,
+                        values: Record::read(rowid.unwrap_or(0), &payload)
+                            .values
+                            .iter()
+                            .map(|value| value.sql_literal())
+                            .collect(),
+                    })
+            })
+            .collect()
+    }
+}
+
+fn read_u32(data: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]])
+}
+
+/// Reads a varint that must end inside `data`.
+fn read_varint(data: &[u8], at: usize) -> Option<(i64, usize)> {
+    let bytes = data.get(at..)?;
+    let (value, len) = varient::read(bytes);
+    if len == 0 {
+        return None;
+    }
+    let terminated = len == 9 || bytes[len - 1] & 0x80 == 0;
+    terminated.then_some((value, len))
+}
+
+/// Cell pointers that point inside the page.
+fn cell_pointers(page: &[u8], base: usize, header_size: usize) -> impl Iterator<Item = usize> + '_ {
+    let count = u16::from_be_bytes([page[base + 3], page[base + 4]]) as usize;
+    let start = base + header_size;
+    let count = count.min(page.len().saturating_sub(start) / 2);
+
+    (0..count)
+        .map(move |i| u16::from_be_bytes([page[start + 2 * i], page[start + 2 * i + 1]]) as usize)
+        .filter(move |pointer| *pointer >= start && *pointer < page.len())
+}
+
+/// A record is plausible when its header is well formed and the sizes of its
+/// values add up to exactly the rest of the payload.
+fn is_plausible_record(payload: &[u8]) -> bool {
+    let Some((header_size, mut cursor)) = read_varint(payload, 0) else { return false; };
+    let header_size = header_size as usize;
+    if header_size < cursor || header_size > payload.len() {
+        return false;
+    }
+
+    let mut body_size = 0;
+    while cursor < header_size {
+        let Some((serial_type, len)) = read_varint(payload, cursor) else { return false; };
+        cursor += len;
+        body_size += match serial_type {
+            0 | 8 | 9 => 0,
+            1..=4 => serial_type as usize,
+            5 => 6,
+            6 | 7 => 8,
+            n if n >= 12 => (n as usize - 12) / 2,
+            _ => return false,
+        };
+    }
+
+    cursor == header_size && header_size + body_size == payload.len()
+}
+
+/// A row of sqlite_schema, as far as it could be read.
+struct SchemaEntry {
+    kind: String,
+    name: String,
+    rootpage: u32,
+    sql: Option<String>,
+}
+
+impl SchemaEntry {
+    fn read(record: &Record) -> Option<Self> {
+        let text = |i: usize| match record.values.get(i) {
+            Some(ColumnValue::Text(text)) => Some(String::from_utf8_lossy(text).to_string()),
+            _ => None,
+        };
+        let rootpage = match record.values.get(3) {
+            Some(value) if value.is_number() => {
+                let n: i64 = value.clone().into();
+                n as u32
+            }
+            _ => 0,
+        };
+
+        Some(Self {
+            kind: text(0)?,
+            name: text(1)?,
+            rootpage,
+            sql: text(4),
+        })
+    }
+}
+
+/// Rows of an fts5 table, from its `%_content` table. Tables with external
+/// or no content cannot be refilled.
+fn fts5_rows(
+    file: &RawFile,
+    name: &str,
+    arguments: &[String],
+    shadow_tables: &HashMap<String, u32>,
+    visited: &mut HashSet<u32>,
+) -> Vec<RecoveredRow> {
+    if fts5::option(arguments, "content").is_some() {
+        return vec![];
+    }
+    let Some(rootpage) = shadow_tables.get(&format!("{}_content", name)) else {
+        return vec![];
+    };
+
+    // The content table is (id INTEGER PRIMARY KEY, c0, c1, ...).
+    file.carve_records(*rootpage, visited)
+        .into_iter()
+        .map(|(rowid, payload)| RecoveredRow {
+            page_number: 0,
+            rowid: Some(rowid),
+            values: Record::read(rowid, &payload)
+                .values
+                .iter()
+                .skip(1)
+                .map(|value| value.sql_literal())
+                .collect(),
+        })
+        .collect()
+}
+
+/// Rows of an rtree table, from the leaf nodes reachable from the root node
+/// and the auxiliary columns in `%_rowid`.
+fn rtree_rows(
+    file: &RawFile,
+    name: &str,
+    arguments: &[String],
+    is_integer: bool,
+    shadow_tables: &HashMap<String, u32>,
+    visited: &mut HashSet<u32>,
+) -> Vec<RecoveredRow> {
+    let dimensions = (rtree::coordinate_columns(arguments).len().max(1) - 1) / 2;
+    let mut shadow = |suffix: &str| match shadow_tables.get(&format!("{}_{}", name, suffix)) {
+        Some(rootpage) => file.carve_records(*rootpage, visited),
+        None => vec![],
+    };
+
+    let nodes = shadow("node")
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+    let auxiliary = shadow("rowid")
+        .into_iter()
+        .map(|(rowid, payload)| {
+            let values = Record::read(rowid, &payload)
+                .values
+                .iter()
+                .skip(2)
+                .map(|value| value.sql_literal())
+                .collect::<Vec<_>>();
+            (rowid, values)
+        })
+        .collect::<HashMap<_, _>>();
+
+    let node = |node_number: i64| {
+        let payload = nodes.get(&node_number)?;
+        match Record::read(node_number, payload).values.get(1) {
+            Some(ColumnValue::Blob(data)) => rtree::parse_node(data, dimensions, is_integer),
+            _ => None,
+        }
+    };
+
+    let mut rows = vec![];
+    let mut seen = HashSet::new();
+    let Some(root) = node(ROOT_NODE) else { return rows; };
+    let mut stack = vec![(root.depth, root)];
+    while let Some((depth, current)) = stack.pop() {
+        for cell in current.cells {
+            if depth > 0 {
+                if seen.insert(cell.id) {
+                    stack.extend(node(cell.id).map(|child| (depth - 1, child)));
+                }
+                continue;
+            }
+
+            let mut values = cell
+                .values(is_integer)
+                .iter()
+                .map(|value| value.sql_literal())
+                .collect::<Vec<_>>();
+            values.extend(auxiliary.get(&cell.id).cloned().unwrap_or_default());
+            rows.push(RecoveredRow {
+                page_number: 0,
+                rowid: None,
+                values,
+            });
+        }
+    }
+
+    rows
+}
+
+impl Recovery {
+    pub fn read(path: &str) -> Result<Self> {
+        let mut data = vec![];
+        File::open(path)?.read_to_end(&mut data)?;
+        if data.len() < 100 {
+            bail!("File is too small to be a database: {}", path);
+        }
+
+        let page_size = match u16::from_be_bytes([data[16], data[17]]) {
+            1 => 65536,
+            n if n.is_power_of_two() && n >= 512 => n as usize,
+            _ => DEFAULT_PAGE_SIZE,
+        };
+        let usable_size = (page_size - data[20] as usize).max(480) as u32;
+        let file = RawFile {
+            data,
+            page_size,
+            usable_size,
+        };
+
+        let mut visited = HashSet::new();
+        let schema = file
+            .carve_records(1, &mut visited)
+            .into_iter()
+            .filter_map(|(rowid, payload)| SchemaEntry::read(&Record::read(rowid, &payload)))
+            .collect::<Vec<_>>();
+
+        let mut virtual_tables = vec![];
+        for entry in schema.iter() {
+            let Some(sql) = &entry.sql else { continue; };
+            if let Ok((_, sql::SQLCommand::CreateVirtualTable(t))) =
+                sql::parse_create(sql.as_bytes())
+            {
+                virtual_tables.push((sql.clone(), t));
+            }
+        }
+        // Shadow tables are recreated along with their virtual table.
+        let shadow_tables = schema
+            .iter()
+            .filter(|entry| {
+                virtual_tables
+                    .iter()
+                    .any(|(_, t)| entry.name.starts_with(&format!("{}_", t.table)))
+            })
+            .map(|entry| (entry.name.clone(), entry.rootpage))
+            .collect::<HashMap<_, _>>();
+
+        let mut tables = vec![];
+        let mut other_sql = vec![];
+        for entry in schema.iter() {
+            if entry.kind != "table" {
+                // Indexes are rebuilt from their definition.
+                file.btree_pages(entry.rootpage, &mut visited);
+                other_sql.extend(entry.sql.clone());
+                continue;
+            }
+            if entry.rootpage == 0 || shadow_tables.contains_key(&entry.name) {
+                continue;
+            }
+            // Internal tables such as sqlite_sequence are rebuilt by SQLite.
+            if entry.name.starts_with("sqlite_") {
+                file.btree_pages(entry.rootpage, &mut visited);
+                continue;
+            }
+            let Some(sql) = &entry.sql else { continue; };
+
+            let parsed = sql::parse_create(sql.as_bytes()).ok().map(|(_, command)| command);
+            let (columns, rowid_alias) = match parsed {
+                Some(sql::SQLCommand::CreateTable(t)) => (
+                    t.record_columns(),
+                    t.fields
+                        .iter()
+                        .position(|field| field.is_primary_key)
+                        .filter(|_| !t.without_rowid),
+                ),
+                // Without column names, rows can still go in by position.
+                _ => (vec![], None),
+            };
+
+            let pages = file.btree_pages(entry.rootpage, &mut visited);
+            tables.push(RecoveredTable {
+                name: entry.name.clone(),
+                sql: sql.clone(),
+                columns,
+                rowid_alias,
+                rows: file.carve_rows(&pages),
+            });
+        }
+
+        for (sql, t) in virtual_tables {
+            let module = t.module.to_ascii_lowercase();
+            let (columns, rows) = match module.as_str() {
+                "fts5" => (
+                    fts5::column_names(&t.arguments),
+                    fts5_rows(&file, &t.table, &t.arguments, &shadow_tables, &mut visited),
+                ),
+                "rtree" | "rtree_i32" => (
+                    t.arguments
+                        .iter()
+                        .filter_map(|argument| argument.split_whitespace().next())
+                        .map(|name| name.trim_start_matches('+').to_string())
+                        .collect(),
+                    rtree_rows(
+                        &file,
+                        &t.table,
+                        &t.arguments,
+                        module == "rtree_i32",
+                        &shadow_tables,
+                        &mut visited,
+                    ),
+                ),
+                _ => (vec![], vec![]),
+            };
+
+            tables.push(RecoveredTable {
+                name: t.table,
+                sql,
+                columns,
+                rowid_alias: None,
+                rows,
+            });
+        }
+
+        // Pages of shadow tables that could not be used still belong to them.
+        for rootpage in shadow_tables.values() {
+            file.btree_pages(*rootpage, &mut visited);
+        }
+
+        // Anything else that looks like table rows, such as detached subtrees
+        // or pages on the freelist.
+        let orphans = (2..=file.page_count())
+            .filter(|page_number| !visited.contains(page_number))
+            .filter(|page_number| file.kind(*page_number) == Some(PageKind::LeafTable))
+            .collect::<Vec<_>>();
+        let mut lost_and_found = file.carve_rows(&orphans);
+
+        // Rows with more values than their table has columns cannot be put
+        // back where they came from.
+        for table in tables.iter_mut().filter(|table| !table.columns.is_empty()) {
+            let (rows, lost): (Vec<_>, Vec<_>) = std::mem::take(&mut table.rows)
+                .into_iter()
+                .partition(|row| row.values.len() <= table.columns.len());
+            table.rows = rows;
+            lost_and_found.extend(lost);
+        }
+
+        Ok(Self {
+            tables,
+            lost_and_found,
+            other_sql,
+        })
+    }
+}
+
+impl RecoveredTable {
+    fn write_insert(&self, f: &mut std::fmt::Formatter<'_>, row: &RecoveredRow) -> std::fmt::Result {
+        let mut names = self
+            .columns
+            .iter()
+            .take(row.values.len())
+            .map(|name| quote_identifier(name))
+            .collect::<Vec<_>>();
+        let mut values = row.values.clone();
+
+        if let Some(rowid) = row.rowid {
+            match self.rowid_alias {
+                Some(i) if values.get(i).map(String::as_str) == Some("NULL") => {
+                    values[i] = rowid.to_string()
+                }
+                _ if !names.is_empty() => {
+                    names.insert(0, "_rowid_".to_string());
+                    values.insert(0, rowid.to_string());
+                }
+                _ => {}
+            }
+        }
+
+        let names = if names.is_empty() {
+            String::new()
+        } else {
+            format!("({})", names.join(", "))
+        };
+        writeln!(
+            f,
+            "INSERT INTO {}{} VALUES({});",
+            quote_identifier(&self.name),
+            names,
+            values.join(", ")
+        )
+    }
+}
+
+impl std::fmt::Display for Recovery {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "BEGIN;")?;
+
+        for table in self.tables.iter() {
+            writeln!(f, "{};", table.sql)?;
+            for row in table.rows.iter() {
+                table.write_insert(f, row)?;
+            }
+        }
+
+        if !self.lost_and_found.is_empty() {
+            let width = self
+                .lost_and_found
+                .iter()
+                .map(|row| row.values.len())
+                .max()
+                .unwrap_or(0);
+            let columns = (0..width).map(|i| format!(", c{}", i)).collect::<String>();
+            writeln!(f, "CREATE TABLE {}(pgno, nfield, id{});", LOST_AND_FOUND, columns)?;
+
+            for row in self.lost_and_found.iter() {
+                let mut values = row.values.clone();
+                values.resize(width, "NULL".to_string());
+                writeln!(
+                    f,
+                    "INSERT INTO {} VALUES({}, {}, {}, {});",
+                    LOST_AND_FOUND,
+                    row.page_number,
+                    row.values.len(),
+                    row.rowid.map_or("NULL".to_string(), |rowid| rowid.to_string()),
+                    values.join(", ")
+                )?;
+            }
+        }
+
+        for sql in self.other_sql.iter() {
+            writeln!(f, "{};", sql)?;
+        }
+
+        write!(f, "COMMIT;")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plausible_records() {
+        // Header of 3 bytes: an 8-bit integer and a 1-character text.
+        assert!(is_plausible_record(&[3, 1, 15, 42, b'a']));
+        // Body is one byte short.
+        assert!(!is_plausible_record(&[3, 1, 15, 42]));
+        // Serial types 10 and 11 are reserved.
+        assert!(!is_plausible_record(&[2, 10]));
+        // Header size larger than the payload.
+        assert!(!is_plausible_record(&[9, 1, 1]));
+    }
+}
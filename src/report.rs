@@ -0,0 +1,314 @@
+use std::collections::BTreeMap;
+
+use crate::database::Database;
+use crate::error::{bail, Error, Result};
+use crate::page::{Cell, PageKind};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PageCategory {
+    TableInterior,
+    TableLeaf,
+    IndexInterior,
+    IndexLeaf,
+    Overflow,
+    FreelistTrunk,
+    FreelistLeaf,
+    PointerMap,
+    Unaccounted,
+}
+
+impl From<&PageKind> for PageCategory {
+    fn from(kind: &PageKind) -> Self {
+        match kind {
+            PageKind::InteriorTable => Self::TableInterior,
+            PageKind::LeafTable => Self::TableLeaf,
+            PageKind::InteriorIndex => Self::IndexInterior,
+            PageKind::LeafIndex => Self::IndexLeaf,
+        }
+    }
+}
+
+impl std::fmt::Display for PageCategory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PageCategory::TableInterior => write!(f, "Table interior pages"),
+            PageCategory::TableLeaf => write!(f, "Table leaf pages"),
+            PageCategory::IndexInterior => write!(f, "Index interior pages"),
+            PageCategory::IndexLeaf => write!(f, "Index leaf pages"),
+            PageCategory::Overflow => write!(f, "Overflow pages"),
+            PageCategory::FreelistTrunk => write!(f, "Freelist trunk pages"),
+            PageCategory::FreelistLeaf => write!(f, "Freelist leaf pages"),
+            PageCategory::PointerMap => write!(f, "Pointer-map pages"),
+            PageCategory::Unaccounted => write!(f, "Unaccounted pages"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ObjectUsage {
+    pub name: String,
+    pub is_index: bool,
+    pub pages: u32,
+    pub overflow_pages: u32,
+    pub unused_bytes: u64,
+}
+
+/// Summary of how the pages of a database file are used, similar to the
+/// summary section of sqlite3_analyzer.
+#[derive(Debug)]
+pub struct SpaceReport {
+    pub page_size: u32,
+    pub page_count: u32,
+    pub header_page_count: u32,
+    pub categories: BTreeMap<PageCategory, u32>,
+    pub objects: Vec<ObjectUsage>,
+    pub unused_bytes: u64,
+}
+
+impl SpaceReport {
+    pub fn read(database: &mut Database) -> Result<Self> {
+        let page_count = database.page_count()?;
+        let mut pages: Vec<Option<PageCategory>> = vec![None; page_count as usize + 1];
+
+        let mut roots = vec![("sqlite_schema".to_string(), false, 1)];
+        for table in database.schema.tables.values().filter(|table| !table.is_virtual()) {
+            roots.push((table.name.clone(), false, table.rootpage));
+            for index in table.indexes.iter() {
+                roots.push((index.name.clone(), true, index.rootpage));
+            }
+        }
+        roots.sort();
+
+        let mut objects = Vec::with_capacity(roots.len());
+        for (name, is_index, rootpage) in roots {
+            let mut usage = ObjectUsage {
+                name,
+                is_index,
+                pages: 0,
+                overflow_pages: 0,
+                unused_bytes: 0,
+            };
+            Self::read_btree(database, rootpage, &mut pages, &mut usage)?;
+            objects.push(usage);
+        }
+
+        Self::read_freelist(database, &mut pages)?;
+
+        if let Some(pointer_map) = database.header.pointer_map() {
+            for page_number in pointer_map.ptrmap_pages(page_count) {
+                pages[page_number as usize] = Some(PageCategory::PointerMap);
+            }
+        }
+
+        let mut categories = BTreeMap::new();
+        for category in pages.iter().skip(1) {
+            *categories
+                .entry(category.unwrap_or(PageCategory::Unaccounted))
+                .or_insert(0) += 1;
+        }
+
+        Ok(Self {
+            page_size: database.header.page_size,
+            page_count,
+            header_page_count: database.header.page_count,
+            categories,
+            unused_bytes: objects.iter().map(|object| object.unused_bytes).sum(),
+            objects,
+        })
+    }
+
+    fn read_btree(
+        database: &mut Database,
+        rootpage: u32,
+        pages: &mut [Option<PageCategory>],
+        usage: &mut ObjectUsage,
+    ) -> Result<()> {
+        let usable_size = database.header.usable_size();
+        if rootpage == 0 || rootpage as usize >= pages.len() {
+            bail!("Root page {} of {} is out of range", rootpage, usage.name);
+        }
+        let mut stack = vec![rootpage];
+
+        while let Some(page_number) = stack.pop() {
+            let Some(slot) = pages.get_mut(page_number as usize) else { continue; };
+            if slot.is_some() {
+                continue;
+            }
+
+            let page = database.get_page(page_number - 1)?;
+            *slot = Some(PageCategory::from(&page.header.kind));
+            usage.pages += 1;
+            usage.unused_bytes += page.unused_bytes() as u64;
+
+            // Children past the end of the file, or 0, which no page has.
+            let page_count = pages.len() - 1;
+            let child_page = |child: u32, offset: usize| {
+                if child == 0 || child as usize > page_count {
+                    return Err(Error::corrupt_page(
+                        page_number,
+                        offset,
+                        format!("Child page {} is out of range", child),
+                    ));
+                }
+                Ok(child)
+            };
+
+            for (index, cell) in page.cells().enumerate() {
+                match cell {
+                    Cell::InteriorIndex { left_child_page, .. }
+                    | Cell::InteriorTable { left_child_page, .. } => {
+                        stack.push(child_page(left_child_page, page.cell_offset(index))?)
+                    }
+                    _ => {}
+                }
+
+                let Some(first_overflow_page) = cell.overflow_page() else { continue; };
+                let local = page.header.kind.local_payload_size(cell.payload_size(), usable_size);
+                let mut remaining = cell.payload_size() - local;
+                let mut overflow_page = first_overflow_page;
+
+                while overflow_page != 0 && remaining > 0 {
+                    let Some(slot) = pages.get_mut(overflow_page as usize) else { break; };
+                    *slot = Some(PageCategory::Overflow);
+                    usage.pages += 1;
+                    usage.overflow_pages += 1;
+
+                    let chunk = remaining.min(usable_size as u64 - 4);
+                    usage.unused_bytes += usable_size as u64 - 4 - chunk;
+                    remaining -= chunk;
+
+                    let data = database.read_raw_page(overflow_page)?;
+                    overflow_page = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+                }
+            }
+
+            if let Some(right_child_page) = page.header.right_child_page_number {
+                stack.push(child_page(right_child_page, page.offset as usize + 8)?);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn read_freelist(database: &mut Database, pages: &mut [Option<PageCategory>]) -> Result<()> {
+        // Where the pointer to each trunk is: in the database header, or at
+        // the start of the trunk before it.
+        let (mut previous, mut offset) = (1, 32);
+        for trunk in database.freelist_pages()?.trunks {
+            let Some(slot) = pages.get_mut(trunk.page_number as usize) else {
+                return Err(Error::corrupt_page(
+                    previous,
+                    offset,
+                    format!("Freelist trunk page {} is out of range", trunk.page_number),
+                ));
+            };
+            *slot = Some(PageCategory::FreelistTrunk);
+            for (i, &leaf) in trunk.leaves.iter().enumerate() {
+                let Some(slot) = pages.get_mut(leaf as usize) else {
+                    return Err(Error::corrupt_page(
+                        trunk.page_number,
+                        8 + 4 * i,
+                        format!("Freelist leaf page {} is out of range", leaf),
+                    ));
+                };
+                *slot = Some(PageCategory::FreelistLeaf);
+            }
+            (previous, offset) = (trunk.page_number, 0);
+        }
+        Ok(())
+    }
+}
+
+fn write_line(
+    f: &mut std::fmt::Formatter<'_>,
+    label: impl std::fmt::Display,
+    value: impl std::fmt::Display,
+) -> std::fmt::Result {
+    let label = format!("{} ", label);
+    writeln!(f, "{:.<50} {}", label, value)
+}
+
+fn percent(part: u32, whole: u32) -> f64 {
+    if whole == 0 {
+        0.0
+    } else {
+        part as f64 * 100.0 / whole as f64
+    }
+}
+
+impl std::fmt::Display for SpaceReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "/** Disk-Space Utilization Report */")?;
+        writeln!(f)?;
+        write_line(f, "Page size in bytes", self.page_size)?;
+        write_line(f, "Pages in the whole file (measured)", self.page_count)?;
+        write_line(f, "Pages in the whole file (from header)", self.header_page_count)?;
+        write_line(f, "Bytes of unused space", self.unused_bytes)?;
+
+        writeln!(f)?;
+        writeln!(f, "*** Page counts by category ***")?;
+        writeln!(f)?;
+        for (category, count) in self.categories.iter() {
+            let value = format!("{:<8} {:5.1}%", count, percent(*count, self.page_count));
+            write_line(f, category, value)?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "*** Page counts for all tables and indices ***")?;
+        writeln!(f)?;
+        for object in self.objects.iter() {
+            let label = if object.is_index {
+                format!("{} (index)", object.name.to_uppercase())
+            } else {
+                object.name.to_uppercase()
+            };
+            let value = format!(
+                "{:<8} {:5.1}%",
+                object.pages,
+                percent(object.pages, self.page_count)
+            );
+            write_line(f, label, value)?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "*** Unused bytes for all tables and indices ***")?;
+        writeln!(f)?;
+        for object in self.objects.iter() {
+            write_line(f, object.name.to_uppercase(), object.unused_bytes)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Written by sqlite3 with 512-byte pages, `CREATE TABLE t (a)` and 150
+    /// rows of 100-byte keys, so that the table has interior pages.
+    const KEYS_DB: &[u8] = include_bytes!("../testdata/keys.db");
+
+    #[test]
+    fn report_child_page_zero_as_corrupt() {
+        let path = std::env::temp_dir().join(format!("report-{}.db", std::process::id()));
+        let mut data = KEYS_DB.to_vec();
+        std::fs::write(&path, &data).unwrap();
+        let mut database = Database::open(path.to_str().unwrap()).unwrap();
+        assert!(SpaceReport::read(&mut database).is_ok());
+        let rootpage = database.schema.find_table("t").unwrap().rootpage;
+
+        // Points the right child of the root at page 0.
+        let at = (rootpage as usize - 1) * 512 + 8;
+        data[at..at + 4].copy_from_slice(&0u32.to_be_bytes());
+        std::fs::write(&path, &data).unwrap();
+        let mut database = Database::open(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let error = SpaceReport::read(&mut database).unwrap_err();
+        assert!(
+            matches!(error, Error::CorruptPage { page, offset: 8, .. } if page == rootpage),
+            "unexpected error: {:?}",
+            error
+        );
+    }
+}
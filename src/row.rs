@@ -0,0 +1,159 @@
+use crate::error::{bail, Error, Result};
+use crate::record::OwnedValue;
+use crate::statement::Row;
+
+/// A type a column value can be read as.
+pub trait FromValue: Sized {
+    fn from_value(value: &OwnedValue) -> Result<Self>;
+}
+
+/// A type a whole result row can be read as, like a tuple with a field for
+/// every column.
+pub trait FromRow: Sized {
+    fn from_row(row: &Row) -> Result<Self>;
+}
+
+fn mismatch<T>(value: &OwnedValue, expected: &'static str) -> Result<T> {
+    Err(Error::TypeMismatch {
+        expected,
+        found: value.type_name(),
+    })
+}
+
+impl FromValue for OwnedValue {
+    fn from_value(value: &OwnedValue) -> Result<Self> {
+        Ok(value.clone())
+    }
+}
+
+impl FromValue for i64 {
+    fn from_value(value: &OwnedValue) -> Result<Self> {
+        match value {
+            OwnedValue::Integer(n) => Ok(*n),
+            value => mismatch(value, "integer"),
+        }
+    }
+}
+
+macro_rules! from_integer {
+    ($($ty:ty)*) => {
+        $(impl FromValue for $ty {
+            fn from_value(value: &OwnedValue) -> Result<Self> {
+                let n = i64::from_value(value)?;
+                match <$ty>::try_from(n) {
+                    Ok(n) => Ok(n),
+                    Err(_) => bail!("Integer out of range for {}: {}", stringify!($ty), n),
+                }
+            }
+        })*
+    };
+}
+
+from_integer!(i8 i16 i32 u8 u16 u32 u64 usize);
+
+/// Integers are read as reals too, like SQLite converts them.
+impl FromValue for f64 {
+    fn from_value(value: &OwnedValue) -> Result<Self> {
+        match value {
+            OwnedValue::Real(n) => Ok(*n),
+            OwnedValue::Integer(n) => Ok(*n as f64),
+            value => mismatch(value, "real"),
+        }
+    }
+}
+
+/// SQLite keeps booleans as integers, any but 0 being true.
+impl FromValue for bool {
+    fn from_value(value: &OwnedValue) -> Result<Self> {
+        Ok(i64::from_value(value)? != 0)
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: &OwnedValue) -> Result<Self> {
+        match value {
+            OwnedValue::Text(text) => Ok(text.clone()),
+            value => mismatch(value, "text"),
+        }
+    }
+}
+
+impl FromValue for Vec<u8> {
+    fn from_value(value: &OwnedValue) -> Result<Self> {
+        match value {
+            OwnedValue::Blob(blob) => Ok(blob.clone()),
+            value => mismatch(value, "blob"),
+        }
+    }
+}
+
+/// NULL is `None`; other values are read as `T`.
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: &OwnedValue) -> Result<Self> {
+        match value {
+            OwnedValue::Null => Ok(None),
+            value => T::from_value(value).map(Some),
+        }
+    }
+}
+
+macro_rules! from_row_tuple {
+    ($count:literal: $($ty:ident $index:tt),*) => {
+        impl<$($ty: FromValue),*> FromRow for ($($ty,)*) {
+            fn from_row(row: &Row) -> Result<Self> {
+                if row.values().len() != $count {
+                    bail!("Expected {} columns, got {}", $count, row.values().len());
+                }
+                Ok(($(row.get::<$ty>($index)?,)*))
+            }
+        }
+    };
+}
+
+from_row_tuple!(1: A 0);
+from_row_tuple!(2: A 0, B 1);
+from_row_tuple!(3: A 0, B 1, C 2);
+from_row_tuple!(4: A 0, B 1, C 2, D 3);
+from_row_tuple!(5: A 0, B 1, C 2, D 3, E 4);
+from_row_tuple!(6: A 0, B 1, C 2, D 3, E 4, F 5);
+from_row_tuple!(7: A 0, B 1, C 2, D 3, E 4, F 5, G 6);
+from_row_tuple!(8: A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7);
+
+/// The values of the row as they are.
+impl FromRow for Vec<OwnedValue> {
+    fn from_row(row: &Row) -> Result<Self> {
+        Ok(row.values().to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_rows_as_tuples() {
+        let row = Row::from(vec![
+            OwnedValue::Integer(7),
+            OwnedValue::Text("pear".to_string()),
+            OwnedValue::Null,
+            OwnedValue::Integer(1),
+        ]);
+        let (id, name, price, stocked) = <(u32, String, Option<f64>, bool)>::from_row(&row).unwrap();
+        assert_eq!((id, name.as_str(), price, stocked), (7, "pear", None, true));
+        assert_eq!(row.get::<f64>(0).unwrap(), 7.0);
+        assert_eq!(row.get::<Option<String>>(1).unwrap().as_deref(), Some("pear"));
+
+        let error = row.get::<i64>(1).unwrap_err();
+        assert!(matches!(error, Error::TypeMismatch { expected: "integer", found: "text" }));
+        assert_eq!(row.get::<i64>(4).unwrap_err().to_string(), "Column index out of range: 4");
+        let error = <(i64, String)>::from_row(&row).unwrap_err();
+        assert_eq!(error.to_string(), "Expected 2 columns, got 4");
+        let error = Row::from(vec![OwnedValue::Integer(-1)]).get::<u8>(0).unwrap_err();
+        assert_eq!(error.to_string(), "Integer out of range for u8: -1");
+
+        let mut database = crate::database::Database::open_in_memory().unwrap();
+        database.query("CREATE TABLE t (a, b)").unwrap();
+        let counts: Vec<(i64,)> = database.query_rows("SELECT count(*) FROM t").unwrap();
+        assert_eq!(counts, [(0,)]);
+    }
+}
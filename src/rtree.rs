@@ -0,0 +1,257 @@
+use crate::database::Database;
+use crate::error::{bail, format_err, Result};
+use crate::record::{ColumnValue, Record};
+use crate::sqlite_schema::Table;
+
+/// Node number of the root node in `%_node`.
+pub const ROOT_NODE: i64 = 1;
+const COORDINATE_SIZE: usize = 4;
+const CELL_HEADER_SIZE: usize = 8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RTreeEntry {
+    pub id: i64,
+    /// `(min, max)` for every dimension.
+    pub bounds: Vec<(f64, f64)>,
+}
+
+impl RTreeEntry {
+    fn overlaps(&self, bounds: &[(f64, f64)]) -> bool {
+        self.bounds
+            .iter()
+            .zip(bounds)
+            .all(|((min, max), (other_min, other_max))| min <= other_max && other_min <= max)
+    }
+
+    fn is_within(&self, bounds: &[(f64, f64)]) -> bool {
+        self.bounds
+            .iter()
+            .zip(bounds)
+            .all(|((min, max), (other_min, other_max))| other_min <= min && max <= other_max)
+    }
+
+    fn contains(&self, bounds: &[(f64, f64)]) -> bool {
+        self.bounds
+            .iter()
+            .zip(bounds)
+            .all(|((min, max), (other_min, other_max))| min <= other_min && other_max <= max)
+    }
+
+    /// Column values in table order: id, then min and max of each dimension.
+    pub fn values(&self, is_integer: bool) -> Vec<ColumnValue<'static>> {
+        let mut values = vec![ColumnValue::I64(self.id)];
+        for (min, max) in self.bounds.iter() {
+            if is_integer {
+                values.push(ColumnValue::I64(*min as i64));
+                values.push(ColumnValue::I64(*max as i64));
+            } else {
+                values.push(ColumnValue::F64(*min));
+                values.push(ColumnValue::F64(*max));
+            }
+        }
+        values
+    }
+}
+
+/// The id column followed by the min/max columns of every dimension.
+/// Auxiliary columns start with '+' and are not part of the index.
+pub fn coordinate_columns(arguments: &[String]) -> Vec<String> {
+    arguments
+        .iter()
+        .filter(|argument| !argument.starts_with('+'))
+        .filter_map(|argument| argument.split_whitespace().next())
+        .map(|name| name.to_string())
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpatialFilter {
+    /// Entries sharing at least one point with the search box.
+    Overlaps,
+    /// Entries lying entirely inside the search box.
+    Within,
+    /// Entries enclosing the whole search box.
+    Contains,
+}
+
+#[derive(Debug)]
+pub struct Node {
+    /// Only stored in the root node; 0 everywhere else.
+    pub depth: u16,
+    pub cells: Vec<RTreeEntry>,
+}
+
+/// Parses the blob of a `%_node` row. Cells of leaf nodes are entries, cells
+/// of interior nodes point to child nodes through their id.
+pub fn parse_node(data: &[u8], dimensions: usize, is_integer: bool) -> Option<Node> {
+    if data.len() < 4 {
+        return None;
+    }
+
+    let depth = u16::from_be_bytes([data[0], data[1]]);
+    let count = u16::from_be_bytes([data[2], data[3]]) as usize;
+    let cell_size = CELL_HEADER_SIZE + 2 * dimensions * COORDINATE_SIZE;
+    if 4 + count * cell_size > data.len() {
+        return None;
+    }
+
+    let cells = data[4..4 + count * cell_size]
+        .chunks_exact(cell_size)
+        .map(|cell| {
+            let id = i64::from_be_bytes(cell[..CELL_HEADER_SIZE].try_into().unwrap());
+            let coordinates = cell[CELL_HEADER_SIZE..]
+                .chunks_exact(COORDINATE_SIZE)
+                .map(|bytes| {
+                    let bytes = bytes.try_into().unwrap();
+                    if is_integer {
+                        i32::from_be_bytes(bytes) as f64
+                    } else {
+                        f32::from_be_bytes(bytes) as f64
+                    }
+                })
+                .collect::<Vec<_>>();
+            let bounds = coordinates.chunks_exact(2).map(|c| (c[0], c[1])).collect();
+
+            RTreeEntry { id, bounds }
+        })
+        .collect();
+
+    Some(Node { depth, cells })
+}
+
+/// An rtree virtual table, stored in its `%_node` shadow table.
+#[derive(Debug)]
+pub struct RTree {
+    pub name: String,
+    pub columns: Vec<String>,
+    pub dimensions: usize,
+    pub is_integer: bool,
+    pub node_rootpage: u32,
+}
+
+impl RTree {
+    pub fn read(database: &mut Database, table: &Table) -> Result<Self> {
+        let is_integer = table.is_module("rtree_i32");
+        if !is_integer && !table.is_module("rtree") {
+            bail!("Not an rtree table: {}", table.name);
+        }
+
+        let arguments = &table.module.as_ref().expect("rtree table has a module").arguments;
+        let columns = coordinate_columns(arguments);
+        if columns.len() < 3 || columns.len() % 2 == 0 {
+            bail!("Malformed rtree table: {}", table.name);
+        }
+
+        let node_table = format!("{}_node", table.name);
+        let node_rootpage = database
+            .schema
+            .tables
+            .get(&node_table)
+            .ok_or(format_err!("Missing rtree shadow table: {}", node_table))?
+            .rootpage;
+
+        Ok(Self {
+            name: table.name.clone(),
+            dimensions: (columns.len() - 1) / 2,
+            columns,
+            is_integer,
+            node_rootpage,
+        })
+    }
+
+    fn read_node(&self, database: &mut Database, node_number: i64) -> Result<Node> {
+        let Some(payload) = database.find_row_payload(self.node_rootpage, node_number)? else {
+            bail!("Missing rtree node: {}", node_number);
+        };
+        let record = Record::read(node_number, &payload);
+        let Some(ColumnValue::Blob(data)) = record.values.get(1) else {
+            bail!("Malformed rtree node: {}", node_number);
+        };
+
+        parse_node(data, self.dimensions, self.is_integer)
+            .ok_or(format_err!("Malformed rtree node: {}", node_number))
+    }
+
+    /// Every entry of the tree, in node order.
+    pub fn entries(&self, database: &mut Database) -> Result<Vec<RTreeEntry>> {
+        self.walk(database, &mut |_| true, &mut |_| true)
+    }
+
+    /// Entries matching `filter` against the search box `bounds`, which has a
+    /// `(min, max)` pair for every dimension.
+    pub fn search(
+        &self,
+        database: &mut Database,
+        bounds: &[(f64, f64)],
+        filter: SpatialFilter,
+    ) -> Result<Vec<RTreeEntry>> {
+        if bounds.len() != self.dimensions {
+            bail!(
+                "Expected {} dimensions for rtree {}, got {}",
+                self.dimensions,
+                self.name,
+                bounds.len()
+            );
+        }
+
+        // A child box can only hold matches if it overlaps (or, when looking
+        // for entries containing the box, itself contains) the search box.
+        let mut visit = |child: &RTreeEntry| match filter {
+            SpatialFilter::Overlaps | SpatialFilter::Within => child.overlaps(bounds),
+            SpatialFilter::Contains => child.contains(bounds),
+        };
+        let mut keep = |entry: &RTreeEntry| match filter {
+            SpatialFilter::Overlaps => entry.overlaps(bounds),
+            SpatialFilter::Within => entry.is_within(bounds),
+            SpatialFilter::Contains => entry.contains(bounds),
+        };
+
+        self.walk(database, &mut visit, &mut keep)
+    }
+
+    fn walk(
+        &self,
+        database: &mut Database,
+        visit: &mut impl FnMut(&RTreeEntry) -> bool,
+        keep: &mut impl FnMut(&RTreeEntry) -> bool,
+    ) -> Result<Vec<RTreeEntry>> {
+        let root = self.read_node(database, ROOT_NODE)?;
+        let mut entries = vec![];
+        let mut stack = vec![(root.depth, root)];
+
+        while let Some((depth, node)) = stack.pop() {
+            if depth == 0 {
+                entries.extend(node.cells.into_iter().filter(|entry| keep(entry)));
+                continue;
+            }
+
+            for child in node.cells.iter().rev().filter(|child| visit(child)) {
+                let mut child_node = self.read_node(database, child.id)?;
+                child_node.depth = depth - 1;
+                stack.push((depth - 1, child_node));
+            }
+        }
+
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spatial_filters() {
+        let entry = RTreeEntry {
+            id: 1,
+            bounds: vec![(10.0, 20.0), (10.0, 20.0)],
+        };
+
+        assert!(entry.overlaps(&[(15.0, 30.0), (0.0, 12.0)]));
+        assert!(!entry.overlaps(&[(21.0, 30.0), (0.0, 12.0)]));
+        assert!(entry.is_within(&[(0.0, 20.0), (10.0, 50.0)]));
+        assert!(!entry.is_within(&[(0.0, 19.0), (10.0, 50.0)]));
+        assert!(entry.contains(&[(12.0, 13.0), (10.0, 20.0)]));
+        assert!(!entry.contains(&[(12.0, 23.0), (10.0, 20.0)]));
+    }
+}
@@ -0,0 +1,255 @@
+use std::cmp::Ordering;
+use std::fs::{File, OpenOptions};
+use std::io::{prelude::*, BufReader, BufWriter, SeekFrom};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
+
+use crate::error::{Error, Result};
+use crate::output::RowSink;
+use crate::record::{self, Collation, ColumnValue, Record};
+
+/// Bytes a buffered row takes besides its payload.
+const ROW_OVERHEAD: u64 = std::mem::size_of::<Vec<u8>>() as u64;
+
+/// Tells the run files of concurrent sorts apart.
+static RUN_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// One ORDER BY term: which value of the row to sort by, which way, and
+/// how text compares.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SortKey {
+    pub column: usize,
+    pub descending: bool,
+    pub collation: Collation,
+}
+
+/// Collects rows and passes them on in order once all have been seen.
+///
+/// Rows are kept encoded as records. When they would take more than the
+/// memory limit, the ones collected so far are sorted and written to a
+/// temporary file, and the files are merged at the end.
+#[derive(Debug)]
+pub struct Sorter {
+    keys: Vec<SortKey>,
+    /// Values of every row passed on; the ones after them are only there to
+    /// be sorted by.
+    columns: usize,
+    memory_limit: Option<u64>,
+    rows: Vec<Vec<u8>>,
+    memory_used: u64,
+    peak_memory: u64,
+    runs: Vec<(PathBuf, File)>,
+}
+
+impl Sorter {
+    pub fn new(keys: Vec<SortKey>, columns: usize, memory_limit: Option<u64>) -> Self {
+        Self {
+            keys,
+            columns,
+            memory_limit,
+            rows: vec![],
+            memory_used: 0,
+            peak_memory: 0,
+            runs: vec![],
+        }
+    }
+
+    /// Times rows were written out to a temporary file.
+    pub fn spills(&self) -> u64 {
+        self.runs.len() as u64
+    }
+
+    /// Most bytes held in memory at once.
+    pub fn peak_memory(&self) -> u64 {
+        self.peak_memory
+    }
+
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        let (a, b) = (Record::read(0, a), Record::read(0, b));
+        for key in self.keys.iter() {
+            let null = ColumnValue::Null;
+            let a = a.values.get(key.column).unwrap_or(&null);
+            let b = b.values.get(key.column).unwrap_or(&null);
+            let ordering = a.compare_collated(b, key.collation);
+            let ordering = if key.descending { ordering.reverse() } else { ordering };
+            if ordering.is_ne() {
+                return ordering;
+            }
+        }
+        Ordering::Equal
+    }
+
+    fn sort_rows(&mut self) {
+        let mut rows = std::mem::take(&mut self.rows);
+        rows.sort_by(|a, b| self.compare(a, b));
+        self.rows = rows;
+    }
+
+    /// Writes the sorted rows to a new run file, each prefixed with its
+    /// length.
+    fn spill(&mut self) -> Result<()> {
+        self.sort_rows();
+
+        let path = std::env::temp_dir().join(format!(
+            "simple-sqlite-sort-{}-{}",
+            std::process::id(),
+            RUN_COUNTER.fetch_add(1, AtomicOrdering::Relaxed)
+        ));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        // Registered first, so the file is removed even if writing fails.
+        self.runs.push((path, file.try_clone()?));
+
+        let mut writer = BufWriter::new(file);
+        for row in self.rows.drain(..) {
+            writer.write_all(&(row.len() as u32).to_be_bytes())?;
+            writer.write_all(&row)?;
+        }
+        writer.flush()?;
+        self.memory_used = 0;
+        Ok(())
+    }
+
+    /// Passes the rows on to `out` in order, merging the run files with the
+    /// rows still in memory.
+    pub fn finish(mut self, out: &mut impl RowSink) -> Result<()> {
+        self.sort_rows();
+
+        let mut readers = self
+            .runs
+            .iter()
+            .map(|(_, file)| {
+                let mut file = file.try_clone()?;
+                file.seek(SeekFrom::Start(0))?;
+                Ok(BufReader::new(file))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let read_row = |reader: &mut BufReader<File>| -> Result<Option<Vec<u8>>> {
+            let mut length = [0; 4];
+            if reader.read(&mut length[..1])? == 0 {
+                return Ok(None);
+            }
+            reader.read_exact(&mut length[1..])?;
+            let mut row = vec![0; u32::from_be_bytes(length) as usize];
+            reader.read_exact(&mut row)?;
+            Ok(Some(row))
+        };
+
+        // The head of every run, then of the rows in memory. Earlier runs
+        // win ties, which keeps the sort stable.
+        let mut in_memory = std::mem::take(&mut self.rows).into_iter();
+        let mut heads = readers
+            .iter_mut()
+            .map(read_row)
+            .collect::<Result<Vec<_>>>()?;
+        heads.push(in_memory.next());
+
+        loop {
+            let mut smallest: Option<usize> = None;
+            for (i, head) in heads.iter().enumerate() {
+                let Some(row) = head else { continue; };
+                let is_smaller = match smallest {
+                    None => true,
+                    Some(j) => self.compare(row, heads[j].as_ref().unwrap()).is_lt(),
+                };
+                if is_smaller {
+                    smallest = Some(i);
+                }
+            }
+            let Some(i) = smallest.filter(|_| !out.is_done()) else { break; };
+
+            let row = match readers.get_mut(i) {
+                Some(reader) => std::mem::replace(&mut heads[i], read_row(reader)?),
+                None => std::mem::replace(&mut heads[i], in_memory.next()),
+            }
+            .unwrap();
+            let record = Record::read(0, &row);
+            out.write_row(&record.values[..self.columns.min(record.values.len())])?;
+        }
+        Ok(())
+    }
+}
+
+impl RowSink for Sorter {
+    fn write_row(&mut self, values: &[ColumnValue]) -> Result<()> {
+        let row = record::encode(values);
+        let size = row.len() as u64 + ROW_OVERHEAD;
+
+        if let Some(limit) = self.memory_limit {
+            if self.memory_used + size > limit {
+                if self.rows.is_empty() {
+                    return Err(Error::MemoryLimit { limit });
+                }
+                self.spill()?;
+            }
+        }
+
+        self.rows.push(row);
+        self.memory_used += size;
+        self.peak_memory = self.peak_memory.max(self.memory_used);
+        Ok(())
+    }
+}
+
+impl Drop for Sorter {
+    fn drop(&mut self) {
+        for (path, _) in self.runs.iter() {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct Rows(Vec<String>);
+
+    impl RowSink for Rows {
+        fn write_row(&mut self, values: &[ColumnValue]) -> Result<()> {
+            self.0.push(values.iter().map(|value| value.to_string()).collect::<Vec<_>>().join("|"));
+            Ok(())
+        }
+    }
+
+    fn sort(keys: Vec<SortKey>, memory_limit: Option<u64>) -> (Vec<String>, u64) {
+        let rows = [
+            vec![ColumnValue::Text(b"pear"), ColumnValue::I8(3)],
+            vec![ColumnValue::Text(b"fig"), ColumnValue::Null],
+            vec![ColumnValue::Text(b"apple"), ColumnValue::F64(3.0)],
+            vec![ColumnValue::Text(b"kiwi"), ColumnValue::Text(b"2")],
+            vec![ColumnValue::Text(b"plum"), ColumnValue::I8(1)],
+        ];
+
+        let mut sorter = Sorter::new(keys, 1, memory_limit);
+        for row in rows.iter() {
+            sorter.write_row(row).unwrap();
+        }
+        let spills = sorter.spills();
+        let mut out = Rows::default();
+        sorter.finish(&mut out).unwrap();
+        (out.0, spills)
+    }
+
+    #[test]
+    fn sort_by_type_then_value() {
+        let ascending = vec![
+            SortKey { column: 1, descending: false, collation: Collation::Binary },
+            SortKey { column: 0, descending: false, collation: Collation::Binary },
+        ];
+        let expected = vec!["fig", "plum", "apple", "pear", "kiwi"];
+        assert_eq!(sort(ascending.clone(), None).0, expected);
+
+        let descending = vec![SortKey { column: 1, descending: true, collation: Collation::Binary }];
+        assert_eq!(sort(descending, None).0, vec!["kiwi", "pear", "apple", "plum", "fig"]);
+
+        // Two rows fit in memory at a time.
+        let (rows, spills) = sort(ascending, Some(2 * (ROW_OVERHEAD + 12)));
+        assert_eq!(rows, expected);
+        assert_eq!(spills, 2);
+    }
+}
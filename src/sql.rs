@@ -1,416 +1,3627 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
+
 use nom::{
   branch::alt,
-  bytes::complete::{tag, tag_no_case, take_until, take_while1},
+  bytes::complete::{tag, tag_no_case, take, take_until, take_while1},
   character::{
-      complete::{multispace0, multispace1},
-      is_alphanumeric, is_space,
+      complete::{digit1, multispace0, multispace1},
+      is_alphanumeric,
   },
-  combinator::{map, opt},
-  multi::{many0, many1},
-  sequence::{delimited, terminated, tuple},
+  combinator::{consumed, map, map_opt, not, opt, recognize, rest, verify},
+  multi::{many0, many1, separated_list0, separated_list1},
+  sequence::{delimited, preceded, terminated, tuple},
   IResult,
 };
 
-#[derive(Debug, PartialEq)]
+use crate::error::Error;
+use crate::expr::{Affinity, BinaryOperator, Expr, Literal};
+use crate::record::Collation;
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum SelectStatement {
   Fields(SelectFields),
-  Count(String),
+  Expressions(ExpressionSelection),
+  Count(CountSelection),
+  MinMax(MinMaxSelection),
+  Aggregate(AggregateSelection),
+  Compound(CompoundSelection),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompoundOperator {
+  /// Leaves out rows already returned.
+  Union,
+  UnionAll,
+}
+
+/// SELECTs combined with UNION or UNION ALL. ORDER BY and LIMIT, written
+/// after the last one, apply to the combined rows.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompoundSelection {
+  pub arms: Vec<SelectStatement>,
+  /// The operator before every arm but the first.
+  pub operators: Vec<CompoundOperator>,
+  /// Result columns, by name in the first arm.
+  pub order_by: Vec<OrderingTerm>,
+  pub limit: Option<u64>,
+  pub offset: u64,
+}
+
+/// `SELECT COUNT(*) FROM table [WHERE ...]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CountSelection {
+  pub table: String,
+  pub where_clause: Option<WhereClause>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MinMax {
+  Min,
+  Max,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MinMaxSelection {
+  pub function: MinMax,
+  pub field: String,
+  pub table: String,
 }
 
-#[derive(Debug, PartialEq)]
-pub struct WhereClause {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AggregateFunction {
+  Count,
+  Min,
+  Max,
+  Sum,
+  Avg,
+  Total,
+}
+
+/// A value of the result of a grouped query.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResultColumn {
+  Column(String),
+  /// An aggregate over a column, or over rows for `COUNT(*)`.
+  Aggregate(AggregateFunction, Option<String>),
+}
+
+impl ResultColumn {
+  /// How the column is named in the result, and in HAVING and ORDER BY,
+  /// e.g. `count(*)` or `max(price)`.
+  pub fn name(&self) -> String {
+      match self {
+          ResultColumn::Column(name) => name.clone(),
+          ResultColumn::Aggregate(function, argument) => {
+              let function = match function {
+                  AggregateFunction::Count => "count",
+                  AggregateFunction::Min => "min",
+                  AggregateFunction::Max => "max",
+                  AggregateFunction::Sum => "sum",
+                  AggregateFunction::Avg => "avg",
+                  AggregateFunction::Total => "total",
+              };
+              // Names that need quotes keep them, so the name reads back.
+              let argument = match argument {
+                  Some(name) if !name.bytes().all(is_sql_identifier) => quote_identifier(name),
+                  Some(name) => name.clone(),
+                  None => "*".to_string(),
+              };
+              format!("{}({})", function, argument)
+          }
+      }
+  }
+}
+
+/// A SELECT with aggregates, whose rows are grouped by the GROUP BY
+/// columns, or all in one group without them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AggregateSelection {
+  pub columns: Vec<ResultColumn>,
+  /// Names of the result columns: their alias, or the name of the column.
+  pub names: Vec<String>,
+  pub table: String,
+  pub where_clause: Option<WhereClause>,
+  pub group_by: Vec<String>,
+  /// Compares result columns, like `count(*)`, of every group.
+  pub having: Option<WhereClause>,
+  pub order_by: Vec<OrderingTerm>,
+  pub limit: Option<u64>,
+  pub offset: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operator {
+  Equal,
+  NotEqual,
+  Less,
+  LessEqual,
+  Greater,
+  GreaterEqual,
+  Match,
+  /// `%` matches any run of characters and `_` any one character, ignoring
+  /// ASCII case.
+  Like,
+}
+
+/// A single `field operator value` test.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
   pub field: String,
+  pub operator: Operator,
   pub value: String,
 }
 
-#[derive(Debug, PartialEq)]
+/// A comparison of two expressions, when it is more than a column and a
+/// constant.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExpressionComparison {
+  pub left: Expr,
+  pub operator: Operator,
+  pub right: Expr,
+}
+
+impl ExpressionComparison {
+  /// A column compared with a constant becomes a plain comparison, which
+  /// indexes can answer. Blobs stay expressions, compared byte by byte.
+  pub fn simplify(self) -> WhereClause {
+      match (self.left, self.right) {
+          (Expr::Column(field), Expr::Literal(value))
+              if !matches!(value, Literal::Null | Literal::Blob(_)) =>
+          {
+              WhereClause::Comparison(Comparison {
+                  field,
+                  operator: self.operator,
+                  value: value.to_string(),
+              })
+          }
+          (left, right) => WhereClause::Expression(ExpressionComparison {
+              left,
+              operator: self.operator,
+              right,
+          }),
+      }
+  }
+}
+
+/// A `field IN (values)` test; `None` stands for a NULL in the list.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InList {
+  pub field: String,
+  pub values: Vec<Option<String>>,
+}
+
+/// A `field IN (SELECT ...)` test, whose subquery returns one column.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InSelect {
+  pub field: String,
+  pub select: Box<SelectStatement>,
+}
+
+/// What `field IN (SELECT ...)` becomes once the subquery has run: the
+/// values it returned, as made into keys by `database::set_key`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InSet {
+  pub field: String,
+  pub keys: HashSet<Vec<u8>>,
+}
+
+/// Comparisons combined with AND and OR. AND binds tighter than OR, and
+/// parentheses group as usual.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WhereClause {
+  Comparison(Comparison),
+  Expression(ExpressionComparison),
+  In(InList),
+  InSelect(InSelect),
+  InSet(InSet),
+  And(Box<WhereClause>, Box<WhereClause>),
+  Or(Box<WhereClause>, Box<WhereClause>),
+}
+
+impl WhereClause {
+  /// The comparison, when the clause is nothing but one.
+  pub fn as_comparison(&self) -> Option<&Comparison> {
+      match self {
+          WhereClause::Comparison(comparison) => Some(comparison),
+          _ => None,
+      }
+  }
+
+  /// The comparisons every row passing the clause passes, being joined to
+  /// the rest by AND, left to right. Those under an OR aren't.
+  pub fn conjuncts(&self) -> Vec<&Comparison> {
+      match self {
+          WhereClause::Comparison(comparison) => vec![comparison],
+          WhereClause::And(left, right) => {
+              let mut comparisons = left.conjuncts();
+              comparisons.extend(right.conjuncts());
+              comparisons
+          }
+          _ => vec![],
+      }
+  }
+
+  /// Every comparison in the clause, left to right.
+  pub fn comparisons(&self) -> Vec<&Comparison> {
+      match self {
+          WhereClause::Comparison(comparison) => vec![comparison],
+          WhereClause::Expression(_)
+          | WhereClause::In(_)
+          | WhereClause::InSelect(_)
+          | WhereClause::InSet(_) => vec![],
+          WhereClause::And(left, right) | WhereClause::Or(left, right) => {
+              let mut comparisons = left.comparisons();
+              comparisons.extend(right.comparisons());
+              comparisons
+          }
+      }
+  }
+
+  /// Every expression compared in the clause, left to right.
+  pub fn expressions(&self) -> Vec<&Expr> {
+      match self {
+          WhereClause::Expression(comparison) => vec![&comparison.left, &comparison.right],
+          WhereClause::Comparison(_)
+          | WhereClause::In(_)
+          | WhereClause::InSelect(_)
+          | WhereClause::InSet(_) => vec![],
+          WhereClause::And(left, right) | WhereClause::Or(left, right) => {
+              let mut expressions = left.expressions();
+              expressions.extend(right.expressions());
+              expressions
+          }
+      }
+  }
+
+  /// Every subquery of the clause, left to right.
+  pub fn subqueries(&self) -> Vec<&SelectStatement> {
+      match self {
+          WhereClause::InSelect(subquery) => vec![&subquery.select],
+          WhereClause::And(left, right) | WhereClause::Or(left, right) => {
+              let mut subqueries = left.subqueries();
+              subqueries.extend(right.subqueries());
+              subqueries
+          }
+          _ => vec![],
+      }
+  }
+
+  /// Every column the clause tests, left to right.
+  pub fn columns(&self) -> Vec<&String> {
+      match self {
+          WhereClause::Comparison(comparison) => vec![&comparison.field],
+          WhereClause::Expression(comparison) => {
+              let mut columns = comparison.left.columns();
+              columns.extend(comparison.right.columns());
+              columns
+          }
+          WhereClause::In(list) => vec![&list.field],
+          WhereClause::InSelect(subquery) => vec![&subquery.field],
+          WhereClause::InSet(set) => vec![&set.field],
+          WhereClause::And(left, right) | WhereClause::Or(left, right) => {
+              let mut columns = left.columns();
+              columns.extend(right.columns());
+              columns
+          }
+      }
+  }
+}
+
+/// A term of ORDER BY.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderingTerm {
+  pub column: String,
+  pub descending: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct SelectFields {
   pub fields: Vec<String>,
   pub table: String,
   pub where_clause: Option<WhereClause>,
+  pub order_by: Vec<OrderingTerm>,
+  /// At most this many rows; a negative LIMIT means no limit, as in SQLite.
+  pub limit: Option<u64>,
+  /// Rows skipped before the first one returned.
+  pub offset: u64,
+}
+
+/// An expression of a SELECT list, and the name of its result column: its
+/// alias, or the expression as written.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelectColumn {
+  pub expression: Expr,
+  pub name: String,
+}
+
+/// A SELECT whose result columns are computed from the columns of every
+/// row, like `SELECT price * 2 FROM products`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExpressionSelection {
+  pub columns: Vec<SelectColumn>,
+  pub table: String,
+  pub where_clause: Option<WhereClause>,
+  /// Result columns, by name, or columns of the table.
+  pub order_by: Vec<OrderingTerm>,
+  pub limit: Option<u64>,
+  pub offset: u64,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ColumnConstraint {
   PrimaryKey,
+  AutoIncrement,
+  NotNull,
+  /// The value, and the expression as written.
+  Default(Expr, String),
+  Unique,
+  Check(WhereClause),
+  /// `REFERENCES table(columns)`.
+  References(String, Vec<String>),
+  Generated(Generated),
+  Collate(Collation),
+}
+
+/// How a generated column gets its value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Generated {
+  pub expression: Expr,
+  /// STORED columns are kept in the record like any other. VIRTUAL ones,
+  /// the default, are left out and computed when read.
+  pub stored: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TableConstraint {
+  PrimaryKey(Vec<String>),
+  Unique(Vec<String>),
+  Check(WhereClause),
+  ForeignKey(ForeignKey),
 }
 
-#[derive(Debug, PartialEq)]
+/// Columns that refer to rows of another table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForeignKey {
+  pub columns: Vec<String>,
+  pub table: String,
+  /// The referenced columns. Empty means the other table's primary key.
+  pub references: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Field {
   pub name: String,
+  /// The declared type as written, like `VARCHAR(255)`, if there is one.
+  pub type_name: Option<String>,
+  /// Whether the column is the rowid, being declared INTEGER PRIMARY KEY.
   pub is_primary_key: bool,
+  /// Whether the column is part of the PRIMARY KEY, alone or with others.
+  pub in_primary_key: bool,
+  /// Declared INTEGER PRIMARY KEY AUTOINCREMENT: rowids are never reused,
+  /// the largest one handed out being kept in `sqlite_sequence`.
+  pub autoincrement: bool,
+  /// False when declared NOT NULL.
+  pub nullable: bool,
+  /// The value given by a DEFAULT constraint.
+  pub default_value: Option<Expr>,
+  /// The DEFAULT as written, like `'none'` or `(1 + 2)`.
+  pub default_text: Option<String>,
+  pub unique: bool,
+  /// Conditions from CHECK constraints on the column.
+  pub checks: Vec<WhereClause>,
+  /// From a REFERENCES constraint on the column.
+  pub foreign_key: Option<ForeignKey>,
+  /// From `GENERATED ALWAYS AS (expression)`.
+  pub generated: Option<Generated>,
+  /// From a COLLATE constraint, BINARY otherwise.
+  pub collation: Collation,
 }
 
 impl Field {
   pub fn new(name: String) -> Self {
       Self {
           name,
+          type_name: None,
           is_primary_key: false,
+          in_primary_key: false,
+          autoincrement: false,
+          nullable: true,
+          default_value: None,
+          default_text: None,
+          unique: false,
+          checks: vec![],
+          foreign_key: None,
+          generated: None,
+          collation: Collation::Binary,
       }
   }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct CreateTableStatement {
   pub table: String,
   pub fields: Vec<Field>,
+  /// Columns of the PRIMARY KEY, from a table constraint or a column.
+  pub primary_key: Vec<String>,
+  /// Column sets from UNIQUE table constraints.
+  pub unique: Vec<Vec<String>>,
+  /// Conditions from CHECK table constraints.
+  pub checks: Vec<WhereClause>,
+  /// From FOREIGN KEY table constraints.
+  pub foreign_keys: Vec<ForeignKey>,
+  pub without_rowid: bool,
+  pub if_not_exists: bool,
+  /// `CREATE TEMP TABLE`, for a table in the temp database.
+  pub temporary: bool,
+  /// Column sets of the PRIMARY KEY and UNIQUE constraints SQLite makes
+  /// automatic indexes for, in the order it numbers them. The PRIMARY KEY
+  /// of a WITHOUT ROWID table takes a number but is the table itself.
+  pub autoindexes: Vec<Vec<String>>,
+}
+
+impl CreateTableStatement {
+  /// Column names in the order their values are stored in a record.
+  /// WITHOUT ROWID tables store their primary key columns first. Virtual
+  /// generated columns aren't stored.
+  pub fn record_columns(&self) -> Vec<String> {
+      let names = self
+          .fields
+          .iter()
+          .filter(|field| field.generated.as_ref().is_none_or(|generated| generated.stored))
+          .map(|field| field.name.clone());
+      if !self.without_rowid {
+          return names.collect();
+      }
+
+      let mut columns = self.primary_key.clone();
+      columns.extend(names.filter(|name| !self.primary_key.contains(name)));
+      columns
+  }
+}
+
+/// The text SQLite keeps in the schema table for a CREATE TABLE statement:
+/// `CREATE TABLE` and the rest of it from the name of the table on, which
+/// leaves out IF NOT EXISTS.
+pub fn table_definition(sql: &str) -> String {
+  definition(sql, "table")
+}
+
+/// Like [`table_definition`], for a CREATE INDEX statement.
+pub fn index_definition(sql: &str) -> String {
+  definition(sql, "index")
+}
+
+fn definition(sql: &str, kind: &'static str) -> String {
+  let text = strip_comments(sql);
+  let mut head = tuple((
+      keyword("create"),
+      opt(alt((keyword("temporary"), keyword("temp")))),
+      keyword(kind),
+      opt(tuple((keyword("if"), keyword("not"), keyword("exists")))),
+  ));
+  let start = match head(text.as_bytes()) {
+    Ok((rest, _)) => text.len() - rest.len(),
+    Err(_) => 0,
+  };
+  format!(
+      "CREATE {} {}",
+      kind.to_ascii_uppercase(),
+      sql[start..].trim_end().trim_end_matches(';').trim_end()
+  )
+}
+
+/// The CREATE TABLE text `table_sql` with `column`, a column definition,
+/// after its last column or table constraint, which is how SQLite keeps a
+/// column added by ALTER TABLE.
+pub fn add_column_definition(table_sql: &str, column: &str) -> String {
+  match strip_comments(table_sql).rfind(')') {
+    Some(end) => format!("{}, {}{}", &table_sql[..end], column, &table_sql[end..]),
+    None => format!("{}, {}", table_sql, column),
+  }
+}
+
+/// Quotes an identifier for use in generated statements.
+pub fn quote_identifier(name: &str) -> String {
+  format!("\"{}\"", name.replace('"', "\"\""))
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreateVirtualTableStatement {
+  pub table: String,
+  pub module: String,
+  pub arguments: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct CreateIndexStatement {
   pub name: String,
   pub table: String,
   pub fields: Vec<String>,
+  /// Collations given to the fields by COLLATE clauses, if any.
+  pub collations: Vec<Option<Collation>>,
+  /// Whether each field is in descending order.
+  pub descending: Vec<bool>,
+  pub if_not_exists: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreateViewStatement {
+  pub name: String,
+  /// Names given to the columns, if any. Otherwise they are those of the
+  /// result columns of the SELECT.
+  pub columns: Vec<String>,
+  pub select: SelectStatement,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriggerTiming {
+  Before,
+  After,
+  InsteadOf,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TriggerEvent {
+  Delete,
+  Insert,
+  /// `UPDATE`, or `UPDATE OF` the columns.
+  Update(Vec<String>),
+}
+
+/// `DELETE FROM table WHERE condition`, which deletes the rows `selection`
+/// finds, by their rowids.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeleteStatement {
+  pub table: String,
+  /// `SELECT rowid FROM table WHERE condition`.
+  pub selection: SelectStatement,
+}
+
+/// `DROP TABLE [IF EXISTS] table`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DropTableStatement {
+  pub table: String,
+  pub if_exists: bool,
+}
+
+/// `ALTER TABLE table ADD [COLUMN] column`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlterTableStatement {
+  pub table: String,
+  pub column: Field,
+  /// The definition of the column as written.
+  pub definition: String,
+}
+
+/// `ATTACH [DATABASE] 'path' AS name`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AttachStatement {
+  pub path: String,
+  /// The schema name its tables are qualified with.
+  pub name: String,
+}
+
+/// `PRAGMA [schema.]name`, then `= value` or `(value)`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PragmaStatement {
+  /// The schema name of the database it is about, if given.
+  pub schema: Option<String>,
+  pub name: String,
+  pub argument: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreateTriggerStatement {
+  pub name: String,
+  pub table: String,
+  pub timing: TriggerTiming,
+  pub event: TriggerEvent,
+  /// The rest of the definition, from `FOR EACH ROW` or `WHEN` to `END`,
+  /// as written.
+  pub body: String,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum SQLCommand {
   Select(SelectStatement),
   CreateTable(CreateTableStatement),
+  CreateVirtualTable(CreateVirtualTableStatement),
   CreateIndex(CreateIndexStatement),
+  CreateView(CreateViewStatement),
+  CreateTrigger(CreateTriggerStatement),
+  /// `EXPLAIN QUERY PLAN` of a SELECT.
+  ExplainQueryPlan(SelectStatement),
+  Delete(DeleteStatement),
+  DropTable(DropTableStatement),
+  AlterTable(AlterTableStatement),
+  /// `BEGIN [DEFERRED | IMMEDIATE | EXCLUSIVE] [TRANSACTION]`.
+  Begin,
+  /// `COMMIT [TRANSACTION]` or `END [TRANSACTION]`.
+  Commit,
+  /// `ROLLBACK [TRANSACTION]`.
+  Rollback,
+  Attach(AttachStatement),
+  /// `DETACH [DATABASE] name`.
+  Detach(String),
+  Pragma(PragmaStatement),
 }
 
 pub fn parse(input: &[u8]) -> IResult<&[u8], SQLCommand> {
   alt((
       map(parse_creation, |c| SQLCommand::CreateTable(c)),
+      map(parse_virtual_table_creation, |c| SQLCommand::CreateVirtualTable(c)),
+      map(compound_selection, |s| SQLCommand::Select(s)),
       map(selection, |s| SQLCommand::Select(s)),
       map(count_selection, |s| SQLCommand::Select(s)),
+      map(min_max_selection, |s| SQLCommand::Select(s)),
+      map(aggregate_selection, |s| SQLCommand::Select(s)),
       map(parse_index_creation, |c| SQLCommand::CreateIndex(c)),
+      map(parse_view_creation, |c| SQLCommand::CreateView(c)),
+      map(parse_trigger_creation, |c| SQLCommand::CreateTrigger(c)),
+      map(explain_query_plan, |s| SQLCommand::ExplainQueryPlan(s)),
+      map(parse_delete, |d| SQLCommand::Delete(d)),
+      map(parse_drop_table, |d| SQLCommand::DropTable(d)),
+      map(parse_alter_table, |a| SQLCommand::AlterTable(a)),
+      transaction_control,
+      attachment,
+      pragma,
   ))(input)
 }
 
-fn count_selection(input: &[u8]) -> IResult<&[u8], SelectStatement> {
-  let (remaining_input, (_, _, _, _, _, _, table, _)) = tuple((
-      tag_no_case("select"),
-      multispace1,
-      tag_no_case("count(*)"),
-      multispace1,
-      tag_no_case("from"),
-      multispace1,
-      identifier,
-      opt(tag(";")),
-  ))(input)?;
+/// Blanks out `-- line` and `/* block */` comments outside quotes, so that
+/// offsets in the text stay those of the input. A block comment without
+/// its end runs to the end of the input, as in SQLite.
+pub fn strip_comments(input: &str) -> Cow<'_, str> {
+  if !input.contains("--") && !input.contains("/*") {
+    return Cow::Borrowed(input);
+  }
 
-  Ok((remaining_input, SelectStatement::Count(table)))
+  let bytes = input.as_bytes();
+  let mut text = bytes.to_vec();
+  let mut quote = None;
+  let mut i = 0;
+  while i < bytes.len() {
+    let end = match (quote, bytes[i], bytes.get(i + 1)) {
+      (Some(q), c, _) => {
+        if c == q {
+          quote = None;
+        }
+        i + 1
+      }
+      (None, c @ (b'\'' | b'"' | b'`'), _) => {
+        quote = Some(c);
+        i + 1
+      }
+      (None, b'[', _) => {
+        quote = Some(b']');
+        i + 1
+      }
+      (None, b'-', Some(b'-')) => {
+        let end = bytes[i..].iter().position(|&c| c == b'\n').map_or(bytes.len(), |n| i + n);
+        text[i..end].fill(b' ');
+        end
+      }
+      (None, b'/', Some(b'*')) => {
+        let end = bytes[i + 2..]
+            .windows(2)
+            .position(|pair| pair == b"*/")
+            .map_or(bytes.len(), |n| i + 2 + n + 2);
+        text[i..end].fill(b' ');
+        end
+      }
+      (None, _, _) => i + 1,
+    };
+    i = end;
+  }
+  // Only whole characters were blanked out.
+  Cow::Owned(String::from_utf8(text).unwrap())
 }
 
-fn selection(input: &[u8]) -> IResult<&[u8], SelectStatement> {
-  let (remaining_input, (_, _, fields, _, _, _, table, where_clause, _)) = tuple((
-      tag_no_case("select"),
-      multispace1,
-      identifiers,
-      multispace0,
-      tag_no_case("from"),
-      multispace1,
-      identifier,
-      parse_where_clause,
-      opt(tag(";")),
-  ))(input)?;
-
-  Ok((
-      remaining_input,
-      SelectStatement::Fields(SelectFields {
-          table,
-          fields,
-          where_clause,
-      }),
-  ))
+/// The statements of a script, split at the semicolons outside quotes and
+/// comments and trimmed, each with the offset it starts at. Blank ones are
+/// left out.
+pub fn split_statements(input: &str) -> Vec<(usize, &str)> {
+  let text = strip_comments(input);
+  let mut statements = vec![];
+  let mut start = 0;
+  let mut quote = None;
+  for (i, c) in text.char_indices() {
+    match (quote, c) {
+      // A doubled quote ends the quoted text and starts it again.
+      (Some(q), c) if c == q => quote = None,
+      (Some(_), _) => {}
+      (None, '\'' | '"' | '`') => quote = Some(c),
+      (None, '[') => quote = Some(']'),
+      (None, ';') => {
+        statements.push(start..i);
+        start = i + 1;
+      }
+      (None, _) => {}
+    }
+  }
+  statements.push(start..text.len());
+  statements
+      .into_iter()
+      .filter(|range| !text[range.clone()].trim().is_empty())
+      .map(|range| {
+        let statement = &text[range.clone()];
+        let start = range.start + statement.len() - statement.trim_start().len();
+        (start, &input[start..range.start + statement.trim_end().len()])
+      })
+      .collect()
 }
 
-fn identifiers(input: &[u8]) -> IResult<&[u8], Vec<String>> {
-  many1(terminated(
-      identifier,
-      opt(delimited(multispace0, tag(","), multispace0)),
-  ))(input)
+/// Parses a script of statements separated by semicolons. Error offsets
+/// count from the start of the script.
+pub fn parse_statements(input: &str) -> Result<Vec<SQLCommand>, Error> {
+  split_statements(input)
+      .into_iter()
+      .map(|(start, statement)| parse_statement(statement).map_err(|error| moved(error, start)))
+      .collect()
 }
 
-fn parse_where_clause(input: &[u8]) -> IResult<&[u8], Option<WhereClause>> {
-  let (remaining_input, maybe_where) = opt(tuple((
-      multispace0,
-      tag_no_case("where"),
-      multispace0,
-      identifier,
-      multispace0,
-      tag("="),
-      multispace0,
-      tag("'"),
-      take_until("'"),
-  )))(input)?;
+/// The error of a statement found `start` bytes into the text.
+fn moved(error: Error, start: usize) -> Error {
+  match error {
+    Error::Parse { message, offset } => Error::Parse {
+        message,
+        offset: start + offset,
+    },
+    error => error,
+  }
+}
 
-  let maybe_where = if let Some((_, _, _, field, _, _, _, _, value)) = maybe_where {
-      let value = String::from_utf8(value.to_vec()).unwrap();
-      Some(WhereClause { field, value })
-  } else {
-      None
-  };
+type CommandParser = fn(&[u8]) -> IResult<&[u8], SQLCommand>;
 
-  Ok((remaining_input, maybe_where))
+/// Parses a statement. When nothing matches, the error points at the
+/// furthest any kind of statement got, which is usually where the mistake is.
+pub fn parse_statement(input: &str) -> Result<SQLCommand, Error> {
+  let text = strip_comments(input);
+  let start = text.len() - text.trim_start().len();
+  parse_command(&text[start..]).map_err(|error| moved(error, start))
 }
 
-pub fn parse_create(input: &[u8]) -> IResult<&[u8], SQLCommand> {
-  alt((
-      map(parse_creation, |c| SQLCommand::CreateTable(c)),
-      map(parse_index_creation, |c| SQLCommand::CreateIndex(c)),
-  ))(input)
-}
+fn parse_command(input: &str) -> Result<SQLCommand, Error> {
+  let parsers: [CommandParser; 17] = [
+      |i| map(parse_creation, SQLCommand::CreateTable)(i),
+      |i| map(parse_virtual_table_creation, SQLCommand::CreateVirtualTable)(i),
+      |i| map(compound_selection, SQLCommand::Select)(i),
+      |i| map(selection, SQLCommand::Select)(i),
+      |i| map(count_selection, SQLCommand::Select)(i),
+      |i| map(min_max_selection, SQLCommand::Select)(i),
+      |i| map(aggregate_selection, SQLCommand::Select)(i),
+      |i| map(parse_index_creation, SQLCommand::CreateIndex)(i),
+      |i| map(parse_view_creation, SQLCommand::CreateView)(i),
+      |i| map(parse_trigger_creation, SQLCommand::CreateTrigger)(i),
+      |i| map(explain_query_plan, SQLCommand::ExplainQueryPlan)(i),
+      |i| map(parse_delete, SQLCommand::Delete)(i),
+      |i| map(parse_drop_table, SQLCommand::DropTable)(i),
+      |i| map(parse_alter_table, SQLCommand::AlterTable)(i),
+      transaction_control,
+      attachment,
+      pragma,
+  ];
 
-pub fn parse_creation(input: &[u8]) -> IResult<&[u8], CreateTableStatement> {
-  let (remaining_input, (_, _, _, _, _, table, _, _, _, fields, _, _, _)) = tuple((
-      tag_no_case("create"),
-      multispace1,
-      tag_no_case("table"),
-      multispace1,
-      opt(tuple((tag_no_case("IF NOT EXISTS"), multispace1))),
-      identifier,
-      multispace0,
-      tag("("),
-      multispace0,
-      field_specification_list,
-      multispace0,
-      tag(")"),
-      opt(tag(";")),
-  ))(input)?;
+  let mut furthest = 0;
+  for parser in parsers {
+    match parser(input.as_bytes()) {
+      Ok((rest, command)) if rest.iter().all(u8::is_ascii_whitespace) => return Ok(command),
+      // Anything left over wasn't understood, which must not be ignored.
+      Ok((rest, _)) => furthest = furthest.max(input.len() - rest.len()),
+      Err(nom::Err::Error(e) | nom::Err::Failure(e)) => {
+        furthest = furthest.max(input.len() - e.input.len());
+      }
+      Err(nom::Err::Incomplete(_)) => furthest = input.len(),
+    }
+  }
 
-  Ok((remaining_input, CreateTableStatement { table, fields }))
+  let rest = String::from_utf8_lossy(&input.as_bytes()[furthest..]);
+  let near = rest.split_whitespace().next();
+  Err(Error::Parse {
+      message: match near {
+        Some(token) => format!("near \"{}\": syntax error", token),
+        None => "incomplete input".to_string(),
+      },
+      offset: furthest,
+  })
 }
 
-pub fn parse_index_creation(input: &[u8]) -> IResult<&[u8], CreateIndexStatement> {
-  let (remaining_input, (_, _, _, _, _, name, _, _, _, table, _, _, _, columns, _, _, _)) =
+fn count_selection(input: &[u8]) -> IResult<&[u8], SelectStatement> {
+  let (remaining_input, (_, _, _, _, _, _, _, _, _, _, _, _, table, where_clause, _)) =
       tuple((
-          tag_no_case("create"),
-          multispace1,
-          tag_no_case("index"),
-          multispace1,
-          opt(tuple((tag_no_case("IF NOT EXISTS"), multispace1))),
-          identifier,
-          multispace1,
-          tag_no_case("ON"),
+          tag_no_case("select"),
           multispace1,
-          identifier,
+          tag_no_case("count"),
           multispace0,
           tag("("),
           multispace0,
-          many1(identifier),
+          tag("*"),
           multispace0,
           tag(")"),
+          multispace1,
+          tag_no_case("from"),
+          multispace1,
+          table_name,
+          parse_where_clause,
           opt(tag(";")),
       ))(input)?;
 
   Ok((
       remaining_input,
-      CreateIndexStatement {
-          name,
+      SelectStatement::Count(CountSelection {
           table,
-          fields: columns,
-      },
+          where_clause,
+      }),
   ))
 }
 
-fn identifier(input: &[u8]) -> IResult<&[u8], String> {
-  let (input, name) = alt((
-      delimited(
-          tag("\""),
-          take_while1(is_sql_identifier_with_space),
-          tag("\""),
-      ),
-      take_while1(is_sql_identifier),
-  ))(input)?;
-  let name = String::from_utf8(name.to_vec()).unwrap();
-
-  Ok((input, name))
-}
-
-fn is_sql_identifier_with_space(chr: u8) -> bool {
-  is_alphanumeric(chr) || chr == b'_' || is_space(chr)
-}
+fn min_max_selection(input: &[u8]) -> IResult<&[u8], SelectStatement> {
+  let (remaining_input, (_, _, function, _, _, _, field, _, _, _, _, _, table, _)) =
+      tuple((
+          tag_no_case("select"),
+          multispace1,
+          alt((
+              map(tag_no_case("min"), |_| MinMax::Min),
+              map(tag_no_case("max"), |_| MinMax::Max),
+          )),
+          multispace0,
+          tag("("),
+          multispace0,
+          identifier,
+          multispace0,
+          tag(")"),
+          multispace1,
+          tag_no_case("from"),
+          multispace1,
+          table_name,
+          opt(tag(";")),
+      ))(input)?;
 
-fn is_sql_identifier(chr: u8) -> bool {
-  is_alphanumeric(chr) || chr == b'_'
+  Ok((
+      remaining_input,
+      SelectStatement::MinMax(MinMaxSelection {
+          function,
+          field,
+          table,
+      }),
+  ))
 }
 
-fn field_specification_list(input: &[u8]) -> IResult<&[u8], Vec<Field>> {
-  many1(field_specification)(input)
-}
+/// A result column: the text of its expression, the expression and its
+/// alias.
+type SelectedColumn<'a> = ((&'a [u8], Expr), Option<String>);
 
-fn column_constraint(input: &[u8]) -> IResult<&[u8], Option<ColumnConstraint>> {
-  let not_null = map(
-      delimited(multispace0, tag_no_case("NOT NULL"), multispace0),
-      |_| None,
+fn selection(input: &[u8]) -> IResult<&[u8], SelectStatement> {
+  let comma = || delimited(multispace0, tag(","), multispace0);
+  // `*` and `table.*` stand for columns of the table until the statement
+  // is bound to a schema.
+  let wildcard = map(
+      tuple((opt(terminated(identifier, tag("."))), tag("*"))),
+      |(table, _)| Expr::Column(table.map_or("*".to_string(), |table| format!("{}.*", table))),
   );
-  let auto_increment = map(
-      delimited(multispace0, tag_no_case("AUTOINCREMENT"), multispace0),
-      |_| None,
+  // Aggregates are left to the grouped selection.
+  let columns = verify(
+      separated_list1(comma(), tuple((consumed(alt((wildcard, expression))), alias))),
+      |columns: &Vec<SelectedColumn>| {
+          columns.iter().flat_map(|((_, expression), _)| expression.columns()).all(|name| {
+              !matches!(parse_result_column(name), ResultColumn::Aggregate(..))
+          })
+      },
   );
-  let primary_key = map(
-      delimited(multispace0, tag_no_case("PRIMARY KEY"), multispace0),
-      |_| Some(ColumnConstraint::PrimaryKey),
+  let (remaining_input, (_, _, columns, _, _, _, table, where_clause, order_by, limit, _)) = tuple((
+      tag_no_case("select"),
+      multispace1,
+      columns,
+      multispace0,
+      tag_no_case("from"),
+      multispace1,
+      table_name,
+      parse_where_clause,
+      parse_order_by_clause,
+      parse_limit_clause,
+      opt(tag(";")),
+  ))(input)?;
+  let (limit, offset) = limit.unwrap_or((None, 0));
+
+  let fields = columns
+      .iter()
+      .map(|((_, expression), alias)| match (expression, alias) {
+          (Expr::Column(name), None) => Some(name.clone()),
+          _ => None,
+      })
+      .collect::<Option<Vec<_>>>();
+  let Some(fields) = fields else {
+      let columns = columns
+          .into_iter()
+          .map(|((text, expression), alias)| SelectColumn {
+              expression,
+              name: alias.unwrap_or_else(|| String::from_utf8_lossy(text).into_owned()),
+          })
+          .collect();
+      return Ok((
+          remaining_input,
+          SelectStatement::Expressions(ExpressionSelection {
+              columns,
+              table,
+              where_clause,
+              order_by,
+              limit,
+              offset,
+          }),
+      ));
+  };
+
+  Ok((
+      remaining_input,
+      SelectStatement::Fields(SelectFields {
+          table,
+          fields,
+          where_clause,
+          order_by,
+          limit,
+          offset,
+      }),
+  ))
+}
+
+/// The ORDER BY, LIMIT and OFFSET of a SELECT, taken out of it.
+fn take_ordering(select: &mut SelectStatement) -> (Vec<OrderingTerm>, Option<u64>, u64) {
+  let (order_by, limit, offset) = match select {
+    SelectStatement::Fields(s) => (&mut s.order_by, &mut s.limit, &mut s.offset),
+    SelectStatement::Expressions(s) => (&mut s.order_by, &mut s.limit, &mut s.offset),
+    SelectStatement::Aggregate(s) => (&mut s.order_by, &mut s.limit, &mut s.offset),
+    _ => return (vec![], None, 0),
+  };
+  (std::mem::take(order_by), limit.take(), std::mem::take(offset))
+}
+
+fn compound_operator(input: &[u8]) -> IResult<&[u8], CompoundOperator> {
+  map(tuple((keyword("union"), opt(keyword("all")))), |(_, all)| match all {
+      Some(_) => CompoundOperator::UnionAll,
+      None => CompoundOperator::Union,
+  })(input)
+}
+
+fn compound_selection(input: &[u8]) -> IResult<&[u8], SelectStatement> {
+  let arm = || alt((selection, count_selection, min_max_selection, aggregate_selection));
+  let (remaining_input, first) = arm()(input)?;
+  // Without a UNION it is a single SELECT, whose own parser reports errors.
+  if compound_operator(remaining_input).is_err() {
+    return Err(nom::Err::Error(nom::error::Error::new(input, nom::error::ErrorKind::Tag)));
+  }
+  let (remaining_input, (rest, order_by, limit, _)) = tuple((
+      many1(tuple((compound_operator, arm()))),
+      parse_order_by_clause,
+      parse_limit_clause,
+      opt(tag(";")),
+  ))(remaining_input)?;
+
+  let (operators, mut arms): (Vec<_>, Vec<_>) = rest.into_iter().unzip();
+  arms.insert(0, first);
+  // The last arm takes the ORDER BY and LIMIT if it can have them, and
+  // no other arm may.
+  let (mut order_by, mut limit) = (order_by, limit.unwrap_or((None, 0)));
+  let last = take_ordering(arms.last_mut().unwrap());
+  if last != (vec![], None, 0) {
+    (order_by, limit) = (last.0, (last.1, last.2));
+  }
+  if arms.iter_mut().any(|arm| take_ordering(arm) != (vec![], None, 0)) {
+    return Err(nom::Err::Error(nom::error::Error::new(input, nom::error::ErrorKind::Verify)));
+  }
+
+  Ok((
+      remaining_input,
+      SelectStatement::Compound(CompoundSelection {
+          arms,
+          operators,
+          order_by,
+          limit: limit.0,
+          offset: limit.1,
+      }),
+  ))
+}
+
+fn aggregate_selection(input: &[u8]) -> IResult<&[u8], SelectStatement> {
+  let comma = || delimited(multispace0, tag(","), multispace0);
+  let (
+      remaining_input,
+      (_, _, columns, _, _, _, table, where_clause, group_by, having, order_by, limit, _),
+  ) = tuple((
+      tag_no_case("select"),
+      multispace1,
+      separated_list1(comma(), tuple((result_column, alias))),
+      multispace0,
+      tag_no_case("from"),
+      multispace1,
+      table_name,
+      parse_where_clause,
+      opt(preceded(
+          tuple((keyword("group"), keyword("by"))),
+          separated_list1(comma(), identifier),
+      )),
+      opt(preceded(keyword("having"), condition)),
+      parse_order_by_clause,
+      parse_limit_clause,
+      opt(tag(";")),
+  ))(input)?;
+  let (limit, offset) = limit.unwrap_or((None, 0));
+
+  Ok((
+      remaining_input,
+      SelectStatement::Aggregate(AggregateSelection {
+          names: columns
+              .iter()
+              .map(|(column, alias)| alias.clone().unwrap_or_else(|| column.name()))
+              .collect(),
+          columns: columns.into_iter().map(|(column, _)| column).collect(),
+          table,
+          where_clause,
+          group_by: group_by.unwrap_or_default(),
+          having,
+          order_by,
+          limit,
+          offset,
+      }),
+  ))
+}
+
+fn aggregate_call(input: &[u8]) -> IResult<&[u8], ResultColumn> {
+  let (remaining_input, (function, _, _, _, argument, _, _)) = tuple((
+      alt((
+          map(tag_no_case("count"), |_| AggregateFunction::Count),
+          map(tag_no_case("min"), |_| AggregateFunction::Min),
+          map(tag_no_case("max"), |_| AggregateFunction::Max),
+          map(tag_no_case("sum"), |_| AggregateFunction::Sum),
+          map(tag_no_case("avg"), |_| AggregateFunction::Avg),
+          map(tag_no_case("total"), |_| AggregateFunction::Total),
+      )),
+      multispace0,
+      tag("("),
+      multispace0,
+      alt((map(tag("*"), |_| None), map(identifier, Some))),
+      multispace0,
+      tag(")"),
+  ))(input)?;
+
+  if argument.is_none() && function != AggregateFunction::Count {
+      return Err(nom::Err::Error(nom::error::Error::new(
+          input,
+          nom::error::ErrorKind::Verify,
+      )));
+  }
+  Ok((remaining_input, ResultColumn::Aggregate(function, argument)))
+}
+
+/// `AS name` after a result column.
+fn alias(input: &[u8]) -> IResult<&[u8], Option<String>> {
+  opt(preceded(keyword("as"), identifier))(input)
+}
+
+fn result_column(input: &[u8]) -> IResult<&[u8], ResultColumn> {
+  alt((aggregate_call, map(identifier, ResultColumn::Column)))(input)
+}
+
+/// Reads back the name of a result column. Anything but an aggregate is
+/// the name of a column, as quoted names are kept without their quotes.
+pub fn parse_result_column(name: &str) -> ResultColumn {
+  match aggregate_call(name.as_bytes()) {
+      Ok(([], column)) => column,
+      _ => ResultColumn::Column(name.to_string()),
+  }
+}
+
+/// A column name, or an aggregate named like `count(*)`.
+fn result_column_name(input: &[u8]) -> IResult<&[u8], String> {
+  map(result_column, |column| column.name())(input)
+}
+
+fn parse_where_clause(input: &[u8]) -> IResult<&[u8], Option<WhereClause>> {
+  opt(preceded(
+      tuple((multispace0, tag_no_case("where"), multispace0)),
+      condition,
+  ))(input)
+}
+
+fn parse_order_by_clause(input: &[u8]) -> IResult<&[u8], Vec<OrderingTerm>> {
+  let term = map(
+      tuple((
+          result_column_name,
+          opt(alt((
+              map(keyword("asc"), |_| false),
+              map(keyword("desc"), |_| true),
+          ))),
+      )),
+      |(column, descending)| OrderingTerm {
+          column,
+          descending: descending.unwrap_or(false),
+      },
+  );
+
+  map(
+      opt(preceded(
+          tuple((keyword("order"), keyword("by"))),
+          separated_list1(delimited(multispace0, tag(","), multispace0), term),
+      )),
+      Option::unwrap_or_default,
+  )(input)
+}
+
+/// `LIMIT count [OFFSET skip]`, or `LIMIT skip, count`.
+fn parse_limit_clause(input: &[u8]) -> IResult<&[u8], Option<(Option<u64>, u64)>> {
+  let number = || {
+      map(
+          tuple((opt(tag("-")), take_while1(|chr: u8| chr.is_ascii_digit()))),
+          |(minus, digits): (Option<&[u8]>, &[u8])| {
+              let value = String::from_utf8_lossy(digits).parse::<u64>().unwrap_or(u64::MAX);
+              (minus.is_none() || value == 0).then_some(value)
+          },
+      )
+  };
+
+  opt(preceded(
+      keyword("limit"),
+      alt((
+          map(
+              tuple((number(), delimited(multispace0, tag(","), multispace0), number())),
+              |(skip, _, count)| (count, skip.unwrap_or(0)),
+          ),
+          map(
+              tuple((number(), opt(preceded(keyword("offset"), number())))),
+              |(count, skip)| (count, skip.flatten().unwrap_or(0)),
+          ),
+      )),
+  ))(input)
+}
+
+/// A keyword standing on its own, not the start of a longer name.
+fn keyword<'a>(word: &'static str) -> impl FnMut(&'a [u8]) -> IResult<&'a [u8], &'a [u8]> {
+  delimited(
+      multispace0,
+      terminated(
+          tag_no_case(word),
+          not(verify(take(1usize), |next: &[u8]| is_sql_identifier(next[0]))),
+      ),
+      multispace0,
+  )
+}
+
+fn condition(input: &[u8]) -> IResult<&[u8], WhereClause> {
+  let (remaining_input, (first, rest)) =
+      tuple((and_condition, many0(preceded(keyword("or"), and_condition))))(input)?;
+
+  let condition = rest.into_iter().fold(first, |left, right| {
+      WhereClause::Or(Box::new(left), Box::new(right))
+  });
+  Ok((remaining_input, condition))
+}
+
+fn and_condition(input: &[u8]) -> IResult<&[u8], WhereClause> {
+  let (remaining_input, (first, rest)) =
+      tuple((primary_condition, many0(preceded(keyword("and"), primary_condition))))(input)?;
+
+  let condition = rest.into_iter().fold(first, |left, right| {
+      WhereClause::And(Box::new(left), Box::new(right))
+  });
+  Ok((remaining_input, condition))
+}
+
+fn primary_condition(input: &[u8]) -> IResult<&[u8], WhereClause> {
+  alt((
+      delimited(
+          tuple((tag("("), multispace0)),
+          condition,
+          tuple((multispace0, tag(")"))),
+      ),
+      map(in_select, WhereClause::InSelect),
+      map(in_list, WhereClause::In),
+      between,
+      comparison,
+  ))(input)
+}
+
+/// `value BETWEEN low AND high`, which is `value >= low AND value <= high`,
+/// and `value NOT BETWEEN low AND high`, which is `value < low OR value > high`.
+fn between(input: &[u8]) -> IResult<&[u8], WhereClause> {
+  let (remaining_input, (value, not, _, low, _, high)) = tuple((
+      expression,
+      opt(keyword("not")),
+      keyword("between"),
+      expression,
+      keyword("and"),
+      expression,
+  ))(input)?;
+
+  let bound = |operator, right| {
+    Box::new(
+        ExpressionComparison {
+            left: value.clone(),
+            operator,
+            right,
+        }
+        .simplify(),
+    )
+  };
+  let condition = match not {
+    None => WhereClause::And(bound(Operator::GreaterEqual, low), bound(Operator::LessEqual, high)),
+    Some(_) => WhereClause::Or(bound(Operator::Less, low), bound(Operator::Greater, high)),
+  };
+  Ok((remaining_input, condition))
+}
+
+/// Any kind of SELECT.
+fn select_statement(input: &[u8]) -> IResult<&[u8], SelectStatement> {
+  alt((
+      compound_selection,
+      selection,
+      count_selection,
+      min_max_selection,
+      aggregate_selection,
+  ))(input)
+}
+
+fn explain_query_plan(input: &[u8]) -> IResult<&[u8], SelectStatement> {
+  preceded(
+      tuple((keyword("explain"), keyword("query"), keyword("plan"))),
+      select_statement,
+  )(input)
+}
+
+fn parse_delete(input: &[u8]) -> IResult<&[u8], DeleteStatement> {
+  let (remaining_input, (_, _, table, where_clause, _)) = tuple((
+      keyword("delete"),
+      keyword("from"),
+      table_name,
+      parse_where_clause,
+      opt(tag(";")),
+  ))(input)?;
+
+  Ok((
+      remaining_input,
+      DeleteStatement {
+          table: table.clone(),
+          selection: SelectStatement::Fields(SelectFields {
+              fields: vec!["rowid".to_string()],
+              table,
+              where_clause,
+              order_by: vec![],
+              limit: None,
+              offset: 0,
+          }),
+      },
+  ))
+}
+
+fn parse_drop_table(input: &[u8]) -> IResult<&[u8], DropTableStatement> {
+  let (remaining_input, (_, _, if_exists, table, _)) = tuple((
+      keyword("drop"),
+      keyword("table"),
+      opt(tuple((keyword("if"), keyword("exists")))),
+      table_name,
+      opt(tag(";")),
+  ))(input)?;
+
+  Ok((
+      remaining_input,
+      DropTableStatement {
+          table,
+          if_exists: if_exists.is_some(),
+      },
+  ))
+}
+
+fn parse_alter_table(input: &[u8]) -> IResult<&[u8], AlterTableStatement> {
+  let (remaining_input, (_, _, table, _, _, (definition, column), _)) = tuple((
+      keyword("alter"),
+      keyword("table"),
+      identifier,
+      keyword("add"),
+      opt(keyword("column")),
+      consumed(field_specification),
+      opt(tag(";")),
+  ))(input)?;
+
+  Ok((
+      remaining_input,
+      AlterTableStatement {
+          table,
+          column,
+          definition: String::from_utf8_lossy(definition)
+              .trim_end()
+              .trim_end_matches(',')
+              .trim_end()
+              .to_string(),
+      },
+  ))
+}
+
+/// BEGIN, COMMIT or ROLLBACK. The kinds of BEGIN only differ in the locks
+/// they take at once, which are the same here.
+fn transaction_control(input: &[u8]) -> IResult<&[u8], SQLCommand> {
+  let begin = map(
+      tuple((
+          keyword("begin"),
+          opt(alt((keyword("deferred"), keyword("immediate"), keyword("exclusive")))),
+      )),
+      |_| SQLCommand::Begin,
+  );
+  let commit = map(alt((keyword("commit"), keyword("end"))), |_| SQLCommand::Commit);
+  let rollback = map(keyword("rollback"), |_| SQLCommand::Rollback);
+  terminated(
+      alt((begin, commit, rollback)),
+      tuple((opt(keyword("transaction")), opt(tag(";")))),
+  )(input)
+}
+
+fn attachment(input: &[u8]) -> IResult<&[u8], SQLCommand> {
+  let attach = map(
+      tuple((keyword("attach"), opt(keyword("database")), string, keyword("as"), identifier)),
+      |(_, _, path, _, name)| SQLCommand::Attach(AttachStatement { path, name }),
+  );
+  let detach = map(
+      tuple((keyword("detach"), opt(keyword("database")), identifier)),
+      |(_, _, name)| SQLCommand::Detach(name),
+  );
+  terminated(alt((attach, detach)), opt(tag(";")))(input)
+}
+
+fn pragma(input: &[u8]) -> IResult<&[u8], SQLCommand> {
+  let value = || {
+    alt((
+        identifier,
+        map(recognize(tuple((opt(alt((tag("-"), tag("+")))), digit1))), |number: &[u8]| {
+          String::from_utf8_lossy(number).into_owned()
+        }),
+    ))
+  };
+  map(
+      tuple((
+          keyword("pragma"),
+          opt(terminated(identifier, tag("."))),
+          identifier,
+          multispace0,
+          opt(alt((
+              preceded(tuple((tag("="), multispace0)), value()),
+              delimited(tuple((tag("("), multispace0)), value(), tuple((multispace0, tag(")")))),
+          ))),
+          multispace0,
+          opt(tag(";")),
+      )),
+      |(_, schema, name, _, argument, _, _)| SQLCommand::Pragma(PragmaStatement { schema, name, argument }),
+  )(input)
+}
+
+fn in_select(input: &[u8]) -> IResult<&[u8], InSelect> {
+  let (remaining_input, (field, _, select, _)) = tuple((
+      result_column_name,
+      tuple((keyword("in"), tag("("), multispace0)),
+      select_statement,
+      tuple((multispace0, tag(")"))),
+  ))(input)?;
+
+  Ok((
+      remaining_input,
+      InSelect {
+          field,
+          select: Box::new(select),
+      },
+  ))
+}
+
+fn in_list(input: &[u8]) -> IResult<&[u8], InList> {
+  let value = alt((map(keyword("null"), |_| None), map(literal, Some)));
+  let (remaining_input, (field, _, values, _)) = tuple((
+      result_column_name,
+      tuple((keyword("in"), tag("("), multispace0)),
+      separated_list0(tuple((multispace0, tag(","), multispace0)), value),
+      tuple((multispace0, tag(")"))),
+  ))(input)?;
+
+  Ok((remaining_input, InList { field, values }))
+}
+
+/// Compares two expressions. A column compared with a constant makes a
+/// plain comparison, which indexes and virtual tables know how to use.
+fn comparison(input: &[u8]) -> IResult<&[u8], WhereClause> {
+  let (remaining_input, (left, _, operator, _, right)) = tuple((
+      expression,
+      multispace0,
+      alt((
+          map(alt((tag("!="), tag("<>"))), |_| Operator::NotEqual),
+          map(tag("<="), |_| Operator::LessEqual),
+          map(tag(">="), |_| Operator::GreaterEqual),
+          map(alt((tag("=="), tag("="))), |_| Operator::Equal),
+          map(tag("<"), |_| Operator::Less),
+          map(tag(">"), |_| Operator::Greater),
+          map(tag_no_case("match"), |_| Operator::Match),
+          map(keyword("like"), |_| Operator::Like),
+      )),
+      multispace0,
+      expression,
+  ))(input)?;
+
+  let comparison = ExpressionComparison {
+      left,
+      operator,
+      right,
+  };
+  Ok((remaining_input, comparison.simplify()))
+}
+
+/// An expression: sums of products of signed operands, as in SQLite.
+fn expression(input: &[u8]) -> IResult<&[u8], Expr> {
+  let operator = alt((
+      map(tag("+"), |_| BinaryOperator::Add),
+      map(tag("-"), |_| BinaryOperator::Subtract),
+  ));
+  let (remaining_input, (first, rest)) = tuple((
+      term,
+      many0(tuple((delimited(multispace0, operator, multispace0), term))),
+  ))(input)?;
+
+  let expression = rest.into_iter().fold(first, |left, (operator, right)| {
+      Expr::Binary(operator, Box::new(left), Box::new(right))
+  });
+  Ok((remaining_input, expression))
+}
+
+fn term(input: &[u8]) -> IResult<&[u8], Expr> {
+  let operator = alt((
+      map(tag("*"), |_| BinaryOperator::Multiply),
+      map(tag("/"), |_| BinaryOperator::Divide),
+      map(tag("%"), |_| BinaryOperator::Remainder),
+  ));
+  let (remaining_input, (first, rest)) = tuple((
+      factor,
+      many0(tuple((delimited(multispace0, operator, multispace0), factor))),
+  ))(input)?;
+
+  let term = rest.into_iter().fold(first, |left, (operator, right)| {
+      Expr::Binary(operator, Box::new(left), Box::new(right))
+  });
+  Ok((remaining_input, term))
+}
+
+/// An operand with any signs in front. Negative numbers are kept as
+/// constants.
+fn factor(input: &[u8]) -> IResult<&[u8], Expr> {
+  alt((
+      map(preceded(tuple((tag("-"), multispace0)), factor), |operand| match operand {
+          Expr::Literal(Literal::Integer(n)) if n != i64::MIN => Expr::Literal(Literal::Integer(-n)),
+          Expr::Literal(Literal::Real(n)) => Expr::Literal(Literal::Real(-n)),
+          operand => Expr::Negate(Box::new(operand)),
+      }),
+      preceded(tuple((tag("+"), multispace0)), factor),
+      primary_expression,
+  ))(input)
+}
+
+fn primary_expression(input: &[u8]) -> IResult<&[u8], Expr> {
+  alt((
+      delimited(
+          tuple((tag("("), multispace0)),
+          expression,
+          tuple((multispace0, tag(")"))),
+      ),
+      map(number, Expr::Literal),
+      map(string, |text| Expr::Literal(Literal::Text(text))),
+      map(blob, |bytes| Expr::Literal(Literal::Blob(bytes))),
+      map(keyword("null"), |_| Expr::Literal(Literal::Null)),
+      parameter,
+      map(aggregate_call, |aggregate| Expr::Column(aggregate.name())),
+      cast,
+      function_call,
+      map(identifier, Expr::Column),
+  ))(input)
+}
+
+/// `X'hex'`, with an even number of hex digits.
+fn blob(input: &[u8]) -> IResult<&[u8], Vec<u8>> {
+  map_opt(
+      preceded(tag_no_case("x"), delimited(tag("'"), take_until("'"), tag("'"))),
+      |hex: &[u8]| {
+        let pairs = hex.chunks_exact(2);
+        if !pairs.remainder().is_empty() {
+          return None;
+        }
+        pairs
+            .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+            .collect()
+      },
+  )(input)
+}
+
+/// `?`, or `?N` for N from 1.
+fn parameter(input: &[u8]) -> IResult<&[u8], Expr> {
+  preceded(
+      tag("?"),
+      alt((
+          map_opt(digit1, |digits: &[u8]| {
+              let n = String::from_utf8_lossy(digits).parse::<usize>().ok()?;
+              (n > 0).then_some(Expr::Parameter(n))
+          }),
+          map(not(verify(take(1usize), |next: &[u8]| is_sql_identifier(next[0]))), |_| {
+              Expr::Parameter(0)
+          }),
+      )),
+  )(input)
+}
+
+/// `CAST(expression AS type)`.
+fn cast(input: &[u8]) -> IResult<&[u8], Expr> {
+  let (remaining_input, (_, _, operand, _, type_name, _)) = tuple((
+      tag_no_case("cast"),
+      tuple((multispace0, tag("("), multispace0)),
+      expression,
+      keyword("as"),
+      type_name,
+      tuple((multispace0, tag(")"))),
+  ))(input)?;
+
+  Ok((remaining_input, Expr::Cast(Box::new(operand), Affinity::of(&type_name))))
+}
+
+/// A type name of one or more words, with an optional size or precision
+/// like `VARCHAR(255)` or `DECIMAL(10, 2)`, kept as written. Words that
+/// start a column constraint end it.
+fn type_name(input: &[u8]) -> IResult<&[u8], String> {
+  let word = || verify(identifier, |word: &String| !is_constraint_keyword(word));
+  let size = || tuple((opt(alt((tag("+"), tag("-")))), number));
+  map(
+      recognize(tuple((
+          word(),
+          many0(preceded(multispace1, word())),
+          opt(tuple((
+              multispace0,
+              tag("("),
+              multispace0,
+              size(),
+              opt(tuple((multispace0, tag(","), multispace0, size()))),
+              multispace0,
+              tag(")"),
+          ))),
+      ))),
+      |type_name: &[u8]| String::from_utf8_lossy(type_name).into_owned(),
+  )(input)
+}
+
+fn function_call(input: &[u8]) -> IResult<&[u8], Expr> {
+  let (remaining_input, (name, _, arguments, _)) = tuple((
+      identifier,
+      tuple((multispace0, tag("("), multispace0)),
+      separated_list0(delimited(multispace0, tag(","), multispace0), expression),
+      tuple((multispace0, tag(")"))),
+  ))(input)?;
+
+  Ok((remaining_input, Expr::Function(name, arguments)))
+}
+
+/// An unsigned number: `42`, `2.5`, `5.`, `.5` or `1e6`. It is a real
+/// when it has a decimal point or an exponent, or doesn't fit in 64 bits.
+fn number(input: &[u8]) -> IResult<&[u8], Literal> {
+  // Hexadecimal integers are 64-bit two's complement, as in SQLite.
+  if let Ok((remaining_input, hex)) = preceded(
+      tag_no_case::<_, _, nom::error::Error<&[u8]>>("0x"),
+      take_while1(|chr: u8| chr.is_ascii_hexdigit()),
+  )(input)
+  {
+    return match u64::from_str_radix(&String::from_utf8_lossy(hex), 16) {
+      Ok(n) => Ok((remaining_input, Literal::Integer(n as i64))),
+      Err(_) => Err(nom::Err::Error(nom::error::Error::new(input, nom::error::ErrorKind::Verify))),
+    };
+  }
+  let digits = || take_while1(|chr: u8| chr.is_ascii_digit());
+  let (remaining_input, text) = recognize(tuple((
+      alt((
+          recognize(tuple((digits(), opt(tuple((tag("."), opt(digits()))))))),
+          recognize(tuple((tag("."), digits()))),
+      )),
+      opt(tuple((tag_no_case("e"), opt(alt((tag("+"), tag("-")))), digits()))),
+  )))(input)?;
+
+  let text = String::from_utf8_lossy(text);
+  match (text.parse::<i64>(), text.parse::<f64>()) {
+      (Ok(n), _) => Ok((remaining_input, Literal::Integer(n))),
+      (_, Ok(n)) => Ok((remaining_input, Literal::Real(n))),
+      _ => Err(nom::Err::Error(nom::error::Error::new(
+          input,
+          nom::error::ErrorKind::Verify,
+      ))),
+  }
+}
+
+/// Whether `text` matches a LIKE pattern. Only ASCII letters match their
+/// other case, as in SQLite.
+pub fn like(pattern: &str, text: &str) -> bool {
+  let pattern = pattern.chars().collect::<Vec<_>>();
+  let text = text.chars().collect::<Vec<_>>();
+  let (mut p, mut t) = (0, 0);
+  // Where to resume after the last `%`: its position in the pattern, and
+  // the text it has swallowed up to.
+  let mut backtrack: Option<(usize, usize)> = None;
+
+  while t < text.len() {
+      match pattern.get(p) {
+          Some('%') => {
+              p += 1;
+              backtrack = Some((p, t));
+          }
+          Some('_') => {
+              p += 1;
+              t += 1;
+          }
+          Some(c) if c.eq_ignore_ascii_case(&text[t]) => {
+              p += 1;
+              t += 1;
+          }
+          _ => match backtrack {
+              Some((after, swallowed)) => {
+                  p = after;
+                  t = swallowed + 1;
+                  backtrack = Some((after, swallowed + 1));
+              }
+              None => return false,
+          },
+      }
+  }
+  pattern[p..].iter().all(|&c| c == '%')
+}
+
+/// A string, or a number, possibly negative, written as its value.
+fn literal(input: &[u8]) -> IResult<&[u8], String> {
+  alt((
+      string,
+      map(tuple((opt(tuple((tag("-"), multispace0))), number)), |(minus, number)| {
+        match minus {
+          Some(_) => format!("-{}", number),
+          None => number.to_string(),
+        }
+      }),
+  ))(input)
+}
+
+pub fn parse_create(input: &[u8]) -> IResult<&[u8], SQLCommand> {
+  alt((
+      map(parse_creation, |c| SQLCommand::CreateTable(c)),
+      map(parse_virtual_table_creation, |c| SQLCommand::CreateVirtualTable(c)),
+      map(parse_index_creation, |c| SQLCommand::CreateIndex(c)),
+      map(parse_view_creation, |c| SQLCommand::CreateView(c)),
+      map(parse_trigger_creation, |c| SQLCommand::CreateTrigger(c)),
+  ))(input)
+}
+
+pub fn parse_creation(input: &[u8]) -> IResult<&[u8], CreateTableStatement> {
+  let (remaining_input, (_, _, temporary, _, _, if_not_exists, table, _, _, _, mut fields, constraints, _, _, without_rowid, _)) =
+      tuple((
+          tag_no_case("create"),
+          multispace1,
+          opt(tuple((alt((tag_no_case("temporary"), tag_no_case("temp"))), multispace1))),
+          tag_no_case("table"),
+          multispace1,
+          opt(tuple((tag_no_case("IF NOT EXISTS"), multispace1))),
+          identifier,
+          multispace0,
+          tag("("),
+          multispace0,
+          field_specification_list,
+          many0(table_constraint),
+          multispace0,
+          tag(")"),
+          opt(tuple((multispace0, tag_no_case("WITHOUT"), multispace1, tag_no_case("ROWID")))),
+          opt(tag(";")),
+      ))(input)?;
+
+  // Constraints on columns come first, then those of the table, each in
+  // the order written. An INTEGER PRIMARY KEY is the rowid instead.
+  let mut autoindexes: Vec<Vec<String>> = vec![];
+  let mut add_autoindex = |columns: &[String]| {
+    let same = |other: &Vec<String>| {
+      other.len() == columns.len() && other.iter().zip(columns).all(|(a, b)| a.eq_ignore_ascii_case(b))
+    };
+    if !autoindexes.iter().any(same) {
+      autoindexes.push(columns.to_vec());
+    }
+  };
+  for field in &fields {
+    if field.in_primary_key && !field.is_primary_key {
+      add_autoindex(std::slice::from_ref(&field.name));
+    }
+    if field.unique {
+      add_autoindex(std::slice::from_ref(&field.name));
+    }
+  }
+  let integer_key = |columns: &[String]| {
+    columns.len() == 1 && fields.iter().any(|field| field.name == columns[0] && is_integer(&field.type_name))
+  };
+
+  let mut primary_key = None;
+  let mut unique = vec![];
+  let mut checks = vec![];
+  let mut foreign_keys = vec![];
+  for constraint in constraints {
+      match constraint {
+          TableConstraint::PrimaryKey(columns) => {
+            if !integer_key(&columns) {
+              add_autoindex(&columns);
+            }
+            primary_key = primary_key.or(Some(columns));
+          }
+          TableConstraint::Unique(columns) => {
+            add_autoindex(&columns);
+            unique.push(columns);
+          }
+          TableConstraint::Check(condition) => checks.push(condition),
+          TableConstraint::ForeignKey(foreign_key) => foreign_keys.push(foreign_key),
+      }
+  }
+
+  let primary_key = match primary_key {
+      Some(columns) => {
+          for field in fields.iter_mut().filter(|field| columns.contains(&field.name)) {
+              field.in_primary_key = true;
+              // PRIMARY KEY(id) on a single INTEGER column makes it the rowid too.
+              field.is_primary_key = columns.len() == 1 && is_integer(&field.type_name);
+          }
+          columns
+      }
+      None => fields
+          .iter()
+          .filter(|field| field.in_primary_key)
+          .map(|field| field.name.clone())
+          .collect(),
+  };
+
+  // A WITHOUT ROWID table has no rowid for its INTEGER PRIMARY KEY to be,
+  // which SQLite only finds out at the end.
+  if without_rowid.is_some() && fields.iter().any(|field| field.is_primary_key) {
+    add_autoindex(&primary_key);
+  }
+
+  Ok((
+      remaining_input,
+      CreateTableStatement {
+          primary_key,
+          table,
+          fields,
+          unique,
+          checks,
+          foreign_keys,
+          without_rowid: without_rowid.is_some(),
+          if_not_exists: if_not_exists.is_some(),
+          temporary: temporary.is_some(),
+          autoindexes,
+      },
+  ))
+}
+
+pub fn parse_virtual_table_creation(input: &[u8]) -> IResult<&[u8], CreateVirtualTableStatement> {
+  let (remaining_input, (_, _, _, _, _, _, _, table, _, _, _, module, _, arguments, _)) =
+      tuple((
+          tag_no_case("create"),
+          multispace1,
+          tag_no_case("virtual"),
+          multispace1,
+          tag_no_case("table"),
+          multispace1,
+          opt(tuple((tag_no_case("IF NOT EXISTS"), multispace1))),
+          identifier,
+          multispace1,
+          tag_no_case("using"),
+          multispace1,
+          identifier,
+          multispace0,
+          opt(delimited(tag("("), module_arguments, tag(")"))),
+          opt(tag(";")),
+      ))(input)?;
+
+  Ok((
+      remaining_input,
+      CreateVirtualTableStatement {
+          table,
+          module,
+          arguments: arguments.unwrap_or_default(),
+      },
+  ))
+}
+
+/// Splits the arguments of `USING module(...)` on top-level commas. Arguments
+/// are kept verbatim since their meaning depends on the module.
+fn module_arguments(input: &[u8]) -> IResult<&[u8], Vec<String>> {
+  let mut arguments = vec![];
+  let mut depth = 0;
+  let mut quote = None;
+  let mut start = 0;
+
+  for (i, chr) in input.iter().enumerate() {
+      match (quote, *chr) {
+          (Some(q), chr) if chr == q => quote = None,
+          (Some(_), _) => {}
+          (None, b'\'' | b'"' | b'`') => quote = Some(*chr),
+          (None, b'(') => depth += 1,
+          (None, b')') if depth > 0 => depth -= 1,
+          (None, b')') => {
+              arguments.push(&input[start..i]);
+              let arguments = arguments
+                  .into_iter()
+                  .map(|argument| String::from_utf8_lossy(argument).trim().to_string())
+                  .filter(|argument| !argument.is_empty())
+                  .collect();
+              return Ok((&input[i..], arguments));
+          }
+          (None, b',') if depth == 0 => {
+              arguments.push(&input[start..i]);
+              start = i + 1;
+          }
+          _ => {}
+      }
+  }
+
+  Err(nom::Err::Error(nom::error::Error::new(
+      input,
+      nom::error::ErrorKind::TakeUntil,
+  )))
+}
+
+fn table_constraint(input: &[u8]) -> IResult<&[u8], TableConstraint> {
+  delimited(
+      opt(constraint_name),
+      alt((
+          map(
+              delimited(
+                  tuple((keyword("primary"), keyword("key"))),
+                  column_list,
+                  opt(conflict_clause),
+              ),
+              TableConstraint::PrimaryKey,
+          ),
+          map(
+              delimited(keyword("unique"), column_list, opt(conflict_clause)),
+              TableConstraint::Unique,
+          ),
+          map(check, TableConstraint::Check),
+          map(
+              tuple((
+                  preceded(tuple((keyword("foreign"), keyword("key"))), column_list),
+                  preceded(multispace0, foreign_key_clause),
+              )),
+              |(columns, (table, references))| {
+                TableConstraint::ForeignKey(ForeignKey {
+                    columns,
+                    table,
+                    references,
+                })
+              },
+          ),
+      )),
+      opt(delimited(multispace0, tag(","), multispace0)),
+  )(input)
+}
+
+/// `CONSTRAINT name`, which may come before any constraint. The name isn't
+/// kept.
+fn constraint_name(input: &[u8]) -> IResult<&[u8], &[u8]> {
+  recognize(tuple((keyword("constraint"), identifier, multispace0)))(input)
+}
+
+/// `ON CONFLICT` and what to do then, which only matters to writes.
+fn conflict_clause(input: &[u8]) -> IResult<&[u8], &[u8]> {
+  recognize(tuple((
+      keyword("on"),
+      keyword("conflict"),
+      alt((
+          keyword("rollback"),
+          keyword("abort"),
+          keyword("fail"),
+          keyword("ignore"),
+          keyword("replace"),
+      )),
+  )))(input)
+}
+
+/// `COLLATE name`, naming one of the built-in collations.
+fn collate(input: &[u8]) -> IResult<&[u8], Collation> {
+  map_opt(preceded(keyword("collate"), identifier), |name| {
+    Collation::from_name(&name)
+  })(input)
+}
+
+/// A column of an index with its collation and whether it is in
+/// descending order.
+type IndexedColumn = (String, Option<Collation>, bool);
+
+/// `(a, b COLLATE NOCASE DESC)`, the columns of an index with their
+/// collations and whether they are in descending order.
+fn indexed_columns(input: &[u8]) -> IResult<&[u8], Vec<IndexedColumn>> {
+  delimited(
+      tuple((multispace0, tag("("), multispace0)),
+      separated_list1(
+          delimited(multispace0, tag(","), multispace0),
+          tuple((
+              identifier,
+              opt(collate),
+              map(opt(alt((keyword("asc"), keyword("desc")))), |order| {
+                order.is_some_and(|order| order.eq_ignore_ascii_case(b"desc"))
+              }),
+          )),
+      ),
+      tuple((multispace0, tag(")"))),
+  )(input)
+}
+
+/// `(a, b DESC)`, the names of the columns of a PRIMARY KEY or UNIQUE
+/// constraint.
+fn column_list(input: &[u8]) -> IResult<&[u8], Vec<String>> {
+  map(indexed_columns, |columns| {
+    columns.into_iter().map(|(name, _, _)| name).collect()
+  })(input)
+}
+
+/// `REFERENCES table(columns)`, with the columns optional. ON DELETE, ON
+/// UPDATE, MATCH and DEFERRABLE clauses after it are accepted and ignored.
+fn foreign_key_clause(input: &[u8]) -> IResult<&[u8], (String, Vec<String>)> {
+  let action = alt((
+      recognize(tuple((keyword("set"), alt((keyword("null"), keyword("default")))))),
+      keyword("cascade"),
+      keyword("restrict"),
+      recognize(tuple((keyword("no"), keyword("action")))),
+  ));
+  let clause = alt((
+      recognize(tuple((
+          keyword("on"),
+          alt((keyword("delete"), keyword("update"))),
+          action,
+      ))),
+      recognize(tuple((keyword("match"), identifier))),
+      recognize(tuple((
+          opt(keyword("not")),
+          keyword("deferrable"),
+          opt(tuple((
+              keyword("initially"),
+              alt((keyword("deferred"), keyword("immediate"))),
+          ))),
+      ))),
+  ));
+  map(
+      tuple((
+          keyword("references"),
+          identifier,
+          opt(column_list),
+          many0(clause),
+      )),
+      |(_, table, columns, _)| (table, columns.unwrap_or_default()),
+  )(input)
+}
+
+/// `CHECK (condition)`.
+fn check(input: &[u8]) -> IResult<&[u8], WhereClause> {
+  preceded(
+      keyword("check"),
+      delimited(
+          tuple((tag("("), multispace0)),
+          condition,
+          tuple((multispace0, tag(")"))),
+      ),
+  )(input)
+}
+
+pub fn parse_view_creation(input: &[u8]) -> IResult<&[u8], CreateViewStatement> {
+  let (remaining_input, (_, _, _, _, name, columns, _, select)) = tuple((
+      keyword("create"),
+      opt(alt((keyword("temporary"), keyword("temp")))),
+      keyword("view"),
+      opt(tuple((keyword("if"), keyword("not"), keyword("exists")))),
+      identifier,
+      opt(column_list),
+      keyword("as"),
+      select_statement,
+  ))(input)?;
+
+  Ok((
+      remaining_input,
+      CreateViewStatement {
+          name,
+          columns: columns.unwrap_or_default(),
+          select,
+      },
+  ))
+}
+
+/// The head of a CREATE TRIGGER, up to the table. The statements it runs
+/// are kept as written.
+pub fn parse_trigger_creation(input: &[u8]) -> IResult<&[u8], CreateTriggerStatement> {
+  let timing = alt((
+      map(keyword("before"), |_| TriggerTiming::Before),
+      map(keyword("after"), |_| TriggerTiming::After),
+      map(tuple((keyword("instead"), keyword("of"))), |_| TriggerTiming::InsteadOf),
+  ));
+  let event = alt((
+      map(keyword("delete"), |_| TriggerEvent::Delete),
+      map(keyword("insert"), |_| TriggerEvent::Insert),
+      map(
+          preceded(
+              keyword("update"),
+              opt(preceded(
+                  keyword("of"),
+                  separated_list1(delimited(multispace0, tag(","), multispace0), identifier),
+              )),
+          ),
+          |columns| TriggerEvent::Update(columns.unwrap_or_default()),
+      ),
+  ));
+  let (remaining_input, (_, _, _, _, name, timing, event, _, table, body)) = tuple((
+      keyword("create"),
+      opt(alt((keyword("temporary"), keyword("temp")))),
+      keyword("trigger"),
+      opt(tuple((keyword("if"), keyword("not"), keyword("exists")))),
+      identifier,
+      opt(timing),
+      event,
+      keyword("on"),
+      identifier,
+      rest,
+  ))(input)?;
+
+  Ok((
+      remaining_input,
+      CreateTriggerStatement {
+          name,
+          table,
+          // Without a time, triggers run before the change.
+          timing: timing.unwrap_or(TriggerTiming::Before),
+          event,
+          body: String::from_utf8_lossy(body).trim().to_string(),
+      },
+  ))
+}
+
+pub fn parse_index_creation(input: &[u8]) -> IResult<&[u8], CreateIndexStatement> {
+  let (remaining_input, (_, _, _, _, if_not_exists, name, _, _, _, table, columns, _)) =
+      tuple((
+          tag_no_case("create"),
+          multispace1,
+          tag_no_case("index"),
+          multispace1,
+          opt(tuple((tag_no_case("IF NOT EXISTS"), multispace1))),
+          identifier,
+          multispace1,
+          tag_no_case("ON"),
+          multispace1,
+          identifier,
+          indexed_columns,
+          opt(tag(";")),
+      ))(input)?;
+
+  let mut fields = vec![];
+  let mut collations = vec![];
+  let mut descending = vec![];
+  for (field, collation, desc) in columns {
+    fields.push(field);
+    collations.push(collation);
+    descending.push(desc);
+  }
+  Ok((
+      remaining_input,
+      CreateIndexStatement {
+          name,
+          table,
+          fields,
+          collations,
+          descending,
+          if_not_exists: if_not_exists.is_some(),
+      },
+  ))
+}
+
+/// A name, bare or quoted in any of SQLite's ways: `"name"`, `` `name` ``,
+/// `[name]`, or `'name'` where a string can't go. Quoted names may hold
+/// spaces and keywords, and a doubled quote stands for one.
+fn identifier(input: &[u8]) -> IResult<&[u8], String> {
+  let quoted = alt((quoted(b'"', b'"'), quoted(b'`', b'`'), quoted(b'[', b']'), string));
+  alt((
+      verify(quoted, |name: &String| !name.is_empty()),
+      map(take_while1(is_sql_identifier), |name: &[u8]| {
+        String::from_utf8_lossy(name).into_owned()
+      }),
+  ))(input)
+}
+
+/// The name of a table, which may be qualified by the schema name of its
+/// database, like `aux.t`. The two are kept together as written, like the
+/// table of a `table.column` name.
+fn table_name(input: &[u8]) -> IResult<&[u8], String> {
+  map(tuple((identifier, opt(preceded(tag("."), identifier)))), |(first, second)| match second {
+      Some(name) => format!("{}.{}", first, name),
+      None => first,
+  })(input)
+}
+
+/// A string literal, where `''` stands for a quote.
+fn string(input: &[u8]) -> IResult<&[u8], String> {
+  quoted(b'\'', b'\'')(input)
+}
+
+/// Text between `open` and `close`, where a doubled `close` stands for one
+/// unless it is a bracket.
+fn quoted(open: u8, close: u8) -> impl Fn(&[u8]) -> IResult<&[u8], String> {
+  move |input: &[u8]| {
+    let error = || nom::Err::Error(nom::error::Error::new(input, nom::error::ErrorKind::Tag));
+    if input.first() != Some(&open) {
+      return Err(error());
+    }
+
+    let mut name = vec![];
+    let mut i = 1;
+    loop {
+      match (input.get(i), input.get(i + 1)) {
+        // Brackets can't be escaped.
+        (Some(&c), Some(&next)) if c == close && next == close && open != b'[' => {
+          name.push(c);
+          i += 2;
+        }
+        (Some(&c), _) if c == close => break,
+        (Some(&c), _) => {
+          name.push(c);
+          i += 1;
+        }
+        (None, _) => return Err(error()),
+      }
+    }
+    Ok((&input[i + 1..], String::from_utf8_lossy(&name).into_owned()))
+  }
+}
+
+fn is_sql_identifier(chr: u8) -> bool {
+  is_alphanumeric(chr) || chr == b'_'
+}
+
+/// Words that start a column or table constraint rather than a name or type.
+fn is_constraint_keyword(word: &str) -> bool {
+  matches!(
+      word.to_ascii_lowercase().as_str(),
+      "primary"
+          | "not"
+          | "null"
+          | "unique"
+          | "check"
+          | "default"
+          | "references"
+          | "collate"
+          | "constraint"
+          | "generated"
+          | "as"
+          | "autoincrement"
+  )
+}
+
+fn field_specification_list(input: &[u8]) -> IResult<&[u8], Vec<Field>> {
+  many1(field_specification)(input)
+}
+
+fn column_constraint(input: &[u8]) -> IResult<&[u8], Option<ColumnConstraint>> {
+  let not_null = map(
+      tuple((keyword("not"), keyword("null"), opt(conflict_clause))),
+      |_| Some(ColumnConstraint::NotNull),
+  );
+  let null = map(keyword("null"), |_| None);
+  // A literal, a signed number, a parenthesized expression or a name like
+  // CURRENT_TIMESTAMP.
+  let default = map(
+      delimited(keyword("default"), consumed(factor), multispace0),
+      |(text, value)| Some(ColumnConstraint::Default(value, String::from_utf8_lossy(text).into_owned())),
+  );
+  let auto_increment = map(
+      delimited(multispace0, tag_no_case("AUTOINCREMENT"), multispace0),
+      |_| Some(ColumnConstraint::AutoIncrement),
+  );
+  let primary_key = map(
+      tuple((
+          delimited(multispace0, tag_no_case("PRIMARY KEY"), multispace0),
+          opt(alt((keyword("asc"), keyword("desc")))),
+          opt(conflict_clause),
+      )),
+      |_| Some(ColumnConstraint::PrimaryKey),
+  );
+  let unique = map(
+      tuple((keyword("unique"), opt(conflict_clause))),
+      |_| Some(ColumnConstraint::Unique),
+  );
+  let check = map(terminated(check, multispace0), |condition| {
+      Some(ColumnConstraint::Check(condition))
+  });
+  let references = map(terminated(foreign_key_clause, multispace0), |(table, columns)| {
+      Some(ColumnConstraint::References(table, columns))
+  });
+  let generated = map(
+      tuple((
+          opt(tuple((keyword("generated"), keyword("always")))),
+          keyword("as"),
+          delimited(
+              tuple((tag("("), multispace0)),
+              expression,
+              tuple((multispace0, tag(")"))),
+          ),
+          opt(alt((keyword("virtual"), keyword("stored")))),
+          multispace0,
+      )),
+      |(_, _, expression, storage, _)| {
+        Some(ColumnConstraint::Generated(Generated {
+            expression,
+            stored: storage.is_some_and(|storage| storage.eq_ignore_ascii_case(b"stored")),
+        }))
+      },
   );
 
-  alt((not_null, auto_increment, primary_key))(input)
-}
+  let collate = map(terminated(collate, multispace0), |collation| {
+      Some(ColumnConstraint::Collate(collation))
+  });
+
+  preceded(
+      opt(constraint_name),
+      alt((
+          not_null,
+          null,
+          default,
+          auto_increment,
+          primary_key,
+          unique,
+          check,
+          references,
+          generated,
+          collate,
+      )),
+  )(input)
+}
+
+/// Only a column declared exactly INTEGER PRIMARY KEY is the rowid.
+fn is_integer(type_name: &Option<String>) -> bool {
+  type_name
+      .as_ref()
+      .map(|ty| ty.eq_ignore_ascii_case("integer"))
+      .unwrap_or(false)
+}
+
+fn field_specification(input: &[u8]) -> IResult<&[u8], Field> {
+  let (remaining_input, (column, type_name, constraints, _)) = tuple((
+      preceded(
+          not(alt((
+              recognize(tuple((tag_no_case("PRIMARY"), multispace1, tag_no_case("KEY")))),
+              recognize(keyword("unique")),
+              recognize(keyword("check")),
+              recognize(tuple((keyword("foreign"), keyword("key")))),
+              recognize(keyword("constraint")),
+          ))),
+          identifier,
+      ),
+      opt(delimited(multispace0, type_name, multispace0)),
+      many0(column_constraint),
+      opt(delimited(multispace0, tag(","), multispace0)),
+  ))(input)?;
+
+  let mut field = Field {
+      type_name,
+      ..Field::new(column)
+  };
+  for constraint in constraints.into_iter().flatten() {
+      match constraint {
+          ColumnConstraint::PrimaryKey => field.in_primary_key = true,
+          ColumnConstraint::AutoIncrement => field.autoincrement = true,
+          ColumnConstraint::NotNull => field.nullable = false,
+          ColumnConstraint::Default(value, text) => {
+            field.default_value = Some(value);
+            field.default_text = Some(text);
+          }
+          ColumnConstraint::Unique => field.unique = true,
+          ColumnConstraint::Check(condition) => field.checks.push(condition),
+          ColumnConstraint::Generated(generated) => field.generated = Some(generated),
+          ColumnConstraint::Collate(collation) => field.collation = collation,
+          ColumnConstraint::References(table, references) => {
+              field.foreign_key = Some(ForeignKey {
+                  columns: vec![field.name.clone()],
+                  table,
+                  references,
+              })
+          }
+      }
+  }
+  field.is_primary_key = field.in_primary_key && is_integer(&field.type_name);
+
+  Ok((remaining_input, field))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn typed_field(name: &str, type_name: &str) -> Field {
+      Field {
+          type_name: Some(type_name.to_string()),
+          ..Field::new(name.to_string())
+      }
+  }
+
+  #[test]
+  fn parse_select_with_one_field() {
+      let input = b"SELECT id FROM test";
+      let (_, result) = parse(input).unwrap();
+
+      assert_eq!(
+          result,
+          SQLCommand::Select(SelectStatement::Fields(SelectFields {
+              table: "test".to_string(),
+              fields: vec!["id".to_string()],
+              where_clause: None,
+              order_by: vec![],
+              limit: None,
+              offset: 0,
+          }))
+      );
+  }
+
+  #[test]
+  fn parse_select_with_two_fields() {
+      let input = b"SELECT id, name FROM test";
+      let (_, result) = parse(input).unwrap();
+
+      assert_eq!(
+          result,
+          SQLCommand::Select(SelectStatement::Fields(SelectFields {
+              table: "test".to_string(),
+              fields: vec!["id".to_string(), "name".to_string()],
+              where_clause: None,
+              order_by: vec![],
+              limit: None,
+              offset: 0,
+          }))
+      );
+  }
+
+  #[test]
+  fn parse_select_with_where() {
+      let input = b"SELECT id, name FROM test WHERE super_name = 'test string'";
+      let (_, result) = parse(input).unwrap();
+
+      assert_eq!(
+          result,
+          SQLCommand::Select(SelectStatement::Fields(SelectFields {
+              table: "test".to_string(),
+              fields: vec!["id".to_string(), "name".to_string()],
+              where_clause: Some(WhereClause::Comparison(Comparison {
+                  field: "super_name".to_string(),
+                  operator: Operator::Equal,
+                  value: "test string".to_string()
+              })),
+              order_by: vec![],
+              limit: None,
+              offset: 0,
+          }))
+      );
+  }
+
+  #[test]
+  fn like_patterns() {
+      assert!(like("App%", "apple"));
+      assert!(like("%PLE", "Apple"));
+      assert!(like("a_p%e", "apple"));
+      assert!(like("%", ""));
+      assert!(like("%p%p%", "apple"));
+      assert!(like("_é_", "cés"));
+      assert!(!like("App_", "apple"));
+      assert!(!like("_", ""));
+      assert!(!like("%x%", "apple"));
+      assert!(!like("É", "é"));
+
+      let (_, result) = parse(b"SELECT name FROM apples WHERE name like 'App%'").unwrap();
+      let SQLCommand::Select(SelectStatement::Fields(select)) = result else {
+          panic!("Expected a select");
+      };
+      assert_eq!(
+          select.where_clause,
+          Some(WhereClause::Comparison(Comparison {
+              field: "name".to_string(),
+              operator: Operator::Like,
+              value: "App%".to_string(),
+          }))
+      );
+  }
+
+  #[test]
+  fn parse_select_with_expressions() {
+      let (_, result) =
+          parse(b"SELECT name, price * -2 + 1 FROM apples WHERE (size + 1) * 2 > 3 and id = -4").unwrap();
+      let SQLCommand::Select(SelectStatement::Expressions(select)) = result else {
+          panic!("Expected a select with expressions");
+      };
+      let column = |name: &str| Box::new(Expr::Column(name.to_string()));
+      let integer = |n| Box::new(Expr::Literal(Literal::Integer(n)));
+
+      assert_eq!(
+          select.columns,
+          vec![
+              SelectColumn {
+                  expression: Expr::Column("name".to_string()),
+                  name: "name".to_string(),
+              },
+              SelectColumn {
+                  expression: Expr::Binary(
+                      BinaryOperator::Add,
+                      Box::new(Expr::Binary(BinaryOperator::Multiply, column("price"), integer(-2))),
+                      integer(1),
+                  ),
+                  name: "price * -2 + 1".to_string(),
+              },
+          ]
+      );
+      assert_eq!(
+          select.where_clause,
+          Some(WhereClause::And(
+              Box::new(WhereClause::Expression(ExpressionComparison {
+                  left: Expr::Binary(
+                      BinaryOperator::Multiply,
+                      Box::new(Expr::Binary(BinaryOperator::Add, column("size"), integer(1))),
+                      integer(2),
+                  ),
+                  operator: Operator::Greater,
+                  right: Expr::Literal(Literal::Integer(3)),
+              })),
+              Box::new(WhereClause::Comparison(Comparison {
+                  field: "id".to_string(),
+                  operator: Operator::Equal,
+                  value: "-4".to_string(),
+              })),
+          ))
+      );
+
+      // Plain columns keep to the simpler statement, and aggregates to the
+      // grouped one.
+      let (_, result) = parse(b"SELECT name, color FROM apples").unwrap();
+      assert!(matches!(result, SQLCommand::Select(SelectStatement::Fields(_))));
+      let (_, result) = parse(b"SELECT name AS label, price * 2 as doubled FROM apples").unwrap();
+      let SQLCommand::Select(SelectStatement::Expressions(select)) = result else {
+          panic!("Expected a select with expressions");
+      };
+      let names = select.columns.iter().map(|column| column.name.as_str()).collect::<Vec<_>>();
+      assert_eq!(names, vec!["label", "doubled"]);
+
+      let (_, result) = parse(b"SELECT upper(name), substr(name, 2, -1) FROM apples").unwrap();
+      let SQLCommand::Select(SelectStatement::Expressions(select)) = result else {
+          panic!("Expected a select with expressions");
+      };
+      let (_, result) = parse(b"SELECT CAST(price * 2 AS double precision) FROM apples").unwrap();
+      let SQLCommand::Select(SelectStatement::Expressions(cast)) = result else {
+          panic!("Expected a select with expressions");
+      };
+      assert_eq!(
+          cast.columns[0].expression,
+          Expr::Cast(
+              Box::new(Expr::Binary(BinaryOperator::Multiply, column("price"), integer(2))),
+              Affinity::Real,
+          )
+      );
+      assert_eq!(
+          select.columns[1].expression,
+          Expr::Function(
+              "substr".to_string(),
+              vec![*column("name"), Expr::Literal(Literal::Integer(2)), Expr::Literal(Literal::Integer(-1))],
+          )
+      );
+
+      let (_, result) = parse(b"SELECT *, apples.* FROM apples").unwrap();
+      let SQLCommand::Select(SelectStatement::Fields(select)) = result else {
+          panic!("Expected a select");
+      };
+      assert_eq!(select.fields, vec!["*", "apples.*"]);
+      let (_, result) = parse(b"SELECT max(price), name FROM apples").unwrap();
+      assert!(!matches!(result, SQLCommand::Select(SelectStatement::Expressions(_))));
+
+      let (_, result) = parse(b"SELECT name FROM apples WHERE size > ?2 * ?").unwrap();
+      let SQLCommand::Select(SelectStatement::Fields(select)) = result else {
+          panic!("Expected a select");
+      };
+      assert_eq!(
+          select.where_clause.unwrap().expressions()[1],
+          &Expr::Binary(
+              BinaryOperator::Multiply,
+              Box::new(Expr::Parameter(2)),
+              Box::new(Expr::Parameter(0)),
+          )
+      );
+      assert!(parse_statement("SELECT name FROM apples WHERE size > ?0").is_err());
+  }
+
+  #[test]
+  fn parse_comparison_operators() {
+      let operators = [
+          ("=", Operator::Equal),
+          ("==", Operator::Equal),
+          ("!=", Operator::NotEqual),
+          ("<>", Operator::NotEqual),
+          ("<", Operator::Less),
+          ("<=", Operator::LessEqual),
+          (">", Operator::Greater),
+          (">=", Operator::GreaterEqual),
+      ];
+      for (text, operator) in operators {
+          let input = format!("SELECT name FROM apples WHERE size {} 3.5", text);
+          let (_, result) = parse(input.as_bytes()).unwrap();
+          let SQLCommand::Select(SelectStatement::Fields(select)) = result else {
+              panic!("Expected a select");
+          };
+          assert_eq!(
+              select.where_clause,
+              Some(WhereClause::Comparison(Comparison {
+                  field: "size".to_string(),
+                  operator,
+                  value: "3.5".to_string(),
+              }))
+          );
+      }
+  }
+
+  #[test]
+  fn parse_where_with_in() {
+      let (_, result) =
+          parse(b"SELECT name FROM apples WHERE id IN (1, 2,3) or color in ('Red', NULL)").unwrap();
+      let SQLCommand::Select(SelectStatement::Fields(select)) = result else {
+          panic!("Expected a select");
+      };
+      let values = |values: &[Option<&str>]| {
+          values.iter().map(|value| value.map(str::to_string)).collect::<Vec<_>>()
+      };
+      assert_eq!(
+          select.where_clause,
+          Some(WhereClause::Or(
+              Box::new(WhereClause::In(InList {
+                  field: "id".to_string(),
+                  values: values(&[Some("1"), Some("2"), Some("3")]),
+              })),
+              Box::new(WhereClause::In(InList {
+                  field: "color".to_string(),
+                  values: values(&[Some("Red"), None]),
+              })),
+          ))
+      );
+
+      let (_, result) =
+          parse(b"SELECT name FROM apples WHERE id IN (SELECT apple_id FROM orders WHERE size > 2)").unwrap();
+      let SQLCommand::Select(SelectStatement::Fields(select)) = result else {
+          panic!("Expected a select");
+      };
+      let Some(WhereClause::InSelect(subquery)) = select.where_clause else {
+          panic!("Expected IN with a subquery");
+      };
+      assert_eq!(subquery.field, "id");
+      let SelectStatement::Fields(inner) = *subquery.select else {
+          panic!("Expected a select");
+      };
+      assert_eq!(inner.fields, vec!["apple_id"]);
+      assert!(inner.where_clause.is_some());
+
+      let (_, result) = parse(b"SELECT name FROM apples WHERE id in ()").unwrap();
+      let SQLCommand::Select(SelectStatement::Fields(select)) = result else {
+          panic!("Expected a select");
+      };
+      assert_eq!(select.where_clause.unwrap().columns(), vec!["id"]);
+  }
+
+  #[test]
+  fn parse_where_with_and_or() {
+      let equal = |field: &str, value: &str| {
+          Box::new(WhereClause::Comparison(Comparison {
+              field: field.to_string(),
+              operator: Operator::Equal,
+              value: value.to_string(),
+          }))
+      };
+
+      let input = b"SELECT name FROM apples WHERE color = 'Red' AND size = 3 OR origin = 'Chile'";
+      let (_, result) = parse(input).unwrap();
+      let SQLCommand::Select(SelectStatement::Fields(select)) = result else {
+          panic!("Expected a select");
+      };
+      assert_eq!(
+          select.where_clause,
+          Some(WhereClause::Or(
+              Box::new(WhereClause::And(equal("color", "Red"), equal("size", "3"))),
+              equal("origin", "Chile"),
+          ))
+      );
+
+      let input = b"SELECT name FROM apples WHERE color = 'Red' and (size = 3 or origin = 'Chile')";
+      let (_, result) = parse(input).unwrap();
+      let SQLCommand::Select(SelectStatement::Fields(select)) = result else {
+          panic!("Expected a select");
+      };
+      assert_eq!(
+          select.where_clause,
+          Some(WhereClause::And(
+              equal("color", "Red"),
+              Box::new(WhereClause::Or(equal("size", "3"), equal("origin", "Chile"))),
+          ))
+      );
+  }
+
+  #[test]
+  fn parse_where_with_between() {
+      let compare = |operator: Operator, value: &str| {
+          Box::new(WhereClause::Comparison(Comparison {
+              field: "price".to_string(),
+              operator,
+              value: value.to_string(),
+          }))
+      };
+      let where_clause = |input: &[u8]| {
+          let (_, result) = parse(input).unwrap();
+          let SQLCommand::Select(SelectStatement::Fields(select)) = result else {
+              panic!("Expected a select");
+          };
+          select.where_clause.unwrap()
+      };
+
+      let between = where_clause(b"SELECT name FROM products WHERE price BETWEEN 10 AND 20.5 AND id = 1");
+      let WhereClause::And(range, _) = &between else {
+          panic!("Expected AND");
+      };
+      assert_eq!(
+          **range,
+          WhereClause::And(compare(Operator::GreaterEqual, "10"), compare(Operator::LessEqual, "20.5"))
+      );
+      assert_eq!(between.conjuncts().len(), 3);
+
+      let outside = where_clause(b"SELECT name FROM products WHERE price NOT BETWEEN 10 AND 20");
+      assert_eq!(
+          outside,
+          WhereClause::Or(compare(Operator::Less, "10"), compare(Operator::Greater, "20"))
+      );
+      assert!(outside.conjuncts().is_empty());
+  }
+
+  #[test]
+  fn parse_select_with_order_by() {
+      let input = b"SELECT name, price FROM products ORDER BY price DESC, name ASC";
+      let (_, result) = parse(input).unwrap();
+      let SQLCommand::Select(SelectStatement::Fields(select)) = result else {
+          panic!("Expected a select");
+      };
+      assert_eq!(
+          select.order_by,
+          vec![
+              OrderingTerm {
+                  column: "price".to_string(),
+                  descending: true
+              },
+              OrderingTerm {
+                  column: "name".to_string(),
+                  descending: false
+              },
+          ]
+      );
+  }
+
+  #[test]
+  fn parse_select_with_limit() {
+      let limit = |input: &str| match parse_statement(input) {
+          Ok(SQLCommand::Select(SelectStatement::Fields(select))) => (select.limit, select.offset),
+          result => panic!("unexpected result: {:?}", result),
+      };
+
+      assert_eq!(limit("SELECT name FROM apples"), (None, 0));
+      assert_eq!(limit("SELECT name FROM apples LIMIT 10"), (Some(10), 0));
+      assert_eq!(limit("SELECT name FROM apples ORDER BY name LIMIT 10 OFFSET 5"), (Some(10), 5));
+      assert_eq!(limit("SELECT name FROM apples LIMIT 5, 10"), (Some(10), 5));
+      assert_eq!(limit("SELECT name FROM apples LIMIT -1 OFFSET 2"), (None, 2));
+  }
+
+  #[test]
+  fn parse_select_with_group_by() {
+      let input = "SELECT color, COUNT(*) AS total FROM apples GROUP BY color HAVING COUNT(*) > 2";
+      let Ok(SQLCommand::Select(SelectStatement::Aggregate(select))) = parse_statement(input) else {
+          panic!("Expected an aggregate select");
+      };
+
+      assert_eq!(
+          select.columns,
+          vec![
+              ResultColumn::Column("color".to_string()),
+              ResultColumn::Aggregate(AggregateFunction::Count, None),
+          ]
+      );
+      assert_eq!(select.names, vec!["color".to_string(), "total".to_string()]);
+      assert_eq!(select.group_by, vec!["color".to_string()]);
+      assert_eq!(
+          select.having,
+          Some(WhereClause::Comparison(Comparison {
+              field: "count(*)".to_string(),
+              operator: Operator::Greater,
+              value: "2".to_string(),
+          }))
+      );
+      assert!(parse_statement("SELECT max(*) FROM apples GROUP BY color").is_err());
+  }
+
+  #[test]
+  fn parse_select_with_union() {
+      let input = "SELECT name FROM apples UNION SELECT color FROM apples UNION ALL SELECT COUNT(*) FROM apples ORDER BY name DESC LIMIT 3";
+      let Ok(SQLCommand::Select(SelectStatement::Compound(select))) = parse_statement(input) else {
+          panic!("Expected a compound select");
+      };
+
+      assert_eq!(select.arms.len(), 3);
+      assert!(matches!(select.arms[2], SelectStatement::Count(_)));
+      assert_eq!(select.operators, vec![CompoundOperator::Union, CompoundOperator::UnionAll]);
+      assert_eq!(
+          select.order_by,
+          vec![OrderingTerm {
+              column: "name".to_string(),
+              descending: true,
+          }]
+      );
+      assert_eq!(select.limit, Some(3));
+      assert!(parse_statement("SELECT name FROM apples LIMIT 1 UNION SELECT color FROM apples").is_err());
+  }
+
+  #[test]
+  fn parse_statement_rejects_trailing_input() {
+      let error = parse_statement("SELECT name FROM apples WHERE size ~ 3").unwrap_err();
+      assert!(matches!(error, Error::Parse { offset: 23, .. }));
+  }
+
+  #[test]
+  fn parse_select_with_count() {
+      let input = b"SELECT COUNT(*) FROM test";
+      let (_, result) = parse(input).unwrap();
+
+      assert_eq!(
+          result,
+          SQLCommand::Select(SelectStatement::Count(CountSelection {
+              table: "test".to_string(),
+              where_clause: None,
+          }))
+      );
+
+      let input = b"select count( * ) from test where name = 'a'";
+      let (_, result) = parse(input).unwrap();
+      let SQLCommand::Select(SelectStatement::Count(count)) = result else {
+          panic!("Expected a count");
+      };
+      assert!(count.where_clause.is_some());
+  }
+
+  #[test]
+  fn parse_create_table_with_one_entry() {
+      let input = b"CREATE TABLE IF NOT EXISTS test (id INTEGER primary key autoincrement)";
+      let (_, result) = parse(input).unwrap();
+
+      assert_eq!(
+          result,
+          SQLCommand::CreateTable(CreateTableStatement {
+              table: "test".to_string(),
+              fields: vec![Field {
+                  is_primary_key: true,
+                  in_primary_key: true,
+                  autoincrement: true,
+                  ..typed_field("id", "INTEGER")
+              },],
+              primary_key: vec!["id".to_string()],
+              unique: vec![],
+              checks: vec![],
+              foreign_keys: vec![],
+              without_rowid: false,
+              if_not_exists: true,
+              temporary: false,
+              autoindexes: vec![],
+          })
+      );
+  }
+
+  #[test]
+  fn parse_create_table_with_two_entries() {
+      let input = b"CREATE TABLE \"test\" (id INTEGER primary key, \"name field\" TEXT NOT NULL)";
+      let (_, result) = parse(input).unwrap();
+
+      assert_eq!(
+          result,
+          SQLCommand::CreateTable(CreateTableStatement {
+              table: "test".to_string(),
+              fields: vec![
+                  Field {
+                      is_primary_key: true,
+                      in_primary_key: true,
+                      ..typed_field("id", "INTEGER")
+                  },
+                  Field {
+                      nullable: false,
+                      ..typed_field("name field", "TEXT")
+                  }
+              ],
+              primary_key: vec!["id".to_string()],
+              unique: vec![],
+              checks: vec![],
+              foreign_keys: vec![],
+              without_rowid: false,
+              if_not_exists: false,
+              temporary: false,
+              autoindexes: vec![],
+          })
+      );
+  }
+
+  #[test]
+  fn parse_create_table_with_type_names() {
+      let input = b"CREATE TABLE t (a VARCHAR(255), b DECIMAL(10, 2), c UNSIGNED BIG INT, d double precision NOT NULL, e)";
+      let (_, result) = parse(input).unwrap();
+
+      assert_eq!(
+          result,
+          SQLCommand::CreateTable(CreateTableStatement {
+              table: "t".to_string(),
+              fields: vec![
+                  typed_field("a", "VARCHAR(255)"),
+                  typed_field("b", "DECIMAL(10, 2)"),
+                  typed_field("c", "UNSIGNED BIG INT"),
+                  Field {
+                      nullable: false,
+                      ..typed_field("d", "double precision")
+                  },
+                  Field::new("e".to_string())
+              ],
+              primary_key: vec![],
+              unique: vec![],
+              checks: vec![],
+              foreign_keys: vec![],
+              without_rowid: false,
+              if_not_exists: false,
+              temporary: false,
+              autoindexes: vec![],
+          })
+      );
+  }
+
+  #[test]
+  fn parse_create_table_with_defaults() {
+      let input = b"CREATE TABLE t (a INTEGER NOT NULL DEFAULT -1, b TEXT DEFAULT 'none' NULL, c DEFAULT (1 + 2), d REAL DEFAULT CURRENT_TIMESTAMP)";
+      let (_, result) = parse(input).unwrap();
 
-fn field_specification(input: &[u8]) -> IResult<&[u8], Field> {
-  let (remaining_input, (column, ty, constraints, _)) = tuple((
-      identifier,
-      opt(delimited(multispace0, identifier, multispace0)),
-      many0(column_constraint),
-      opt(delimited(multispace0, tag(","), multispace0)),
-  ))(input)?;
+      let SQLCommand::CreateTable(create) = result else {
+          panic!("Expected a CREATE TABLE, got {:?}", result);
+      };
+      let constraints = create
+          .fields
+          .iter()
+          .map(|field| (field.nullable, field.default_value.clone()))
+          .collect::<Vec<_>>();
+      assert_eq!(
+          constraints,
+          vec![
+              (false, Some(Expr::Literal(Literal::Integer(-1)))),
+              (true, Some(Expr::Literal(Literal::Text("none".to_string())))),
+              (
+                  true,
+                  Some(Expr::Binary(
+                      BinaryOperator::Add,
+                      Box::new(Expr::Literal(Literal::Integer(1))),
+                      Box::new(Expr::Literal(Literal::Integer(2)))
+                  ))
+              ),
+              (true, Some(Expr::Column("CURRENT_TIMESTAMP".to_string()))),
+          ]
+      );
+      let texts = create.fields.iter().map(|field| field.default_text.as_deref()).collect::<Vec<_>>();
+      assert_eq!(texts, [Some("-1"), Some("'none'"), Some("(1 + 2)"), Some("CURRENT_TIMESTAMP")]);
+  }
 
-  let is_primary_key = constraints
-      .iter()
-      .flatten()
-      .find(|c| **c == ColumnConstraint::PrimaryKey)
-      .is_some()
-      && ty
-          .map(|ty| ty.to_ascii_lowercase() == "integer")
-          .unwrap_or(false);
+  #[test]
+  fn parse_create_table_with_unique_and_check() {
+      let input = b"CREATE TABLE products (code TEXT UNIQUE NOT NULL, price REAL CHECK (price > 0), stock INT, UNIQUE (code, stock), CHECK(stock >= 0 AND price < 1000))";
+      let (_, result) = parse(input).unwrap();
 
-  Ok((
-      remaining_input,
-      Field {
-          name: column,
-          is_primary_key,
-      },
-  ))
-}
+      let condition = |sql: &str| condition(sql.as_bytes()).unwrap().1;
+      assert_eq!(
+          result,
+          SQLCommand::CreateTable(CreateTableStatement {
+              table: "products".to_string(),
+              fields: vec![
+                  Field {
+                      nullable: false,
+                      unique: true,
+                      ..typed_field("code", "TEXT")
+                  },
+                  Field {
+                      checks: vec![condition("price > 0")],
+                      ..typed_field("price", "REAL")
+                  },
+                  typed_field("stock", "INT")
+              ],
+              primary_key: vec![],
+              unique: vec![vec!["code".to_string(), "stock".to_string()]],
+              checks: vec![condition("stock >= 0 AND price < 1000")],
+              foreign_keys: vec![],
+              without_rowid: false,
+              if_not_exists: false,
+              temporary: false,
+              autoindexes: vec![vec!["code".to_string()], vec!["code".to_string(), "stock".to_string()]],
+          })
+      );
+  }
 
-#[cfg(test)]
-mod tests {
-  use super::*;
+  #[test]
+  fn parse_create_table_with_foreign_keys() {
+      let input = b"CREATE TABLE orders (id INTEGER PRIMARY KEY, customer INT REFERENCES customers(id) ON DELETE CASCADE NOT NULL, shop REFERENCES shops, a, b, FOREIGN KEY (a, b) REFERENCES \"other table\" (x, y) ON UPDATE SET NULL DEFERRABLE INITIALLY DEFERRED)";
+      let (_, result) = parse(input).unwrap();
+
+      let SQLCommand::CreateTable(create) = result else {
+          panic!("Expected a CREATE TABLE, got {:?}", result);
+      };
+      let strings = |names: &[&str]| names.iter().map(|name| name.to_string()).collect::<Vec<_>>();
+      assert_eq!(
+          create.fields[1],
+          Field {
+              nullable: false,
+              foreign_key: Some(ForeignKey {
+                  columns: strings(&["customer"]),
+                  table: "customers".to_string(),
+                  references: strings(&["id"]),
+              }),
+              ..typed_field("customer", "INT")
+          }
+      );
+      assert_eq!(
+          create.fields[2].foreign_key,
+          Some(ForeignKey {
+              columns: strings(&["shop"]),
+              table: "shops".to_string(),
+              references: vec![],
+          })
+      );
+      assert_eq!(
+          create.foreign_keys,
+          vec![ForeignKey {
+              columns: strings(&["a", "b"]),
+              table: "other table".to_string(),
+              references: strings(&["x", "y"]),
+          }]
+      );
+  }
 
   #[test]
-  fn parse_select_with_one_field() {
-      let input = b"SELECT id FROM test";
+  fn parse_create_super_heroes() {
+      let input = b"CREATE TABLE IF NOT EXISTS \"superheroes\" (id integer primary key autoincrement, name text not null, eye_color text, hair_color text, appearance_count integer, first_appearance text, first_appearance_year text);";
       let (_, result) = parse(input).unwrap();
 
       assert_eq!(
           result,
-          SQLCommand::Select(SelectStatement::Fields(SelectFields {
-              table: "test".to_string(),
-              fields: vec!["id".to_string()],
-              where_clause: None
-          }))
+          SQLCommand::CreateTable(CreateTableStatement {
+              table: "superheroes".to_string(),
+              fields: vec![
+                  Field {
+                      is_primary_key: true,
+                      in_primary_key: true,
+                      autoincrement: true,
+                      ..typed_field("id", "integer")
+                  },
+                  Field {
+                      nullable: false,
+                      ..typed_field("name", "text")
+                  },
+                  typed_field("eye_color", "text"),
+                  typed_field("hair_color", "text"),
+                  typed_field("appearance_count", "integer"),
+                  typed_field("first_appearance", "text"),
+                  typed_field("first_appearance_year", "text")
+              ],
+              primary_key: vec!["id".to_string()],
+              unique: vec![],
+              checks: vec![],
+              foreign_keys: vec![],
+              without_rowid: false,
+              if_not_exists: true,
+              temporary: false,
+              autoindexes: vec![],
+          })
       );
   }
-
   #[test]
-  fn parse_select_with_two_fields() {
-      let input = b"SELECT id, name FROM test";
+  fn parse_create_index() {
+      let input = b"CREATE INDEX idx_companies_country on companies (country);";
       let (_, result) = parse(input).unwrap();
 
       assert_eq!(
           result,
-          SQLCommand::Select(SelectStatement::Fields(SelectFields {
-              table: "test".to_string(),
-              fields: vec!["id".to_string(), "name".to_string()],
-              where_clause: None
+          SQLCommand::CreateIndex(CreateIndexStatement {
+              table: "companies".to_string(),
+              name: "idx_companies_country".to_string(),
+              fields: vec!["country".to_string()],
+              collations: vec![None],
+              descending: vec![false],
+              if_not_exists: false,
+          })
+      );
+  }
+
+  #[test]
+  fn parse_numeric_literals() {
+      let numbers = [
+          ("42", Literal::Integer(42)),
+          ("2.75", Literal::Real(2.75)),
+          ("5.", Literal::Real(5.0)),
+          (".5", Literal::Real(0.5)),
+          ("1e6", Literal::Real(1e6)),
+          ("2.5E-2", Literal::Real(0.025)),
+          ("1e+2", Literal::Real(100.0)),
+          ("9223372036854775808", Literal::Real(9223372036854775808.0)),
+          ("0x1F", Literal::Integer(31)),
+          ("0XFFFFFFFFFFFFFFFF", Literal::Integer(-1)),
+      ];
+      for (text, literal) in numbers {
+          assert_eq!(number(text.as_bytes()).unwrap(), (&b""[..], literal));
+      }
+      assert!(number(b".").is_err());
+      assert!(number(b"0x10000000000000000").is_err());
+      assert_eq!(number(b"1e").unwrap(), (&b"e"[..], Literal::Integer(1)));
+
+      let (_, result) = parse(b"SELECT name FROM apples WHERE size < -1.5e1 AND id IN (1e1, -2)").unwrap();
+      let SQLCommand::Select(SelectStatement::Fields(select)) = result else {
+          panic!("Expected a select");
+      };
+      let WhereClause::And(left, right) = select.where_clause.unwrap() else {
+          panic!("Expected AND");
+      };
+      assert_eq!(left.as_comparison().unwrap().value, "-15");
+      let WhereClause::In(list) = *right else {
+          panic!("Expected IN");
+      };
+      assert_eq!(list.values, vec![Some("10".to_string()), Some("-2".to_string())]);
+  }
+
+  #[test]
+  fn parse_blob_literals() {
+      assert_eq!(blob(b"X'DEADbeef'").unwrap(), (&b""[..], vec![0xde, 0xad, 0xbe, 0xef]));
+      assert_eq!(blob(b"x''").unwrap(), (&b""[..], vec![]));
+      assert!(blob(b"X'ABC'").is_err());
+      assert!(blob(b"X'zz'").is_err());
+
+      // Blobs compare byte by byte, without a column's type.
+      let (_, result) = parse(b"SELECT id FROM files WHERE data = X'00ff'").unwrap();
+      let SQLCommand::Select(SelectStatement::Fields(select)) = result else {
+          panic!("Expected a select");
+      };
+      assert_eq!(
+          select.where_clause,
+          Some(WhereClause::Expression(ExpressionComparison {
+              left: Expr::Column("data".to_string()),
+              operator: Operator::Equal,
+              right: Expr::Literal(Literal::Blob(vec![0x00, 0xff])),
           }))
       );
   }
 
   #[test]
-  fn parse_select_with_where() {
-      let input = b"SELECT id, name FROM test WHERE super_name = 'test string'";
+  fn parse_string_literals() {
+      let (_, result) = parse(b"SELECT name FROM people WHERE name = 'O''Brien' OR name IN ('it''s', '')").unwrap();
+      let SQLCommand::Select(SelectStatement::Fields(select)) = result else {
+          panic!("Expected a select");
+      };
+      let WhereClause::Or(left, right) = select.where_clause.unwrap() else {
+          panic!("Expected OR");
+      };
+      assert_eq!(left.as_comparison().unwrap().value, "O'Brien");
+      let WhereClause::In(list) = *right else {
+          panic!("Expected IN");
+      };
+      assert_eq!(list.values, vec![Some("it's".to_string()), Some(String::new())]);
+      assert!(string(b"'open").is_err());
+  }
+
+  #[test]
+  fn parse_quoted_identifiers() {
+      let input = b"CREATE INDEX \"by \"\"unit\"\" price\" on [order items] (`select`);";
       let (_, result) = parse(input).unwrap();
 
       assert_eq!(
           result,
-          SQLCommand::Select(SelectStatement::Fields(SelectFields {
-              table: "test".to_string(),
-              fields: vec!["id".to_string(), "name".to_string()],
-              where_clause: Some(WhereClause {
-                  field: "super_name".to_string(),
-                  value: "test string".to_string()
-              })
-          }))
+          SQLCommand::CreateIndex(CreateIndexStatement {
+              table: "order items".to_string(),
+              name: "by \"unit\" price".to_string(),
+              fields: vec!["select".to_string()],
+              collations: vec![None],
+              descending: vec![false],
+              if_not_exists: false,
+          })
       );
+      assert_eq!(identifier(b"`a``b` c").unwrap(), (&b" c"[..], "a`b".to_string()));
+      assert_eq!(identifier(b"[a]]").unwrap(), (&b"]"[..], "a".to_string()));
+      assert!(identifier(b"\"open").is_err());
+
+      let max = ResultColumn::Aggregate(AggregateFunction::Max, Some("unit price".to_string()));
+      assert_eq!(max.name(), "max(\"unit price\")");
+      assert_eq!(parse_result_column(&max.name()), max);
   }
 
   #[test]
-  fn parse_select_with_count() {
-      let input = b"SELECT COUNT(*) FROM test";
+  fn parse_select_with_match() {
+      let input = b"SELECT rowid FROM docs WHERE docs MATCH 'zebra'";
       let (_, result) = parse(input).unwrap();
 
       assert_eq!(
           result,
-          SQLCommand::Select(SelectStatement::Count("test".to_string()))
+          SQLCommand::Select(SelectStatement::Fields(SelectFields {
+              table: "docs".to_string(),
+              fields: vec!["rowid".to_string()],
+              where_clause: Some(WhereClause::Comparison(Comparison {
+                  field: "docs".to_string(),
+                  operator: Operator::Match,
+                  value: "zebra".to_string()
+              })),
+              order_by: vec![],
+              limit: None,
+              offset: 0,
+          }))
       );
   }
 
   #[test]
-  fn parse_create_table_with_one_entry() {
-      let input = b"CREATE TABLE IF NOT EXISTS test (id INTEGER primary key autoincrement)";
+  fn parse_create_virtual_table() {
+      let input = b"CREATE VIRTUAL TABLE docs USING fts5(title, body, tokenize = 'porter ascii')";
       let (_, result) = parse(input).unwrap();
 
       assert_eq!(
           result,
-          SQLCommand::CreateTable(CreateTableStatement {
-              table: "test".to_string(),
-              fields: vec![Field {
-                  name: "id".to_string(),
-                  is_primary_key: true
-              },]
+          SQLCommand::CreateVirtualTable(CreateVirtualTableStatement {
+              table: "docs".to_string(),
+              module: "fts5".to_string(),
+              arguments: vec![
+                  "title".to_string(),
+                  "body".to_string(),
+                  "tokenize = 'porter ascii'".to_string()
+              ],
           })
       );
   }
 
   #[test]
-  fn parse_create_table_with_two_entries() {
-      let input = b"CREATE TABLE \"test\" (id INTEGER primary key, \"name field\" TEXT NOT NULL)";
+  fn parse_create_without_rowid_table() {
+      let input = b"CREATE TABLE 'docs_config'(k PRIMARY KEY, v) WITHOUT ROWID";
       let (_, result) = parse(input).unwrap();
 
       assert_eq!(
           result,
           SQLCommand::CreateTable(CreateTableStatement {
-              table: "test".to_string(),
+              table: "docs_config".to_string(),
               fields: vec![
                   Field {
-                      name: "id".to_string(),
-                      is_primary_key: true
+                      in_primary_key: true,
+                      ..Field::new("k".to_string())
                   },
-                  Field::new("name field".to_string())
-              ]
+                  Field::new("v".to_string())
+              ],
+              primary_key: vec!["k".to_string()],
+              unique: vec![],
+              checks: vec![],
+              foreign_keys: vec![],
+              without_rowid: true,
+              if_not_exists: false,
+              temporary: false,
+              autoindexes: vec![vec!["k".to_string()]],
           })
       );
   }
 
   #[test]
-  fn parse_create_super_heroes() {
-      let input = b"CREATE TABLE IF NOT EXISTS \"superheroes\" (id integer primary key autoincrement, name text not null, eye_color text, hair_color text, appearance_count integer, first_appearance text, first_appearance_year text);";
+  fn parse_create_table_with_primary_key_constraint() {
+      let input = b"CREATE TABLE 'docs_idx'(segid, term, pgno, PRIMARY KEY(segid, term)) WITHOUT ROWID";
       let (_, result) = parse(input).unwrap();
 
       assert_eq!(
           result,
           SQLCommand::CreateTable(CreateTableStatement {
-              table: "superheroes".to_string(),
+              table: "docs_idx".to_string(),
               fields: vec![
                   Field {
-                      name: "id".to_string(),
-                      is_primary_key: true,
+                      in_primary_key: true,
+                      ..Field::new("segid".to_string())
                   },
-                  Field::new("name".to_string()),
-                  Field::new("eye_color".to_string()),
-                  Field::new("hair_color".to_string()),
-                  Field::new("appearance_count".to_string()),
-                  Field::new("first_appearance".to_string()),
-                  Field::new("first_appearance_year".to_string())
-              ]
+                  Field {
+                      in_primary_key: true,
+                      ..Field::new("term".to_string())
+                  },
+                  Field::new("pgno".to_string())
+              ],
+              primary_key: vec!["segid".to_string(), "term".to_string()],
+              unique: vec![],
+              checks: vec![],
+              foreign_keys: vec![],
+              without_rowid: true,
+              if_not_exists: false,
+              temporary: false,
+              autoindexes: vec![vec!["segid".to_string(), "term".to_string()]],
           })
       );
   }
+
   #[test]
-  fn parse_create_index() {
-      let input = b"CREATE INDEX idx_companies_country on companies (country);";
+  fn parse_create_table_with_rowid_primary_key_constraint() {
+      let input = b"CREATE TABLE t (a INTEGER, b, PRIMARY KEY (a DESC))";
+      let (_, result) = parse(input).unwrap();
+
+      let SQLCommand::CreateTable(create) = result else {
+          panic!("Expected a CREATE TABLE, got {:?}", result);
+      };
+      assert_eq!(create.primary_key, vec!["a".to_string()]);
+      assert!(create.fields[0].is_primary_key && create.fields[0].in_primary_key);
+      assert!(!create.fields[1].in_primary_key);
+
+      let input = b"CREATE TABLE t (b TEXT, a INTEGER, PRIMARY KEY (a, b))";
+      let (_, result) = parse(input).unwrap();
+
+      let SQLCommand::CreateTable(create) = result else {
+          panic!("Expected a CREATE TABLE, got {:?}", result);
+      };
+      assert_eq!(create.primary_key, vec!["a".to_string(), "b".to_string()]);
+      assert!(create.fields.iter().all(|field| field.in_primary_key && !field.is_primary_key));
+  }
+
+  #[test]
+  fn parse_create_view() {
+      let input = b"CREATE VIEW IF NOT EXISTS \"red apples\" (id, label) AS SELECT id, name FROM apples WHERE color = 'red';";
+      let (_, result) = parse(input).unwrap();
+
+      assert_eq!(
+          result,
+          SQLCommand::CreateView(CreateViewStatement {
+              name: "red apples".to_string(),
+              columns: vec!["id".to_string(), "label".to_string()],
+              select: SelectStatement::Fields(SelectFields {
+                  table: "apples".to_string(),
+                  fields: vec!["id".to_string(), "name".to_string()],
+                  where_clause: Some(WhereClause::Comparison(Comparison {
+                      field: "color".to_string(),
+                      operator: Operator::Equal,
+                      value: "red".to_string()
+                  })),
+                  order_by: vec![],
+                  limit: None,
+                  offset: 0,
+              }),
+          })
+      );
+  }
+
+  #[test]
+  fn parse_create_trigger() {
+      let input = b"CREATE TRIGGER IF NOT EXISTS log_price AFTER UPDATE OF price, name ON products FOR EACH ROW WHEN new.price > 0 BEGIN INSERT INTO log VALUES (old.price); END";
+      let (_, result) = parse(input).unwrap();
+
+      assert_eq!(
+          result,
+          SQLCommand::CreateTrigger(CreateTriggerStatement {
+              name: "log_price".to_string(),
+              table: "products".to_string(),
+              timing: TriggerTiming::After,
+              event: TriggerEvent::Update(vec!["price".to_string(), "name".to_string()]),
+              body: "FOR EACH ROW WHEN new.price > 0 BEGIN INSERT INTO log VALUES (old.price); END"
+                  .to_string(),
+          })
+      );
+
+      let input = b"CREATE TEMP TRIGGER cleanup DELETE ON \"order items\" BEGIN DELETE FROM notes; END";
+      let (_, result) = parse(input).unwrap();
+
+      let SQLCommand::CreateTrigger(trigger) = result else {
+          panic!("Expected a CREATE TRIGGER, got {:?}", result);
+      };
+      assert_eq!(trigger.timing, TriggerTiming::Before);
+      assert_eq!(trigger.event, TriggerEvent::Delete);
+      assert_eq!(trigger.table, "order items");
+  }
+
+  #[test]
+  fn parse_create_table_with_named_constraints() {
+      let input = b"CREATE TABLE t (a INTEGER CONSTRAINT a_set NOT NULL ON CONFLICT FAIL, b TEXT UNIQUE ON CONFLICT IGNORE, CONSTRAINT t_key PRIMARY KEY (a, b) ON CONFLICT REPLACE, CONSTRAINT b_unique UNIQUE (b), CONSTRAINT a_positive CHECK (a > 0), CONSTRAINT b_ref FOREIGN KEY (b) REFERENCES other (x))";
+      let (_, result) = parse(input).unwrap();
+
+      let SQLCommand::CreateTable(create) = result else {
+          panic!("Expected a CREATE TABLE, got {:?}", result);
+      };
+      assert_eq!(create.fields.len(), 2);
+      assert!(!create.fields[0].nullable);
+      assert!(create.fields[1].unique);
+      assert_eq!(create.primary_key, vec!["a".to_string(), "b".to_string()]);
+      assert_eq!(create.unique, vec![vec!["b".to_string()]]);
+      assert_eq!(create.checks.len(), 1);
+      assert_eq!(create.foreign_keys.len(), 1);
+  }
+
+  #[test]
+  fn parse_create_table_with_generated_columns() {
+      let input = b"CREATE TABLE t (a INT, b AS (a * 2) STORED, c INTEGER GENERATED ALWAYS AS (a + b) VIRTUAL, d TEXT AS (upper(b)))";
+      let (_, result) = parse(input).unwrap();
+
+      let SQLCommand::CreateTable(create) = result else {
+          panic!("Expected a CREATE TABLE, got {:?}", result);
+      };
+      let stored = create
+          .fields
+          .iter()
+          .map(|field| field.generated.as_ref().map(|generated| generated.stored))
+          .collect::<Vec<_>>();
+      assert_eq!(stored, vec![None, Some(true), Some(false), Some(false)]);
+      assert_eq!(create.fields[2].type_name, Some("INTEGER".to_string()));
+      assert_eq!(
+          create.fields[1].generated.as_ref().unwrap().expression,
+          Expr::Binary(
+              BinaryOperator::Multiply,
+              Box::new(Expr::Column("a".to_string())),
+              Box::new(Expr::Literal(Literal::Integer(2)))
+          )
+      );
+      assert_eq!(create.record_columns(), vec!["a".to_string(), "b".to_string()]);
+  }
+
+  #[test]
+  fn parse_collations() {
+      let input = b"CREATE TABLE t (a TEXT COLLATE NOCASE NOT NULL, b COLLATE rtrim, c TEXT, UNIQUE (a COLLATE BINARY, b))";
       let (_, result) = parse(input).unwrap();
 
+      let SQLCommand::CreateTable(create) = result else {
+          panic!("Expected a CREATE TABLE, got {:?}", result);
+      };
+      let collations = create.fields.iter().map(|field| field.collation).collect::<Vec<_>>();
+      assert_eq!(collations, vec![Collation::NoCase, Collation::RTrim, Collation::Binary]);
+      assert!(!create.fields[0].nullable);
+      assert_eq!(create.unique, vec![vec!["a".to_string(), "b".to_string()]]);
+      assert_eq!(collate(b" collate NoCase").unwrap(), (&b""[..], Collation::NoCase));
+      assert!(collate(b"COLLATE unicode").is_err());
+
+      let (_, result) = parse(b"CREATE INDEX i ON t (a, b COLLATE NOCASE DESC)").unwrap();
       assert_eq!(
           result,
           SQLCommand::CreateIndex(CreateIndexStatement {
-              table: "companies".to_string(),
-              name: "idx_companies_country".to_string(),
-              fields: vec!["country".to_string()],
+              name: "i".to_string(),
+              table: "t".to_string(),
+              fields: vec!["a".to_string(), "b".to_string()],
+              collations: vec![None, Some(Collation::NoCase)],
+              descending: vec![false, true],
+              if_not_exists: false,
+          })
+      );
+      let Ok(SQLCommand::CreateIndex(create)) = parse_statement("CREATE INDEX IF NOT EXISTS i ON t (a)") else {
+        panic!("expected a CREATE INDEX");
+      };
+      assert!(create.if_not_exists);
+  }
+
+  #[test]
+  fn parse_select_max() {
+      let input = b"SELECT MAX(price) FROM products";
+      let (_, result) = parse(input).unwrap();
+
+      assert_eq!(
+          result,
+          SQLCommand::Select(SelectStatement::MinMax(MinMaxSelection {
+              function: MinMax::Max,
+              field: "price".to_string(),
+              table: "products".to_string(),
+          }))
+      );
+  }
+
+  #[test]
+  fn skip_comments() {
+      assert_eq!(
+          strip_comments("SELECT a /* b; */ FROM t -- c\nWHERE d = '--e' /* f"),
+          "SELECT a          FROM t     \nWHERE d = '--e'     "
+      );
+      assert!(matches!(strip_comments("SELECT a - -1 FROM t"), Cow::Borrowed(_)));
+
+      let input = "CREATE TABLE t ( -- rows\n  id INTEGER PRIMARY KEY, /* key */\n  name TEXT\n)";
+      let Ok((_, SQLCommand::CreateTable(table))) = parse_create(strip_comments(input).as_bytes()) else {
+          panic!("Expected a create table");
+      };
+      assert_eq!(table.fields.len(), 2);
+
+      assert_eq!(
+          split_statements("/* a; */ SELECT a FROM t; -- b;\nSELECT b FROM t /* c */"),
+          vec![(9, "SELECT a FROM t"), (32, "SELECT b FROM t")]
+      );
+      match parse_statement("-- hello\nSELECT b FORM t") {
+        Err(Error::Parse { offset, .. }) => assert_eq!(offset, 18),
+        result => panic!("unexpected result: {:?}", result),
+      }
+  }
+
+  #[test]
+  fn parse_statements_of_a_script() {
+      let script = "SELECT name FROM apples WHERE color = 'a;b';\n  SELECT \"x;\" FROM t ;;";
+      assert_eq!(
+          split_statements(script),
+          vec![
+              (0, "SELECT name FROM apples WHERE color = 'a;b'"),
+              (47, "SELECT \"x;\" FROM t"),
+          ]
+      );
+      assert!(split_statements(" ; \n").is_empty());
+
+      let commands = parse_statements("SELECT a FROM t; SELECT COUNT(*) FROM t;").unwrap();
+      assert_eq!(commands.len(), 2);
+      assert!(matches!(commands[1], SQLCommand::Select(SelectStatement::Count(_))));
+      match parse_statements("SELECT a FROM t; SELECT b FORM t") {
+        Err(Error::Parse { offset, .. }) => assert_eq!(offset, 26),
+        result => panic!("unexpected result: {:?}", result),
+      }
+  }
+
+  #[test]
+  fn parse_statement_reports_error_offset() {
+      match parse_statement("SELECT name FORM apples") {
+        Err(Error::Parse { message, offset }) => {
+          assert_eq!(message, "near \"FORM\": syntax error");
+          assert_eq!(offset, 12);
+        }
+        result => panic!("unexpected result: {:?}", result),
+      }
+  }
+
+  #[test]
+  fn parse_delete_statement() {
+      let Ok(SQLCommand::Delete(delete)) = parse_statement("DELETE FROM apples WHERE color = 'Red';") else {
+        panic!("expected a DELETE");
+      };
+      assert_eq!(delete.table, "apples");
+      let SelectStatement::Fields(select) = delete.selection else {
+        panic!("expected a selection of rowids");
+      };
+      assert_eq!(select.fields, vec!["rowid"]);
+      assert_eq!(select.table, "apples");
+      assert!(select.where_clause.is_some());
+
+      let Ok(SQLCommand::Delete(delete)) = parse_statement("delete from \"old apples\"") else {
+        panic!("expected a DELETE");
+      };
+      assert_eq!(delete.table, "old apples");
+      assert!(parse_statement("DELETE apples").is_err());
+  }
+
+  #[test]
+  fn parse_drop_table_statement() {
+      assert_eq!(
+          parse_statement("DROP TABLE apples;").unwrap(),
+          SQLCommand::DropTable(DropTableStatement {
+              table: "apples".to_string(),
+              if_exists: false,
+          })
+      );
+      assert_eq!(
+          parse_statement("drop table if exists \"old apples\"").unwrap(),
+          SQLCommand::DropTable(DropTableStatement {
+              table: "old apples".to_string(),
+              if_exists: true,
+          })
+      );
+      assert!(parse_statement("DROP apples").is_err());
+  }
+
+  #[test]
+  fn parse_attach_statement() {
+      assert_eq!(
+          parse_statement("ATTACH DATABASE 'other.db' AS aux;").unwrap(),
+          SQLCommand::Attach(AttachStatement {
+              path: "other.db".to_string(),
+              name: "aux".to_string(),
           })
       );
+      assert_eq!(parse_statement("detach aux").unwrap(), SQLCommand::Detach("aux".to_string()));
+      assert!(parse_statement("ATTACH 'other.db'").is_err());
+
+      let Ok(SQLCommand::Select(SelectStatement::Fields(select))) = parse_statement("SELECT a FROM aux.\"t 1\"") else {
+        panic!("expected a SELECT");
+      };
+      assert_eq!(select.table, "aux.t 1");
+  }
+
+  #[test]
+  fn parse_pragma_statement() {
+      let pragma = |schema: Option<&str>, name: &str, argument: Option<&str>| {
+        SQLCommand::Pragma(PragmaStatement {
+            schema: schema.map(str::to_string),
+            name: name.to_string(),
+            argument: argument.map(str::to_string),
+        })
+      };
+      assert_eq!(
+          parse_statement("PRAGMA table_info(products);").unwrap(),
+          pragma(None, "table_info", Some("products"))
+      );
+      assert_eq!(
+          parse_statement("pragma aux.index_info = 'by name'").unwrap(),
+          pragma(Some("aux"), "index_info", Some("by name"))
+      );
+      assert_eq!(
+          parse_statement("PRAGMA cache_size = -2000").unwrap(),
+          pragma(None, "cache_size", Some("-2000"))
+      );
+      assert_eq!(parse_statement("PRAGMA user_version").unwrap(), pragma(None, "user_version", None));
+      assert!(parse_statement("PRAGMA table_info(a b)").is_err());
+  }
+
+  #[test]
+  fn parse_alter_table_statement() {
+      let Ok(SQLCommand::AlterTable(alter)) =
+          parse_statement("ALTER TABLE apples ADD COLUMN weight REAL DEFAULT 1.5 NOT NULL;")
+      else {
+        panic!("expected an ALTER TABLE");
+      };
+      assert_eq!(alter.table, "apples");
+      assert_eq!(alter.column.name, "weight");
+      assert_eq!(alter.column.default_value, Some(Expr::Literal(Literal::Real(1.5))));
+      assert!(!alter.column.nullable);
+      assert_eq!(alter.definition, "weight REAL DEFAULT 1.5 NOT NULL");
+
+      let Ok(SQLCommand::AlterTable(alter)) = parse_statement("alter table \"old apples\" add color") else {
+        panic!("expected an ALTER TABLE");
+      };
+      assert_eq!((alter.table.as_str(), alter.definition.as_str()), ("old apples", "color"));
+      assert!(parse_statement("ALTER TABLE apples ADD").is_err());
+  }
+
+  #[test]
+  fn parse_transaction_control() {
+      assert_eq!(parse_statement("BEGIN").unwrap(), SQLCommand::Begin);
+      assert_eq!(parse_statement("begin immediate transaction;").unwrap(), SQLCommand::Begin);
+      assert_eq!(parse_statement("COMMIT TRANSACTION").unwrap(), SQLCommand::Commit);
+      assert_eq!(parse_statement("END;").unwrap(), SQLCommand::Commit);
+      assert_eq!(parse_statement("ROLLBACK").unwrap(), SQLCommand::Rollback);
+      assert!(parse_statement("BEGIN LATER").is_err());
+  }
+
+  #[test]
+  fn keep_table_definitions_like_sqlite() {
+      assert_eq!(
+          table_definition("create table if not exists   t (a, b);"),
+          "CREATE TABLE t (a, b)"
+      );
+      assert_eq!(table_definition("CREATE TABLE \"my table\"(a) -- note"), "CREATE TABLE \"my table\"(a) -- note");
+      assert_eq!(table_definition("create temp table t (a)"), "CREATE TABLE t (a)");
+      assert_eq!(index_definition("create index if not exists i on t(a);"), "CREATE INDEX i on t(a)");
+      assert_eq!(add_column_definition("CREATE TABLE t (a, b)", "c TEXT"), "CREATE TABLE t (a, b, c TEXT)");
+      assert_eq!(
+          add_column_definition("CREATE TABLE t(a PRIMARY KEY) WITHOUT ROWID -- (note)", "b"),
+          "CREATE TABLE t(a PRIMARY KEY, b) WITHOUT ROWID -- (note)"
+      );
+  }
+
+  #[test]
+  fn parse_create_temp_table() {
+      for sql in ["CREATE TEMP TABLE t (a)", "create temporary table if not exists t (a)"] {
+        let Ok(SQLCommand::CreateTable(create)) = parse_statement(sql) else {
+          panic!("expected a CREATE TABLE");
+        };
+        assert!(create.temporary);
+      }
+      let Ok(SQLCommand::CreateTable(create)) = parse_statement("CREATE TABLE temperature (a)") else {
+        panic!("expected a CREATE TABLE");
+      };
+      assert!(!create.temporary);
+      assert_eq!(create.table, "temperature");
+  }
+
+  #[test]
+  fn number_automatic_indexes_like_sqlite() {
+      let Ok(SQLCommand::CreateTable(create)) = parse_statement(
+        "CREATE TABLE t (a UNIQUE, b TEXT PRIMARY KEY, c, d UNIQUE, UNIQUE (c), UNIQUE (a), UNIQUE (d, c))",
+      ) else {
+        panic!("expected a CREATE TABLE");
+      };
+      let expected: Vec<Vec<String>> = vec![
+          vec!["a".into()],
+          vec!["b".into()],
+          vec!["d".into()],
+          vec!["c".into()],
+          vec!["d".into(), "c".into()],
+      ];
+      assert_eq!(create.autoindexes, expected);
+
+      let Ok(SQLCommand::CreateTable(create)) = parse_statement("CREATE TABLE u (id INTEGER PRIMARY KEY, x UNIQUE)") else {
+        panic!("expected a CREATE TABLE");
+      };
+      assert_eq!(create.autoindexes, vec![vec!["x".to_string()]]);
   }
-}
\ No newline at end of file
+}
@@ -1,68 +1,217 @@
 use std::collections::HashMap;
 
 use crate::{
-    page::{Cell, Page},
-    record::{ColumnValue, Record},
+    btree::{BTreeCursor, Direction},
+    database::Database,
+    error::{bail, format_err, Error, Result},
+    expr::{Affinity, Expr, Value},
+    fts5,
+    record::{Collation, ColumnValue, Record},
     sql,
 };
-use anyhow::Result;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct SchemaStore {
     pub tables: HashMap<String, Table>,
     pub table_names: Vec<String>,
+    pub views: HashMap<String, View>,
+    view_names: Vec<String>,
+    triggers: Vec<Trigger>,
 }
 
 impl SchemaStore {
-    pub fn read(page: Page) -> Result<Self> {
-        let schema_table = SQLiteSchema::read(page)?;
+    pub fn read(database: &mut Database) -> Result<Self> {
+        let schema_table = SQLiteSchema::read(database)?;
         let mut tables: HashMap<String, Table> = HashMap::new();
         let mut table_names: Vec<String> = Vec::new();
+        let mut views: HashMap<String, View> = HashMap::new();
+        let mut view_names: Vec<String> = Vec::new();
+        let mut triggers: Vec<Trigger> = Vec::new();
+
+        for row in schema_table.rows.iter().filter(|row| !row.sql.is_empty()) {
+            let sql = match sql::parse_create(sql::strip_comments(&row.sql).as_bytes()) {
+                Ok((_, sql)) => sql,
+                // A view may select in ways not supported here, which only
+                // matters once it is queried.
+                Err(_) if row.kind == "view" => continue,
+                Err(_) => bail!("Failed to parse table definition"),
+            };
 
-        for row in schema_table.rows.iter() {
-            let (_, sql) = sql::parse_create(row.sql.as_bytes())
-                .map_err(|_e| anyhow::anyhow!("Failed to parse table definition"))?;
-
-            if let sql::SQLCommand::CreateTable(t) = sql {
-                let table = Table {
+            if let sql::SQLCommand::CreateView(v) = sql {
+                let view = View {
+                    name: v.name,
+                    columns: v.columns,
+                    select: v.select,
+                };
+                view_names.push(view.name.clone());
+                views.insert(view.name.clone(), view);
+            } else if let sql::SQLCommand::CreateTrigger(t) = sql {
+                triggers.push(Trigger {
+                    name: t.name,
+                    table: t.table,
+                    timing: t.timing,
+                    event: t.event,
+                    sql: row.sql.clone(),
+                });
+            } else if let sql::SQLCommand::CreateTable(t) = sql {
+                let mut table = Table {
                     name: t.table,
                     columns: t.fields.iter().map(|f| Column::from(f)).collect(),
                     indexes: vec![],
+                    autoindexes: vec![],
                     rootpage: row.rootpage,
+                    without_rowid: t.without_rowid,
+                    module: None,
+                    unique: t.unique,
+                    checks: t.checks,
+                    foreign_key_constraints: t.foreign_keys,
+                    primary_key: t.primary_key,
                 };
+                for (number, columns) in t.autoindexes.into_iter().enumerate() {
+                    let name = format!("sqlite_autoindex_{}_{}", table.name, number + 1);
+                    // The PRIMARY KEY of a WITHOUT ROWID table is the table's
+                    // own B-tree, without a row of its own.
+                    let rootpage = schema_table
+                        .rows
+                        .iter()
+                        .find(|row| row.kind == "index" && row.name == name)
+                        .map_or(row.rootpage, |row| row.rootpage);
+                    table.autoindexes.push(Index {
+                        name,
+                        collations: columns.iter().map(|column| table.collation(column)).collect(),
+                        descending: vec![false; columns.len()],
+                        columns,
+                        table_name: table.name.clone(),
+                        rootpage,
+                        stat: vec![],
+                    });
+                }
 
                 if table.is_user_table() {
                     table_names.push(table.name.clone());
                 }
                 tables.insert(table.name.clone(), table);
+            } else if let sql::SQLCommand::CreateVirtualTable(t) = sql {
+                let columns = match t.module.to_ascii_lowercase().as_str() {
+                    "fts5" => fts5::column_names(&t.arguments)
+                        .into_iter()
+                        .map(Column::new)
+                        .collect(),
+                    "rtree" | "rtree_i32" => t
+                        .arguments
+                        .iter()
+                        .filter_map(|argument| argument.split_whitespace().next())
+                        .enumerate()
+                        .map(|(i, name)| Column {
+                            is_primary_key: i == 0,
+                            ..Column::new(name.trim_start_matches('+').to_string())
+                        })
+                        .collect(),
+                    _ => vec![],
+                };
+                let table = Table {
+                    name: t.table,
+                    columns,
+                    indexes: vec![],
+                    autoindexes: vec![],
+                    rootpage: row.rootpage,
+                    without_rowid: false,
+                    module: Some(VirtualTableModule {
+                        name: t.module,
+                        arguments: t.arguments,
+                    }),
+                    unique: vec![],
+                    checks: vec![],
+                    foreign_key_constraints: vec![],
+                    primary_key: vec![],
+                };
+
+                table_names.push(table.name.clone());
+                tables.insert(table.name.clone(), table);
             }
         }
 
-        for row in schema_table.rows.iter() {
-            let (_, sql) = sql::parse_create(row.sql.as_bytes())
-                .map_err(|_e| anyhow::anyhow!("Failed to parse table definition"))?;
+        let index_rows = schema_table
+            .rows
+            .iter()
+            .filter(|row| row.kind == "index" && !row.sql.is_empty());
+        for row in index_rows {
+            let (_, sql) = sql::parse_create(sql::strip_comments(&row.sql).as_bytes())
+                .map_err(|_e| format_err!("Failed to parse table definition"))?;
 
             if let sql::SQLCommand::CreateIndex(i) = sql {
+                let table = tables.get_mut(&i.table).expect("Index without table");
+                // Columns compare by their own collation unless the index
+                // gives another.
+                let collations = i
+                    .fields
+                    .iter()
+                    .zip(i.collations)
+                    .map(|(field, collation)| collation.unwrap_or(table.collation(field)))
+                    .collect();
                 let index = Index {
                     name: i.name,
                     columns: i.fields,
+                    collations,
+                    descending: i.descending,
                     table_name: i.table,
                     rootpage: row.rootpage,
+                    stat: vec![],
                 };
-                tables
-                    .get_mut(&index.table_name)
-                    .expect("Index without table")
-                    .indexes
-                    .push(index);
+                table.indexes.push(index);
             };
         }
 
+        // ANALYZE leaves a row for every index it counted in sqlite_stat1,
+        // with the numbers in its stat column, then keywords.
+        if let Some(rootpage) = tables.get("sqlite_stat1").map(|table| table.rootpage) {
+            for entry in BTreeCursor::new(database, rootpage, Direction::Forward)? {
+                let entry = entry?;
+                let record = Record::read(entry.rowid, &entry.payload);
+                let [table, index, stat] = &record.values[..] else {
+                    continue;
+                };
+                let index = tables
+                    .get_mut(&table.to_string())
+                    .filter(|_| !index.is_null())
+                    .and_then(|table| table.indexes.iter_mut().find(|i| i.name == index.to_string()));
+                if let Some(index) = index {
+                    index.stat = stat
+                        .to_string()
+                        .split_whitespace()
+                        .map_while(|number| number.parse().ok())
+                        .collect();
+                }
+            }
+        }
+
         Ok(Self {
             tables,
             table_names,
+            views,
+            view_names,
+            triggers,
         })
     }
 
+    /// Tables in the order of the schema, virtual ones included, without
+    /// the ones SQLite keeps for itself.
+    pub fn tables(&self) -> impl Iterator<Item = &Table> {
+        self.table_names.iter().filter_map(|name| self.tables.get(name))
+    }
+
+    /// Views in the order of the schema.
+    pub fn views(&self) -> impl Iterator<Item = &View> {
+        self.view_names.iter().filter_map(|name| self.views.get(name))
+    }
+
+    /// Indexes table by table in the order of [`SchemaStore::tables`],
+    /// those SQLite makes for the constraints of a table first, as they
+    /// are in the schema.
+    pub fn indexes(&self) -> impl Iterator<Item = &Index> {
+        self.tables().flat_map(Table::all_indexes)
+    }
+
     pub fn user_tables(&self) -> impl Iterator<Item = &Table> {
         self.tables.values().filter(|table| table.is_user_table())
     }
@@ -70,6 +219,26 @@ impl SchemaStore {
     pub fn find_table(&self, table_name: &str) -> Option<&Table> {
         self.user_tables().find(|table| table.name == table_name)
     }
+
+    pub fn find_view(&self, view_name: &str) -> Option<&View> {
+        self.views.get(view_name)
+    }
+
+    /// Triggers of every table, in the order of the schema.
+    pub fn triggers(&self) -> &[Trigger] {
+        &self.triggers
+    }
+
+    pub fn find_trigger(&self, trigger_name: &str) -> Option<&Trigger> {
+        self.triggers.iter().find(|trigger| trigger.name == trigger_name)
+    }
+
+    pub fn find_index(&self, index_name: &str) -> Option<&Index> {
+        self.tables
+            .values()
+            .flat_map(Table::all_indexes)
+            .find(|index| index.name == index_name)
+    }
 }
 
 impl Default for SchemaStore {
@@ -77,6 +246,9 @@ impl Default for SchemaStore {
         Self {
             tables: HashMap::new(),
             table_names: vec![],
+            views: HashMap::new(),
+            view_names: vec![],
+            triggers: vec![],
         }
     }
 }
@@ -86,27 +258,166 @@ pub struct Table {
     pub name: String,
     pub columns: Vec<Column>,
     pub indexes: Vec<Index>,
+    /// The indexes SQLite makes for PRIMARY KEY and UNIQUE constraints, in
+    /// the order it numbers them. Unlike `indexes`, queries don't read
+    /// them.
+    pub autoindexes: Vec<Index>,
     pub rootpage: u32,
+    pub without_rowid: bool,
+    pub module: Option<VirtualTableModule>,
+    /// Columns of the PRIMARY KEY in key order, from a table constraint or
+    /// a column.
+    pub primary_key: Vec<String>,
+    /// Column sets from UNIQUE table constraints.
+    pub unique: Vec<Vec<String>>,
+    /// Conditions from CHECK table constraints.
+    pub checks: Vec<sql::WhereClause>,
+    /// From FOREIGN KEY table constraints. See [`Table::foreign_keys`] for
+    /// these along with the REFERENCES of single columns.
+    pub foreign_key_constraints: Vec<sql::ForeignKey>,
+}
+
+/// A SELECT stored under a name, which can be queried like a table.
+#[derive(Debug, Clone)]
+pub struct View {
+    pub name: String,
+    /// Names given to the columns, if any. Otherwise they are those of the
+    /// result columns of the SELECT.
+    pub columns: Vec<String>,
+    pub select: sql::SelectStatement,
+}
+
+/// Statements SQLite runs on a change to a table, kept for inspection.
+#[derive(Debug, Clone)]
+pub struct Trigger {
+    pub name: String,
+    pub table: String,
+    pub timing: sql::TriggerTiming,
+    pub event: sql::TriggerEvent,
+    /// The whole CREATE TRIGGER statement.
+    pub sql: String,
+}
+
+/// The `USING module(arguments)` part of a virtual table definition.
+#[derive(Debug, Clone)]
+pub struct VirtualTableModule {
+    pub name: String,
+    pub arguments: Vec<String>,
 }
 
 impl Table {
+    /// The automatic indexes of the table, then the others.
+    pub fn all_indexes(&self) -> impl Iterator<Item = &Index> {
+        self.autoindexes.iter().chain(self.indexes.iter())
+    }
+
+    /// The column named `column_name`, with the position of its value in a
+    /// record. Virtual generated columns have none, so aren't found.
     pub fn find_column(&self, column_name: &str) -> Option<(usize, &Column)> {
         self.columns
             .iter()
+            .filter(|column| column.is_stored())
             .enumerate()
             .find(|(_, column)| column.name == column_name)
     }
 
+    /// The value at `pos` in `record`, a row of the table. Rows stored
+    /// before a column was added by ALTER TABLE end before it, and read as
+    /// its DEFAULT, or NULL, like in SQLite. SQLite stores reals without a
+    /// fraction as integers, which columns of REAL affinity read back as
+    /// reals.
+    pub fn record_value<'a>(&'a self, record: &Record<'a>, pos: usize) -> ColumnValue<'a> {
+        let column = self.columns.iter().filter(|column| column.is_stored()).nth(pos);
+        let value = match (record.values.get(pos), column.and_then(|column| column.default_value.as_ref())) {
+            (Some(value), _) => value.clone(),
+            (None, Some(default)) => match default.evaluate(&|_| ColumnValue::Null) {
+                Value::Read(value) => value,
+                _ => ColumnValue::Null,
+            },
+            (None, None) => ColumnValue::Null,
+        };
+        match value.as_integer() {
+            Some(n) if column.is_some_and(|column| column.affinity == Affinity::Real) => ColumnValue::F64(n as f64),
+            _ => value,
+        }
+    }
+
+    /// How values of the column named `column_name` compare as text. The
+    /// rowid and names that aren't columns compare byte by byte.
+    pub fn collation(&self, column_name: &str) -> Collation {
+        self.columns
+            .iter()
+            .find(|column| column.name == column_name)
+            .map_or(Collation::Binary, |column| column.collation)
+    }
+
+    /// Every foreign key of the table, whether declared on a column or as a
+    /// table constraint.
+    pub fn foreign_keys(&self) -> impl Iterator<Item = &sql::ForeignKey> {
+        self.columns
+            .iter()
+            .filter_map(|column| column.foreign_key.as_ref())
+            .chain(self.foreign_key_constraints.iter())
+    }
+
     pub fn is_user_table(&self) -> bool {
         !self.name.starts_with("sqlite_")
     }
 
-    pub fn find_applicable_index(&self, filter: &Option<sql::WhereClause>) -> Option<&Index> {
-        let Some(filter) = filter else { return None; };
+    pub fn is_virtual(&self) -> bool {
+        self.module.is_some()
+    }
 
+    pub fn is_module(&self, module: &str) -> bool {
+        self.module
+            .as_ref()
+            .is_some_and(|m| m.name.eq_ignore_ascii_case(module))
+    }
+
+    /// An index to look up the rows in: the one expected to find the fewest
+    /// rows, or of those the one whose leading columns the WHERE clause
+    /// bounds the most of. See [`Table::index_bounds`].
+    pub fn find_applicable_index(&self, filter: &Option<sql::WhereClause>) -> Option<&Index> {
+        let filter = filter.as_ref()?;
         self.indexes
             .iter()
-            .find(|index| filter.field == index.columns[0])
+            .map(|index| (index, self.index_bounds(index, filter)))
+            .filter(|(_, bounds)| !bounds.is_empty())
+            .min_by_key(|(index, bounds)| (index.estimated_rows(bounds), std::cmp::Reverse(bounds.len())))
+            .map(|(index, _)| index)
+    }
+
+    /// The comparisons of `filter` that narrow down the keys of `index`:
+    /// equalities on its leading columns, then at most a lower and an upper
+    /// bound on the column after them.
+    pub fn index_bounds<'a>(&self, index: &Index, filter: &'a sql::WhereClause) -> Vec<&'a sql::Comparison> {
+        use sql::Operator;
+
+        let conjuncts = filter.conjuncts();
+        let mut bounds = vec![];
+        for (column, collation) in index.columns.iter().zip(&index.collations) {
+            // Lookups compare keys byte by byte, so a column is only used
+            // when it and the index both compare with BINARY.
+            if *collation != Collation::Binary || self.collation(column) != Collation::Binary {
+                break;
+            }
+            let on_column = |c: &&&sql::Comparison| c.field == *column;
+            if let Some(equal) = conjuncts.iter().filter(on_column).find(|c| c.operator == Operator::Equal) {
+                bounds.push(*equal);
+                continue;
+            }
+            let lower = conjuncts
+                .iter()
+                .filter(on_column)
+                .find(|c| matches!(c.operator, Operator::Greater | Operator::GreaterEqual));
+            let upper = conjuncts
+                .iter()
+                .filter(on_column)
+                .find(|c| matches!(c.operator, Operator::Less | Operator::LessEqual));
+            bounds.extend(lower.into_iter().chain(upper).copied());
+            break;
+        }
+        bounds
     }
 }
 
@@ -116,7 +427,14 @@ impl From<Index> for Table {
             name: index.table_name.clone(),
             columns: vec![],
             indexes: vec![index],
+            autoindexes: vec![],
             rootpage: 0,
+            without_rowid: false,
+            module: None,
+            unique: vec![],
+            checks: vec![],
+            foreign_key_constraints: vec![],
+            primary_key: vec![],
         }
     }
 }
@@ -124,14 +442,75 @@ impl From<Index> for Table {
 #[derive(Debug, Clone)]
 pub struct Column {
     pub name: String,
+    /// The type the column was declared with, as written.
+    pub type_name: Option<String>,
+    /// The affinity of `type_name`.
+    pub affinity: Affinity,
+    /// Whether the column is the rowid, being declared INTEGER PRIMARY KEY.
     pub is_primary_key: bool,
+    /// Whether the column is part of the PRIMARY KEY, alone or with others.
+    pub in_primary_key: bool,
+    /// Declared AUTOINCREMENT. See [`crate::database::Database::sequence`].
+    pub autoincrement: bool,
+    /// False when declared NOT NULL.
+    pub nullable: bool,
+    /// The value given by a DEFAULT constraint.
+    pub default_value: Option<Expr>,
+    /// The DEFAULT as written, like `'none'` or `(1 + 2)`.
+    pub default_text: Option<String>,
+    pub unique: bool,
+    /// Conditions from CHECK constraints on the column.
+    pub checks: Vec<sql::WhereClause>,
+    /// From a REFERENCES constraint on the column.
+    pub foreign_key: Option<sql::ForeignKey>,
+    pub generated: Option<sql::Generated>,
+    pub collation: Collation,
+}
+
+impl Column {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            type_name: None,
+            affinity: Affinity::Blob,
+            is_primary_key: false,
+            in_primary_key: false,
+            autoincrement: false,
+            nullable: true,
+            default_value: None,
+            default_text: None,
+            unique: false,
+            checks: vec![],
+            foreign_key: None,
+            generated: None,
+            collation: Collation::Binary,
+        }
+    }
+
+    /// Whether the value is kept in the record, which it is unless the
+    /// column is a virtual generated one.
+    pub fn is_stored(&self) -> bool {
+        self.generated.as_ref().is_none_or(|generated| generated.stored)
+    }
 }
 
 impl From<&sql::Field> for Column {
     fn from(field: &sql::Field) -> Self {
         Self {
             name: field.name.clone(),
+            type_name: field.type_name.clone(),
+            affinity: Affinity::of(field.type_name.as_deref().unwrap_or("")),
             is_primary_key: field.is_primary_key,
+            in_primary_key: field.in_primary_key,
+            autoincrement: field.autoincrement,
+            nullable: field.nullable,
+            default_value: field.default_value.clone(),
+            default_text: field.default_text.clone(),
+            unique: field.unique,
+            checks: field.checks.clone(),
+            foreign_key: field.foreign_key.clone(),
+            generated: field.generated.clone(),
+            collation: field.collation,
         }
     }
 }
@@ -140,8 +519,16 @@ impl From<&sql::Field> for Column {
 pub struct Index {
     pub name: String,
     pub columns: Vec<String>,
+    /// How each column compares in the index.
+    pub collations: Vec<Collation>,
+    /// Whether each column is in descending order.
+    pub descending: Vec<bool>,
     pub table_name: String,
     pub rootpage: u32,
+    /// From `sqlite_stat1` once ANALYZE has run: the rows in the index, then
+    /// how many rows on average share a value of the first column, of the
+    /// first two, and so on.
+    pub stat: Vec<u64>,
 }
 
 impl Index {
@@ -151,6 +538,24 @@ impl Index {
             .enumerate()
             .find(|(_, column)| *column == column_name)
     }
+
+    /// Rows a lookup by `bounds` is expected to find. See
+    /// [`Table::index_bounds`]. Without statistics, like SQLite, an index
+    /// is taken to have a million rows, ten of which share a key prefix.
+    pub fn estimated_rows(&self, bounds: &[&sql::Comparison]) -> u64 {
+        let equalities = bounds
+            .iter()
+            .filter(|bound| bound.operator == sql::Operator::Equal)
+            .count();
+        let rows = match self.stat.as_slice() {
+            [] if equalities == 0 => 1_000_000,
+            [] => 10,
+            stat => stat[equalities.min(stat.len() - 1)],
+        };
+        // Every bound of a range is taken to keep a quarter of the rows.
+        let ranges = (bounds.len() - equalities) as u32;
+        (rows / 4u64.pow(ranges)).max(1)
+    }
 }
 
 #[derive(Debug)]
@@ -159,11 +564,14 @@ pub struct SQLiteSchema {
 }
 
 impl SQLiteSchema {
-    pub fn read(page: Page) -> Result<Self> {
-        let rows: Vec<SQLiteSchemaRow> = page
-            .cells()
-            .map(|cell| SQLiteSchemaRow::try_from(cell))
-            .collect::<Result<_>>()?;
+    /// Reads the rows of the schema table, whose B-tree starts on the first
+    /// page and may span others.
+    pub fn read(database: &mut Database) -> Result<Self> {
+        let mut rows = vec![];
+        for entry in BTreeCursor::new(database, 1, Direction::Forward)? {
+            let entry = entry?;
+            rows.push(SQLiteSchemaRow::try_from(Record::read(entry.rowid, &entry.payload))?);
+        }
 
         Ok(Self { rows })
     }
@@ -179,74 +587,107 @@ pub struct SQLiteSchemaRow {
     pub sql: String,
 }
 
-impl<'page> TryFrom<Cell<'page>> for SQLiteSchemaRow {
-    type Error = anyhow::Error;
-
-    fn try_from(cell: Cell) -> std::result::Result<Self, Self::Error> {
-        if let Cell::LeafTable {
-            size: _,
-            rowid,
-            payload,
-            overflow_page: _,
-        } = cell
-        {
-            let record = Record::read(rowid, payload);
-
-            let mut values = record.values.into_iter();
-            let kind = values
-                .next()
-                .and_then(|v| match v {
-                    ColumnValue::Text(text) => Some(String::from_utf8_lossy(text).into()),
-                    _ => None,
-                })
-                .map_or_else(|| Err(anyhow::anyhow!("Invalid schema kind")), Ok)?;
-
-            let name = values
-                .next()
-                .and_then(|v| match v {
-                    ColumnValue::Text(text) => Some(String::from_utf8_lossy(text).into()),
-                    _ => None,
-                })
-                .map_or_else(|| Err(anyhow::anyhow!("Invalid schema name")), Ok)?;
-
-            let tbl_name = values
-                .next()
-                .and_then(|v| match v {
-                    ColumnValue::Text(text) => Some(String::from_utf8_lossy(text).into()),
-                    _ => None,
-                })
-                .map_or_else(|| Err(anyhow::anyhow!("Invalid schema table name")), Ok)?;
-
-            let rootpage = values
-                .next()
-                .and_then(|v| {
-                    if v.is_number() {
-                        let page_number: i64 = v.into();
-                        Some(page_number as u32)
-                    } else {
-                        None
-                    }
-                })
-                .map_or_else(|| Err(anyhow::anyhow!("Invalid schema root page")), Ok)?;
-
-            let sql = values
-                .next()
-                .and_then(|v| match v {
-                    ColumnValue::Text(text) => Some(String::from_utf8_lossy(text).into()),
-                    _ => None,
-                })
-                .map_or_else(|| Err(anyhow::anyhow!("Invalid schema SQL")), Ok)?;
-
-            Ok(SQLiteSchemaRow {
-                rowid,
-                kind,
-                name,
-                tbl_name,
-                rootpage,
-                sql,
+impl TryFrom<Record<'_>> for SQLiteSchemaRow {
+    type Error = Error;
+
+    fn try_from(record: Record) -> std::result::Result<Self, Self::Error> {
+        let mut values = record.values.into_iter();
+        let kind = values
+            .next()
+            .and_then(|v| match v {
+                ColumnValue::Text(text) => Some(String::from_utf8_lossy(text).into()),
+                _ => None,
+            })
+            .map_or_else(|| Err(format_err!("Invalid schema kind")), Ok)?;
+
+        let name = values
+            .next()
+            .and_then(|v| match v {
+                ColumnValue::Text(text) => Some(String::from_utf8_lossy(text).into()),
+                _ => None,
+            })
+            .map_or_else(|| Err(format_err!("Invalid schema name")), Ok)?;
+
+        let tbl_name = values
+            .next()
+            .and_then(|v| match v {
+                ColumnValue::Text(text) => Some(String::from_utf8_lossy(text).into()),
+                _ => None,
+            })
+            .map_or_else(|| Err(format_err!("Invalid schema table name")), Ok)?;
+
+        let rootpage = values
+            .next()
+            .and_then(|v| {
+                if v.is_number() {
+                    let page_number: i64 = v.into();
+                    Some(page_number as u32)
+                } else {
+                    None
+                }
+            })
+            .map_or_else(|| Err(format_err!("Invalid schema root page")), Ok)?;
+
+        // Indexes made for UNIQUE and PRIMARY KEY constraints have no SQL.
+        let sql = values
+            .next()
+            .and_then(|v| match v {
+                ColumnValue::Text(text) => Some(String::from_utf8_lossy(text).into()),
+                ColumnValue::Null => Some(String::new()),
+                _ => None,
             })
-        } else {
-            Err(anyhow::anyhow!("Invalid cell kind"))
+            .map_or_else(|| Err(format_err!("Invalid schema SQL")), Ok)?;
+
+        Ok(SQLiteSchemaRow {
+            rowid: record.rowid,
+            kind,
+            name,
+            tbl_name,
+            rootpage,
+            sql,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::Database;
+    use crate::record::Collation;
+
+    #[test]
+    fn describe_tables_and_indexes() {
+        let mut database = Database::open_in_memory().unwrap();
+        for sql in [
+            "CREATE TABLE orders (id, item TEXT NOT NULL, placed DEFAULT CURRENT_TIMESTAMP, PRIMARY KEY (item, id))",
+            "CREATE TABLE notes (body)",
+            "CREATE INDEX by_placed ON orders (placed, item COLLATE NOCASE)",
+        ] {
+            database.query(sql).unwrap();
         }
+        let schema = database.schema();
+        let tables: Vec<_> = schema.tables().map(|table| table.name.as_str()).collect();
+        assert_eq!(tables, ["orders", "notes"]);
+        assert_eq!(schema.views().count(), 0);
+
+        let orders = schema.find_table("orders").unwrap();
+        assert_eq!(orders.primary_key, ["item", "id"]);
+        let columns: Vec<_> = orders
+            .columns
+            .iter()
+            .map(|column| (column.type_name.as_deref(), column.nullable, column.default_text.as_deref()))
+            .collect();
+        assert_eq!(
+            columns,
+            [(None, true, None), (Some("TEXT"), false, None), (None, true, Some("CURRENT_TIMESTAMP"))]
+        );
+
+        let indexes: Vec<_> = schema.indexes().collect();
+        assert_eq!(indexes.len(), 2);
+        assert_eq!(indexes[0].name, "sqlite_autoindex_orders_1");
+        assert_eq!(indexes[0].columns, ["item", "id"]);
+        assert_eq!(indexes[1].columns, ["placed", "item"]);
+        assert_eq!(indexes[1].collations, [Collation::Binary, Collation::NoCase]);
+        assert_eq!(indexes[1].descending, [false, false]);
+        assert!(schema.find_index("sqlite_autoindex_orders_1").is_some());
     }
-}
\ No newline at end of file
+}
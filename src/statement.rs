@@ -0,0 +1,2230 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::btree::{BTreeCursor, Direction};
+use crate::database::{column_value, evaluate, is_rowid_alias, set_key, Database, RowCounter};
+use crate::error::{bail, Error, Result};
+use crate::expr::{Expr, Literal, Value};
+use crate::group::Grouper;
+use crate::output::{Limit, RowSink, RowWriter};
+use crate::planner::{Plan, QueryPlan, Step};
+use crate::pragma;
+use crate::record::{Collation, ColumnValue, OwnedValue, Record};
+use crate::row::FromValue;
+use crate::sort::{SortKey, Sorter};
+use crate::sql::{
+    self, AggregateFunction, AggregateSelection, CompoundOperator, CompoundSelection,
+    DropTableStatement, ExpressionSelection, InSelect, InSet, MinMax, OrderingTerm, ResultColumn,
+    SQLCommand, SelectColumn, SelectFields, SelectStatement, WhereClause,
+};
+use crate::sqlite_schema::{Table, View};
+use crate::stats::{Access, Stats};
+
+/// A parsed statement, which can be executed any number of times. The
+/// counters of the last execution are kept in `stats`.
+#[derive(Debug, Clone)]
+pub struct Statement {
+    sql: String,
+    command: SQLCommand,
+    /// How many `?` parameters the statement has: the highest number.
+    parameter_count: usize,
+    /// Values of the parameters, in order; missing ones are NULL.
+    parameters: Vec<Literal>,
+    /// Names of the result columns.
+    columns: Vec<String>,
+    /// Where every result column comes from, once expanded.
+    origins: Vec<Option<ColumnOrigin>>,
+    stats: Stats,
+}
+
+/// The column of a table a result column reads, seen through views, as
+/// SQLite reports it for `sqlite3_column_table_name` and the like.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnOrigin {
+    /// The name of the table in its database, without the schema name.
+    pub table: String,
+    /// The name of the column, as declared.
+    pub column: String,
+    /// The type the column was declared with, as written.
+    pub decl_type: Option<String>,
+}
+
+impl Statement {
+    pub fn prepare(sql: &str) -> Result<Self> {
+        Self::new(sql, sql::parse_statement(sql)?)
+    }
+
+    /// Prepares every statement of a script, failing before any runs if
+    /// one doesn't parse.
+    pub fn prepare_all(script: &str) -> Result<Vec<Self>> {
+        let commands = sql::parse_statements(script)?;
+        sql::split_statements(script)
+            .into_iter()
+            .zip(commands)
+            .map(|((_, sql), command)| Self::new(sql, command))
+            .collect()
+    }
+
+    fn new(sql: &str, mut command: SQLCommand) -> Result<Self> {
+        let parameter_count = match &mut command {
+            // Nothing in a table definition takes a parameter.
+            SQLCommand::CreateTable(_)
+            | SQLCommand::CreateIndex(_)
+            | SQLCommand::DropTable(_)
+            | SQLCommand::AlterTable(_)
+            | SQLCommand::Begin
+            | SQLCommand::Commit
+            | SQLCommand::Rollback
+            | SQLCommand::Attach(_)
+            | SQLCommand::Detach(_) => 0,
+            SQLCommand::Pragma(pragma) => match pragma::columns(&pragma.name) {
+                Some(_) => 0,
+                None => bail!("Unsupported PRAGMA: {}", pragma.name),
+            },
+            command => match selection_mut(command) {
+                Some(select) => number_parameters(select)?,
+                None => bail!("Unsupported command: {}", sql),
+            },
+        };
+
+        let columns = command_columns(&command);
+        Ok(Self {
+            sql: sql.to_string(),
+            origins: vec![None; columns.len()],
+            columns,
+            command,
+            parameter_count,
+            parameters: vec![],
+            stats: Stats::default(),
+        })
+    }
+
+    /// Expands `*` and `table.*` in the result columns to the columns of
+    /// the table, in schema order. Until then they are named as written.
+    pub fn expand(&mut self, database: &Database) -> Result<()> {
+        if let SQLCommand::Select(select) = &mut self.command {
+            expand_select(database, select)?;
+            self.columns = result_columns(select);
+            self.origins = column_origins(database, select);
+        } else if let Some(select) = selection_mut(&mut self.command) {
+            expand_select(database, select)?;
+        }
+        Ok(())
+    }
+
+    /// Sets the values of the `?` parameters for the following executions,
+    /// the first for `?1`. Parameters without a value are NULL.
+    pub fn bind(&mut self, values: &[Literal]) -> Result<()> {
+        if values.len() > self.parameter_count {
+            bail!(
+                "Too many values: the statement has {} parameters, got {}",
+                self.parameter_count,
+                values.len()
+            );
+        }
+        self.parameters = values.to_vec();
+        Ok(())
+    }
+
+    pub fn parameter_count(&self) -> usize {
+        self.parameter_count
+    }
+
+    pub fn sql(&self) -> &str {
+        &self.sql
+    }
+
+    /// Names of the result columns.
+    pub fn columns(&self) -> Vec<String> {
+        self.columns.clone()
+    }
+
+    pub fn column_count(&self) -> usize {
+        self.columns.len()
+    }
+
+    pub fn column_name(&self, index: usize) -> Option<&str> {
+        self.columns.get(index).map(String::as_str)
+    }
+
+    /// The declared type of the table column that result column `index`
+    /// reads. Expressions, and columns of a statement not yet expanded,
+    /// have none.
+    pub fn column_decl_type(&self, index: usize) -> Option<&str> {
+        self.column_origin(index)?.decl_type.as_deref()
+    }
+
+    /// The table column that result column `index` reads, if it is one
+    /// rather than an expression, once the statement is expanded.
+    pub fn column_origin(&self, index: usize) -> Option<&ColumnOrigin> {
+        self.origins.get(index)?.as_ref()
+    }
+
+    /// Whether the statement leaves the database as it is.
+    pub fn is_read_only(&self) -> bool {
+        matches!(
+            self.command,
+            SQLCommand::Select(_) | SQLCommand::ExplainQueryPlan(_) | SQLCommand::Pragma(_)
+        )
+    }
+
+    /// Whether the statement is `EXPLAIN QUERY PLAN`, whose rows are the
+    /// steps of [`Statement::query_plan`].
+    pub fn is_explain(&self) -> bool {
+        matches!(self.command, SQLCommand::ExplainQueryPlan(_))
+    }
+
+    /// How the SELECT, or the one explained, would read its rows with the
+    /// values bound so far. Nothing is read but the schema.
+    pub fn query_plan(&mut self, database: &Database) -> Result<QueryPlan> {
+        self.expand(database)?;
+        Ok(QueryPlan {
+            steps: plan_steps(database, &self.bound_select()?)?,
+        })
+    }
+
+    fn bound_select(&self) -> Result<SelectStatement> {
+        let Some(select) = selection(&self.command) else {
+            bail!("Unsupported command: {}", self.sql);
+        };
+        let mut select = select.clone();
+        bind_parameters(&mut select, &self.parameters);
+        Ok(select)
+    }
+
+    /// Writes the result rows to `out`. The counters of the run are kept
+    /// until the next one, including when it fails part way.
+    pub fn execute(
+        &mut self,
+        database: &mut Database,
+        out: &mut RowWriter<'_, impl std::io::Write>,
+    ) -> Result<()> {
+        self.expand(database)?;
+        database.stats.reset();
+        let rows = out.rows();
+        let result = self.run(database, out);
+
+        self.stats = database.stats.clone();
+        self.stats.rows_returned = out.rows() - rows;
+        result
+    }
+
+    /// Runs the statement, returning its rows as they are asked for rather
+    /// than writing them out. A SELECT of the columns of a table, without
+    /// ORDER BY and without an index or rowid to find its rows by, reads
+    /// one record at a time, only as far as the rows taken; other
+    /// statements are run to the end first. The counters of the run are
+    /// kept once the rows are dropped.
+    pub fn query<'a>(&'a mut self, database: &'a mut Database) -> Result<Rows<'a>> {
+        self.expand(database)?;
+        database.stats.reset();
+
+        let scanned = match &self.command {
+            SQLCommand::Select(select) => scanned_table(database, select).map(|table| (table, self.bound_select())),
+            _ => None,
+        };
+        let source = match scanned {
+            Some((table, select)) => {
+                let select = select?;
+                let select = run_subqueries(database, &select);
+                self.stats = database.stats.clone();
+                let SelectStatement::Fields(command) = select? else {
+                    unreachable!("Subqueries are replaced in place");
+                };
+                let stats = std::mem::take(&mut database.stats);
+                let (database, _) = database.resolve_mut(&command.table);
+                database.stats = stats;
+                database.stats.access = Some(Access::TableScan);
+                Source::Scan(Box::new(Scan {
+                    cursor: BTreeCursor::new(database, table.rootpage, Direction::Forward)?,
+                    table,
+                    offset: command.offset,
+                    limit: command.limit,
+                    command,
+                }))
+            }
+            None => {
+                let mut rows = RowBuffer::default();
+                let result = self.run(database, &mut rows);
+                self.stats = database.stats.clone();
+                self.stats.rows_returned = rows.0.len() as u64;
+                result?;
+                Source::Buffered(rows.0.into_iter())
+            }
+        };
+        Ok(Rows {
+            source,
+            stats: &mut self.stats,
+            returned: 0,
+        })
+    }
+
+    /// Runs the statement to the end, writing the rows of a SELECT to `out`.
+    fn run(&self, database: &mut Database, out: &mut impl RowSink) -> Result<()> {
+        match &self.command {
+            SQLCommand::CreateTable(create) if create.temporary => {
+                write(database.temp_mut()?, |temp| temp.create_table(create, &self.sql))
+            }
+            SQLCommand::CreateTable(create) => write(database, |database| database.create_table(create, &self.sql)),
+            SQLCommand::CreateIndex(create) => database.with_database_of(&create.table, |database, _| {
+                write(database, |database| database.create_index(create, &self.sql))
+            }),
+            SQLCommand::DropTable(drop) => database.with_database_of(&drop.table, |database, name| {
+                let drop = DropTableStatement {
+                    table: name.to_string(),
+                    if_exists: drop.if_exists,
+                };
+                write(database, |database| database.drop_table(&drop))
+            }),
+            SQLCommand::AlterTable(alter) => database.with_database_of(&alter.table, |database, _| {
+                write(database, |database| database.alter_table(alter))
+            }),
+            SQLCommand::Begin => database.begin_transaction(),
+            SQLCommand::Commit => database.commit_transaction(),
+            SQLCommand::Rollback => database.rollback_transaction(),
+            SQLCommand::Attach(attach) => database.attach(&attach.path, &attach.name),
+            SQLCommand::Detach(name) => database.detach(name),
+            SQLCommand::Delete(delete) => {
+                // Subqueries may read tables of the other database.
+                let mut select = run_subqueries(database, &self.bound_select()?)?;
+                database.with_database_of(&delete.table, |database, name| {
+                    set_selected_table(&mut select, name);
+                    write(database, |database| delete_rows(database, name, &select))
+                })
+            }
+            SQLCommand::ExplainQueryPlan(_) => write_plan(database, &self.bound_select()?, out),
+            SQLCommand::Pragma(pragma) => pragma::run(database, pragma, out),
+            _ => run(database, &self.bound_select()?, out),
+        }
+    }
+
+    /// Counters of the last execution. Sort spills are the times ORDER BY
+    /// ran out of memory and wrote rows to a temporary file.
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+}
+
+/// Statements kept by default by [`Database::prepare_cached`].
+pub const DEFAULT_STATEMENT_CACHE_SIZE: usize = 16;
+
+/// Prepared statements by their SQL, dropping the least recently used one
+/// once `capacity` are held. They are forgotten when the schema they were
+/// prepared with changes, since the tables they read may be different.
+#[derive(Debug)]
+pub struct StatementCache {
+    capacity: usize,
+    statements: HashMap<String, (Statement, u64)>,
+    /// SQL of the statements by the tick of their last use, oldest first.
+    recency: BTreeMap<u64, String>,
+    tick: u64,
+    /// Schema cookies of the databases the statements were prepared for.
+    schema: Vec<u32>,
+    /// Lookups that found their statement, since the cache was created.
+    pub hits: u64,
+    /// Lookups that had to parse their statement.
+    pub misses: u64,
+}
+
+impl StatementCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            statements: HashMap::new(),
+            recency: BTreeMap::new(),
+            tick: 0,
+            schema: vec![],
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Changes how many statements are kept, dropping the least recently
+    /// used ones above the new capacity.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.evict();
+    }
+
+    pub fn len(&self) -> usize {
+        self.statements.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.statements.is_empty()
+    }
+
+    /// A copy of the statement prepared from `sql` for the databases with
+    /// these schema cookies, now the most recently used, without values
+    /// bound or counters.
+    pub fn get(&mut self, sql: &str, schema: &[u32]) -> Option<Statement> {
+        if self.schema != schema {
+            self.clear();
+            self.schema = schema.to_vec();
+        }
+        let Some((statement, used)) = self.statements.get_mut(sql) else {
+            self.misses += 1;
+            return None;
+        };
+        self.hits += 1;
+        self.tick += 1;
+        self.recency.remove(used);
+        self.recency.insert(self.tick, sql.to_string());
+        *used = self.tick;
+        Some(statement.clone())
+    }
+
+    /// Keeps `statement`, prepared from its SQL for the databases whose
+    /// schema cookies were last given to `get`.
+    pub fn insert(&mut self, statement: &Statement) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.tick += 1;
+        let sql = statement.sql().to_string();
+        if let Some((_, used)) = self.statements.insert(sql.clone(), (statement.clone(), self.tick)) {
+            self.recency.remove(&used);
+        }
+        self.recency.insert(self.tick, sql);
+        self.evict();
+    }
+
+    /// Forgets every statement. The counters carry on.
+    pub fn clear(&mut self) {
+        self.statements.clear();
+        self.recency.clear();
+    }
+
+    fn evict(&mut self) {
+        while self.statements.len() > self.capacity {
+            let Some((_, sql)) = self.recency.pop_first() else {
+                break;
+            };
+            self.statements.remove(&sql);
+        }
+    }
+}
+
+impl Default for StatementCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_STATEMENT_CACHE_SIZE)
+    }
+}
+
+/// A result row, holding its values.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Row {
+    values: Vec<OwnedValue>,
+}
+
+impl Row {
+    fn new(values: &[ColumnValue]) -> Self {
+        Self {
+            values: values.iter().map(ColumnValue::to_owned).collect(),
+        }
+    }
+
+    pub fn values(&self) -> &[OwnedValue] {
+        &self.values
+    }
+
+    /// The value of the column at `index`, from 0, read as `T`.
+    pub fn get<T: FromValue>(&self, index: usize) -> Result<T> {
+        let Some(value) = self.values.get(index) else {
+            bail!("Column index out of range: {}", index);
+        };
+        T::from_value(value)
+    }
+
+    /// Takes the values out of the row.
+    pub fn into_values(self) -> Vec<OwnedValue> {
+        self.values
+    }
+}
+
+impl From<Vec<OwnedValue>> for Row {
+    fn from(values: Vec<OwnedValue>) -> Self {
+        Self { values }
+    }
+}
+
+/// The rows of a statement, from [`Statement::query`].
+pub struct Rows<'a> {
+    source: Source<'a>,
+    /// The counters of the statement, updated once the rows are dropped.
+    stats: &'a mut Stats,
+    returned: u64,
+}
+
+enum Source<'a> {
+    Scan(Box<Scan<'a>>),
+    /// Rows of a statement that was run to the end.
+    Buffered(std::vec::IntoIter<Row>),
+}
+
+/// The rows of a table read through a cursor, filtered and cut down to
+/// the columns selected one at a time.
+struct Scan<'a> {
+    cursor: BTreeCursor<'a>,
+    table: Table,
+    command: SelectFields,
+    /// Rows that pass the WHERE clause still to skip.
+    offset: u64,
+    /// Rows still to return.
+    limit: Option<u64>,
+}
+
+impl Scan<'_> {
+    fn next_row(&mut self) -> Result<Option<Row>> {
+        while self.limit != Some(0) {
+            let Some(entry) = self.cursor.next().transpose()? else {
+                return Ok(None);
+            };
+            let record = Record::read(entry.rowid, &entry.payload);
+            let value = |name: &str| column_value(&self.table, &record, name);
+            if let Some(filter) = &self.command.where_clause {
+                if !evaluate(filter, &value, &|name| self.table.collation(name)) {
+                    continue;
+                }
+            }
+            if self.offset > 0 {
+                self.offset -= 1;
+                continue;
+            }
+
+            self.limit = self.limit.map(|limit| limit - 1);
+            let values = self.command.fields.iter().map(|field| value(field)).collect::<Vec<_>>();
+            return Ok(Some(Row::new(&values)));
+        }
+        Ok(None)
+    }
+}
+
+impl Iterator for Rows<'_> {
+    type Item = Result<Row>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let row = match &mut self.source {
+            Source::Scan(scan) => scan.next_row().transpose(),
+            Source::Buffered(rows) => rows.next().map(Ok),
+        };
+        if let Some(Ok(_)) = row {
+            self.returned += 1;
+        }
+        row
+    }
+}
+
+impl Drop for Rows<'_> {
+    fn drop(&mut self) {
+        if let Source::Scan(scan) = &self.source {
+            *self.stats = scan.cursor.database().stats.clone();
+            self.stats.rows_returned = self.returned;
+        }
+    }
+}
+
+/// Keeps the rows of a statement run to the end for [`Rows`].
+#[derive(Default)]
+struct RowBuffer(Vec<Row>);
+
+impl RowSink for RowBuffer {
+    fn write_row(&mut self, values: &[ColumnValue]) -> Result<()> {
+        self.0.push(Row::new(values));
+        Ok(())
+    }
+}
+
+/// Checks the expressions of `select` and numbers its `?` parameters,
+/// returning how many it has: the highest number. A bare `?` comes after the
+/// highest number so far, as in SQLite.
+fn number_parameters(select: &mut SelectStatement) -> Result<usize> {
+    check_expressions(select)?;
+    let mut parameter_count = 0;
+    for expression in expressions_mut(select) {
+        expression.visit_mut(&mut |expression| {
+            if let Expr::Parameter(n) = expression {
+                if *n == 0 {
+                    *n = parameter_count + 1;
+                }
+                parameter_count = parameter_count.max(*n);
+            }
+        });
+    }
+    Ok(parameter_count)
+}
+
+/// Collects the rowids a DELETE selects.
+#[derive(Default)]
+struct Rowids(Vec<i64>);
+
+impl RowSink for Rowids {
+    fn write_row(&mut self, values: &[ColumnValue]) -> Result<()> {
+        if let Some(rowid) = values.first().and_then(ColumnValue::as_integer) {
+            self.0.push(rowid);
+        }
+        Ok(())
+    }
+}
+
+/// The SELECT a command runs: its own, the one it explains, or the one
+/// finding the rows a DELETE deletes.
+fn selection(command: &SQLCommand) -> Option<&SelectStatement> {
+    match command {
+        SQLCommand::Select(select) | SQLCommand::ExplainQueryPlan(select) => Some(select),
+        SQLCommand::Delete(delete) => Some(&delete.selection),
+        _ => None,
+    }
+}
+
+fn selection_mut(command: &mut SQLCommand) -> Option<&mut SelectStatement> {
+    match command {
+        SQLCommand::Select(select) | SQLCommand::ExplainQueryPlan(select) => Some(select),
+        SQLCommand::Delete(delete) => Some(&mut delete.selection),
+        _ => None,
+    }
+}
+
+/// Makes the changes of `change` to the database: all of them once it
+/// succeeds, or, if it fails part way, none.
+fn write(database: &mut Database, change: impl FnOnce(&mut Database) -> Result<()>) -> Result<()> {
+    database.begin_write()?;
+    match change(database) {
+        Ok(()) => database.commit(),
+        Err(error) => {
+            database.rollback()?;
+            Err(error)
+        }
+    }
+}
+
+/// Deletes the rows `select` finds from `table`, and their index entries.
+fn delete_rows(database: &mut Database, table: &str, select: &SelectStatement) -> Result<()> {
+    let mut rowids = Rowids::default();
+    run(database, select, &mut rowids)?;
+    database.stats.rows_changed = database.delete_rows(table, &rowids.0)?;
+    Ok(())
+}
+
+/// The table a SELECT can read one row at a time straight from its
+/// B-tree: the rows are returned in the order of the table, none is looked
+/// up through an index, and every column read is stored in the table.
+fn scanned_table(database: &Database, select: &SelectStatement) -> Option<Table> {
+    let SelectStatement::Fields(command) = select else {
+        return None;
+    };
+    let (database, name) = database.resolve(&command.table);
+    let table = database.schema.find_table(name)?;
+    let compared = command.where_clause.iter().flat_map(|clause| clause.columns());
+    let mut names = command.fields.iter().chain(compared);
+    let mut comparisons = command.where_clause.iter().flat_map(|clause| clause.comparisons());
+    if !command.order_by.is_empty()
+        || table.is_virtual()
+        || table.without_rowid
+        || reads_computed(database, name, names.clone())
+        || !names.all(|name| table.find_column(name).is_some() || is_rowid_alias(name))
+        || comparisons.any(|comparison| comparison.operator == sql::Operator::Match)
+    {
+        return None;
+    }
+    match Plan::new(table, &command.where_clause) {
+        Plan::FullScan => Some(table.clone()),
+        _ => None,
+    }
+}
+
+/// Checks the functions called in the statement and its subqueries.
+fn check_expressions(select: &SelectStatement) -> Result<()> {
+    let (columns, conditions) = match select {
+        SelectStatement::Expressions(command) => {
+            (command.columns.iter().collect(), vec![&command.where_clause])
+        }
+        SelectStatement::Fields(command) => (vec![], vec![&command.where_clause]),
+        SelectStatement::Count(command) => (vec![], vec![&command.where_clause]),
+        SelectStatement::Aggregate(command) => {
+            (vec![], vec![&command.where_clause, &command.having])
+        }
+        SelectStatement::MinMax(_) => (vec![], vec![]),
+        SelectStatement::Compound(command) => {
+            return command.arms.iter().try_for_each(check_expressions);
+        }
+    };
+    let conditions = conditions.into_iter().flatten().collect::<Vec<_>>();
+
+    let expressions = columns
+        .into_iter()
+        .map(|column: &SelectColumn| &column.expression)
+        .chain(conditions.iter().flat_map(|condition| condition.expressions()));
+    for expression in expressions {
+        expression.check()?;
+    }
+    for subquery in conditions.iter().flat_map(|condition| condition.subqueries()) {
+        check_expressions(subquery)?;
+    }
+    Ok(())
+}
+
+/// Every expression of the statement and its subqueries, in the order
+/// they are written.
+fn expressions_mut(select: &mut SelectStatement) -> Vec<&mut Expr> {
+    fn condition_expressions<'a>(condition: &'a mut WhereClause, out: &mut Vec<&'a mut Expr>) {
+        match condition {
+            WhereClause::Expression(comparison) => {
+                out.push(&mut comparison.left);
+                out.push(&mut comparison.right);
+            }
+            WhereClause::InSelect(subquery) => out.extend(expressions_mut(&mut subquery.select)),
+            WhereClause::And(left, right) | WhereClause::Or(left, right) => {
+                condition_expressions(left, out);
+                condition_expressions(right, out);
+            }
+            _ => {}
+        }
+    }
+
+    let mut expressions = vec![];
+    let conditions = match select {
+        SelectStatement::Expressions(command) => {
+            expressions.extend(command.columns.iter_mut().map(|column| &mut column.expression));
+            vec![&mut command.where_clause]
+        }
+        select => conditions_mut(select),
+    };
+    for condition in conditions.into_iter().flatten() {
+        condition_expressions(condition, &mut expressions);
+    }
+    expressions
+}
+
+/// The WHERE and HAVING clauses of the statement, and of every arm of a
+/// compound one.
+fn conditions_mut(select: &mut SelectStatement) -> Vec<&mut Option<WhereClause>> {
+    match select {
+        SelectStatement::Fields(command) => vec![&mut command.where_clause],
+        SelectStatement::Expressions(command) => vec![&mut command.where_clause],
+        SelectStatement::Count(command) => vec![&mut command.where_clause],
+        SelectStatement::Aggregate(command) => vec![&mut command.where_clause, &mut command.having],
+        SelectStatement::MinMax(_) => vec![],
+        SelectStatement::Compound(command) => {
+            command.arms.iter_mut().flat_map(conditions_mut).collect()
+        }
+    }
+}
+
+/// Replaces the parameters with their values, and simplifies the
+/// comparisons that became comparisons with a constant.
+fn bind_parameters(select: &mut SelectStatement, values: &[Literal]) {
+    fn simplify(condition: WhereClause) -> WhereClause {
+        match condition {
+            WhereClause::Expression(comparison) => comparison.simplify(),
+            WhereClause::InSelect(mut subquery) => {
+                simplify_conditions(&mut subquery.select);
+                WhereClause::InSelect(subquery)
+            }
+            WhereClause::And(left, right) => {
+                WhereClause::And(Box::new(simplify(*left)), Box::new(simplify(*right)))
+            }
+            WhereClause::Or(left, right) => {
+                WhereClause::Or(Box::new(simplify(*left)), Box::new(simplify(*right)))
+            }
+            condition => condition,
+        }
+    }
+    fn simplify_conditions(select: &mut SelectStatement) {
+        for condition in conditions_mut(select) {
+            *condition = condition.take().map(simplify);
+        }
+    }
+
+    for expression in expressions_mut(select) {
+        expression.visit_mut(&mut |expression| {
+            if let Expr::Parameter(n) = expression {
+                let value = values.get(*n - 1).cloned().unwrap_or(Literal::Null);
+                *expression = Expr::Literal(value);
+            }
+        });
+    }
+    simplify_conditions(select);
+}
+
+fn expand_select(database: &Database, select: &mut SelectStatement) -> Result<()> {
+    match select {
+        SelectStatement::Fields(command) => {
+            let mut fields = vec![];
+            for field in command.fields.iter() {
+                match expand_wildcard(database, &command.table, field)? {
+                    Some(columns) => fields.extend(columns),
+                    None => fields.push(field.clone()),
+                }
+            }
+            command.fields = fields;
+        }
+        SelectStatement::Expressions(command) => {
+            let mut columns = vec![];
+            for column in command.columns.iter() {
+                let expanded = match &column.expression {
+                    Expr::Column(name) => expand_wildcard(database, &command.table, name)?,
+                    _ => None,
+                };
+                match expanded {
+                    Some(names) => columns.extend(names.into_iter().map(|name| SelectColumn {
+                        expression: Expr::Column(name.clone()),
+                        name,
+                    })),
+                    None => columns.push(column.clone()),
+                }
+            }
+            command.columns = columns;
+        }
+        SelectStatement::Compound(command) => {
+            for arm in command.arms.iter_mut() {
+                expand_select(database, arm)?;
+            }
+            let columns = result_columns(&command.arms[0]).len();
+            for (arm, operator) in command.arms[1..].iter().zip(command.operators.iter()) {
+                if result_columns(arm).len() != columns {
+                    let operator = match operator {
+                        CompoundOperator::Union => "UNION",
+                        CompoundOperator::UnionAll => "UNION ALL",
+                    };
+                    bail!(
+                        "SELECTs to the left and right of {} do not have the same number of result columns",
+                        operator
+                    );
+                }
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Names of the result columns of `command`, with wildcards as written
+/// until expanded.
+fn command_columns(command: &SQLCommand) -> Vec<String> {
+    match command {
+        SQLCommand::Select(select) => result_columns(select),
+        SQLCommand::ExplainQueryPlan(_) => ["id", "parent", "notused", "detail"]
+            .iter()
+            .map(|name| name.to_string())
+            .collect(),
+        SQLCommand::Pragma(pragma) => pragma::columns(&pragma.name)
+            .unwrap_or_default()
+            .iter()
+            .map(|name| name.to_string())
+            .collect(),
+        _ => vec![],
+    }
+}
+
+fn result_columns(select: &SelectStatement) -> Vec<String> {
+    match select {
+        SelectStatement::Count(_) => vec!["count(*)".to_string()],
+        SelectStatement::Fields(command) => command.fields.clone(),
+        SelectStatement::Expressions(command) => {
+            command.columns.iter().map(|column| column.name.clone()).collect()
+        }
+        SelectStatement::MinMax(command) => {
+            let function = match command.function {
+                MinMax::Min => "min",
+                MinMax::Max => "max",
+            };
+            vec![format!("{}({})", function, command.field)]
+        }
+        SelectStatement::Aggregate(command) => command.names.clone(),
+        SelectStatement::Compound(command) => result_columns(&command.arms[0]),
+    }
+}
+
+/// Where the result columns of an expanded SELECT come from, for those
+/// that are columns of a table. As in SQLite, a compound SELECT takes them
+/// from its first arm, and views are looked through.
+fn column_origins(database: &Database, select: &SelectStatement) -> Vec<Option<ColumnOrigin>> {
+    let columns: Vec<Option<&String>> = match select {
+        SelectStatement::Fields(command) => command.fields.iter().map(Some).collect(),
+        SelectStatement::Expressions(command) => command
+            .columns
+            .iter()
+            .map(|column| match &column.expression {
+                Expr::Column(name) => Some(name),
+                _ => None,
+            })
+            .collect(),
+        SelectStatement::Compound(command) => return column_origins(database, &command.arms[0]),
+        select => return vec![None; result_columns(select).len()],
+    };
+    let Some(table) = selected_table(select) else {
+        return vec![None; columns.len()];
+    };
+    let (database, table) = database.resolve(table);
+
+    if let Some(view) = database.schema.find_view(table) {
+        let view_columns = describe_view(database, view).unwrap_or_default();
+        return columns
+            .into_iter()
+            .map(|column| {
+                let column = column?;
+                let (_, origin) = view_columns.iter().find(|(name, _)| name.eq_ignore_ascii_case(column))?;
+                origin.clone()
+            })
+            .collect();
+    }
+
+    let table = database.schema.find_table(table);
+    columns
+        .into_iter()
+        .map(|column| column_origin(table?, column?))
+        .collect()
+}
+
+/// The columns of `view`, a view of `database`, and where each comes from.
+pub(crate) fn describe_view(database: &Database, view: &View) -> Result<Vec<(String, Option<ColumnOrigin>)>> {
+    let mut select = view.select.clone();
+    expand_select(database, &mut select)?;
+    let names = view_columns(view, &select)?;
+    Ok(names.into_iter().zip(column_origins(database, &select)).collect())
+}
+
+/// The column of `table` named `name`, which may be the rowid.
+fn column_origin(table: &Table, name: &str) -> Option<ColumnOrigin> {
+    if let Some(column) = table.columns.iter().find(|column| column.name.eq_ignore_ascii_case(name)) {
+        return Some(ColumnOrigin {
+            table: table.name.clone(),
+            column: column.name.clone(),
+            decl_type: column.type_name.clone(),
+        });
+    }
+    (is_rowid_alias(name) && !table.without_rowid).then(|| ColumnOrigin {
+        table: table.name.clone(),
+        column: "rowid".to_string(),
+        decl_type: Some("INTEGER".to_string()),
+    })
+}
+
+fn run(database: &mut Database, select: &SelectStatement, out: &mut impl RowSink) -> Result<()> {
+    let mut select = run_subqueries(database, select)?;
+    let Some(table) = selected_table(&select).map(str::to_string) else {
+        return run_select(database, &select, out);
+    };
+    database.with_database_of(&table, |database, name| {
+        set_selected_table(&mut select, name);
+        run_select(database, &select, out)
+    })
+}
+
+/// The table a SELECT reads, unless it is a compound SELECT, whose arms
+/// each read their own.
+fn selected_table(select: &SelectStatement) -> Option<&str> {
+    match select {
+        SelectStatement::Fields(command) => Some(&command.table),
+        SelectStatement::Expressions(command) => Some(&command.table),
+        SelectStatement::Count(command) => Some(&command.table),
+        SelectStatement::MinMax(command) => Some(&command.table),
+        SelectStatement::Aggregate(command) => Some(&command.table),
+        SelectStatement::Compound(_) => None,
+    }
+}
+
+/// Names the table of a SELECT as it is in the database it is read from,
+/// without its schema name.
+fn set_selected_table(select: &mut SelectStatement, name: &str) {
+    let table = match select {
+        SelectStatement::Fields(command) => &mut command.table,
+        SelectStatement::Expressions(command) => &mut command.table,
+        SelectStatement::Count(command) => &mut command.table,
+        SelectStatement::MinMax(command) => &mut command.table,
+        SelectStatement::Aggregate(command) => &mut command.table,
+        SelectStatement::Compound(_) => return,
+    };
+    *table = name.to_string();
+}
+
+/// Runs a SELECT whose subqueries were replaced by their results, on the
+/// database its table is in.
+fn run_select(database: &mut Database, select: &SelectStatement, out: &mut impl RowSink) -> Result<()> {
+    match select {
+        SelectStatement::Count(command)
+            if reads_computed(
+                database,
+                &command.table,
+                command.where_clause.iter().flat_map(|clause| clause.columns()),
+            ) =>
+        {
+            let select = SelectFields {
+                fields: vec![],
+                table: command.table.clone(),
+                where_clause: command.where_clause.clone(),
+                order_by: vec![],
+                limit: None,
+                offset: 0,
+            };
+            let mut counter = RowCounter::default();
+            select_rows(database, &select, &mut counter)?;
+            out.write_row(&[ColumnValue::I64(counter.0 as i64)])
+        }
+        SelectStatement::Count(command) => {
+            let count = database.select_count(command)?;
+            out.write_row(&[ColumnValue::I64(count as i64)])
+        }
+        SelectStatement::Fields(command) if !command.order_by.is_empty() => {
+            let columns = command.fields.len();
+            let (command, keys) = sort_plan(database, command);
+            let mut sorter = Sorter::new(keys, columns, database.memory_limit());
+            let result = select_rows(database, &command, &mut sorter);
+
+            database.stats.sort_spills += sorter.spills();
+            database.stats.memory_used += sorter.peak_memory();
+            result?;
+            sorter.finish(&mut Limit::new(out, command.offset, command.limit))
+        }
+        SelectStatement::Fields(command) => {
+            let mut out = Limit::new(out, command.offset, command.limit);
+            select_rows(database, command, &mut out)
+        }
+        SelectStatement::Expressions(command) => run_expressions(database, command, out),
+        SelectStatement::MinMax(command)
+            if reads_computed(database, &command.table, std::iter::once(&command.field)) =>
+        {
+            let function = match command.function {
+                MinMax::Min => AggregateFunction::Min,
+                MinMax::Max => AggregateFunction::Max,
+            };
+            let aggregate = AggregateSelection {
+                columns: vec![ResultColumn::Aggregate(function, Some(command.field.clone()))],
+                names: result_columns(select),
+                table: command.table.clone(),
+                where_clause: None,
+                group_by: vec![],
+                having: None,
+                order_by: vec![],
+                limit: None,
+                offset: 0,
+            };
+            run_aggregate(database, &aggregate, out)
+        }
+        SelectStatement::MinMax(command) => database.select_min_max(command, out),
+        SelectStatement::Aggregate(command) => run_aggregate(database, command, out),
+        SelectStatement::Compound(command) => {
+            let columns = result_columns(&command.arms[0]);
+            let keys = command
+                .order_by
+                .iter()
+                .map(|term| {
+                    let column = columns.iter().position(|name| *name == term.column);
+                    let Some(column) = column else {
+                        bail!("ORDER BY term does not match any column in the result set: {}", term.column);
+                    };
+                    Ok(SortKey {
+                        column,
+                        descending: term.descending,
+                        collation: Collation::Binary,
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+
+            let mut limit = Limit::new(out, command.offset, command.limit);
+            if keys.is_empty() {
+                return run_compound(database, command, &mut limit);
+            }
+            let mut sorter = Sorter::new(keys, columns.len(), database.memory_limit());
+            let result = run_compound(database, command, &mut sorter);
+
+            database.stats.sort_spills += sorter.spills();
+            database.stats.memory_used += sorter.peak_memory();
+            result?;
+            sorter.finish(&mut limit)
+        }
+    }
+}
+
+/// Writes the steps of the plan of `select` as rows, like SQLite.
+fn write_plan(database: &Database, select: &SelectStatement, out: &mut impl RowSink) -> Result<()> {
+    let plan = QueryPlan {
+        steps: plan_steps(database, select)?,
+    };
+    for (id, parent, detail) in plan.rows() {
+        let values = [
+            ColumnValue::I64(id),
+            ColumnValue::I64(parent),
+            ColumnValue::I64(0),
+            ColumnValue::Text(detail.as_bytes()),
+        ];
+        out.write_row(&values)?;
+    }
+    Ok(())
+}
+
+/// The steps EXPLAIN QUERY PLAN shows for `select`, going the way [`run`]
+/// does: rows are read as [`select_rows`] reads them, then grouped and
+/// sorted in temporary B-trees.
+fn plan_steps(database: &Database, select: &SelectStatement) -> Result<Vec<Step>> {
+    let mut steps = match select {
+        SelectStatement::Fields(command) => {
+            access_steps(database, &command.table, &command.where_clause, command.fields.iter())?
+        }
+        SelectStatement::Expressions(command) => {
+            let names = command.columns.iter().flat_map(|column| column.expression.columns());
+            access_steps(database, &command.table, &command.where_clause, names)?
+        }
+        SelectStatement::Count(command) => {
+            access_steps(database, &command.table, &command.where_clause, std::iter::empty())?
+        }
+        SelectStatement::MinMax(command) => {
+            let (resolved, name) = database.resolve(&command.table);
+            let table = resolved.schema.find_table(name);
+            match table.filter(|table| !table.is_virtual()) {
+                Some(table) if !reads_computed(database, &command.table, std::iter::once(&command.field)) => {
+                    vec![Step::new(min_max_detail(table, &command.field))]
+                }
+                _ => access_steps(database, &command.table, &None, std::iter::once(&command.field))?,
+            }
+        }
+        SelectStatement::Aggregate(command) => {
+            let names = command.columns.iter().filter_map(|column| match column {
+                ResultColumn::Column(name) => Some(name),
+                ResultColumn::Aggregate(_, argument) => argument.as_ref(),
+            });
+            let mut steps = access_steps(database, &command.table, &command.where_clause, names)?;
+            if !command.group_by.is_empty() {
+                steps.push(Step::new("USE TEMP B-TREE FOR GROUP BY"));
+            }
+            steps
+        }
+        SelectStatement::Compound(command) => {
+            let mut arms = vec![];
+            for (i, arm) in command.arms.iter().enumerate() {
+                let detail = match i.checked_sub(1).map(|i| command.operators[i]) {
+                    None => "LEFT-MOST SUBQUERY",
+                    Some(CompoundOperator::UnionAll) => "UNION ALL",
+                    Some(CompoundOperator::Union) => "UNION USING TEMP B-TREE",
+                };
+                arms.push(Step::with_children(detail, plan_steps(database, arm)?));
+            }
+            vec![Step::with_children("COMPOUND QUERY", arms)]
+        }
+    };
+
+    // The arms of a compound SELECT list their own subqueries.
+    let mut select = select.clone();
+    let mut subqueries = vec![];
+    if !matches!(select, SelectStatement::Compound(_)) {
+        for condition in conditions_mut(&mut select).into_iter().flatten() {
+            collect_subqueries(condition, &mut subqueries);
+        }
+    }
+    for (i, subquery) in subqueries.iter().enumerate() {
+        let detail = format!("LIST SUBQUERY {}", i + 1);
+        steps.push(Step::with_children(detail, plan_steps(database, subquery)?));
+    }
+
+    let ordered = match &select {
+        SelectStatement::Fields(command) => !command.order_by.is_empty(),
+        SelectStatement::Expressions(command) => !command.order_by.is_empty(),
+        SelectStatement::Aggregate(command) => !command.order_by.is_empty(),
+        SelectStatement::Compound(command) => !command.order_by.is_empty(),
+        SelectStatement::Count(_) | SelectStatement::MinMax(_) => false,
+    };
+    if ordered {
+        steps.push(Step::new("USE TEMP B-TREE FOR ORDER BY"));
+    }
+    Ok(steps)
+}
+
+/// The step reading the rows of `table` that may pass `filter`, or the
+/// steps of the SELECT of a view. The WHERE clause narrows down the rows
+/// read unless it or `names` read virtual generated columns.
+fn access_steps<'a>(
+    database: &Database,
+    table: &str,
+    filter: &'a Option<WhereClause>,
+    names: impl Iterator<Item = &'a String>,
+) -> Result<Vec<Step>> {
+    let (database, table) = database.resolve(table);
+    if let Some(view) = database.schema.find_view(table) {
+        return plan_steps(database, &view.select);
+    }
+    let table = database
+        .schema
+        .find_table(table)
+        .ok_or_else(|| Error::NoSuchTable(table.to_string()))?;
+    if table.is_virtual() {
+        return Ok(vec![Step::new(format!("SCAN {} VIRTUAL TABLE", table.name))]);
+    }
+
+    let compared = filter.iter().flat_map(|clause| clause.columns());
+    let plan = if reads_computed(database, &table.name, names.chain(compared)) {
+        Plan::FullScan
+    } else {
+        Plan::new(table, filter)
+    };
+    Ok(vec![Step::new(plan.detail(&table.name))])
+}
+
+/// How [`Database::select_min_max`] finds the smallest or largest value of
+/// `field`: at an end of the table or of an index, or else by a scan.
+fn min_max_detail(table: &Table, field: &str) -> String {
+    let is_rowid = match table.find_column(field) {
+        Some((_, column)) => column.is_primary_key,
+        None => is_rowid_alias(field),
+    };
+    if is_rowid && !table.without_rowid {
+        return format!("SEARCH {} USING INTEGER PRIMARY KEY", table.name);
+    }
+    match table.indexes.iter().find(|index| index.columns.first().map(String::as_str) == Some(field)) {
+        Some(index) => format!("SEARCH {} USING INDEX {}", table.name, index.name),
+        None => format!("SCAN {}", table.name),
+    }
+}
+
+fn collect_subqueries(clause: &WhereClause, subqueries: &mut Vec<SelectStatement>) {
+    match clause {
+        WhereClause::InSelect(subquery) => subqueries.push(*subquery.select.clone()),
+        WhereClause::And(left, right) | WhereClause::Or(left, right) => {
+            collect_subqueries(left, subqueries);
+            collect_subqueries(right, subqueries);
+        }
+        _ => {}
+    }
+}
+
+/// Reads the `fields` of the rows of a table or a view that pass the WHERE
+/// clause. A view runs its SELECT, whose rows are then filtered, and so
+/// are the rows of a table when virtual generated columns are read.
+fn select_rows(database: &mut Database, command: &SelectFields, out: &mut impl RowSink) -> Result<()> {
+    let compared = command.where_clause.iter().flat_map(|clause| clause.columns());
+    let names = command.fields.iter().chain(compared).collect::<Vec<_>>();
+
+    if let Some(view) = database.schema.find_view(&command.table).cloned() {
+        let mut select = view.select.clone();
+        expand_select(database, &mut select)?;
+        let columns = view_columns(&view, &select)?;
+        if let Some(name) = names.iter().find(|name| !columns.contains(name)) {
+            return Err(Error::NoSuchColumn(name.to_string()));
+        }
+
+        let mut rows = ComputedRows {
+            table: None,
+            columns: &columns,
+            generated: &[],
+            fields: &command.fields,
+            filter: command.where_clause.as_ref(),
+            out,
+        };
+        return run(database, &select, &mut rows);
+    }
+
+    let generated = match database.schema.find_table(&command.table) {
+        Some(table) => virtual_columns(table)
+            .into_iter()
+            .filter(|(column, _)| names.contains(&column))
+            .collect::<Vec<_>>(),
+        None => vec![],
+    };
+    if generated.is_empty() {
+        return database.select_fields(command, out);
+    }
+
+    // The stored columns read, by themselves or to compute the others.
+    let mut columns: Vec<String> = vec![];
+    for name in names {
+        let inputs = match generated.iter().find(|(column, _)| column == name) {
+            Some((_, expression)) => expression.columns(),
+            None => vec![name],
+        };
+        for input in inputs {
+            if !columns.contains(input) {
+                columns.push(input.clone());
+            }
+        }
+    }
+    let select = SelectFields {
+        fields: columns.clone(),
+        table: command.table.clone(),
+        where_clause: None,
+        order_by: vec![],
+        limit: None,
+        offset: 0,
+    };
+    let mut rows = ComputedRows {
+        table: database.schema.find_table(&command.table).cloned(),
+        columns: &columns,
+        generated: &generated,
+        fields: &command.fields,
+        filter: command.where_clause.as_ref(),
+        out,
+    };
+    database.select_fields(&select, &mut rows)
+}
+
+/// Whether reading the columns `names` of `table` goes through
+/// [`select_rows`], for a view or virtual generated columns.
+fn reads_computed<'a>(database: &Database, table: &str, mut names: impl Iterator<Item = &'a String>) -> bool {
+    let (database, table) = database.resolve(table);
+    if database.schema.find_view(table).is_some() {
+        return true;
+    }
+    let Some(table) = database.schema.find_table(table) else {
+        return false;
+    };
+    names.any(|name| {
+        table
+            .columns
+            .iter()
+            .any(|column| column.name == *name && !column.is_stored())
+    })
+}
+
+/// The virtual generated columns of a table, with the expressions giving
+/// their values in terms of stored columns.
+fn virtual_columns(table: &Table) -> Vec<(String, Expr)> {
+    let mut columns = table
+        .columns
+        .iter()
+        .filter(|column| !column.is_stored())
+        .filter_map(|column| {
+            let generated = column.generated.as_ref()?;
+            Some((column.name.clone(), generated.expression.clone()))
+        })
+        .collect::<Vec<_>>();
+
+    // A column may be computed from others, whose expressions go in their
+    // place. There are no cycles, so this ends after as many rounds.
+    for _ in 0..columns.len() {
+        let substitutes = columns.clone();
+        for (_, expression) in columns.iter_mut() {
+            expression.visit_mut(&mut |expression| {
+                if let Expr::Column(name) = expression {
+                    if let Some((_, substitute)) = substitutes.iter().find(|(column, _)| column == name) {
+                        *expression = substitute.clone();
+                    }
+                }
+            });
+        }
+    }
+    columns
+}
+
+/// The columns of a view, given its SELECT with wildcards expanded.
+fn view_columns(view: &View, select: &SelectStatement) -> Result<Vec<String>> {
+    let names = result_columns(select);
+    if view.columns.is_empty() {
+        return Ok(names);
+    }
+    if view.columns.len() != names.len() {
+        bail!(
+            "expected {} columns for '{}' but got {}",
+            view.columns.len(),
+            view.name,
+            names.len()
+        );
+    }
+    Ok(view.columns.clone())
+}
+
+/// Passes on the rows that pass the WHERE clause of a query, with the
+/// columns it reads, given rows of `columns`. `generated` columns are
+/// computed from those first.
+struct ComputedRows<'query, 'sink> {
+    /// The table read, whose columns lend their collations; none for views.
+    table: Option<Table>,
+    columns: &'query [String],
+    generated: &'query [(String, Expr)],
+    fields: &'query [String],
+    filter: Option<&'query WhereClause>,
+    out: &'sink mut dyn RowSink,
+}
+
+impl RowSink for ComputedRows<'_, '_> {
+    fn write_row(&mut self, values: &[ColumnValue]) -> Result<()> {
+        let read = |name: &str| {
+            let i = self.columns.iter().position(|column| column == name).unwrap();
+            values[i].clone()
+        };
+        let computed = self
+            .generated
+            .iter()
+            .map(|(_, expression)| expression.evaluate(&read))
+            .collect::<Vec<_>>();
+        let value = |name: &str| match self.generated.iter().position(|(column, _)| column == name) {
+            Some(i) => computed[i].get(),
+            None => read(name),
+        };
+
+        if let Some(filter) = self.filter {
+            let collation = |name: &str| {
+                self.table
+                    .as_ref()
+                    .map_or(Collation::Binary, |table| table.collation(name))
+            };
+            if !evaluate(filter, &value, &collation) {
+                return Ok(());
+            }
+        }
+        let row = self.fields.iter().map(|field| value(field)).collect::<Vec<_>>();
+        self.out.write_row(&row)
+    }
+
+    fn is_done(&self) -> bool {
+        self.out.is_done()
+    }
+}
+
+/// Passes on the rows of the arms of a compound SELECT, leaving out the
+/// ones already passed on while `distinct` is set.
+struct Union<'sink> {
+    seen: HashSet<Vec<u8>>,
+    bytes: u64,
+    distinct: bool,
+    out: &'sink mut dyn RowSink,
+}
+
+impl RowSink for Union<'_> {
+    fn write_row(&mut self, values: &[ColumnValue]) -> Result<()> {
+        if self.distinct {
+            let key = values.iter().flat_map(set_key).collect::<Vec<_>>();
+            if self.seen.contains(&key) {
+                return Ok(());
+            }
+            self.bytes += (key.len() + std::mem::size_of::<Vec<u8>>()) as u64;
+            self.seen.insert(key);
+        }
+        self.out.write_row(values)
+    }
+
+    fn is_done(&self) -> bool {
+        self.out.is_done()
+    }
+}
+
+/// Runs the arms in turn. A UNION removes duplicates from everything to
+/// its left as well, so rows are only deduplicated up to the last one.
+fn run_compound(
+    database: &mut Database,
+    command: &CompoundSelection,
+    out: &mut dyn RowSink,
+) -> Result<()> {
+    let distinct = command
+        .operators
+        .iter()
+        .rposition(|operator| *operator == CompoundOperator::Union)
+        .map_or(0, |i| i + 2);
+    let mut union = Union {
+        seen: HashSet::new(),
+        bytes: 0,
+        distinct: false,
+        out,
+    };
+    for (i, arm) in command.arms.iter().enumerate() {
+        union.distinct = i < distinct;
+        run(database, arm, &mut union)?;
+        database.reserve_memory(std::mem::take(&mut union.bytes))?;
+        if union.is_done() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Collects the values of a subquery, leaving out NULLs, which never match.
+#[derive(Default)]
+struct ValueSet {
+    keys: HashSet<Vec<u8>>,
+    bytes: u64,
+}
+
+impl RowSink for ValueSet {
+    fn write_row(&mut self, values: &[ColumnValue]) -> Result<()> {
+        if !values[0].is_null() {
+            let key = set_key(&values[0]);
+            self.bytes += (key.len() + std::mem::size_of::<Vec<u8>>()) as u64;
+            self.keys.insert(key);
+        }
+        Ok(())
+    }
+}
+
+/// Runs the subqueries of the WHERE and HAVING clauses, each once, and
+/// puts the values they return in their place.
+fn run_subqueries(database: &mut Database, select: &SelectStatement) -> Result<SelectStatement> {
+    let mut select = select.clone();
+    for condition in conditions_mut(&mut select) {
+        if let Some(clause) = condition.take() {
+            *condition = Some(replace_subqueries(database, clause)?);
+        }
+    }
+    Ok(select)
+}
+
+fn replace_subqueries(database: &mut Database, clause: WhereClause) -> Result<WhereClause> {
+    Ok(match clause {
+        WhereClause::InSelect(InSelect { field, mut select }) => {
+            expand_select(database, &mut select)?;
+            let columns = result_columns(&select).len();
+            if columns != 1 {
+                bail!("Sub-select returns {} columns - expected 1", columns);
+            }
+
+            let mut values = ValueSet::default();
+            run(database, &select, &mut values)?;
+            database.reserve_memory(values.bytes)?;
+            WhereClause::InSet(InSet {
+                field,
+                keys: values.keys,
+            })
+        }
+        WhereClause::And(left, right) => WhereClause::And(
+            Box::new(replace_subqueries(database, *left)?),
+            Box::new(replace_subqueries(database, *right)?),
+        ),
+        WhereClause::Or(left, right) => WhereClause::Or(
+            Box::new(replace_subqueries(database, *left)?),
+            Box::new(replace_subqueries(database, *right)?),
+        ),
+        clause => clause,
+    })
+}
+
+/// The columns of `table` that `name` stands for, when it is `*` or
+/// `table.*`.
+fn expand_wildcard(database: &Database, table: &str, name: &str) -> Result<Option<Vec<String>>> {
+    let qualifier = match name.strip_suffix('*') {
+        Some(qualifier) => qualifier.strip_suffix('.'),
+        None => return Ok(None),
+    };
+    let (database, table) = database.resolve(table);
+    if let Some(qualifier) = qualifier.filter(|qualifier| !qualifier.eq_ignore_ascii_case(table)) {
+        bail!("No such table: {}", qualifier);
+    }
+
+    if let Some(view) = database.schema.find_view(table) {
+        let mut select = view.select.clone();
+        expand_select(database, &mut select)?;
+        return Ok(Some(view_columns(view, &select)?));
+    }
+
+    let table = database
+        .schema
+        .find_table(table)
+        .ok_or_else(|| Error::NoSuchTable(table.to_string()))?;
+    Ok(Some(table.columns.iter().map(|column| column.name.clone()).collect()))
+}
+
+/// How text in the column `column` of `table` compares. Columns of views
+/// and names that aren't columns compare byte by byte.
+fn collation(database: &Database, table: &str, column: &str) -> Collation {
+    database
+        .schema
+        .find_table(table)
+        .map_or(Collation::Binary, |table| table.collation(column))
+}
+
+/// Selects the ORDER BY columns missing from the result after the others,
+/// so rows can be sorted by them, and says where every sort key is.
+fn sort_plan(database: &Database, command: &SelectFields) -> (SelectFields, Vec<SortKey>) {
+    let mut command = command.clone();
+    let keys = command
+        .order_by
+        .iter()
+        .map(|term| {
+            let column = match command.fields.iter().position(|field| *field == term.column) {
+                Some(column) => column,
+                None => {
+                    command.fields.push(term.column.clone());
+                    command.fields.len() - 1
+                }
+            };
+            SortKey {
+                column,
+                descending: term.descending,
+                collation: collation(database, &command.table, &term.column),
+            }
+        })
+        .collect();
+    (command, keys)
+}
+
+/// Computes the result columns of every row from the columns it reads,
+/// followed by the ORDER BY columns that aren't results.
+struct Projection<'command, 'sink, S: RowSink> {
+    columns: &'command [SelectColumn],
+    /// ORDER BY columns of the table, by their position in `inputs`.
+    hidden: Vec<usize>,
+    inputs: &'command [String],
+    out: &'sink mut S,
+}
+
+impl<S: RowSink> RowSink for Projection<'_, '_, S> {
+    fn write_row(&mut self, values: &[ColumnValue]) -> Result<()> {
+        let value = |name: &str| {
+            let i = self.inputs.iter().position(|input| input == name).unwrap();
+            values[i].clone()
+        };
+        let results = self
+            .columns
+            .iter()
+            .map(|column| column.expression.evaluate(&value))
+            .collect::<Vec<_>>();
+        let mut row = results.iter().map(Value::get).collect::<Vec<_>>();
+        row.extend(self.hidden.iter().map(|&i| values[i].clone()));
+        self.out.write_row(&row)
+    }
+
+    fn is_done(&self) -> bool {
+        self.out.is_done()
+    }
+}
+
+/// Reads the columns the expressions need, and computes the result columns
+/// of every row, sorted for ORDER BY.
+fn run_expressions(
+    database: &mut Database,
+    command: &ExpressionSelection,
+    out: &mut impl RowSink,
+) -> Result<()> {
+    let mut inputs: Vec<String> = vec![];
+    let names = command
+        .columns
+        .iter()
+        .flat_map(|column| column.expression.columns())
+        .chain(
+            command
+                .order_by
+                .iter()
+                .map(|term| &term.column)
+                .filter(|name| command.columns.iter().all(|column| column.name != **name)),
+        );
+    for name in names {
+        if !inputs.contains(name) {
+            inputs.push(name.clone());
+        }
+    }
+
+    // ORDER BY sorts by a result column of that name if there is one, and
+    // by the column of the table otherwise. Result columns that are columns
+    // of the table keep their collation.
+    let mut hidden = vec![];
+    let keys = command
+        .order_by
+        .iter()
+        .map(|term| {
+            let (column, source) = match command.columns.iter().position(|c| c.name == term.column) {
+                Some(column) => match &command.columns[column].expression {
+                    Expr::Column(name) => (column, Some(name)),
+                    _ => (column, None),
+                },
+                None => {
+                    hidden.push(inputs.iter().position(|input| *input == term.column).unwrap());
+                    (command.columns.len() + hidden.len() - 1, Some(&term.column))
+                }
+            };
+            let collation = source.map_or(Collation::Binary, |name| {
+                collation(database, &command.table, name)
+            });
+            SortKey {
+                column,
+                descending: term.descending,
+                collation,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let select = SelectFields {
+        fields: inputs.clone(),
+        table: command.table.clone(),
+        where_clause: command.where_clause.clone(),
+        order_by: vec![],
+        limit: None,
+        offset: 0,
+    };
+    let mut limit = Limit::new(out, command.offset, command.limit);
+    if keys.is_empty() {
+        let mut projection = Projection {
+            columns: &command.columns,
+            hidden,
+            inputs: &inputs,
+            out: &mut limit,
+        };
+        return select_rows(database, &select, &mut projection);
+    }
+
+    let mut sorter = Sorter::new(keys, command.columns.len(), database.memory_limit());
+    let mut projection = Projection {
+        columns: &command.columns,
+        hidden,
+        inputs: &inputs,
+        out: &mut sorter,
+    };
+    let result = select_rows(database, &select, &mut projection);
+
+    database.stats.sort_spills += sorter.spills();
+    database.stats.memory_used += sorter.peak_memory();
+    result?;
+    sorter.finish(&mut limit)
+}
+
+/// Groups the rows, computes the aggregates of every group and passes on
+/// the groups HAVING keeps, sorted for ORDER BY.
+fn run_aggregate(
+    database: &mut Database,
+    command: &AggregateSelection,
+    out: &mut impl RowSink,
+) -> Result<()> {
+    // ORDER BY may name a result column by its alias.
+    let order_by = command
+        .order_by
+        .iter()
+        .map(|term| match command.names.iter().position(|name| *name == term.column) {
+            Some(i) => OrderingTerm {
+                column: command.columns[i].name(),
+                descending: term.descending,
+            },
+            None => term.clone(),
+        })
+        .collect::<Vec<_>>();
+
+    // Result columns named in HAVING and ORDER BY count as well, whether
+    // they are selected or not.
+    let mut referenced = command.columns.clone();
+    let names = command
+        .having
+        .iter()
+        .flat_map(|having| having.columns())
+        .chain(order_by.iter().map(|term| &term.column));
+    for name in names {
+        let column = sql::parse_result_column(name);
+        if !referenced.contains(&column) {
+            referenced.push(column);
+        }
+    }
+
+    // The grouper sees the GROUP BY columns, the other columns, and the
+    // arguments of the aggregates.
+    let mut fields = command.group_by.clone();
+    let mut aggregates = vec![];
+    for column in referenced.iter() {
+        let argument = match column {
+            ResultColumn::Column(name) => Some(name),
+            ResultColumn::Aggregate(_, argument) => argument.as_ref(),
+        };
+        if let Some(argument) = argument.filter(|argument| !fields.contains(argument)) {
+            fields.push(argument.clone());
+        }
+        if let ResultColumn::Aggregate(function, argument) = column {
+            let position = argument
+                .as_ref()
+                .map(|argument| fields.iter().position(|field| field == argument).unwrap());
+            let collation = argument
+                .as_ref()
+                .map_or(Collation::Binary, |argument| collation(database, &command.table, argument));
+            aggregates.push((*function, position, collation));
+        }
+    }
+
+    let select = SelectFields {
+        fields: fields.clone(),
+        table: command.table.clone(),
+        where_clause: command.where_clause.clone(),
+        order_by: vec![],
+        limit: None,
+        offset: 0,
+    };
+    let keys = (0..command.group_by.len()).collect();
+    let mut grouper = Grouper::new(keys, aggregates, fields.len(), database.memory_limit());
+    let result = select_rows(database, &select, &mut grouper);
+    database.stats.memory_used += grouper.memory_used();
+    result?;
+
+    // Every result column in a group, by name.
+    let position = |column: &ResultColumn| match column {
+        ResultColumn::Column(name) => fields.iter().position(|field| field == name).unwrap(),
+        aggregate => {
+            let aggregates = referenced.iter().filter(|c| matches!(c, ResultColumn::Aggregate(..)));
+            fields.len() + aggregates.take_while(|c| *c != aggregate).count()
+        }
+    };
+    let row_columns = referenced.iter().map(position).collect::<Vec<_>>();
+
+    let mut sorter = Sorter::new(
+        order_by
+            .iter()
+            .map(|term| {
+                let column = referenced
+                    .iter()
+                    .position(|column| column.name() == term.column)
+                    .unwrap();
+                let collation = match &referenced[column] {
+                    ResultColumn::Column(name) => collation(database, &command.table, name),
+                    ResultColumn::Aggregate(..) => Collation::Binary,
+                };
+                SortKey {
+                    column,
+                    descending: term.descending,
+                    collation,
+                }
+            })
+            .collect(),
+        command.columns.len(),
+        database.memory_limit(),
+    );
+
+    for group in grouper.finish() {
+        let group = Record::read(0, &group);
+        let row = row_columns.iter().map(|&i| group.values[i].clone()).collect::<Vec<_>>();
+
+        if let Some(having) = &command.having {
+            let value = |name: &str| {
+                let i = referenced.iter().position(|column| column.name() == name).unwrap();
+                row[i].clone()
+            };
+            if !evaluate(having, &value, &|name| collation(database, &command.table, name)) {
+                continue;
+            }
+        }
+        sorter.write_row(&row)?;
+    }
+
+    database.stats.sort_spills += sorter.spills();
+    sorter.finish(&mut Limit::new(out, command.offset, command.limit))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::BinaryOperator;
+
+    #[test]
+    fn prepare_selects_only() {
+        let statement = Statement::prepare("SELECT name, color FROM apples").unwrap();
+        assert_eq!(statement.columns(), vec!["name", "color"]);
+
+        let statement = Statement::prepare("select max(id) from apples").unwrap();
+        assert_eq!(statement.columns(), vec!["max(id)"]);
+
+        assert!(Statement::prepare("CREATE VIEW v AS SELECT a FROM t").is_err());
+
+        let error = Statement::prepare("SELECT reverse(name) FROM apples").unwrap_err();
+        assert_eq!(error.to_string(), "No such function: reverse");
+        let error = Statement::prepare("SELECT name FROM apples WHERE upper() = 'A'").unwrap_err();
+        assert_eq!(error.to_string(), "Wrong number of arguments to function upper()");
+        let error = Statement::prepare("SELECT id FROM apples WHERE id IN (SELECT lower() FROM apples)")
+            .unwrap_err();
+        assert_eq!(error.to_string(), "Wrong number of arguments to function lower()");
+    }
+
+    /// Written by sqlite3 with `CREATE TABLE items(name TEXT, price REAL, qty)`
+    /// and `INSERT INTO items VALUES ('pen', 100.0, 2), ('cup', 2.5, 3.0)`,
+    /// which stores the price of the pen as the integer 100.
+    const REAL_AFFINITY_DB: &[u8] = include_bytes!("../testdata/real_affinity.db");
+
+    #[test]
+    fn real_columns_read_integers_as_reals() {
+        let path = std::env::temp_dir().join(format!("real-affinity-{}.db", std::process::id()));
+        std::fs::write(&path, REAL_AFFINITY_DB).unwrap();
+        let mut database = Database::open(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut run = |sql: &str| {
+            let mut statement = Statement::prepare(sql).unwrap();
+            let options = crate::output::OutputOptions::default();
+            let mut out = vec![];
+            let mut writer = RowWriter::new(&mut out, &options, statement.columns());
+            statement.execute(&mut database, &mut writer).unwrap();
+            writer.finish().unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        // As sqlite3 gives them.
+        assert_eq!(
+            run("SELECT name, CAST(price AS TEXT), length(price), CAST(qty AS TEXT) FROM items"),
+            "pen|100.0|5|2\ncup|2.5|3|3.0\n"
+        );
+        assert_eq!(run("SELECT name FROM items WHERE CAST(price AS TEXT) = '100.0'"), "pen\n");
+    }
+
+    #[test]
+    fn prepare_explain_query_plan() {
+        let statement = Statement::prepare("EXPLAIN QUERY PLAN SELECT name FROM apples WHERE id = ?").unwrap();
+        assert!(statement.is_explain());
+        assert_eq!(statement.parameter_count(), 1);
+        assert_eq!(statement.columns(), vec!["id", "parent", "notused", "detail"]);
+
+        assert!(!Statement::prepare("SELECT name FROM apples").unwrap().is_explain());
+        assert!(Statement::prepare("EXPLAIN QUERY PLAN CREATE TABLE t (a)").is_err());
+    }
+
+    #[test]
+    fn prepare_create_table() {
+        let statement = Statement::prepare("CREATE TABLE t (a, b)").unwrap();
+        assert!(!statement.is_read_only());
+        assert_eq!(statement.parameter_count(), 0);
+        assert!(statement.columns().is_empty());
+
+        let statement = Statement::prepare("DROP TABLE IF EXISTS t").unwrap();
+        assert!(!statement.is_read_only());
+        assert_eq!(statement.parameter_count(), 0);
+
+        let statement = Statement::prepare("ALTER TABLE t ADD COLUMN c DEFAULT 0").unwrap();
+        assert!(!statement.is_read_only());
+        assert_eq!(statement.parameter_count(), 0);
+
+        for sql in ["BEGIN", "COMMIT", "ROLLBACK"] {
+            let statement = Statement::prepare(sql).unwrap();
+            assert_eq!(statement.parameter_count(), 0);
+            assert!(statement.columns().is_empty());
+        }
+    }
+
+    #[test]
+    fn prepare_delete() {
+        let statement = Statement::prepare("DELETE FROM apples WHERE id = ?").unwrap();
+        assert!(!statement.is_read_only());
+        assert_eq!(statement.parameter_count(), 1);
+        assert!(statement.columns().is_empty());
+
+        assert!(Statement::prepare("SELECT name FROM apples").unwrap().is_read_only());
+    }
+
+    #[test]
+    fn row_keeps_its_values() {
+        let row = Row::new(&[ColumnValue::I64(7), ColumnValue::Null, ColumnValue::Text(b"apple")]);
+        let values = row.values();
+        assert_eq!(values.len(), 3);
+        assert_eq!(values[0].to_string(), "7");
+        assert!(values[1].is_null());
+        assert_eq!(values[2], OwnedValue::Text("apple".to_string()));
+        assert_eq!(row.into_values()[0], OwnedValue::Integer(7));
+    }
+
+    #[test]
+    fn bind_parameters_by_number() {
+        let sql = "SELECT name FROM apples WHERE id = ?2 AND color = ? AND size > ? + 1";
+        let mut statement = Statement::prepare(sql).unwrap();
+        assert_eq!(statement.parameter_count(), 4);
+        assert!(statement.bind(&vec![Literal::Null; 5]).is_err());
+
+        let values = [Literal::Integer(7), Literal::Integer(3), Literal::Text("red".to_string())];
+        statement.bind(&values).unwrap();
+        let SQLCommand::Select(select) = &statement.command else {
+            panic!("Expected a select");
+        };
+        let mut select = select.clone();
+        bind_parameters(&mut select, &statement.parameters);
+        let SelectStatement::Fields(select) = select else {
+            panic!("Expected a select of fields");
+        };
+
+        let condition = select.where_clause.unwrap();
+        let comparisons = condition.comparisons();
+        assert_eq!(comparisons.len(), 2);
+        assert_eq!((comparisons[0].field.as_str(), comparisons[0].value.as_str()), ("id", "3"));
+        assert_eq!((comparisons[1].field.as_str(), comparisons[1].value.as_str()), ("color", "red"));
+        // `?4` has no value, so the sum is NULL.
+        assert_eq!(
+            condition.expressions()[1],
+            &Expr::Binary(
+                BinaryOperator::Add,
+                Box::new(Expr::Literal(Literal::Null)),
+                Box::new(Expr::Literal(Literal::Integer(1)))
+            )
+        );
+    }
+
+    /// Written by sqlite3 with `CREATE TABLE t (k, a TEXT COLLATE NOCASE)`,
+    /// the rows `(1, 'banana')`, `(1, 'Cherry')`, `(1, 'apple')` and
+    /// `(1, 'Apricot')`, and `CREATE INDEX by_bytes ON t (a COLLATE BINARY)`.
+    const COLLATION_DB: &[u8] = include_bytes!("../testdata/collation.db");
+
+    #[test]
+    fn min_max_compare_by_column_collation() {
+        let path = std::env::temp_dir().join(format!("collation-{}.db", std::process::id()));
+        std::fs::write(&path, COLLATION_DB).unwrap();
+        let mut database = Database::open(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut run = |sql: &str| {
+            let mut statement = Statement::prepare(sql).unwrap();
+            let options = crate::output::OutputOptions::default();
+            let mut out = vec![];
+            let mut writer = RowWriter::new(&mut out, &options, statement.columns());
+            statement.execute(&mut database, &mut writer).unwrap();
+            writer.finish().unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        // As sqlite3 gives them. The index orders the values byte by byte,
+        // so MIN and MAX don't take them from it.
+        assert_eq!(run("SELECT k, min(a), max(a) FROM t GROUP BY k"), "1|apple|Cherry\n");
+        assert_eq!(run("SELECT MIN(a) FROM t"), "apple\n");
+        assert_eq!(run("SELECT MAX(a) FROM t"), "Cherry\n");
+    }
+
+    /// Written by sqlite3 with `CREATE TABLE t (a INTEGER)`, the rows 3,
+    /// NULL, 1, 7 and 5, and `CREATE INDEX by_a_desc ON t (a DESC)`.
+    const DESC_INDEX_DB: &[u8] = include_bytes!("../testdata/desc_index.db");
+
+    #[test]
+    fn min_max_from_descending_index() {
+        let path = std::env::temp_dir().join(format!("desc-index-{}.db", std::process::id()));
+        std::fs::write(&path, DESC_INDEX_DB).unwrap();
+        let mut database = Database::open(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut run = |sql: &str| {
+            let mut statement = Statement::prepare(sql).unwrap();
+            let options = crate::output::OutputOptions::default();
+            let mut out = vec![];
+            let mut writer = RowWriter::new(&mut out, &options, statement.columns());
+            statement.execute(&mut database, &mut writer).unwrap();
+            writer.finish().unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(run("SELECT MIN(a) FROM t"), "1\n");
+        assert_eq!(run("SELECT MAX(a) FROM t"), "7\n");
+        assert_eq!(database.stats.access, Some(crate::stats::Access::Index("by_a_desc".to_string())));
+    }
+
+    /// Written by sqlite3 with 1024-byte pages: `CREATE TABLE t (a INTEGER,
+    /// b INTEGER, c TEXT)` with 300 rows of `i % 5`, `i % 17` (NULL when `i`
+    /// is a multiple of 11) and `'row ' || i`.
+    const PAIRS_DB: &[u8] = include_bytes!("../testdata/pairs.db");
+
+    #[test]
+    fn composite_index_finds_the_rows_a_scan_does() {
+        let path = std::env::temp_dir().join(format!("pairs-{}.db", std::process::id()));
+        std::fs::write(&path, PAIRS_DB).unwrap();
+        let mut database = Database::open(path.to_str().unwrap()).unwrap();
+
+        let filters = [
+            "a = 2",
+            "a = 2 AND b = 7",
+            "a = 3 AND b > 10",
+            "a = 3 AND b >= 4 AND b < 9",
+            "a = 1 AND b <= 3",
+            "b = 5 AND a = 4 AND c LIKE 'row 1%'",
+            "a = 9 AND b = 1",
+        ];
+        let select = |database: &mut Database, filter: &str| {
+            let sql = format!("SELECT rowid, a, b, c FROM t WHERE {}", filter);
+            let mut rows = database.query_rows::<(i64, i64, Option<i64>, String)>(&sql).unwrap();
+            rows.sort();
+            rows
+        };
+        let scanned = filters.map(|filter| select(&mut database, filter));
+        assert_eq!(scanned.each_ref().map(Vec::len), [60, 4, 18, 17, 13, 1, 0]);
+
+        database.query("CREATE INDEX by_a_b ON t (a, b)").unwrap();
+        for (filter, rows) in filters.iter().zip(scanned) {
+            assert_eq!(select(&mut database, filter), rows, "{}", filter);
+            let access = crate::stats::Access::Index("by_a_b".to_string());
+            assert_eq!(database.stats.access, Some(access), "{}", filter);
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn temp_tables_hide_main_tables() {
+        let mut database = Database::open_in_memory().unwrap();
+        let columns = |database: &mut Database, sql: &str| {
+            let mut statement = Statement::prepare(sql).unwrap();
+            statement.query(database).unwrap().for_each(drop);
+            statement.columns()
+        };
+        columns(&mut database, "CREATE TABLE t (a)");
+        columns(&mut database, "CREATE TABLE u (a)");
+        columns(&mut database, "CREATE TEMP TABLE t (b, c)");
+        columns(&mut database, "CREATE INDEX i ON t (c)");
+        assert!(database.schema.find_table("t").is_some());
+        let temp = database.temp().unwrap();
+        assert!(temp.schema.find_index("i").is_some());
+        assert_eq!(columns(&mut database, "SELECT * FROM t"), ["b", "c"]);
+        assert_eq!(columns(&mut database, "SELECT count(*) FROM t WHERE c IN (SELECT a FROM u)"), ["count(*)"]);
+
+        columns(&mut database, "DROP TABLE t");
+        assert_eq!(columns(&mut database, "SELECT * FROM t"), ["a"]);
+    }
+
+    #[test]
+    fn attached_tables_go_by_schema_name() {
+        let run = |database: &mut Database, sql: &str| {
+            let mut statement = Statement::prepare(sql)?;
+            statement.query(database)?.for_each(drop);
+            Ok::<_, Error>(statement.columns())
+        };
+        let mut other = Database::open_in_memory().unwrap();
+        run(&mut other, "CREATE TABLE t (b, c)").unwrap();
+        run(&mut other, "CREATE TABLE v (d)").unwrap();
+        let path = std::env::temp_dir().join(format!("attached-{}.db", std::process::id()));
+        let path = path.to_str().unwrap();
+        std::fs::write(path, other.serialize().unwrap()).unwrap();
+
+        let mut database = Database::open_in_memory().unwrap();
+        run(&mut database, "CREATE TABLE t (a)").unwrap();
+        run(&mut database, &format!("ATTACH DATABASE '{}' AS aux", path)).unwrap();
+        let error = run(&mut database, &format!("ATTACH '{}' AS AUX", path)).unwrap_err();
+        assert_eq!(error.to_string(), "Database already in use: AUX");
+        assert_eq!(run(&mut database, "SELECT * FROM t").unwrap(), ["a"]);
+        assert_eq!(run(&mut database, "SELECT * FROM main.t").unwrap(), ["a"]);
+        assert_eq!(run(&mut database, "SELECT t.* FROM aux.t").unwrap(), ["b", "c"]);
+        assert_eq!(run(&mut database, "SELECT * FROM v").unwrap(), ["d"]);
+        run(&mut database, "SELECT count(*) FROM aux.t WHERE b IN (SELECT a FROM t)").unwrap();
+
+        run(&mut database, "DROP TABLE aux.t").unwrap();
+        assert!(run(&mut database, "SELECT * FROM aux.t").is_err());
+        assert!(database.schema.find_table("t").is_some());
+        run(&mut database, "DETACH aux").unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert!(run(&mut database, "SELECT * FROM v").is_err());
+        let error = run(&mut database, "DETACH aux").unwrap_err();
+        assert_eq!(error.to_string(), "No such database: aux");
+    }
+
+    #[test]
+    fn cache_prepared_statements() {
+        let mut database = Database::open_in_memory().unwrap();
+        database.set_statement_cache_size(2);
+        database.query("CREATE TABLE t (a, b)").unwrap();
+        let mut statement = database.prepare_cached("SELECT * FROM t WHERE a = ?").unwrap();
+        statement.bind(&[Literal::Integer(1)]).unwrap();
+        statement.query(&mut database).unwrap().for_each(drop);
+        let statement = database.prepare_cached("SELECT * FROM t WHERE a = ?").unwrap();
+        assert_eq!(statement.columns(), ["a", "b"]);
+        assert!(statement.parameters.is_empty());
+        let cache = database.statement_cache();
+        assert_eq!((cache.len(), cache.hits, cache.misses), (1, 1, 1));
+
+        database.prepare_cached("SELECT a FROM t").unwrap();
+        database.prepare_cached("SELECT b FROM t").unwrap();
+        database.prepare_cached("SELECT * FROM t WHERE a = ?").unwrap();
+        assert_eq!(database.statement_cache().misses, 4);
+
+        // A column added since is in the result columns.
+        database.query("ALTER TABLE t ADD c").unwrap();
+        let statement = database.prepare_cached("SELECT * FROM t").unwrap();
+        assert_eq!(statement.columns(), ["a", "b", "c"]);
+        assert_eq!(database.statement_cache().len(), 1);
+        assert!(database.prepare_cached("SELECT * FROM u").is_err());
+    }
+
+    #[test]
+    fn result_columns_know_their_origin() {
+        let mut database = Database::open_in_memory().unwrap();
+        database
+            .query("CREATE TABLE apples (id INTEGER PRIMARY KEY, Name VARCHAR(20), weight)")
+            .unwrap();
+        let statement = Statement::prepare("SELECT * FROM apples").unwrap();
+        assert_eq!((statement.column_count(), statement.column_name(0)), (1, Some("*")));
+        assert_eq!(statement.column_origin(0), None);
+
+        let statement = database.prepare("SELECT *, weight * 2 AS double FROM apples").unwrap();
+        assert_eq!(statement.column_count(), 4);
+        assert_eq!(statement.column_name(1), Some("Name"));
+        assert_eq!(statement.column_name(3), Some("double"));
+        assert_eq!(statement.column_name(4), None);
+        assert_eq!(statement.column_decl_type(0), Some("INTEGER"));
+        assert_eq!(statement.column_decl_type(1), Some("VARCHAR(20)"));
+        assert_eq!(statement.column_decl_type(2), None);
+        assert_eq!(statement.column_decl_type(3), None);
+        assert_eq!(
+            statement.column_origin(2),
+            Some(&ColumnOrigin {
+                table: "apples".to_string(),
+                column: "weight".to_string(),
+                decl_type: None,
+            })
+        );
+        assert_eq!(statement.column_origin(3), None);
+
+        let statement = database
+            .prepare("SELECT rowid, name FROM main.apples UNION SELECT count(*), 1 FROM apples")
+            .unwrap();
+        let origins: Vec<_> = (0..statement.column_count())
+            .map(|i| statement.column_origin(i).map(|origin| (origin.table.as_str(), origin.column.as_str())))
+            .collect();
+        assert_eq!(origins, [Some(("apples", "rowid")), Some(("apples", "Name"))]);
+        let statement = database.prepare("SELECT max(weight) FROM apples").unwrap();
+        assert_eq!((statement.column_count(), statement.column_origin(0)), (1, None));
+    }
+
+    #[test]
+    fn fail_statements_over_the_memory_limit() {
+        let path = std::env::temp_dir().join(format!("pairs-limit-{}.db", std::process::id()));
+        std::fs::write(&path, PAIRS_DB).unwrap();
+        let mut database = Database::open(path.to_str().unwrap()).unwrap();
+
+        database.set_memory_limit(Some(64));
+        let error = database.query("SELECT a, count(*) FROM t GROUP BY a").unwrap_err();
+        assert!(matches!(error, Error::MemoryLimit { limit: 64 }), "{:?}", error);
+        // Sorting spills to disk rather than failing.
+        assert_eq!(database.query("SELECT c FROM t ORDER BY c").unwrap().len(), 300);
+
+        database.set_memory_limit(None);
+        assert_eq!(database.query("SELECT a, count(*) FROM t GROUP BY a").unwrap().len(), 5);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn interrupt_a_running_scan() {
+        let path = std::env::temp_dir().join(format!("pairs-interrupt-{}.db", std::process::id()));
+        std::fs::write(&path, PAIRS_DB).unwrap();
+        let mut database = Database::open(path.to_str().unwrap()).unwrap();
+        let interrupt = database.interrupt_handle();
+
+        // The scan stops at the next page it reads.
+        let mut statement = Statement::prepare("SELECT c FROM t").unwrap();
+        let mut rows = statement.query(&mut database).unwrap();
+        assert!(rows.next().unwrap().is_ok());
+        interrupt.interrupt();
+        let rest = rows.collect::<Result<Vec<_>>>();
+        assert!(matches!(rest, Err(Error::Interrupted)), "{:?}", rest.map(|rows| rows.len()));
+
+        // The interrupt only ends the statement it came in during.
+        assert_eq!(database.query("SELECT c FROM t").unwrap().len(), 300);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /// Written by sqlite3 with `CREATE TABLE apples (id INTEGER PRIMARY KEY,
+    /// name TEXT, color TEXT, size INTEGER)` and the rows `('Fuji', 'Red', 3)`,
+    /// `('Gala', 'Red', NULL)`, `('Granny Smith', 'Green', 5)` and
+    /// `('Honeycrisp', 'Red', 4)`.
+    const APPLES_DB: &[u8] = include_bytes!("../testdata/apples.db");
+
+    #[test]
+    fn aggregate_whole_table_as_one_group() {
+        let path = std::env::temp_dir().join(format!("apples-{}.db", std::process::id()));
+        std::fs::write(&path, APPLES_DB).unwrap();
+        let mut database = Database::open(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // As sqlite3 gives them.
+        let aggregates = database
+            .query_rows::<(Option<i64>, Option<f64>, f64)>("SELECT sum(size), avg(size), total(size) FROM apples")
+            .unwrap();
+        assert_eq!(aggregates, [(Some(12), Some(4.0), 12.0)]);
+        let aggregates = database
+            .query_rows::<(i64, Option<i64>, Option<f64>, f64)>(
+                "SELECT count(*), sum(size), avg(size), total(size) FROM apples WHERE color = 'Blue'",
+            )
+            .unwrap();
+        assert_eq!(aggregates, [(0, None, None, 0.0)]);
+    }
+
+    #[test]
+    fn null_is_in_no_subquery() {
+        let path = std::env::temp_dir().join(format!("apples-in-{}.db", std::process::id()));
+        std::fs::write(&path, APPLES_DB).unwrap();
+        let mut database = Database::open(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // Gala has no size, and neither its NULL nor the one the subquery
+        // returns for it match anything.
+        let names = database
+            .query_rows::<(String,)>("SELECT name FROM apples WHERE size IN (SELECT size FROM apples WHERE color = 'Red')")
+            .unwrap();
+        assert_eq!(names, [("Fuji".to_string(),), ("Honeycrisp".to_string(),)]);
+        let names = database
+            .query_rows::<(String,)>("SELECT name FROM apples WHERE size IN (SELECT size FROM apples WHERE name = 'Gala')")
+            .unwrap();
+        assert!(names.is_empty());
+    }
+}
@@ -0,0 +1,62 @@
+/// How a statement got to the rows of its table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Access {
+    /// Every row of the table was read.
+    TableScan,
+    /// Rows were found through the named index.
+    Index(String),
+    /// Rows were found by rowid, without scanning the table.
+    Rowid,
+}
+
+impl std::fmt::Display for Access {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Access::TableScan => write!(f, "table scan"),
+            Access::Index(name) => write!(f, "index {}", name),
+            Access::Rowid => write!(f, "rowid lookup"),
+        }
+    }
+}
+
+/// Counters collected while a statement executes.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Stats {
+    pub pages_read: u64,
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+    pub bytes_allocated: u64,
+    /// Bytes held by intermediate results, counted against the memory limit.
+    pub memory_used: u64,
+    pub rows_scanned: u64,
+    pub rows_returned: u64,
+    /// Rows a DELETE deleted.
+    pub rows_changed: u64,
+    pub sort_spills: u64,
+    /// `None` when no table was read, e.g. for `count(*)`.
+    pub access: Option<Access>,
+}
+
+impl Stats {
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+impl std::fmt::Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Pages read:                          {}", self.pages_read)?;
+        writeln!(f, "Page cache hits:                     {}", self.cache_hits)?;
+        writeln!(f, "Page cache misses:                   {}", self.cache_misses)?;
+        writeln!(f, "Bytes allocated:                     {}", self.bytes_allocated)?;
+        writeln!(f, "Memory used:                         {}", self.memory_used)?;
+        writeln!(f, "Rows scanned:                        {}", self.rows_scanned)?;
+        writeln!(f, "Rows returned:                       {}", self.rows_returned)?;
+        writeln!(f, "Rows changed:                        {}", self.rows_changed)?;
+        writeln!(f, "Sort spills:                         {}", self.sort_spills)?;
+        match &self.access {
+            Some(access) => write!(f, "Access:                              {}", access),
+            None => write!(f, "Access:                              none"),
+        }
+    }
+}
@@ -19,6 +19,28 @@ pub fn read(bytes: &[u8]) -> (i64, usize) {
   (varint, bytes_read)
 }
 
+/// Encodes a varint, the inverse of `read`.
+pub fn write(value: i64) -> Vec<u8> {
+  let value = value as u64;
+  if value > 0x00ff_ffff_ffff_ffff {
+      // Nine bytes: seven bits in each of the first eight, all of the last.
+      let mut bytes = (0..8)
+          .map(|i| (value >> (8 + 7 * (7 - i))) as u8 & 0x7f | 0x80)
+          .collect::<Vec<_>>();
+      bytes.push(value as u8);
+      return bytes;
+  }
+
+  let mut bytes = vec![(value & 0x7f) as u8];
+  let mut rest = value >> 7;
+  while rest > 0 {
+      bytes.push((rest & 0x7f) as u8 | 0x80);
+      rest >>= 7;
+  }
+  bytes.reverse();
+  bytes
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
@@ -42,9 +64,18 @@ mod tests {
       assert_eq!(read(&vec![0xff; 9]), (-1, 9));
   }
 
+  #[test]
+  fn write_round_trips() {
+      for value in [0, 1, 127, 128, 255, 16384, 1 << 40, 0x00ff_ffff_ffff_ffff, -1, i64::MIN] {
+          let bytes = write(value);
+          assert_eq!(read(&bytes), (value, bytes.len()));
+      }
+      assert_eq!(write(129), vec![0b1000_0001, 0b0000_0001]);
+  }
+
   #[test]
   fn read_varint_from_longer_bytes() {
-      assert_eq!(read(&vec![0x01; 10]), (1, 1));
-      assert_eq!(read(&vec![0xff; 10]), (-1, 9));
+      assert_eq!(read(&[0x01; 10]), (1, 1));
+      assert_eq!(read(&[0xff; 10]), (-1, 9));
   }
 }
\ No newline at end of file
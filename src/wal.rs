@@ -0,0 +1,443 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::{prelude::*, BufReader, SeekFrom};
+
+use crate::error::{bail, Error, Result};
+
+const HEADER_SIZE: u64 = 32;
+const FRAME_HEADER_SIZE: u64 = 24;
+/// The low bit of the magic number says in which byte order the checksums
+/// were computed: set for big-endian, clear for little-endian.
+const MAGIC: u32 = 0x377f0682;
+
+/// Running checksum of the WAL, computed over pairs of 32-bit words and
+/// continued from one frame to the next.
+pub fn checksum(data: &[u8], big_endian: bool, initial: (u32, u32)) -> (u32, u32) {
+    let word = |bytes: &[u8]| {
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        if big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }
+    };
+
+    let (mut s0, mut s1) = initial;
+    for pair in data.chunks_exact(8) {
+        s0 = s0.wrapping_add(word(&pair[0..4])).wrapping_add(s1);
+        s1 = s1.wrapping_add(word(&pair[4..8])).wrapping_add(s0);
+    }
+    (s0, s1)
+}
+
+fn read_u32(data: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]])
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WalHeader {
+    pub big_endian: bool,
+    pub version: u32,
+    pub page_size: u32,
+    pub checkpoint_sequence: u32,
+    /// Copied into every frame; frames left over from before the last
+    /// checkpoint carry old salts.
+    pub salt: (u32, u32),
+    pub checksum: (u32, u32),
+}
+
+impl WalHeader {
+    /// Parses the header, returning `None` when the magic number or the
+    /// checksum is wrong, in which case the whole WAL is ignored.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let magic = read_u32(data, 0);
+        if magic & !1 != MAGIC {
+            return None;
+        }
+
+        let header = Self {
+            big_endian: magic & 1 == 1,
+            version: read_u32(data, 4),
+            page_size: match read_u32(data, 8) {
+                1 => 65536,
+                size => size,
+            },
+            checkpoint_sequence: read_u32(data, 12),
+            salt: (read_u32(data, 16), read_u32(data, 20)),
+            checksum: (read_u32(data, 24), read_u32(data, 28)),
+        };
+
+        (checksum(&data[..24], header.big_endian, (0, 0)) == header.checksum).then_some(header)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckpointMode {
+    /// Copies the committed frames into the database and leaves the WAL.
+    Passive,
+    /// Like PASSIVE after waiting for writers and readers, which this reader
+    /// doesn't track, so the two behave the same.
+    Full,
+    /// Also truncates the WAL to zero bytes.
+    Truncate,
+}
+
+impl std::str::FromStr for CheckpointMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_uppercase().as_str() {
+            "PASSIVE" => Ok(Self::Passive),
+            "FULL" => Ok(Self::Full),
+            "TRUNCATE" => Ok(Self::Truncate),
+            _ => bail!("Unknown checkpoint mode: {}", s),
+        }
+    }
+}
+
+/// Outcome of a checkpoint, like the last two columns of
+/// `PRAGMA wal_checkpoint`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checkpoint {
+    /// Committed frames in the WAL before the checkpoint.
+    pub log_frames: u32,
+    /// Frames copied into the database file.
+    pub checkpointed_frames: u32,
+}
+
+impl std::fmt::Display for Checkpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "log frames: {}", self.log_frames)?;
+        write!(f, "checkpointed frames: {}", self.checkpointed_frames)
+    }
+}
+
+/// Write-ahead log of a database in WAL mode. Pages written by committed
+/// transactions live here until the next checkpoint copies them back into
+/// the database file, so reads have to look here first.
+#[derive(Debug)]
+pub struct Wal {
+    path: String,
+    pub file: File,
+    pub header: WalHeader,
+    /// Frames with valid salts and checksums, committed or not.
+    pub valid_frames: u32,
+    /// Last commit frame. Frames after it belong to a transaction that has
+    /// not committed and are ignored.
+    pub max_frame: u32,
+    /// Size of the database in pages as of the last commit.
+    pub database_size: u32,
+    /// Running checksum as of `max_frame`, where scanning resumes.
+    checksum: (u32, u32),
+    /// Latest frame of every page, up to `max_frame`.
+    frames: HashMap<u32, u32>,
+}
+
+impl Wal {
+    /// Opens the WAL next to the database at `path`. Returns `None` when
+    /// there is no WAL, or when it is empty or was never initialized.
+    pub fn open(path: &str) -> Result<Option<Self>> {
+        let path = format!("{}-wal", path);
+        let Some((file, header)) = Self::read_header(&path)? else {
+            return Ok(None);
+        };
+
+        let mut wal = Self {
+            path,
+            file,
+            header,
+            valid_frames: 0,
+            max_frame: 0,
+            database_size: 0,
+            checksum: header.checksum,
+            frames: HashMap::new(),
+        };
+        wal.scan()?;
+        Ok(Some(wal))
+    }
+
+    fn read_header(path: &str) -> Result<Option<(File, WalHeader)>> {
+        let mut file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut data = [0; HEADER_SIZE as usize];
+        if file.read_exact(&mut data).is_err() {
+            return Ok(None);
+        }
+        Ok(WalHeader::parse(&data).map(|header| (file, header)))
+    }
+
+    /// Picks up transactions committed since the WAL was last read. Returns
+    /// whether anything changed.
+    ///
+    /// Writers append frames until a checkpoint restarts the WAL with new
+    /// salts, or deletes it; in both cases every frame read so far is gone
+    /// and the WAL is read again from the start.
+    pub fn refresh(&mut self) -> Result<bool> {
+        let max_frame = self.max_frame;
+
+        let restarted = match Self::read_header(&self.path)? {
+            Some((file, header)) => {
+                let restarted = header != self.header;
+                self.file = file;
+                self.header = header;
+                if restarted {
+                    self.reset();
+                }
+                // Frames after the last commit may have been completed since.
+                self.valid_frames = self.max_frame;
+                self.scan()?;
+                restarted
+            }
+            None => {
+                self.reset();
+                true
+            }
+        };
+
+        Ok(restarted && max_frame != 0 || self.max_frame != max_frame)
+    }
+
+    fn reset(&mut self) {
+        self.valid_frames = 0;
+        self.max_frame = 0;
+        self.database_size = 0;
+        self.checksum = self.header.checksum;
+        self.frames.clear();
+    }
+
+    pub fn try_clone(&self) -> Result<Self> {
+        Ok(Self {
+            path: self.path.clone(),
+            file: self.file.try_clone()?,
+            header: self.header,
+            valid_frames: self.valid_frames,
+            max_frame: self.max_frame,
+            database_size: self.database_size,
+            checksum: self.checksum,
+            frames: self.frames.clone(),
+        })
+    }
+
+    fn frame_size(&self) -> u64 {
+        FRAME_HEADER_SIZE + self.header.page_size as u64
+    }
+
+    /// Reads the frames after `max_frame` in order, checking each one's
+    /// salts and cumulative checksum, and stops at the first invalid frame
+    /// like SQLite's recovery does. Only the frames up to the last commit are
+    /// applied.
+    fn scan(&mut self) -> Result<()> {
+        self.file.seek(SeekFrom::Start(
+            HEADER_SIZE + self.max_frame as u64 * self.frame_size(),
+        ))?;
+        let mut reader = BufReader::new(&self.file);
+        let mut frame = vec![0; self.frame_size() as usize];
+        let mut running = self.checksum;
+        let mut pending = vec![];
+
+        while reader.read_exact(&mut frame).is_ok() {
+            let salt = (read_u32(&frame, 8), read_u32(&frame, 12));
+            if salt != self.header.salt {
+                break;
+            }
+
+            running = checksum(&frame[..8], self.header.big_endian, running);
+            running = checksum(
+                &frame[FRAME_HEADER_SIZE as usize..],
+                self.header.big_endian,
+                running,
+            );
+            if running != (read_u32(&frame, 16), read_u32(&frame, 20)) {
+                break;
+            }
+
+            self.valid_frames += 1;
+            pending.push((read_u32(&frame, 0), self.valid_frames));
+
+            // A non-zero database size marks the last frame of a transaction.
+            let database_size = read_u32(&frame, 4);
+            if database_size != 0 {
+                self.frames.extend(pending.drain(..));
+                self.max_frame = self.valid_frames;
+                self.database_size = database_size;
+                self.checksum = running;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The frame holding the latest committed version of a page.
+    pub fn find_frame(&self, page_number: u32) -> Option<u32> {
+        self.frames.get(&page_number).copied()
+    }
+
+    /// Pages with a committed version in the WAL, with the frame holding it,
+    /// in page order.
+    pub fn pages(&self) -> Vec<(u32, u32)> {
+        let mut pages = self.frames.iter().map(|(&page, &frame)| (page, frame)).collect::<Vec<_>>();
+        pages.sort_unstable();
+        pages
+    }
+
+    /// Starts an empty WAL next to the database at `path`, replacing any
+    /// file there: a header with `salt`, checksummed in the byte order of
+    /// this machine like SQLite does.
+    pub fn create(path: &str, page_size: u32, salt: (u32, u32)) -> Result<Self> {
+        let big_endian = cfg!(target_endian = "big");
+        let mut data = vec![];
+        data.extend((MAGIC | big_endian as u32).to_be_bytes());
+        data.extend(3007000u32.to_be_bytes());
+        // A page size of 65536 doesn't fit in 16 bits and is written as 1.
+        data.extend(match page_size {
+            65536 => 1u32,
+            size => size,
+        }
+        .to_be_bytes());
+        data.extend(0u32.to_be_bytes());
+        data.extend(salt.0.to_be_bytes());
+        data.extend(salt.1.to_be_bytes());
+        let (s0, s1) = checksum(&data, big_endian, (0, 0));
+        data.extend(s0.to_be_bytes());
+        data.extend(s1.to_be_bytes());
+
+        let path = format!("{}-wal", path);
+        let mut file = File::create(&path)?;
+        file.write_all(&data)?;
+        file.sync_all()?;
+
+        let header = WalHeader::parse(&data).expect("header was just written");
+        Ok(Self {
+            file: File::open(&path)?,
+            path,
+            header,
+            valid_frames: 0,
+            max_frame: 0,
+            database_size: 0,
+            checksum: header.checksum,
+            frames: HashMap::new(),
+        })
+    }
+
+    /// Appends a transaction after the last commit: a frame for each of
+    /// `pages`, by page number, the last one committing it with the
+    /// `database_size` in pages. Frames of a transaction that never
+    /// committed are written over. The WAL is synced, then read again.
+    pub fn append(&mut self, pages: &BTreeMap<u32, Vec<u8>>, database_size: u32) -> Result<()> {
+        let mut data = vec![];
+        let mut running = self.checksum;
+        for (index, (&page_number, page)) in pages.iter().enumerate() {
+            let commit = if index + 1 == pages.len() { database_size } else { 0 };
+            let mut frame = vec![];
+            frame.extend(page_number.to_be_bytes());
+            frame.extend(commit.to_be_bytes());
+            running = checksum(&frame, self.header.big_endian, running);
+            running = checksum(page, self.header.big_endian, running);
+            frame.extend(self.header.salt.0.to_be_bytes());
+            frame.extend(self.header.salt.1.to_be_bytes());
+            frame.extend(running.0.to_be_bytes());
+            frame.extend(running.1.to_be_bytes());
+            data.extend(frame);
+            data.extend(page);
+        }
+
+        let mut file = OpenOptions::new().write(true).open(&self.path)?;
+        file.seek(SeekFrom::Start(HEADER_SIZE + self.max_frame as u64 * self.frame_size()))?;
+        file.write_all(&data)?;
+        file.sync_all()?;
+
+        self.valid_frames = self.max_frame;
+        self.scan()
+    }
+
+    /// Empties the WAL file once its frames have been checkpointed.
+    pub fn truncate(&self) -> Result<()> {
+        std::fs::OpenOptions::new().write(true).open(&self.path)?.set_len(0)?;
+        Ok(())
+    }
+
+    /// Marks the wal-index in the -shm file next to the database at `path`,
+    /// if there is one, as uninitialized. This crate doesn't maintain the
+    /// index, so after it changes the WAL, SQLite connections rebuild the
+    /// index from the WAL instead of trusting a stale one.
+    pub fn invalidate_index(path: &str) -> Result<()> {
+        let mut shm = match OpenOptions::new().write(true).open(format!("{}-shm", path)) {
+            Ok(shm) => shm,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        // The isInit byte of both copies of the index header.
+        for at in [12, 60] {
+            shm.seek(SeekFrom::Start(at))?;
+            shm.write_all(&[0])?;
+        }
+        Ok(())
+    }
+
+    /// File offset of the page data of a 1-based frame.
+    pub fn frame_offset(&self, frame: u32) -> u64 {
+        HEADER_SIZE + (frame - 1) as u64 * self.frame_size() + FRAME_HEADER_SIZE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(big_endian: bool) -> Vec<u8> {
+        let mut data = vec![];
+        data.extend((MAGIC | big_endian as u32).to_be_bytes());
+        data.extend(3007000u32.to_be_bytes());
+        data.extend(4096u32.to_be_bytes());
+        data.extend(0u32.to_be_bytes());
+        data.extend(0x1234u32.to_be_bytes());
+        data.extend(0x5678u32.to_be_bytes());
+        let (s0, s1) = checksum(&data, big_endian, (0, 0));
+        data.extend(s0.to_be_bytes());
+        data.extend(s1.to_be_bytes());
+        data
+    }
+
+    #[test]
+    fn checksum_depends_on_byte_order() {
+        let data = [1, 0, 0, 0, 2, 0, 0, 0];
+        assert_eq!(checksum(&data, false, (0, 0)), (1, 3));
+        assert_eq!(checksum(&data, true, (0, 0)), (1 << 24, 3 << 24));
+        assert_eq!(checksum(&data, false, (1, 3)), (5, 10));
+    }
+
+    #[test]
+    fn append_frames_and_read_them_back() {
+        let path = std::env::temp_dir().join(format!("wal-{}.db", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut wal = Wal::create(path, 512, (1, 2)).unwrap();
+        assert_eq!(wal.header.salt, (1, 2));
+
+        let pages = BTreeMap::from([(1, vec![0xaa; 512]), (3, vec![0xbb; 512])]);
+        wal.append(&pages, 3).unwrap();
+        wal.append(&BTreeMap::from([(3, vec![0xcc; 512])]), 4).unwrap();
+        let reopened = Wal::open(path).unwrap().unwrap();
+        std::fs::remove_file(format!("{}-wal", path)).unwrap();
+
+        for wal in [&wal, &reopened] {
+            assert_eq!((wal.max_frame, wal.database_size), (3, 4));
+            assert_eq!(wal.pages(), vec![(1, 1), (3, 3)]);
+        }
+    }
+
+    #[test]
+    fn parse_header() {
+        let parsed = WalHeader::parse(&header(true)).unwrap();
+        assert!(parsed.big_endian);
+        assert_eq!(parsed.page_size, 4096);
+        assert_eq!(parsed.salt, (0x1234, 0x5678));
+
+        let mut corrupted = header(false);
+        corrupted[19] ^= 1;
+        assert!(WalHeader::parse(&corrupted).is_none());
+    }
+}